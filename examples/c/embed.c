@@ -0,0 +1,49 @@
+/*
+ * Runs a ROM for a number of frames through the C API and prints the display.
+ *
+ *   cargo build --release --features ffi
+ *   cc examples/c/embed.c -Iinclude -Ltarget/release -lchip_8_rust -o embed
+ *   LD_LIBRARY_PATH=target/release ./embed path/to/rom.ch8 120
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "chip8.h"
+
+int main(int argc, char **argv) {
+    if (argc < 2) {
+        fprintf(stderr, "usage: %s <rom> [frames]\n", argv[0]);
+        return 1;
+    }
+    int frames = argc > 2 ? atoi(argv[2]) : 60;
+
+    FILE *file = fopen(argv[1], "rb");
+    if (!file) {
+        perror(argv[1]);
+        return 1;
+    }
+    uint8_t rom[4096];
+    size_t length = fread(rom, 1, sizeof(rom), file);
+    fclose(file);
+
+    Chip8 *chip8 = chip8_new();
+    int status = chip8_load_rom(chip8, rom, length);
+    for (int frame = 0; status == CHIP8_OK && frame < frames; frame++) {
+        status = chip8_run_frame(chip8);
+    }
+    if (status != CHIP8_OK) {
+        fprintf(stderr, "chip8 error %d\n", status);
+    }
+
+    uint8_t framebuffer[CHIP8_FRAMEBUFFER_SIZE];
+    chip8_get_framebuffer(chip8, framebuffer, sizeof(framebuffer));
+    for (size_t y = 0; y < CHIP8_DISPLAY_HEIGHT; y++) {
+        for (size_t x = 0; x < CHIP8_DISPLAY_WIDTH; x++) {
+            putchar(framebuffer[y * CHIP8_DISPLAY_WIDTH + x] ? '#' : '.');
+        }
+        putchar('\n');
+    }
+
+    chip8_free(chip8);
+    return status == CHIP8_OK ? 0 : 1;
+}
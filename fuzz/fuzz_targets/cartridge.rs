@@ -0,0 +1,9 @@
+// Reads arbitrary bytes as an Octo cartridge, which exercises the GIF decoder, its LZW codes and the
+// JSON payload. Any error is fine, a panic isn't.
+#![no_main]
+use chip_8_rust::cartridge;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = cartridge::read(data);
+});
@@ -0,0 +1,35 @@
+// Runs arbitrary ROMs. The first byte picks the quirks, the second the keys held and the next two
+// the error policies, two bits for each class. The rest is the ROM. A ROM may stop with an error but
+// must never panic.
+#![no_main]
+use chip_8_rust::interpreter::Interpreter;
+use chip_8_rust::policy::{ErrorPolicies, ErrorPolicy, ERROR_CLASSES};
+use chip_8_rust::quirks::Quirks;
+use libfuzzer_sys::fuzz_target;
+
+const FRAMES: usize = 120;
+const POLICIES: [ErrorPolicy; 4] = [ErrorPolicy::Halt, ErrorPolicy::Wrap, ErrorPolicy::Ignore, ErrorPolicy::Halt];
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let mut interpreter = Interpreter::new(Quirks::from_bits(data[0]), 0);
+    let policy_bits = u16::from_le_bytes([data[2], data[3]]);
+    let mut policies = ErrorPolicies::default();
+    for (index, class) in ERROR_CLASSES.iter().enumerate() {
+        policies.set(*class, POLICIES[(policy_bits >> (index * 2)) as usize & 3]);
+    }
+    interpreter.set_error_policies(policies);
+    if interpreter.load_rom(&data[4..]).is_err() {
+        return;
+    }
+
+    for frame in 0..FRAMES {
+        // Alternate between the keys and none, so Fx0A sees presses and releases
+        interpreter.set_keys(if frame % 2 == 0 { data[1] as u16 * 0x0101 } else { 0 });
+        if interpreter.run_frame().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,23 @@
+// Loads arbitrary save states. Whatever loads has to run without panicking, even with values
+// save_state would never write.
+#![no_main]
+use chip_8_rust::interpreter::Interpreter;
+use chip_8_rust::quirks::Quirks;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+    if interpreter.load_state(data).is_err() {
+        return;
+    }
+
+    // A state can ask for any number of cycles per frame, keep the run short
+    interpreter.set_cycles_per_frame(interpreter.cycles_per_frame().min(1000));
+    for _ in 0..10 {
+        if interpreter.run_frame().is_err() {
+            break;
+        }
+    }
+    let _ = interpreter.save_state();
+    interpreter.reset();
+});
@@ -0,0 +1,10 @@
+// Compiles arbitrary text as Octo source. Any error is fine, a panic isn't.
+#![no_main]
+use chip_8_rust::octo;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = octo::compile(source);
+    }
+});
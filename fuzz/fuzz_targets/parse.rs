@@ -0,0 +1,14 @@
+// Every pair of bytes either decodes or is rejected with an error, and anything that decodes can
+// be shown
+#![no_main]
+use chip_8_rust::instruction::Instruction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for raw in data.chunks_exact(2) {
+        if let Ok(instruction) = Instruction::parse((raw[0], raw[1])) {
+            let _ = instruction.to_string();
+            let _ = instruction.family();
+        }
+    }
+});
@@ -0,0 +1,10 @@
+// Applies arbitrary IPS and BPS patches to a small ROM. Anything can come back as long as it's an
+// error or a ROM rather than a panic.
+#![no_main]
+use chip_8_rust::patch;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let rom: Vec<u8> = (0..=255).collect();
+    let _ = patch::apply(&rom, data);
+});
@@ -0,0 +1,129 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define CHIP8_API_VERSION 2
+
+#define CHIP8_OK 0
+
+#define CHIP8_ERROR_NULL_POINTER -1
+
+#define CHIP8_ERROR_ROM_TOO_LARGE -2
+
+#define CHIP8_ERROR_BUFFER_TOO_SMALL -3
+
+#define CHIP8_ERROR_INVALID_ARGUMENT -4
+
+#define CHIP8_ERROR_INVALID_STATE -5
+
+/**
+ * The ROM did something the interpreter can't continue from. The machine stays halted until it is
+ * reset or another ROM is loaded.
+ */
+#define CHIP8_ERROR_HALTED -6
+
+#define CHIP8_DISPLAY_WIDTH 64
+
+#define CHIP8_DISPLAY_HEIGHT 32
+
+#define CHIP8_FRAMEBUFFER_SIZE (64 * 32)
+
+/**
+ * Opaque to C, only ever handled through a pointer from chip8_new
+ */
+typedef struct Chip8 Chip8;
+
+uint32_t chip8_api_version(void);
+
+/**
+ * Creates a machine with the default quirks and seed 0. Free it with chip8_free.
+ */
+Chip8 *chip8_new(void);
+
+void chip8_free(Chip8 *chip8);
+
+/**
+ * Copies a ROM into memory and resets the machine. The ROM runs with the quirks and speed the ROM
+ * database has for it, or quirks detected from its code, except where chip8_set_quirks or
+ * chip8_set_cycles_per_frame chose otherwise.
+ */
+int chip8_load_rom(Chip8 *chip8, const uint8_t *data, size_t length);
+
+int chip8_reset(Chip8 *chip8);
+
+/**
+ * Takes the same values as the --quirks command line option, e.g. "schip" or "vf_reset,clip_sprites"
+ */
+int chip8_set_quirks(Chip8 *chip8,
+                     const char *quirks);
+
+/**
+ * Takes the same values as the --on-error command line option, e.g. "stack_overflow=wrap" or
+ * "all=ignore". Errors whose policy is halt leave the machine halted with CHIP8_ERROR_HALTED.
+ */
+int chip8_set_error_policies(Chip8 *chip8, const char *policies);
+
+/**
+ * Reseeds the random number generator now, and again with the same seed on every reset or ROM load
+ */
+int chip8_set_seed(Chip8 *chip8, uint64_t seed);
+
+int chip8_set_cycles_per_frame(Chip8 *chip8, uint32_t cycles_per_frame);
+
+/**
+ * Executes a single instruction
+ */
+int chip8_step(Chip8 *chip8);
+
+/**
+ * Executes one 60Hz frame of instructions and ticks the timers
+ */
+int chip8_run_frame(Chip8 *chip8);
+
+/**
+ * Writes one byte per pixel, 1 for lit and 0 for unlit, rows top to bottom. The buffer must hold
+ * at least CHIP8_FRAMEBUFFER_SIZE bytes.
+ */
+int chip8_get_framebuffer(Chip8 *chip8, uint8_t *buffer, size_t length);
+
+/**
+ * Presses (pressed != 0) or releases one of the keypad keys 0x0-0xF
+ */
+int chip8_set_key(Chip8 *chip8, uint8_t key, int pressed);
+
+/**
+ * Returns 1 while the buzzer should sound, 0 when silent, or an error code
+ */
+int chip8_sound_active(Chip8 *chip8);
+
+/**
+ * Sets the buzzer tone in Hz and its volume from 0 to 1
+ */
+int chip8_set_buzzer(Chip8 *chip8, float frequency, float volume);
+
+/**
+ * Writes length mono samples in -1..1 of buzzer output, usually sample_rate / 60 after each frame
+ */
+int chip8_fill_audio(Chip8 *chip8, float *buffer, size_t length, uint32_t sample_rate);
+
+/**
+ * Writes why a halted machine stopped as a nul terminated string, starting with the address of the
+ * instruction, e.g. "0204: return with an empty stack". Returns CHIP8_ERROR_INVALID_STATE if the
+ * machine isn't halted.
+ */
+int chip8_get_halt_reason(Chip8 *chip8, char *buffer, size_t length);
+
+/**
+ * Size in bytes of the buffer chip8_save_state needs, or 0 for a null machine
+ */
+size_t chip8_state_size(Chip8 *chip8);
+
+int chip8_save_state(Chip8 *chip8, uint8_t *buffer, size_t length);
+
+int chip8_load_state(Chip8 *chip8, const uint8_t *data, size_t length);
+
+#endif  /* CHIP8_H */
@@ -0,0 +1,331 @@
+use std::io::{self, Write};
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+// XO-CHIP audio patterns are 16 bytes, played one bit at a time from the most significant bit of
+// the first byte
+pub const PATTERN_SIZE: usize = 16;
+pub const PATTERN_BITS: usize = PATTERN_SIZE * 8;
+pub const DEFAULT_PITCH: u8 = 64;
+
+// Starting or stopping a square wave at full volume clicks, so the gain slides to its new level over
+// a couple of milliseconds instead
+const RAMP_SECONDS: f32 = 0.002;
+
+// The CHIP-8 buzzer. It sounds while the sound timer is non-zero. On plain CHIP-8 the ROM has no
+// control over the tone, so frequency and volume are settings of the host. XO-CHIP ROMs can instead
+// play a 1 bit pattern at a pitch they choose, see fill_pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buzzer {
+    frequency: f32,
+    volume: f32,
+    phase: f64,
+    gain: f32,
+}
+
+impl Buzzer {
+    pub fn new() -> Buzzer {
+        Buzzer {
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            phase: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.max(0.0);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    // 0 is silent and 1 is full scale
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    // True while any sound is still being produced, including the tail of the stop ramp
+    pub fn is_sounding(&self) -> bool {
+        self.gain > 0.0
+    }
+
+    // Writes mono samples in -1..1 of a square wave. The waveform continues across calls, so a host
+    // can fill its audio buffers in whatever sizes it likes.
+    pub fn fill(&mut self, buffer: &mut [f32], sample_rate: u32, active: bool) {
+        let phase_step = self.frequency as f64 / sample_rate.max(1) as f64;
+
+        self.render(buffer, sample_rate, active, |phase| {
+            let level = if *phase < 0.5 { 1.0 } else { -1.0 };
+            *phase = (*phase + phase_step).fract();
+
+            level
+        });
+    }
+
+    // Plays an XO-CHIP audio pattern, a set bit is high and a clear bit low. The pattern is a signal
+    // at pattern_rate(pitch) bits per second, so each output sample is the average of the pattern
+    // over the time the sample covers. That keeps the pattern's timing exact at any host sample
+    // rate and stops high pitches aliasing into noise.
+    pub fn fill_pattern(&mut self, buffer: &mut [f32], sample_rate: u32, active: bool, pattern: &[u8; PATTERN_SIZE], pitch: u8) {
+        let step = pattern_rate(pitch) / sample_rate.max(1) as f64;
+
+        self.render(buffer, sample_rate, active, |position| {
+            let level = pattern_average(pattern, *position, step);
+            *position = (*position + step) % PATTERN_BITS as f64;
+
+            level
+        });
+    }
+
+    // Applies the volume envelope to a waveform. The waveform is given its phase, which it advances
+    // by one sample.
+    fn render(&mut self, buffer: &mut [f32], sample_rate: u32, active: bool, mut waveform: impl FnMut(&mut f64) -> f32) {
+        if sample_rate == 0 {
+            return;
+        }
+
+        let target = if active { self.volume } else { 0.0 };
+        let ramp_step = self.volume.max(f32::EPSILON) / (RAMP_SECONDS * sample_rate as f32).max(1.0);
+
+        for sample in buffer.iter_mut() {
+            self.gain = if self.gain < target {
+                (self.gain + ramp_step).min(target)
+            } else {
+                (self.gain - ramp_step).max(target)
+            };
+
+            if self.gain == 0.0 {
+                // Restart from the beginning of a cycle so every beep sounds the same
+                self.phase = 0.0;
+                *sample = 0.0;
+                continue;
+            }
+
+            *sample = waveform(&mut self.phase) * self.gain;
+        }
+    }
+}
+
+// Playback rate of an XO-CHIP audio pattern in bits per second. Pitch 64 is 4000 bits per second
+// and every 48 steps doubles it.
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+// Average level of the pattern, as -1..1, over the bits from start to start + length
+fn pattern_average(pattern: &[u8; PATTERN_SIZE], start: f64, length: f64) -> f32 {
+    let mut position = start;
+    let mut remaining = length;
+    let mut total = 0.0;
+
+    while remaining > 0.0 {
+        let bit = position.floor();
+        let span = (bit + 1.0 - position).min(remaining);
+        let index = bit as usize % PATTERN_BITS;
+        let high = pattern[index / 8] & (0x80 >> (index % 8)) != 0;
+
+        total += if high { span } else { -span };
+        // Step onto the next bit exactly rather than accumulating rounding errors in position
+        position = if span < remaining { bit + 1.0 } else { position + span };
+        remaining -= span;
+    }
+
+    (total / length) as f32
+}
+
+impl Default for Buzzer {
+    fn default() -> Buzzer {
+        Buzzer::new()
+    }
+}
+
+// Writes samples in -1..1 as a 16 bit mono PCM WAV file
+pub fn write_wav(writer: &mut impl Write, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let data_size = (samples.len() * 2) as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&to_i16(*sample).to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pattern_rate, write_wav, Buzzer, PATTERN_SIZE};
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    // A buzzer already at full volume, so pattern output can be compared without the start ramp
+    fn sounding_buzzer() -> Buzzer {
+        let mut buzzer = Buzzer::new();
+        buzzer.set_volume(1.0);
+        buzzer.gain = 1.0;
+
+        buzzer
+    }
+
+    fn play_pattern(pattern: [u8; PATTERN_SIZE], pitch: u8, sample_rate: u32, length: usize) -> Vec<f32> {
+        let mut buzzer = sounding_buzzer();
+        let mut buffer = vec![0.0; length];
+
+        buzzer.fill_pattern(&mut buffer, sample_rate, true, &pattern, pitch);
+
+        buffer
+    }
+
+    fn assert_waveform(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((actual - expected).abs() < 1e-5, "sample {}: {} != {}", index, actual, expected);
+        }
+    }
+
+    #[test]
+    fn buzzer_is_silent_while_inactive() {
+        let mut buzzer = Buzzer::new();
+        let mut buffer = [1.0; 64];
+
+        buzzer.fill(&mut buffer, SAMPLE_RATE, false);
+
+        assert!(buffer.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn buzzer_plays_a_square_wave_at_its_frequency() {
+        let mut buzzer = Buzzer::new();
+        buzzer.set_frequency(441.0);
+        let mut buffer = [0.0; 1000];
+
+        buzzer.fill(&mut buffer, SAMPLE_RATE, true);
+
+        // 441Hz at 44.1kHz is 100 samples a cycle, half high and half low once the ramp is done
+        let settled = &buffer[200..1000];
+        let high = settled.iter().filter(|sample| **sample == buzzer.volume()).count();
+        let low = settled.iter().filter(|sample| **sample == -buzzer.volume()).count();
+        let edges = settled.windows(2).filter(|pair| pair[0].signum() != pair[1].signum()).count();
+        assert_eq!(high + low, 800);
+        assert!((high as i32 - low as i32).abs() <= 2);
+        assert!(edges == 15 || edges == 16);
+    }
+
+    #[test]
+    fn buzzer_ramps_in_and_out_without_clicks() {
+        let mut buzzer = Buzzer::new();
+        let mut start = [0.0; 200];
+        let mut stop = [0.0; 200];
+
+        buzzer.fill(&mut start, SAMPLE_RATE, true);
+        buzzer.fill(&mut stop, SAMPLE_RATE, false);
+
+        let ramp_step = buzzer.volume() / (0.002 * SAMPLE_RATE as f32);
+        assert!(start[0].abs() <= ramp_step * 1.01);
+        assert!(stop[0].abs() >= buzzer.volume() - ramp_step * 1.01);
+        let magnitudes: Vec<f32> = stop.iter().map(|sample| sample.abs()).collect();
+        assert!(magnitudes.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(stop[199], 0.0);
+        assert!(!buzzer.is_sounding());
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        let mut buzzer = Buzzer::new();
+
+        buzzer.set_volume(3.0);
+
+        assert_eq!(buzzer.volume(), 1.0);
+    }
+
+    #[test]
+    fn wav_has_a_pcm_header_and_samples() {
+        let mut wav = Vec::new();
+
+        write_wav(&mut wav, &[0.0, 1.0, -1.0], 8000).unwrap();
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]), 8000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn pattern_rate_doubles_every_48_pitch_steps() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 1e-9);
+        assert!((pattern_rate(16) - 2000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pattern_at_its_own_rate_plays_one_bit_per_sample() {
+        let pattern = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let output = play_pattern(pattern, 64, 4000, 256);
+
+        let mut expected = vec![1.0; 64];
+        expected.extend(vec![-1.0; 64]);
+        expected.extend(expected.clone());
+        assert_waveform(&output, &expected);
+    }
+
+    #[test]
+    fn pattern_is_held_when_upsampled() {
+        let pattern = [0x96; PATTERN_SIZE];
+
+        let output = play_pattern(pattern, 64, 8000, 16);
+
+        // 1001 0110, each bit twice
+        assert_waveform(&output, &[
+            1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 1.0, 1.0,
+            -1.0, -1.0, 1.0, 1.0, 1.0, 1.0, -1.0, -1.0,
+        ]);
+    }
+
+    #[test]
+    fn pattern_is_averaged_when_downsampled() {
+        let alternating = play_pattern([0xaa; PATTERN_SIZE], 64, 2000, 64);
+        let thirds = play_pattern([0xe0; PATTERN_SIZE], 64, 3000, 12);
+
+        // Alternate bits cancel out, which point sampling would have aliased into a full scale tone
+        assert_waveform(&alternating, &[0.0; 64]);
+        // 1110 0000 at 4/3 bits per sample
+        assert_waveform(&thirds, &[1.0, 1.0, -0.5, -1.0, -1.0, -1.0, 1.0, 1.0, -0.5, -1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn pattern_pitch_changes_the_playback_rate() {
+        let pattern = [0xf0; PATTERN_SIZE];
+
+        let output = play_pattern(pattern, 112, 4000, 8);
+
+        // 8000 bits per second, two bits per sample
+        assert_waveform(&output, &[1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0]);
+    }
+}
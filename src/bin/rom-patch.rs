@@ -0,0 +1,55 @@
+// Applies and creates IPS and BPS patches, and patches single instructions by assembly, for keeping
+// fixed up versions of ROMs as small patches against the originals.
+
+use chip_8_rust::cli::exit_with;
+use chip_8_rust::patch::{self, PatchFormat};
+use std::env;
+use std::fs;
+
+const USAGE: &str = "Usage:
+  rom-patch apply <rom> <patch> <output>
+  rom-patch create <original> <modified> <output.ips|output.bps>
+  rom-patch asm <rom> <address> <instruction> <output>
+
+apply writes the ROM with an IPS or BPS patch applied. create writes a patch that turns the original
+ROM into the modified one, as IPS or BPS by the output's extension. asm replaces the instruction at
+a hexadecimal memory address, e.g. rom-patch asm pong.ch8 2F4 \"LD V5, 0x09\" fix.bps, and writes a
+patch when the output ends in .ips or .bps and the patched ROM otherwise.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["apply", rom_path, patch_path, output_path] => {
+            let patched = patch::apply(&read(rom_path), &read(patch_path))
+                .unwrap_or_else(|error| exit_with(&format!("Could not apply {}: {}", patch_path, error)));
+            write(output_path, &patched);
+        }
+        ["create", original_path, modified_path, output_path] => {
+            let format = PatchFormat::from_path(output_path)
+                .unwrap_or_else(|| exit_with(&format!("{} should end in .ips or .bps", output_path)));
+            write(output_path, &patch::create(format, &read(original_path), &read(modified_path)));
+        }
+        ["asm", rom_path, address, instruction, output_path] => {
+            let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                .unwrap_or_else(|_| exit_with(&format!("{} is not a hexadecimal address", address)));
+            let rom = read(rom_path);
+            let patched = patch::patch_instruction(&rom, address, instruction)
+                .unwrap_or_else(|error| exit_with(&error.to_string()));
+            match PatchFormat::from_path(output_path) {
+                Some(format) => write(output_path, &patch::create(format, &rom, &patched)),
+                None => write(output_path, &patched),
+            }
+        }
+        _ => exit_with(USAGE),
+    }
+}
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|error| exit_with(&format!("Could not read {}: {}", path, error)))
+}
+
+fn write(path: &str, contents: &[u8]) {
+    fs::write(path, contents).unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", path, error)));
+}
@@ -0,0 +1,624 @@
+// Terminal front end. Two pixels are drawn per character cell using the upper half block with the
+// top pixel as the foreground colour and the bottom pixel as the background colour, so the whole
+// 64x32 display fits in 64x16 cells.
+
+use chip_8_rust::cheats::{self, Cheats, Filter, MemorySearch};
+use chip_8_rust::cli::{exit_with, option_value, parse_number};
+use chip_8_rust::database::{parse_colour, RomInfo};
+use chip_8_rust::display::{Rect, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip_8_rust::hash::fnv1a;
+use chip_8_rust::instruction::Instruction;
+use chip_8_rust::interpreter::{ExecError, Interpreter, DEFAULT_CYCLES_PER_FRAME, MEMORY_SIZE};
+use chip_8_rust::keypad::{KeyMap, KEY_COUNT};
+use chip_8_rust::loader::RomLoader;
+use chip_8_rust::movie::{Recorder, DEFAULT_CHECKPOINT_INTERVAL};
+use chip_8_rust::policy::ErrorPolicies;
+use chip_8_rust::quirks::Quirks;
+use crossterm::event::{
+    self,
+    Event,
+    KeyCode,
+    KeyEvent,
+    KeyEventKind,
+    KeyModifiers,
+    KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: tui <rom> [options]
+
+Plays a ROM or Octo cartridge (.gif) in the terminal.
+
+Options:
+  --quirks <quirks>          chip8, schip, xochip, none or a comma separated list of quirk flags,
+                             by default what the ROM database says, or what the ROM looks like
+                             it needs when it isn't in the database
+  --seed <n>                 seed for the random number generator (default 0)
+  --cycles-per-frame <n>     instructions executed per 60Hz frame, by default what the ROM
+                             database says or 10
+  --on-error <policies>      halt, wrap or ignore for each class of error, e.g. all=ignore
+  --keymap <file>            key map config file, see the README
+  --database <file>          ROM metadata to use along with the embedded database, see data/roms.txt
+  --record <movie>           record the session to a movie file
+  --patch <file>             apply an IPS or BPS patch to the ROM as it loads, can be repeated
+  --freeze <cheats>          hold memory at these values every frame, e.g. 2F0=03,2F1=09 with
+                             hexadecimal addresses and values
+  --panel                    start with the register and disassembly panel open
+  --on-colour <rrggbb>       colour of lit pixels (default ffffff, or the ROM database's)
+  --off-colour <rrggbb>      colour of unlit pixels (default 000000, or the ROM database's)
+
+Keys:
+  F1 toggle panel   F2 reset   F3 cheat prompt   F5 pause   F6 step while paused   Esc quit
+
+Cheat prompt:
+  search                     start a memory search with every address a candidate
+  equal, changed, increased  keep the candidates that changed this way since the last search
+  decreased or <value>       command, or that now hold the hexadecimal value
+  poke <address> <value>     write a byte once
+  freeze <address> [value]   hold a byte at a value every frame, by default the one it has now
+  unfreeze <address>|all     let it change again";
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+// Without the kitty keyboard protocol terminals only report presses and auto repeats, never
+// releases, so a key is treated as held until this many frames pass without another press
+const HOLD_FRAMES: u32 = 8;
+
+const PANEL_COLUMN: u16 = DISPLAY_WIDTH as u16 + 2;
+const DISASSEMBLY_BEFORE: u16 = 6;
+const DISASSEMBLY_AFTER: u16 = 10;
+// Candidates listed after a search command, there are usually too many to be useful until then
+const CANDIDATES_SHOWN: usize = 8;
+
+// Settings the ROM database or detection can fill in are None until they have had a say
+struct Options {
+    rom_path: String,
+    quirks: Option<Quirks>,
+    seed: u64,
+    cycles_per_frame: Option<u32>,
+    error_policies: ErrorPolicies,
+    keymap_path: Option<String>,
+    database_path: Option<String>,
+    record_path: Option<String>,
+    patch_paths: Vec<String>,
+    cheats: Cheats,
+    show_panel: bool,
+    on_colour: Option<[u8; 3]>,
+    off_colour: Option<[u8; 3]>,
+}
+
+struct Session {
+    interpreter: Interpreter,
+    key_map: KeyMap,
+    recorder: Option<Recorder>,
+    hold_frames: [u32; KEY_COUNT as usize],
+    release_events: bool,
+    show_panel: bool,
+    paused: bool,
+    // Set when the ROM stops with an error, until the machine is reset
+    error: Option<ExecError>,
+    cheats: Cheats,
+    search: Option<MemorySearch>,
+    prompt: Option<String>, // the cheat command being typed
+    message: String,        // what the last cheat command did
+    on_colour: Color,
+    off_colour: Color,
+}
+
+// Puts the terminal back the way it was however the program exits
+struct TerminalGuard {
+    release_events: bool,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        let release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, cursor::Hide, terminal::Clear(ClearType::All))?;
+        if release_events {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(TerminalGuard { release_events })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal(self.release_events);
+    }
+}
+
+fn restore_terminal(release_events: bool) {
+    let mut stdout = io::stdout();
+    if release_events {
+        let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(stdout, ResetColor, cursor::Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+impl Options {
+    // Anything not given on the command line comes from the ROM's database entry
+    fn apply_rom_info(&mut self, info: &RomInfo) {
+        self.quirks = self.quirks.or_else(|| info.quirks());
+        self.cycles_per_frame = self.cycles_per_frame.or(info.cycles_per_frame);
+        self.on_colour = self.on_colour.or(info.on_colour);
+        self.off_colour = self.off_colour.or(info.off_colour);
+    }
+}
+
+fn main() {
+    let mut options = parse_options(env::args().skip(1)).unwrap_or_else(|message| exit_with(&message));
+    let loaded = RomLoader::from_files(options.database_path.as_ref(), &options.patch_paths)
+        .and_then(|loader| loader.load(Path::new(&options.rom_path)))
+        .unwrap_or_else(|error| exit_with(&error.to_string()));
+    let rom = loaded.rom;
+    let mut message = String::new();
+    if let Some(entry) = &loaded.entry {
+        message = format!("Recognised {}", entry);
+    } else if let (None, Some(detection)) = (options.quirks, &loaded.detection) {
+        message = format!("Detected {}", detection);
+    }
+    options.apply_rom_info(&loaded.info);
+
+    let key_map = match &options.keymap_path {
+        Some(path) => {
+            let rom_name = Path::new(&options.rom_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            KeyMap::load(Path::new(path), &rom_name, fnv1a(&rom)).unwrap_or_else(|error| exit_with(&error.to_string()))
+        }
+        None => loaded.entry.as_ref().map(RomInfo::key_map).unwrap_or_default(),
+    };
+
+    let mut interpreter = Interpreter::new(options.quirks.unwrap_or_default(), options.seed);
+    interpreter.set_cycles_per_frame(options.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME));
+    interpreter.set_error_policies(options.error_policies);
+    interpreter.load_rom(&rom).unwrap_or_else(|error| exit_with(&error.to_string()));
+    let recorder = options
+        .record_path
+        .as_ref()
+        .map(|_| Recorder::new(&interpreter, DEFAULT_CHECKPOINT_INTERVAL));
+
+    // Leave the terminal usable if anything panics
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal(true);
+        default_hook(info);
+    }));
+
+    let guard = TerminalGuard::enter().unwrap_or_else(|error| exit_with(&error.to_string()));
+    let mut session = Session {
+        interpreter,
+        key_map,
+        recorder,
+        hold_frames: [0; KEY_COUNT as usize],
+        release_events: guard.release_events,
+        show_panel: options.show_panel,
+        paused: false,
+        error: None,
+        cheats: options.cheats,
+        search: None,
+        prompt: None,
+        message,
+        on_colour: colour(options.on_colour.unwrap_or([0xff, 0xff, 0xff])),
+        off_colour: colour(options.off_colour.unwrap_or([0, 0, 0])),
+    };
+
+    let result = session.run();
+    drop(guard);
+
+    if let Err(error) = result {
+        exit_with(&error.to_string());
+    }
+    if let (Some(recorder), Some(path)) = (session.recorder, options.record_path) {
+        let movie = recorder.finish();
+        fs::write(&path, movie.to_string())
+            .unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", path, error)));
+        println!("Recorded {} frames to {}", movie.inputs.len(), path);
+    }
+}
+
+impl Session {
+    fn run(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        self.interpreter.display_mut().mark_all_dirty();
+        let mut next_frame = Instant::now();
+
+        loop {
+            next_frame += FRAME_DURATION;
+            while let Some(timeout) = next_frame.checked_duration_since(Instant::now()) {
+                if !event::poll(timeout)? {
+                    break;
+                }
+                if let Event::Key(key_event) = event::read()? {
+                    if !self.handle_key(key_event, &mut stdout)? {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if !self.paused && self.error.is_none() {
+                self.release_expired_keys();
+                self.cheats.apply(&mut self.interpreter);
+                let result = match self.recorder.as_mut() {
+                    Some(recorder) => recorder.record_frame(&mut self.interpreter),
+                    None => self.interpreter.run_frame(),
+                };
+                self.error = result.err();
+            }
+
+            self.draw(&mut stdout)?;
+
+            // Don't try to catch up if the terminal fell behind, just carry on from now
+            let now = Instant::now();
+            if next_frame < now {
+                next_frame = now;
+            }
+        }
+    }
+
+    // Returns false when the user asked to quit
+    fn handle_key(&mut self, key_event: KeyEvent, stdout: &mut io::Stdout) -> io::Result<bool> {
+        let pressed = key_event.kind != KeyEventKind::Release;
+
+        // Typing a command shouldn't press CHIP-8 keys
+        if let Some(prompt) = &mut self.prompt {
+            if pressed {
+                match key_event.code {
+                    KeyCode::Esc => self.prompt = None,
+                    KeyCode::Enter => {
+                        let command = self.prompt.take().unwrap_or_default();
+                        self.message = self.run_command(&command);
+                    }
+                    KeyCode::Backspace => {
+                        prompt.pop();
+                    }
+                    KeyCode::Char(character) => prompt.push(character),
+                    _ => {}
+                }
+            }
+            return Ok(true);
+        }
+
+        if pressed {
+            match key_event.code {
+                KeyCode::Esc => return Ok(false),
+                KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+                KeyCode::F(1) => {
+                    self.show_panel = !self.show_panel;
+                    execute!(stdout, terminal::Clear(ClearType::All))?;
+                    self.interpreter.display_mut().mark_all_dirty();
+                    return Ok(true);
+                }
+                // Resetting or stepping part of a frame would make a recording impossible to replay
+                KeyCode::F(2) if self.recorder.is_none() => {
+                    self.interpreter.reset();
+                    self.error = None;
+                    return Ok(true);
+                }
+                KeyCode::F(3) => {
+                    self.prompt = Some(String::new());
+                    return Ok(true);
+                }
+                KeyCode::F(5) => {
+                    self.paused = !self.paused;
+                    return Ok(true);
+                }
+                KeyCode::F(6) if self.paused && self.error.is_none() && self.recorder.is_none() => {
+                    self.error = self.interpreter.step().err();
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+
+        let key = match host_key_name(key_event.code).and_then(|name| self.key_map.key(&name)) {
+            Some(key) => key,
+            None => return Ok(true),
+        };
+
+        if pressed {
+            self.interpreter.keypad_mut().press(key);
+            self.hold_frames[key as usize] = HOLD_FRAMES;
+        } else {
+            self.interpreter.keypad_mut().release(key);
+        }
+
+        Ok(true)
+    }
+
+    // Returns the message to show for the command
+    fn run_command(&mut self, command: &str) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        // Writing memory outside of the inputs would make a recording impossible to replay
+        let writes = matches!(words.first(), Some(&"poke") | Some(&"freeze"));
+        if writes && self.recorder.is_some() {
+            return "Can't change memory while recording".to_string();
+        }
+        let address = |index: usize| {
+            let text = words.get(index).copied().unwrap_or("");
+            cheats::parse_address(text).ok_or_else(|| format!("{} is not a hexadecimal address", text))
+        };
+        let value = |index: usize| {
+            let text = words.get(index).copied().unwrap_or("");
+            cheats::parse_hex(text).ok_or_else(|| format!("{} is not a hexadecimal byte", text))
+        };
+
+        let result = match words.as_slice() {
+            [] => Ok(String::new()),
+            ["search"] => {
+                self.search = Some(MemorySearch::new(&self.interpreter));
+                Ok(format!("{} candidates", MEMORY_SIZE))
+            }
+            ["poke", _, _] => address(1).and_then(|address| {
+                let value = value(2)?;
+                self.interpreter.poke(address, value);
+                Ok(format!("Poked {:03X}={:02X}", address, value))
+            }),
+            ["freeze", _] | ["freeze", _, _] => address(1).and_then(|address| {
+                let value = match words.len() {
+                    3 => value(2)?,
+                    _ => self.interpreter.memory()[address as usize],
+                };
+                self.cheats.freeze(address, value);
+                Ok(format!("Frozen {}", self.cheats))
+            }),
+            ["unfreeze", "all"] => {
+                self.cheats = Cheats::new();
+                Ok("Nothing frozen".to_string())
+            }
+            ["unfreeze", _] => address(1).map(|address| {
+                self.cheats.unfreeze(address);
+                format!("Frozen {}", self.cheats)
+            }),
+            [filter] => match (filter.parse::<Filter>(), &mut self.search) {
+                (Err(error), _) => Err(error.to_string()),
+                (Ok(_), None) => Err("Start a search first".to_string()),
+                (Ok(filter), Some(search)) => {
+                    let left = search.filter(&self.interpreter, filter);
+                    let memory = self.interpreter.memory();
+                    let shown: Vec<String> = search
+                        .candidates()
+                        .iter()
+                        .take(CANDIDATES_SHOWN)
+                        .map(|address| format!("{:03X}={:02X}", address, memory[*address as usize]))
+                        .collect();
+                    Ok(format!("{} candidates {}", left, shown.join(" ")))
+                }
+            },
+            _ => Err(format!("Unknown cheat command {}", command)),
+        };
+
+        result.unwrap_or_else(|error| error)
+    }
+
+    fn release_expired_keys(&mut self) {
+        if self.release_events {
+            return;
+        }
+
+        for key in 0..KEY_COUNT {
+            let hold = &mut self.hold_frames[key as usize];
+            if *hold > 0 {
+                *hold -= 1;
+                if *hold == 0 {
+                    self.interpreter.keypad_mut().release(key);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let dirty_rects = self.interpreter.display_mut().take_dirty_rects();
+        for rect in dirty_rects {
+            self.draw_rect(stdout, rect)?;
+        }
+
+        if self.show_panel {
+            self.draw_panel(stdout)?;
+        }
+        self.draw_status(stdout)?;
+        queue!(stdout, ResetColor)?;
+
+        stdout.flush()
+    }
+
+    fn draw_rect(&self, stdout: &mut io::Stdout, rect: Rect) -> io::Result<()> {
+        let display = self.interpreter.display();
+
+        for cell_row in rect.y / 2..(rect.y + rect.height).div_ceil(2) {
+            queue!(stdout, cursor::MoveTo(rect.x as u16, cell_row as u16))?;
+            // Colour escapes are only written when they change, which most neighbouring cells share
+            let mut current_colours = None;
+            for x in rect.x..rect.x + rect.width {
+                let top = display.pixel(x, cell_row * 2);
+                let bottom = display.pixel(x, cell_row * 2 + 1);
+                if current_colours != Some((top, bottom)) {
+                    queue!(
+                        stdout,
+                        SetForegroundColor(if top { self.on_colour } else { self.off_colour }),
+                        SetBackgroundColor(if bottom { self.on_colour } else { self.off_colour })
+                    )?;
+                    current_colours = Some((top, bottom));
+                }
+                queue!(stdout, Print('\u{2580}'))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_status(&self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let mut status = String::new();
+        if let Some(error) = &self.error {
+            status.push_str(&format!("[HALTED: {}] ", error));
+        }
+        if self.paused {
+            status.push_str("[PAUSED] ");
+        }
+        if self.recorder.is_some() {
+            status.push_str("[REC] ");
+        }
+        if self.interpreter.sound_active() {
+            status.push_str("[BEEP] ");
+        }
+        status.push_str("F1 panel  F2 reset  F3 cheats  F5 pause  F6 step  Esc quit");
+        let cheat_line = match &self.prompt {
+            Some(prompt) => format!("cheat> {}", prompt),
+            None => self.message.clone(),
+        };
+
+        queue!(
+            stdout,
+            ResetColor,
+            cursor::MoveTo(0, (DISPLAY_HEIGHT / 2) as u16 + 1),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(status),
+            cursor::MoveTo(0, (DISPLAY_HEIGHT / 2) as u16 + 2),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(cheat_line)
+        )
+    }
+
+    fn draw_panel(&self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let mut lines = register_lines(&self.interpreter);
+        lines.push(String::new());
+        lines.extend(disassembly_lines(&self.interpreter));
+
+        queue!(stdout, ResetColor)?;
+        for (row, line) in lines.iter().enumerate() {
+            queue!(
+                stdout,
+                cursor::MoveTo(PANEL_COLUMN, row as u16),
+                terminal::Clear(ClearType::UntilNewLine),
+                Print(line)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn register_lines(interpreter: &Interpreter) -> Vec<String> {
+    let registers = interpreter.registers();
+    let mut lines = vec![
+        format!("PC {:03X}  I {:03X}  SP {:X}", interpreter.pc(), interpreter.i(), interpreter.sp()),
+        format!("DT {:02X}  ST {:02X}  keys {:04X}", interpreter.delay_timer(), interpreter.sound_timer(), interpreter.keys()),
+    ];
+    for (row_index, values) in registers.chunks(4).enumerate() {
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02X}", row_index * 4 + column, value))
+            .collect();
+        lines.push(cells.join("  "));
+    }
+
+    let stack: Vec<String> = interpreter.stack().iter().map(|address| format!("{:03X}", address)).collect();
+    lines.push(format!("stack {}", stack.join(" ")));
+
+    lines
+}
+
+fn disassembly_lines(interpreter: &Interpreter) -> Vec<String> {
+    let memory = interpreter.memory();
+    let pc = interpreter.pc() as usize;
+    let first = pc.saturating_sub(DISASSEMBLY_BEFORE as usize * 2);
+    let last = (pc + DISASSEMBLY_AFTER as usize * 2).min(MEMORY_SIZE - 2);
+
+    (first..=last)
+        .step_by(2)
+        .map(|address| {
+            let raw = (memory[address], memory[address + 1]);
+            let mnemonic = Instruction::decode(raw)
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_else(|| "???".to_string());
+            let marker = if address == pc { '>' } else { ' ' };
+
+            format!("{} {:03X}  {:02X}{:02X}  {}", marker, address, raw.0, raw.1, mnemonic)
+        })
+        .collect()
+}
+
+// Names match the ones used in key map config files
+fn host_key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(character) => character.to_lowercase().to_string(),
+        KeyCode::Up => "up".to_string(),
+        KeyCode::Down => "down".to_string(),
+        KeyCode::Left => "left".to_string(),
+        KeyCode::Right => "right".to_string(),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::Backspace => "backspace".to_string(),
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: None,
+        seed: 0,
+        cycles_per_frame: None,
+        error_policies: ErrorPolicies::default(),
+        keymap_path: None,
+        database_path: None,
+        record_path: None,
+        patch_paths: Vec::new(),
+        cheats: Cheats::new(),
+        show_panel: false,
+        on_colour: None,
+        off_colour: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--quirks" => options.quirks = Some(option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?),
+            "--seed" => options.seed = parse_number(&option_value(&mut args, &arg)?)?,
+            "--cycles-per-frame" => options.cycles_per_frame = Some(parse_number(&option_value(&mut args, &arg)?)? as u32),
+            "--on-error" => options.error_policies = option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?,
+            "--keymap" => options.keymap_path = Some(option_value(&mut args, &arg)?),
+            "--database" => options.database_path = Some(option_value(&mut args, &arg)?),
+            "--record" => options.record_path = Some(option_value(&mut args, &arg)?),
+            "--patch" => options.patch_paths.push(option_value(&mut args, &arg)?),
+            "--freeze" => options.cheats = option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?,
+            "--panel" => options.show_panel = true,
+            "--on-colour" => options.on_colour = Some(parse_colour(&option_value(&mut args, &arg)?)?),
+            "--off-colour" => options.off_colour = Some(parse_colour(&option_value(&mut args, &arg)?)?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ => options.rom_path = arg,
+        }
+    }
+
+    if options.rom_path.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    if options.record_path.is_some() && !options.cheats.is_empty() {
+        return Err("--freeze can't be used with --record".to_string());
+    }
+
+    Ok(options)
+}
+
+fn colour([r, g, b]: [u8; 3]) -> Color {
+    Color::Rgb { r, g, b }
+}
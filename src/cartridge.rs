@@ -0,0 +1,457 @@
+// Octo cartridges, GIF images that carry a program and the options to run it with. The payload is
+// spread over the low 4 bits of every pixel's palette index, two pixels a byte with the high half
+// first, while the high bits pick the label colour so the image still shows a picture of the game.
+// It starts with its length as a 32 bit big endian number, followed by JSON like
+//
+//     {"options": {"tickrate": 20, "shiftQuirks": false, ...}, "program": ": main 0x00 0xE0 ..."}
+//
+// The program is Octo source, compiled by src/octo.rs. Cartridges written here store the ROM as byte
+// literals after `: main`.
+use crate::database::{parse_colour, RomInfo};
+use crate::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::gif::{self, GifError, Image};
+use crate::interpreter::DEFAULT_CYCLES_PER_FRAME;
+use crate::octo::{self, OctoError};
+use crate::quirks::Quirks;
+use std::fmt;
+use std::io::{self, Write};
+
+// Labels are the display at twice its size, so each frame carries 4 KiB of payload
+const LABEL_SCALE: usize = 2;
+const LABEL_WIDTH: usize = DISPLAY_WIDTH * LABEL_SCALE;
+const LABEL_HEIGHT: usize = DISPLAY_HEIGHT * LABEL_SCALE;
+const BYTES_PER_LINE: usize = 16;
+const MAX_JSON_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub quirks: Quirks,
+    pub cycles_per_frame: Option<u32>, // Octo's tickrate
+    pub on_colour: Option<[u8; 3]>,
+    pub off_colour: Option<[u8; 3]>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    Gif(GifError),
+    Payload(String), // the image doesn't hold a cartridge payload
+    Program(OctoError), // Octo source that doesn't compile
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Gif(error) => write!(f, "{}", error),
+            CartridgeError::Payload(message) => write!(f, "not an Octo cartridge: {}", message),
+            CartridgeError::Program(error) => write!(f, "the cartridge's program doesn't compile, {}", error),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Cartridge {
+        Cartridge { rom, quirks: Quirks::default(), cycles_per_frame: None, on_colour: None, off_colour: None }
+    }
+
+    // For front ends, which treat the cartridge's options like a database entry
+    pub fn rom_info(&self) -> RomInfo {
+        RomInfo {
+            title: "Octo cartridge".to_string(),
+            quirks: Some(self.quirks),
+            cycles_per_frame: self.cycles_per_frame,
+            on_colour: self.on_colour,
+            off_colour: self.off_colour,
+            ..RomInfo::default()
+        }
+    }
+}
+
+pub fn read(bytes: &[u8]) -> Result<Cartridge, CartridgeError> {
+    let image = gif::read(bytes).map_err(CartridgeError::Gif)?;
+    let nybbles: Vec<u8> =
+        image.frames.iter().flat_map(|frame| frame.pixels.iter().map(|index| index & 0x0f)).collect();
+    let data: Vec<u8> = nybbles.chunks_exact(2).map(|pair| (pair[0] << 4) | pair[1]).collect();
+
+    let payload_error = |message: &str| CartridgeError::Payload(message.to_string());
+    let length = data.get(..4).ok_or_else(|| payload_error("the image is too small"))?;
+    let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    let payload = data[4..].get(..length).ok_or_else(|| payload_error("the payload is longer than the image"))?;
+    let payload = std::str::from_utf8(payload).map_err(|_| payload_error("the payload isn't text"))?;
+
+    let json = JsonParser::new(payload).parse().map_err(CartridgeError::Payload)?;
+    let program = match json.get("program") {
+        Some(Json::String(program)) => program,
+        _ => return Err(payload_error("there's no program")),
+    };
+    let options = json.get("options");
+    let option = |name| options.and_then(|options| options.get(name));
+    let flag = |name| matches!(option(name), Some(Json::Bool(true)));
+    let colour = |name| match option(name) {
+        Some(Json::String(colour)) => parse_colour(colour).ok(),
+        _ => None,
+    };
+
+    // Octo's quirk options name the departures from the COSMAC VIP, so with none set this is xochip
+    let quirks = Quirks {
+        vf_reset: flag("logicQuirks"),
+        memory_increments_i: !flag("loadStoreQuirks"),
+        display_wait: flag("vBlankQuirks"),
+        clip_sprites: flag("clipQuirks"),
+        shift_uses_vy: !flag("shiftQuirks"),
+        jump_uses_vx: flag("jumpQuirks"),
+    };
+    let cycles_per_frame = match option("tickrate") {
+        Some(Json::Number(tickrate)) if *tickrate >= 1.0 => Some(*tickrate as u32),
+        _ => None,
+    };
+
+    Ok(Cartridge {
+        rom: octo::compile(program).map_err(CartridgeError::Program)?,
+        quirks,
+        cycles_per_frame,
+        on_colour: colour("fillColor"),
+        off_colour: colour("backgroundColor"),
+    })
+}
+
+// Packs the cartridge into a GIF with the display as its label
+pub fn write(writer: &mut impl Write, cartridge: &Cartridge, label: &Display) -> io::Result<()> {
+    let on = cartridge.on_colour.unwrap_or([0xff, 0xff, 0xff]);
+    let off = cartridge.off_colour.unwrap_or([0, 0, 0]);
+    let payload = payload(cartridge, on, off);
+    let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(payload.as_bytes());
+
+    // 16 indices for each label colour, one for every value of the low 4 bits
+    let palette: Vec<[u8; 3]> = (0..32).map(|index| if index < 16 { off } else { on }).collect();
+    let label: Vec<u8> = (0..LABEL_WIDTH * LABEL_HEIGHT)
+        .map(|index| label.pixel(index % LABEL_WIDTH / LABEL_SCALE, index / LABEL_WIDTH / LABEL_SCALE) as u8)
+        .collect();
+    let frames = data
+        .chunks(label.len() / 2)
+        .map(|chunk| {
+            let mut nybbles = chunk.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]);
+            label.iter().map(|colour| (colour << 4) | nybbles.next().unwrap_or(0)).collect()
+        })
+        .collect();
+
+    gif::write(writer, &Image::new(LABEL_WIDTH as u16, LABEL_HEIGHT as u16, palette, frames))
+}
+
+fn payload(cartridge: &Cartridge, on: [u8; 3], off: [u8; 3]) -> String {
+    let quirks = cartridge.quirks;
+    let colour = |[r, g, b]: [u8; 3]| format!("\"#{:02X}{:02X}{:02X}\"", r, g, b);
+    let options = [
+        ("tickrate", cartridge.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME).to_string()),
+        ("fillColor", colour(on)),
+        ("backgroundColor", colour(off)),
+        ("shiftQuirks", (!quirks.shift_uses_vy).to_string()),
+        ("loadStoreQuirks", (!quirks.memory_increments_i).to_string()),
+        ("vBlankQuirks", quirks.display_wait.to_string()),
+        ("clipQuirks", quirks.clip_sprites.to_string()),
+        ("jumpQuirks", quirks.jump_uses_vx.to_string()),
+        ("logicQuirks", quirks.vf_reset.to_string()),
+    ];
+    let options: Vec<String> = options.iter().map(|(name, value)| format!("\"{}\":{}", name, value)).collect();
+
+    let mut program = ": main\n".to_string();
+    for line in cartridge.rom.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        program.push_str(&bytes.join(" "));
+        program.push('\n');
+    }
+
+    format!("{{\"options\":{{{}}},\"program\":{}}}", options.join(","), json_string(&program))
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            character if (character as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", character as u32)),
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(member, _)| member == name).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+// Just enough JSON for the payload, which is written by Octo's JavaScript
+struct JsonParser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(text: &'a str) -> JsonParser<'a> {
+        JsonParser { text, offset: 0 }
+    }
+
+    fn parse(&mut self) -> Result<Json, String> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        match self.offset == self.text.len() {
+            true => Ok(value),
+            false => Err(format!("unexpected text after the JSON at {}", self.offset)),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let character = self.peek()?;
+        self.offset += character.len_utf8();
+
+        Some(character)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, wanted: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(character) if character == wanted => Ok(()),
+            _ => Err(format!("expected {} in the JSON at {}", wanted, self.offset)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_JSON_DEPTH {
+            return Err("the JSON is nested too deeply".to_string());
+        }
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => {
+                self.next();
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.next();
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    members.push((name, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Json::Object(members)),
+                        _ => return Err(format!("expected , or }} in the JSON at {}", self.offset)),
+                    }
+                }
+            }
+            Some('[') => {
+                self.next();
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.next();
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Json::Array(values)),
+                        _ => return Err(format!("expected , or ] in the JSON at {}", self.offset)),
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some(_) => {
+                let start = self.offset;
+                let in_literal = |character: char| character.is_ascii_alphanumeric() || "+-.".contains(character);
+                while self.peek().is_some_and(in_literal) {
+                    self.next();
+                }
+                match &self.text[start..self.offset] {
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    "null" => Ok(Json::Null),
+                    number => number
+                        .parse()
+                        .map(Json::Number)
+                        .map_err(|_| format!("{} is not a JSON value", number)),
+                }
+            }
+            None => Err("the JSON ends early".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.next() != Some('"') {
+            return Err(format!("expected a string in the JSON at {}", self.offset));
+        }
+
+        let mut string = String::new();
+        loop {
+            match self.next().ok_or("a JSON string doesn't end")? {
+                '"' => return Ok(string),
+                '\\' => match self.next().ok_or("a JSON string doesn't end")? {
+                    'n' => string.push('\n'),
+                    't' => string.push('\t'),
+                    'r' => string.push('\r'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'u' => {
+                        let unit = self.code_unit()?;
+                        // Characters outside the basic plane are written as a pair of surrogates
+                        let code = match unit {
+                            0xd800..=0xdbff if self.text[self.offset..].starts_with("\\u") => {
+                                self.offset += 2;
+                                0x10000 + ((unit - 0xd800) << 10) + (self.code_unit()?.wrapping_sub(0xdc00) & 0x3ff)
+                            }
+                            unit => unit,
+                        };
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    character => string.push(character),
+                },
+                character => string.push(character),
+            }
+        }
+    }
+
+    fn code_unit(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.offset..self.offset + 4).ok_or("a JSON \\u escape ends early")?;
+        self.offset += 4;
+
+        u32::from_str_radix(digits, 16).map_err(|_| format!("{} is not a JSON \\u escape", digits))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read, write, Cartridge, CartridgeError, Json, JsonParser};
+    use crate::octo::OctoError;
+    use crate::display::{Display, EdgeMode};
+    use crate::gif::{self, Image};
+    use crate::quirks::Quirks;
+
+    // A cartridge image holding this payload text, as Octo would lay it out
+    fn cartridge_with(payload: &str) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(payload.as_bytes());
+        let pixels: Vec<u8> = data.iter().flat_map(|byte| [0x10 | byte >> 4, 0x10 | byte & 0x0f]).collect();
+        let image = Image::new(pixels.len() as u16, 1, vec![[0, 0, 0]; 32], vec![pixels]);
+
+        let mut bytes = Vec::new();
+        gif::write(&mut bytes, &image).unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn cartridges_round_trip() {
+        let mut label = Display::new(EdgeMode::Clip);
+        label.draw_sprite(10, 5, &[0xf0, 0x90, 0xf0]);
+        let cartridge = Cartridge {
+            rom: (0..5000).map(|index| index as u8).collect(),
+            quirks: Quirks::schip(),
+            cycles_per_frame: Some(30),
+            on_colour: Some([0xff, 0xcc, 0x00]),
+            off_colour: Some([0x99, 0x66, 0x00]),
+        };
+        let mut gif = Vec::new();
+
+        write(&mut gif, &cartridge, &label).unwrap();
+
+        assert_eq!(read(&gif), Ok(cartridge));
+        let image = gif::read(&gif).unwrap();
+        assert!(image.frames.len() > 1);
+        assert_eq!(image.frames[0].pixels[(10 * 2) + (5 * 2) * 128] >> 4, 1);
+        assert_eq!(image.palette[0x10], [0xff, 0xcc, 0x00]);
+    }
+
+    #[test]
+    fn maps_octo_options_onto_quirks_and_settings() {
+        let payload = r##"{
+            "options": {"tickrate": 15, "fillColor": "#FFAA00", "backgroundColor": "#000000", "shiftQuirks": true,
+                "loadStoreQuirks": true, "vBlankQuirks": false, "clipQuirks": true, "jumpQuirks": true,
+                "logicQuirks": false, "screenRotation": 0, "fontStyle": "octo"},
+            "program": "# a comment\n: main\n  0x00 0xE0 # clear\n: spin 0b00010010 2 -1\n"
+        }"##;
+
+        let cartridge = read(&cartridge_with(payload)).unwrap();
+        let plain = read(&cartridge_with(r#"{"program": ": main 18 0"}"#)).unwrap();
+
+        assert_eq!(cartridge.rom, [0x00, 0xe0, 0x12, 0x02, 0xff]);
+        assert_eq!(cartridge.quirks, Quirks::schip());
+        assert_eq!(cartridge.cycles_per_frame, Some(15));
+        assert_eq!(cartridge.on_colour, Some([0xff, 0xaa, 0x00]));
+        assert_eq!(cartridge.off_colour, Some([0, 0, 0]));
+        assert_eq!(plain.rom, [0x12, 0x00]);
+        assert_eq!(plain.quirks, Quirks::xochip());
+        assert_eq!(plain.cycles_per_frame, None);
+    }
+
+    #[test]
+    fn compiles_octo_source() {
+        let error = |line, message: &str| {
+            Err(CartridgeError::Program(OctoError { line, message: message.to_string() }))
+        };
+        let program = read(&cartridge_with(r#"{"program": ": main\n  clear\n  loop again"}"#));
+        let no_main = read(&cartridge_with(r#"{"program": "0x00 0xE0"}"#));
+        let macro_program = read(&cartridge_with(r#"{"program": ": main\n  :macro twice { }"}"#));
+        let truncated = read(&cartridge_with("{}")[..40]);
+
+        assert_eq!(program.map(|cartridge| cartridge.rom), Ok(vec![0x00, 0xe0, 0x12, 0x02]));
+        assert_eq!(no_main, error(1, "main is not defined"));
+        assert_eq!(macro_program, error(2, ":macro needs Octo's own compiler"));
+        assert!(matches!(read(&cartridge_with("[1, 2]")), Err(CartridgeError::Payload(_))));
+        assert!(matches!(truncated, Err(CartridgeError::Gif(_))));
+    }
+
+    #[test]
+    fn parses_json() {
+        let json = JsonParser::new(r#" {"a": [1, -2.5e1, null], "b": "\"\u00e9\ud83d\ude00\n", "c": {}} "#).parse();
+
+        assert_eq!(
+            json,
+            Ok(Json::Object(vec![
+                ("a".to_string(), Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Null])),
+                ("b".to_string(), Json::String("\"é😀\n".to_string())),
+                ("c".to_string(), Json::Object(Vec::new())),
+            ]))
+        );
+        assert!(JsonParser::new(r#"{"a": 1"#).parse().is_err());
+        assert!(JsonParser::new(r#"{"a": 1} x"#).parse().is_err());
+        assert!(JsonParser::new(&"[".repeat(100)).parse().is_err());
+    }
+}
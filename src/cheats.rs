@@ -0,0 +1,296 @@
+// Memory search and cheats, in the style of classic cheat engines. A search starts with every byte
+// of memory as a candidate and each filter compares memory now with the previous snapshot, e.g. lose
+// a life then filter by decreased, play on without dying then filter by equal, until only the lives
+// counter is left. Found addresses can then be poked once or frozen at a value every frame. Handy
+// for locating score and lives variables when writing reward specs or tests too.
+use crate::interpreter::{Interpreter, MEMORY_SIZE};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Equal, // unchanged since the previous snapshot
+    Changed,
+    Increased,
+    Decreased,
+    Value(u8),
+}
+
+impl Filter {
+    fn keeps(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Filter::Equal => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::Value(value) => current == *value,
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Equal => write!(f, "equal"),
+            Filter::Changed => write!(f, "changed"),
+            Filter::Increased => write!(f, "increased"),
+            Filter::Decreased => write!(f, "decreased"),
+            Filter::Value(value) => write!(f, "{:02X}", value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseFilterError(pub String);
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is not a search filter, expected equal, changed, increased, decreased or a hexadecimal byte",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseFilterError {}
+
+// Values are hexadecimal like everything else in the debugger panel
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Filter, ParseFilterError> {
+        match s.trim() {
+            "equal" => Ok(Filter::Equal),
+            "changed" => Ok(Filter::Changed),
+            "increased" => Ok(Filter::Increased),
+            "decreased" => Ok(Filter::Decreased),
+            value => parse_hex(value).map(Filter::Value).ok_or_else(|| ParseFilterError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemorySearch {
+    previous: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    // Snapshots memory with every address a candidate
+    pub fn new(interpreter: &Interpreter) -> MemorySearch {
+        MemorySearch {
+            previous: interpreter.memory().to_vec(),
+            candidates: (0..MEMORY_SIZE as u16).collect(),
+        }
+    }
+
+    // Keeps the candidates that pass the filter and takes a new snapshot to compare the next filter
+    // with. Returns how many candidates are left.
+    pub fn filter(&mut self, interpreter: &Interpreter, filter: Filter) -> usize {
+        let memory = interpreter.memory();
+        let previous = &self.previous;
+        self.candidates
+            .retain(|address| filter.keeps(previous[*address as usize], memory[*address as usize]));
+        self.previous = memory.to_vec();
+
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // The value at address when the last snapshot was taken
+    pub fn previous(&self, address: u16) -> u8 {
+        self.previous[address as usize % MEMORY_SIZE]
+    }
+}
+
+// Addresses held at a value. Frozen bytes are written back before every frame, so the ROM reads the
+// frozen value however it changed it during the frame before.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cheats {
+    frozen: Vec<(u16, u8)>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats::default()
+    }
+
+    // Freezing an address that is already frozen changes its value
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        let address = address % MEMORY_SIZE as u16;
+        match self.frozen.iter_mut().find(|(frozen, _)| *frozen == address) {
+            Some(entry) => entry.1 = value,
+            None => self.frozen.push((address, value)),
+        }
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.frozen.retain(|(frozen, _)| *frozen != address % MEMORY_SIZE as u16);
+    }
+
+    pub fn frozen(&self) -> &[(u16, u8)] {
+        &self.frozen
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frozen.is_empty()
+    }
+
+    pub fn apply(&self, interpreter: &mut Interpreter) {
+        for (address, value) in self.frozen.iter() {
+            interpreter.poke(*address, *value);
+        }
+    }
+}
+
+// Written as a comma separated list of address=value in hexadecimal, e.g. 2F0=03,2F1=09. This is the
+// form used on the command line.
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frozen: Vec<String> =
+            self.frozen.iter().map(|(address, value)| format!("{:03X}={:02X}", address, value)).collect();
+
+        write!(f, "{}", frozen.join(","))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseCheatsError(pub String);
+
+impl fmt::Display for ParseCheatsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not a cheat, expected a hexadecimal address=value such as 2F0=03", self.0)
+    }
+}
+
+impl std::error::Error for ParseCheatsError {}
+
+impl FromStr for Cheats {
+    type Err = ParseCheatsError;
+
+    fn from_str(s: &str) -> Result<Cheats, ParseCheatsError> {
+        let mut cheats = Cheats::new();
+
+        for cheat in s.split(',').map(str::trim).filter(|cheat| !cheat.is_empty()) {
+            let error = || ParseCheatsError(cheat.to_string());
+            let (address, value) = cheat.split_once('=').ok_or_else(error)?;
+            let address = parse_address(address).ok_or_else(error)?;
+            let value = parse_hex(value).ok_or_else(error)?;
+            cheats.freeze(address, value);
+        }
+
+        Ok(cheats)
+    }
+}
+
+// A hexadecimal address inside memory, with or without 0x
+pub fn parse_address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim().trim_start_matches("0x"), 16)
+        .ok()
+        .filter(|address| (*address as usize) < MEMORY_SIZE)
+}
+
+// A hexadecimal byte, with or without 0x
+pub fn parse_hex(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cheats, Filter, MemorySearch, ParseCheatsError};
+    use crate::interpreter::{Backend, Interpreter};
+    use crate::quirks::Quirks;
+
+    // 200: ADD V0, 1
+    // 202: LD I, 300
+    // 204: LD [I], V0
+    // 206: JP 200
+    const COUNTER: [u8; 8] = [0x70, 0x01, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x00];
+
+    fn counter() -> Interpreter {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.set_cycles_per_frame(4);
+        interpreter.load_rom(&COUNTER).unwrap();
+
+        interpreter
+    }
+
+    #[test]
+    fn filters_narrow_the_search_to_the_counter() {
+        let mut interpreter = counter();
+        let mut search = MemorySearch::new(&interpreter);
+        interpreter.run_frame().unwrap();
+
+        search.filter(&interpreter, Filter::Increased);
+        interpreter.run_frame().unwrap();
+        let left = search.filter(&interpreter, Filter::Changed);
+
+        assert_eq!(left, 1);
+        assert_eq!(search.candidates(), &[0x300]);
+        assert_eq!(search.previous(0x300), 2);
+    }
+
+    #[test]
+    fn equal_and_value_filters() {
+        let mut interpreter = counter();
+        let mut search = MemorySearch::new(&interpreter);
+        interpreter.run_frame().unwrap();
+
+        search.filter(&interpreter, Filter::Equal);
+        let left = search.filter(&interpreter, Filter::Value(0x12));
+
+        assert!(!search.candidates().contains(&0x300));
+        assert_eq!(left, 1);
+        assert_eq!(search.candidates(), &[0x206]);
+    }
+
+    #[test]
+    fn frozen_bytes_are_written_back_before_each_frame() {
+        let mut interpreter = counter();
+        let mut cheats = Cheats::new();
+        cheats.freeze(0x300, 0x40);
+        cheats.freeze(0x300, 0x80);
+
+        for _ in 0..3 {
+            cheats.apply(&mut interpreter);
+            assert_eq!(interpreter.memory()[0x300], 0x80);
+            interpreter.run_frame().unwrap();
+        }
+        cheats.unfreeze(0x300);
+
+        assert!(cheats.is_empty());
+        assert_eq!(interpreter.memory()[0x300], 3);
+    }
+
+    #[test]
+    fn poking_code_changes_what_runs() {
+        for backend in [Backend::Interpreter, Backend::Threaded] {
+            let mut interpreter = counter();
+            interpreter.set_backend(backend);
+            interpreter.run_frame().unwrap();
+
+            // ADD V0, 1 becomes ADD V0, 5
+            interpreter.poke(0x201, 0x05);
+            interpreter.run_frame().unwrap();
+
+            assert_eq!(interpreter.registers()[0], 6, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn parse_and_display_cheats() {
+        let cheats: Cheats = "2f0=3, 0x2F1=0x09".parse().unwrap();
+
+        assert_eq!(cheats.frozen(), &[(0x2f0, 3), (0x2f1, 9)]);
+        assert_eq!(cheats.to_string(), "2F0=03,2F1=09");
+        assert_eq!("1000=01".parse::<Cheats>(), Err(ParseCheatsError("1000=01".to_string())));
+        assert_eq!("2F0".parse::<Cheats>(), Err(ParseCheatsError("2F0".to_string())));
+        assert_eq!("decreased".parse(), Ok(Filter::Decreased));
+        assert_eq!("ff".parse(), Ok(Filter::Value(0xff)));
+        assert!("100".parse::<Filter>().is_err());
+    }
+}
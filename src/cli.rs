@@ -0,0 +1,15 @@
+// Argument handling shared by the command line front ends in src/main.rs and src/bin
+use std::process;
+
+pub fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", option))
+}
+
+pub fn parse_number(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("{} is not a number", value))
+}
+
+pub fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
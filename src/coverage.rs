@@ -0,0 +1,257 @@
+// Coverage map of a run, e.g. interpreter.run_frame_with(&mut coverage). Records which bytes of
+// memory were executed as code, read as data (sprites drawn with Dxyn, registers loaded with Fx65,
+// audio patterns) and written, so testers can see which parts of a ROM a play session never
+// reached. The result is shown as an annotated disassembly of the ROM or as a heatmap of all 4 KiB.
+use crate::instruction::Instruction;
+use crate::interpreter::{Interpreter, Observer, MEMORY_SIZE, PROGRAM_START};
+use crate::png;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+// The heatmap has a row of cells for each 64 bytes of memory
+const HEATMAP_COLUMNS: usize = 64;
+const CELL_SIZE: usize = 8;
+
+pub struct Coverage {
+    executed: Vec<u32>, // instructions fetched from each address
+    read: Vec<u32>,
+    written: Vec<u32>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![0; MEMORY_SIZE],
+            read: vec![0; MEMORY_SIZE],
+            written: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    // Whether the byte was part of an executed instruction, either its first or second byte
+    pub fn executed(&self, address: usize) -> bool {
+        self.executed[address % MEMORY_SIZE] > 0 || self.executed[(address + MEMORY_SIZE - 1) % MEMORY_SIZE] > 0
+    }
+
+    pub fn read(&self, address: usize) -> bool {
+        self.read[address % MEMORY_SIZE] > 0
+    }
+
+    pub fn written(&self, address: usize) -> bool {
+        self.written[address % MEMORY_SIZE] > 0
+    }
+
+    // The loaded ROM one instruction or data byte per line, each marked with how it was used:
+    //
+    //     0200 6A 02  x--         1  LD VA, 0x02
+    //     0202 6B 0C  ---            LD VB, 0x0C
+    //     02EA 80     -r-            db 0x80  #.......
+    //
+    // x is executed, r read as data and w written, followed by the number of times an instruction
+    // ran. Lines with no count are code that never ran or bytes only used as data. The bytes are
+    // the ROM as loaded, before anything the run wrote over them.
+    pub fn annotated_disassembly(&self, interpreter: &Interpreter) -> String {
+        let start = PROGRAM_START as usize;
+        let rom = |address: usize| interpreter.rom()[address - start];
+        let end = start + interpreter.rom().len();
+        let count = |test: &dyn Fn(usize) -> bool| (start..end).filter(|address| test(*address)).count();
+        let percent = |count: usize| 100.0 * count as f64 / (end - start).max(1) as f64;
+
+        let executed = count(&|address| self.executed(address));
+        let read = count(&|address| self.read(address));
+        let written = count(&|address| self.written(address));
+        let mut text = String::new();
+        writeln!(text, "; {} of {} ROM bytes executed ({:.1}%)", executed, end - start, percent(executed)).unwrap();
+        writeln!(text, "; {} read as data ({:.1}%), {} written", read, percent(read), written).unwrap();
+        writeln!(text, "; x executed, r read as data, w written, then how many times the instruction ran").unwrap();
+
+        let mut address = start;
+        while address < end {
+            let flags = |length: usize| {
+                let any = |test: &dyn Fn(usize) -> bool| (address..address + length).any(test);
+                format!(
+                    "{}{}{}",
+                    if any(&|address| self.executed(address)) { 'x' } else { '-' },
+                    if any(&|address| self.read(address)) { 'r' } else { '-' },
+                    if any(&|address| self.written(address)) { 'w' } else { '-' }
+                )
+            };
+            let instruction = if address + 1 < end {
+                Instruction::decode((rom(address), rom(address + 1)))
+            } else {
+                None
+            };
+            let data = self.read(address) || self.written(address);
+
+            match instruction {
+                Some(instruction) if self.executed[address] > 0 || (!data && self.executed[address + 1] == 0) => {
+                    let runs = match self.executed[address] {
+                        0 => String::new(),
+                        runs => runs.to_string(),
+                    };
+                    writeln!(
+                        text,
+                        "{:04X} {:02X} {:02X}  {} {:>9}  {}",
+                        address,
+                        rom(address),
+                        rom(address + 1),
+                        flags(2),
+                        runs,
+                        instruction
+                    )
+                    .unwrap();
+                    address += 2;
+                }
+                _ => {
+                    let byte = rom(address);
+                    let bits: String = (0..8).rev().map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' }).collect();
+                    writeln!(text, "{:04X} {:02X}     {} {:>9}  db 0x{:02X}  {}", address, byte, flags(1), "", byte, bits)
+                        .unwrap();
+                    address += 1;
+                }
+            }
+        }
+
+        text
+    }
+
+    // RGB pixels of the heatmap, one cell per byte of memory with the rows of 64 bytes top to
+    // bottom. Green is executed, blue read and red written, brighter the more often. Untouched
+    // bytes of the ROM are grey, so code that never ran stands out.
+    pub fn heatmap(&self, interpreter: &Interpreter) -> (u32, u32, Vec<u8>) {
+        let rom = PROGRAM_START as usize..PROGRAM_START as usize + interpreter.rom().len();
+        let width = HEATMAP_COLUMNS * CELL_SIZE;
+        let height = MEMORY_SIZE / HEATMAP_COLUMNS * CELL_SIZE;
+        let max = |counts: &[u32]| *counts.iter().max().unwrap_or(&0);
+        let peaks = [max(&self.written), max(&self.executed), max(&self.read)];
+
+        let mut pixels = vec![0; width * height * 3];
+        for address in 0..MEMORY_SIZE {
+            let counts = [self.written[address], self.executed[address], self.read[address]];
+            let mut colour = [0u8; 3];
+            for channel in 0..3 {
+                colour[channel] = intensity(counts[channel], peaks[channel]);
+            }
+            if colour == [0, 0, 0] {
+                colour = if rom.contains(&address) { [0x50, 0x50, 0x50] } else { [0x18, 0x18, 0x18] };
+            }
+
+            // Leave a line between cells so neighbouring bytes can be told apart
+            let (cell_x, cell_y) = (address % HEATMAP_COLUMNS * CELL_SIZE, address / HEATMAP_COLUMNS * CELL_SIZE);
+            for y in cell_y..cell_y + CELL_SIZE - 1 {
+                for x in cell_x..cell_x + CELL_SIZE - 1 {
+                    let offset = (y * width + x) * 3;
+                    pixels[offset..offset + 3].copy_from_slice(&colour);
+                }
+            }
+        }
+
+        (width as u32, height as u32, pixels)
+    }
+
+    pub fn write_heatmap(&self, writer: &mut impl Write, interpreter: &Interpreter) -> io::Result<()> {
+        let (width, height, pixels) = self.heatmap(interpreter);
+
+        png::write_rgb(writer, width, height, &pixels)
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Observer for Coverage {
+    fn before_step(&mut self, interpreter: &Interpreter, _opcode: u16) {
+        let address = interpreter.pc() as usize % MEMORY_SIZE;
+        self.executed[address] = self.executed[address].saturating_add(1);
+    }
+
+    fn memory_read(&mut self, address: u16, _value: u8) {
+        self.read[address as usize] = self.read[address as usize].saturating_add(1);
+    }
+
+    fn memory_written(&mut self, address: u16, _value: u8) {
+        self.written[address as usize] = self.written[address as usize].saturating_add(1);
+    }
+}
+
+// Logarithmic, so a byte touched once is still clearly visible next to a loop run millions of times
+fn intensity(count: u32, peak: u32) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let scale = (count as f64).ln_1p() / (peak as f64).ln_1p();
+
+    (96.0 + 159.0 * scale) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Coverage, CELL_SIZE, HEATMAP_COLUMNS};
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    // 200: LD I, 20C
+    // 202: DRW V0, V0, 1
+    // 204: SE V0, 0
+    // 206: CLS
+    // 208: LD [I], V0
+    // 20A: JP 20A
+    // 20C: sprite data 0xA5
+    const PROGRAM: [u8; 13] = [0xa2, 0x0c, 0xd0, 0x01, 0x30, 0x00, 0x00, 0xe0, 0xf0, 0x55, 0x12, 0x0a, 0xa5];
+
+    fn run(steps: usize) -> (Coverage, Interpreter) {
+        let mut interpreter = Interpreter::new(Quirks::schip(), 0);
+        interpreter.load_rom(&PROGRAM).unwrap();
+        let mut coverage = Coverage::new();
+
+        for _ in 0..steps {
+            interpreter.step_with(&mut coverage).unwrap();
+        }
+
+        (coverage, interpreter)
+    }
+
+    #[test]
+    fn records_code_data_and_writes() {
+        let (coverage, _) = run(6);
+
+        assert!(coverage.executed(0x200) && coverage.executed(0x201));
+        assert!(!coverage.executed(0x206));
+        assert!(coverage.read(0x20c));
+        assert!(coverage.written(0x20c));
+        assert!(!coverage.read(0x200));
+    }
+
+    #[test]
+    fn disassembly_marks_how_each_byte_was_used() {
+        let (coverage, interpreter) = run(6);
+
+        let text = coverage.annotated_disassembly(&interpreter);
+
+        assert!(text.starts_with("; 10 of 13 ROM bytes executed (76.9%)\n"));
+        assert!(text.contains("\n0200 A2 0C  x--         1  LD I, 0x20C\n"));
+        assert!(text.contains("\n0206 00 E0  ---            CLS\n"));
+        assert!(text.contains("\n020A 12 0A  x--         2  JP 0x20A\n"));
+        assert!(text.ends_with("\n020C A5     -rw            db 0xA5  #.#..#.#\n"));
+    }
+
+    #[test]
+    fn heatmap_colours_cells_by_use() {
+        let (coverage, interpreter) = run(6);
+
+        let (width, height, pixels) = coverage.heatmap(&interpreter);
+        let cell = |address: usize| {
+            let (x, y) = (address % HEATMAP_COLUMNS * CELL_SIZE, address / HEATMAP_COLUMNS * CELL_SIZE);
+            let offset = (y * width as usize + x) * 3;
+            [pixels[offset], pixels[offset + 1], pixels[offset + 2]]
+        };
+
+        assert_eq!((width, height), (512, 512));
+        assert_eq!(cell(0x20a), [0, 0xff, 0]);
+        assert!(cell(0x20c)[0] > 0 && cell(0x20c)[2] > 0);
+        assert_eq!(cell(0x206), [0x50, 0x50, 0x50]);
+        assert_eq!(cell(0x000), [0x18, 0x18, 0x18]);
+    }
+}
@@ -0,0 +1,387 @@
+// ROM metadata keyed by SHA-1, in the spirit of the community chip-8-database, so a ROM that needs
+// SUPER-CHIP quirks or a faster clock gets them without the player having to know. Front ends look
+// the ROM up as it loads and use what the entry says for anything not set on the command line.
+use crate::hash::sha1;
+use crate::interpreter::Interpreter;
+use crate::keypad::{KeyMap, KEY_COUNT};
+use crate::quirks::Quirks;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Shipped with the crate, see the file for how entries are written
+const EMBEDDED: &str = include_str!("../data/roms.txt");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    Xochip,
+}
+
+impl Platform {
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::chip8(),
+            Platform::Schip => Quirks::schip(),
+            Platform::Xochip => Quirks::xochip(),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "chip8"),
+            Platform::Schip => write!(f, "schip"),
+            Platform::Xochip => write!(f, "xochip"),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s.trim() {
+            "chip8" => Ok(Platform::Chip8),
+            "schip" => Ok(Platform::Schip),
+            "xochip" => Ok(Platform::Xochip),
+            _ => Err(format!("unknown platform {}, expected chip8, schip or xochip", s.trim())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub author: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>, // when the ROM needs something other than its platform's profile
+    pub cycles_per_frame: Option<u32>,
+    pub keys: Vec<(String, u8)>, // host key bindings on top of the usual layout
+    pub on_colour: Option<[u8; 3]>,
+    pub off_colour: Option<[u8; 3]>,
+}
+
+impl RomInfo {
+    // The quirks the ROM asks for, or its platform's profile
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.quirks.or_else(|| self.platform.map(|platform| platform.quirks()))
+    }
+
+    // Sets the quirks and speed the entry asks for, leaving the rest of the machine alone
+    pub fn configure(&self, interpreter: &mut Interpreter) {
+        if let Some(quirks) = self.quirks() {
+            interpreter.set_quirks(quirks);
+        }
+        if let Some(cycles_per_frame) = self.cycles_per_frame {
+            interpreter.set_cycles_per_frame(cycles_per_frame);
+        }
+    }
+
+    // This entry with anything it leaves unset taken from the other one
+    pub fn fill_from(self, other: RomInfo) -> RomInfo {
+        RomInfo {
+            quirks: self.quirks().or(other.quirks),
+            title: if self.title.is_empty() { other.title } else { self.title },
+            author: self.author.or(other.author),
+            platform: self.platform.or(other.platform),
+            cycles_per_frame: self.cycles_per_frame.or(other.cycles_per_frame),
+            keys: if self.keys.is_empty() { other.keys } else { self.keys },
+            on_colour: self.on_colour.or(other.on_colour),
+            off_colour: self.off_colour.or(other.off_colour),
+        }
+    }
+
+    pub fn key_map(&self) -> KeyMap {
+        let mut key_map = KeyMap::qwerty();
+        for (host_key, key) in self.keys.iter() {
+            key_map.bind(host_key, *key);
+        }
+
+        key_map
+    }
+}
+
+// Title, author and platform, e.g. "Racer by Someone (schip)"
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        if let Some(platform) = self.platform {
+            write!(f, " ({})", platform)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DatabaseError {
+    Parse { line: usize, message: String },
+    Io(String),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Parse { line, message } => write!(f, "ROM database line {}: {}", line, message),
+            DatabaseError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    entries: HashMap<[u8; 20], RomInfo>,
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database::default()
+    }
+
+    pub fn embedded() -> Database {
+        Database::from_config(EMBEDDED).expect("the embedded ROM database is checked by its tests")
+    }
+
+    // Reads a database laid out like a key map, with a section per ROM named after its SHA-1:
+    //
+    //     [sha1:0123456789abcdef0123456789abcdef01234567]
+    //     title = Racer
+    //     author = Someone
+    //     platform = schip
+    //     quirks = clip_sprites,jump_uses_vx
+    //     cycles_per_frame = 30
+    //     keys = a=7, d=9
+    //     on_colour = ffcc00
+    //     off_colour = 201000
+    //
+    // Everything but the title is optional. quirks takes the same profile names and flags as
+    // --quirks, and keys takes host_key=keypad_key pairs as in a key map file.
+    pub fn from_config(text: &str) -> Result<Database, DatabaseError> {
+        let mut database = Database::new();
+        let mut current: Option<([u8; 20], RomInfo)> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            // Only whole lines are comments, colours can start with #
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_error = |message: String| DatabaseError::Parse { line: index + 1, message };
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                database.finish_entry(current.take()).map_err(parse_error)?;
+                let hash = name.trim().strip_prefix("sha1:").and_then(parse_sha1);
+                let hash = hash
+                    .ok_or_else(|| parse_error(format!("{} is not a section like [sha1:<40 hex digits>]", name)))?;
+                current = Some((hash, RomInfo::default()));
+                continue;
+            }
+
+            let (_, info) = current
+                .as_mut()
+                .ok_or_else(|| parse_error("setting before the first section".to_string()))?;
+            let (name, setting) = line
+                .split_once('=')
+                .ok_or_else(|| parse_error("expected name = setting".to_string()))?;
+            let setting = setting.trim();
+
+            match name.trim() {
+                "title" => info.title = setting.to_string(),
+                "author" => info.author = Some(setting.to_string()),
+                "platform" => info.platform = Some(setting.parse().map_err(parse_error)?),
+                "quirks" => info.quirks = Some(setting.parse().map_err(|error| parse_error(format!("{}", error)))?),
+                "cycles_per_frame" => match setting.parse() {
+                    Ok(cycles_per_frame) if cycles_per_frame > 0 => info.cycles_per_frame = Some(cycles_per_frame),
+                    _ => return Err(parse_error(format!("{} is not a number of cycles", setting))),
+                },
+                "keys" => info.keys = parse_keys(setting).map_err(parse_error)?,
+                "on_colour" => info.on_colour = Some(parse_colour(setting).map_err(parse_error)?),
+                "off_colour" => info.off_colour = Some(parse_colour(setting).map_err(parse_error)?),
+                name => return Err(parse_error(format!("unknown setting {}", name))),
+            }
+        }
+
+        let last_line = text.lines().count();
+        database
+            .finish_entry(current)
+            .map_err(|message| DatabaseError::Parse { line: last_line, message })?;
+
+        Ok(database)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Database, DatabaseError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| DatabaseError::Io(format!("Could not read {}: {}", path.display(), error)))?;
+
+        Database::from_config(&text)
+    }
+
+    // Adds the other database's entries, replacing any for the same ROM
+    pub fn merge(&mut self, other: Database) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.entries.get(&sha1(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn finish_entry(&mut self, entry: Option<([u8; 20], RomInfo)>) -> Result<(), String> {
+        if let Some((hash, info)) = entry {
+            if info.title.is_empty() {
+                return Err("every ROM needs a title".to_string());
+            }
+            self.entries.insert(hash, info);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    let text = text.trim();
+    if text.len() != 40 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut hash = [0; 20];
+    for (index, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+fn parse_keys(text: &str) -> Result<Vec<(String, u8)>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|binding| !binding.is_empty())
+        .map(|binding| {
+            let error = || format!("{} is not a key binding like w=5", binding);
+            let (host_key, key) = binding.split_once('=').ok_or_else(error)?;
+            match u8::from_str_radix(key.trim(), 16) {
+                Ok(key) if key < KEY_COUNT && !host_key.trim().is_empty() => Ok((host_key.trim().to_lowercase(), key)),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+// A colour like ff8800 or #ff8800
+pub fn parse_colour(text: &str) -> Result<[u8; 3], String> {
+    let digits = text.trim_start_matches('#');
+    match u32::from_str_radix(digits, 16) {
+        Ok(rgb) if digits.len() == 6 => Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]),
+        _ => Err(format!("{} is not a colour like ff8800", text)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Database, DatabaseError, Platform, RomInfo};
+    use crate::hash::sha1;
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn section(rom: &[u8]) -> String {
+        let hex: String = sha1(rom).iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        format!("[sha1:{}]", hex)
+    }
+
+    #[test]
+    fn looks_roms_up_by_sha1() {
+        let text = format!(
+            "# a comment\n{}\ntitle = Loop\nauthor = Someone\nplatform = schip\ncycles_per_frame = 30\n\
+             keys = space=a, W=1\non_colour = #ffcc00\n",
+            section(&ROM)
+        );
+
+        let database = Database::from_config(&text).unwrap();
+
+        let info = database.lookup(&ROM).unwrap();
+        assert_eq!(info.to_string(), "Loop by Someone (schip)");
+        assert_eq!(info.quirks(), Some(Quirks::schip()));
+        assert_eq!(info.keys, vec![("space".to_string(), 0xa), ("w".to_string(), 1)]);
+        assert_eq!(info.key_map().key("w"), Some(1));
+        assert_eq!(info.on_colour, Some([0xff, 0xcc, 0x00]));
+        assert_eq!(database.lookup(&[0x12, 0x02]), None);
+    }
+
+    #[test]
+    fn fill_from_keeps_what_the_first_entry_set() {
+        let first = RomInfo { platform: Some(Platform::Chip8), cycles_per_frame: Some(15), ..RomInfo::default() };
+        let second = RomInfo {
+            title: "Loop".to_string(),
+            quirks: Some(Quirks::xochip()),
+            cycles_per_frame: Some(30),
+            ..RomInfo::default()
+        };
+
+        let info = first.fill_from(second);
+
+        assert_eq!(info.title, "Loop");
+        assert_eq!(info.quirks(), Some(Quirks::chip8()));
+        assert_eq!(info.cycles_per_frame, Some(15));
+    }
+
+    #[test]
+    fn configure_sets_quirks_over_the_platform_and_speed() {
+        let info = RomInfo {
+            title: "Loop".to_string(),
+            platform: Some(Platform::Chip8),
+            quirks: Some("shift_uses_vy".parse().unwrap()),
+            cycles_per_frame: Some(20),
+            ..RomInfo::default()
+        };
+        let mut interpreter = Interpreter::new(Quirks::xochip(), 0);
+
+        info.configure(&mut interpreter);
+
+        assert_eq!(interpreter.quirks(), "shift_uses_vy".parse().unwrap());
+        assert_eq!(interpreter.cycles_per_frame(), 20);
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_setting() {
+        let missing_title = format!("{}\nauthor = Someone\n", section(&ROM));
+        let bad_hash = "[sha1:1234]\ntitle = Loop\n";
+        let bad_platform = format!("{}\ntitle = Loop\nplatform = vip\n", section(&ROM));
+
+        let errors = [missing_title.as_str(), bad_hash, bad_platform.as_str()]
+            .map(|text| Database::from_config(text).unwrap_err());
+
+        assert_eq!(errors[0], DatabaseError::Parse { line: 2, message: "every ROM needs a title".to_string() });
+        assert!(matches!(errors[1], DatabaseError::Parse { line: 1, .. }));
+        assert!(matches!(errors[2], DatabaseError::Parse { line: 3, .. }));
+    }
+
+    #[test]
+    fn merged_entries_replace_existing_ones() {
+        let mut database = Database::embedded();
+        let embedded = database.len();
+
+        database.merge(Database::from_config(&format!("{}\ntitle = Loop\n", section(&ROM))).unwrap());
+        database.merge(Database::from_config(&format!("{}\ntitle = Fixed loop\n", section(&ROM))).unwrap());
+
+        assert_eq!(database.len(), embedded + 1);
+        assert_eq!(database.lookup(&ROM).map(|info| info.title.as_str()), Some("Fixed loop"));
+    }
+}
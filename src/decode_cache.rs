@@ -0,0 +1,107 @@
+// Decoded instructions by address. A ROM spends nearly all its time in a few loops, so each address
+// is decoded on its first fetch and looked up after that. Code can rewrite itself, so every write to
+// memory goes through invalidate, which drops any instruction covering the written byte.
+use crate::instruction::{Instruction, InvalidInstruction};
+use crate::interpreter::MEMORY_SIZE;
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    Empty,
+    Decoded(Instruction),
+    Invalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    entries: Vec<Entry>,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache { entries: vec![Entry::Empty; MEMORY_SIZE] }
+    }
+
+    // The instruction at address, made of the byte there and the one after it
+    pub fn get(&mut self, memory: &[u8; MEMORY_SIZE], address: usize) -> Result<Instruction, InvalidInstruction> {
+        let raw = (memory[address], memory[(address + 1) % MEMORY_SIZE]);
+
+        match self.entries[address] {
+            Entry::Decoded(instruction) => Ok(instruction),
+            Entry::Invalid => Err(InvalidInstruction(u16::from_be_bytes([raw.0, raw.1]))),
+            Entry::Empty => {
+                let instruction = Instruction::parse(raw);
+                self.entries[address] = match instruction {
+                    Ok(instruction) => Entry::Decoded(instruction),
+                    Err(_) => Entry::Invalid,
+                };
+
+                instruction
+            }
+        }
+    }
+
+    // The byte at address is the first byte of one instruction and the second byte of another
+    pub fn invalidate(&mut self, address: usize) {
+        self.entries[address % MEMORY_SIZE] = Entry::Empty;
+        self.entries[(address + MEMORY_SIZE - 1) % MEMORY_SIZE] = Entry::Empty;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(Entry::Empty);
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DecodeCache;
+    use crate::instruction::{Instruction, InvalidInstruction};
+    use crate::interpreter::MEMORY_SIZE;
+
+    #[test]
+    fn returns_the_instruction_decoded_on_the_first_fetch() {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+        let mut cache = DecodeCache::new();
+        cache.get(&memory, 0x200).unwrap();
+        memory[0x200] = 0x00;
+
+        let instruction = cache.get(&memory, 0x200);
+
+        // Memory changed without invalidate, so the stale entry shows the cache was used
+        assert_eq!(instruction, Instruction::parse((0x12, 0x00)));
+    }
+
+    #[test]
+    fn invalidate_covers_both_instructions_overlapping_the_byte() {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[0x200..0x203].copy_from_slice(&[0x12, 0x00, 0xe0]);
+        let mut cache = DecodeCache::new();
+        cache.get(&memory, 0x200).unwrap();
+        cache.get(&memory, 0x201).unwrap();
+        memory[0x201] = 0x04;
+
+        cache.invalidate(0x201);
+
+        assert_eq!(cache.get(&memory, 0x200), Instruction::parse((0x12, 0x04)));
+        assert_eq!(cache.get(&memory, 0x201), Instruction::parse((0x04, 0xe0)));
+    }
+
+    #[test]
+    fn invalid_opcodes_are_cached_with_their_opcode() {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[MEMORY_SIZE - 1] = 0x51;
+        memory[0] = 0x21;
+        let mut cache = DecodeCache::new();
+
+        cache.get(&memory, MEMORY_SIZE - 1).unwrap_err();
+        let instruction = cache.get(&memory, MEMORY_SIZE - 1);
+
+        assert_eq!(instruction, Err(InvalidInstruction(0x5121)));
+    }
+}
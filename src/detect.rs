@@ -0,0 +1,394 @@
+// Guesses the platform and quirks a ROM that isn't in the database needs by scanning its code without
+// running it. The scan follows jumps, calls and skips from the start of the program with the decoder,
+// so sprites and tables after the code aren't mistaken for instructions. SUPER-CHIP and XO-CHIP
+// opcodes give away the platform, and a few idioms hint at the quirks the author tested against:
+//
+// - 8xy6 and 8xyE with Vy set just before and different from Vx expect Vy to be shifted
+// - Bxnn jumps after setting Vx but not V0 expect the jump to add Vx, the other way round V0
+// - Fx55 or Fx65 followed by another without I being set again expect I to be left where it was
+//
+// Nothing here is certain, so the result comes with a confidence from 0 to 1.
+use crate::database::Platform;
+use crate::instruction::{
+    AddressInstruction, AddressInstructionType, Instruction, NoArgInstructionType, RegisterByteInstruction,
+    RegisterByteInstructionType, SingleRegisterInstruction, SingleRegisterInstructionType,
+    TwoRegisterInstruction, TwoRegisterInstructionType,
+};
+use crate::interpreter::PROGRAM_START;
+use crate::quirks::Quirks;
+use std::fmt;
+
+// A hint where the registers involved were set just before counts for more than one where they weren't
+const STRONG: u32 = 2;
+const WEAK: u32 = 1;
+
+// Reachable instructions without a SUPER-CHIP or XO-CHIP opcode that make a plain CHIP-8 guess
+// as likely again
+const CHIP8_EVIDENCE: f32 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HintKind {
+    Schip,
+    Xochip,
+    ShiftUsesVy(bool),
+    JumpUsesVx(bool),
+    MemoryIncrementsI(bool),
+}
+
+impl fmt::Display for HintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quirk = |f: &mut fmt::Formatter, name, enabled| match enabled {
+            true => write!(f, "{}", name),
+            false => write!(f, "no {}", name),
+        };
+
+        match *self {
+            HintKind::Schip => write!(f, "schip"),
+            HintKind::Xochip => write!(f, "xochip"),
+            HintKind::ShiftUsesVy(enabled) => quirk(f, "shift_uses_vy", enabled),
+            HintKind::JumpUsesVx(enabled) => quirk(f, "jump_uses_vx", enabled),
+            HintKind::MemoryIncrementsI(enabled) => quirk(f, "memory_increments_i", enabled),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hint {
+    pub address: u16,
+    pub opcode: u16,
+    pub kind: HintKind,
+    pub weight: u32,
+}
+
+// Written as the address and opcode followed by what it suggests, e.g. 2F4 00FF suggests schip
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X} {:04X} suggests {}", self.address, self.opcode, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub confidence: f32,
+    pub hints: Vec<Hint>,
+    pub instructions: usize, // reachable instructions scanned
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} with quirks {} ({:.0}% confidence)",
+            self.platform,
+            self.quirks,
+            self.confidence * 100.0
+        )
+    }
+}
+
+// What the straight line of code leading up to an instruction did, reset at every jump target
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+    written: [bool; 16], // registers set since the start of the block
+    memory_access: Option<u8>, // Vx of the last Fx55 or Fx65, while I hasn't been changed since
+}
+
+pub fn detect(rom: &[u8]) -> Detection {
+    let (hints, instructions) = scan(rom);
+
+    let count = |wanted: HintKind| hints.iter().filter(|hint| hint.kind == wanted).count();
+    let (platform, extensions) = match (count(HintKind::Xochip), count(HintKind::Schip)) {
+        (0, 0) => (Platform::Chip8, 0),
+        (0, schip) => (Platform::Schip, schip),
+        (xochip, schip) => (Platform::Xochip, xochip + schip),
+    };
+
+    // Plain CHIP-8 is only a default, more so the less code there was to look at
+    let mut scores = vec![match platform {
+        Platform::Chip8 => 1.0 - 0.5f32.powf(1.0 + instructions as f32 / CHIP8_EVIDENCE),
+        _ => 1.0 - 0.5f32.powi(extensions as i32 + 1),
+    }];
+
+    let mut quirks = platform.quirks();
+    let votes = [
+        vote(&hints, HintKind::ShiftUsesVy, &mut quirks.shift_uses_vy),
+        vote(&hints, HintKind::JumpUsesVx, &mut quirks.jump_uses_vx),
+        vote(&hints, HintKind::MemoryIncrementsI, &mut quirks.memory_increments_i),
+    ];
+    scores.extend(votes.iter().flatten());
+
+    Detection {
+        platform,
+        quirks,
+        confidence: scores.iter().sum::<f32>() / scores.len() as f32,
+        hints,
+        instructions,
+    }
+}
+
+// Sets the flag the way the hints for it lean, if they lean at all, and returns how sure they are.
+// None when there were no hints for it.
+fn vote(hints: &[Hint], kind: fn(bool) -> HintKind, flag: &mut bool) -> Option<f32> {
+    let weight = |enabled| hints.iter().filter(|hint| hint.kind == kind(enabled)).map(|hint| hint.weight).sum();
+    let (enabled, disabled): (u32, u32) = (weight(true), weight(false));
+    if enabled + disabled == 0 {
+        return None;
+    }
+    if enabled != disabled {
+        *flag = enabled > disabled;
+    }
+
+    // Agreeing hints make the flag more certain, contradicting ones pull it back towards a coin toss
+    let agreement = (enabled as f32 - disabled as f32).abs() / (enabled + disabled) as f32;
+    let strength = 1.0 - 0.5f32.powi((enabled + disabled) as i32);
+
+    Some(0.5 + 0.5 * agreement * strength)
+}
+
+// Follows every path from the start of the program, returning the hints found on the way and how
+// many instructions were reachable
+fn scan(rom: &[u8]) -> (Vec<Hint>, usize) {
+    let mut visited = vec![false; rom.len()];
+    let mut hints = Vec::new();
+    let mut instructions = 0;
+    let mut targets = vec![PROGRAM_START];
+
+    while let Some(start) = targets.pop() {
+        let mut block = Block::default();
+        let mut address = start;
+
+        while let Some(opcode) = opcode_at(rom, address) {
+            let offset = (address - PROGRAM_START) as usize;
+            if visited[offset] {
+                break;
+            }
+            visited[offset] = true;
+            instructions += 1;
+
+            let mut hint = |kind, weight| hints.push(Hint { address, opcode, kind, weight });
+            let next = address.wrapping_add(instruction_length(opcode));
+
+            if let Some(kind) = extension(opcode) {
+                hint(kind, STRONG);
+                match opcode {
+                    0x00fd => break, // EXIT
+                    0xf000 => block.memory_access = None, // LD I, nnnn
+                    _ if opcode & 0xf0ff == 0xf030 => block.memory_access = None, // LD HF, Vx
+                    _ => {},
+                }
+                address = next;
+                continue;
+            }
+
+            let [high, low] = opcode.to_be_bytes();
+            let instruction = match Instruction::decode((high, low)) {
+                Some(instruction) => instruction,
+                None => break, // data, or an instruction from a platform this doesn't know
+            };
+
+            match instruction {
+                Instruction::NoArgInstruction(NoArgInstructionType::Return) => break,
+                Instruction::AddressInstruction(AddressInstruction { instruction_type, address: target }) => {
+                    match instruction_type {
+                        AddressInstructionType::JumpDirect => {
+                            targets.push(target);
+                            break;
+                        },
+                        AddressInstructionType::Call => targets.push(target),
+                        AddressInstructionType::SetI => block.memory_access = None,
+                        AddressInstructionType::JumpAddV0 => {
+                            let x = (target >> 8) as usize & 0xf;
+                            match (block.written[x], block.written[0]) {
+                                (true, false) if x != 0 => hint(HintKind::JumpUsesVx(true), STRONG),
+                                (false, true) if x != 0 => hint(HintKind::JumpUsesVx(false), STRONG),
+                                _ => {},
+                            }
+                            break; // nowhere to follow without knowing the register
+                        },
+                        AddressInstructionType::SYS => {},
+                    }
+                },
+                Instruction::RegisterByteInstruction(RegisterByteInstruction { instruction_type, register, .. }) => {
+                    match instruction_type {
+                        RegisterByteInstructionType::SkipEqual | RegisterByteInstructionType::SkipNotEqual => {
+                            targets.push(skipped(rom, next));
+                        },
+                        _ => block.written[register as usize] = true,
+                    }
+                },
+                Instruction::TwoRegisterInstruction(TwoRegisterInstruction { instruction_type, Vx: x, Vy: y }) => {
+                    match instruction_type {
+                        TwoRegisterInstructionType::SkipEqual | TwoRegisterInstructionType::SkipNotEqual => {
+                            targets.push(skipped(rom, next));
+                        },
+                        TwoRegisterInstructionType::ShiftRight | TwoRegisterInstructionType::ShiftLeft if x != y => {
+                            match block.written[y as usize] {
+                                true => hint(HintKind::ShiftUsesVy(true), STRONG),
+                                false => hint(HintKind::ShiftUsesVy(false), WEAK),
+                            }
+                            block.written[x as usize] = true;
+                            block.written[0xf] = true;
+                        },
+                        _ => {
+                            block.written[x as usize] = true;
+                            block.written[0xf] = true;
+                        },
+                    }
+                },
+                Instruction::SingleRegisterInstruction(SingleRegisterInstruction { instruction_type, register }) => {
+                    match instruction_type {
+                        SingleRegisterInstructionType::SkipPressed | SingleRegisterInstructionType::SkipNotPressed => {
+                            targets.push(skipped(rom, next));
+                        },
+                        SingleRegisterInstructionType::ReadDelayTimer
+                        | SingleRegisterInstructionType::WaitForKeyPress => block.written[register as usize] = true,
+                        SingleRegisterInstructionType::AddI | SingleRegisterInstructionType::LoadSprite => {
+                            block.memory_access = None;
+                        },
+                        SingleRegisterInstructionType::StoreRegisters
+                        | SingleRegisterInstructionType::ReadToRegisters => {
+                            // Loading back the registers just stored, or storing the ones just loaded
+                            if let Some(previous) = block.memory_access {
+                                let weight = if previous == register { STRONG } else { WEAK };
+                                hint(HintKind::MemoryIncrementsI(false), weight);
+                            }
+                            block.memory_access = Some(register);
+                            if instruction_type == SingleRegisterInstructionType::ReadToRegisters {
+                                block.written[..=register as usize].iter_mut().for_each(|written| *written = true);
+                            }
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {},
+            }
+
+            address = next;
+        }
+    }
+
+    (hints, instructions)
+}
+
+fn opcode_at(rom: &[u8], address: u16) -> Option<u16> {
+    let offset = address.checked_sub(PROGRAM_START)? as usize;
+    rom.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// XO-CHIP's F000 nnnn is the only instruction longer than two bytes
+fn instruction_length(opcode: u16) -> u16 {
+    if opcode == 0xf000 {
+        4
+    } else {
+        2
+    }
+}
+
+// Where a skip lands when it skips, which is past a whole F000 nnnn on XO-CHIP
+fn skipped(rom: &[u8], next: u16) -> u16 {
+    next.wrapping_add(opcode_at(rom, next).map_or(2, instruction_length))
+}
+
+// Opcodes only SUPER-CHIP or XO-CHIP have, checked before decoding since the decoder reads most of
+// them as SYS or nothing at all
+fn extension(opcode: u16) -> Option<HintKind> {
+    let x_cleared = opcode & 0xf0ff;
+    match opcode {
+        0x00c1..=0x00cf | 0x00fb..=0x00ff => Some(HintKind::Schip),
+        _ if x_cleared == 0xf030 || x_cleared == 0xf075 || x_cleared == 0xf085 => Some(HintKind::Schip),
+        0x00d1..=0x00df | 0xf000 | 0xf002 => Some(HintKind::Xochip),
+        _ if opcode & 0xf00f == 0x5002 || opcode & 0xf00f == 0x5003 => Some(HintKind::Xochip),
+        _ if x_cleared == 0xf001 || x_cleared == 0xf03a => Some(HintKind::Xochip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect, Hint, HintKind};
+    use crate::database::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn plain_chip8_is_the_default() {
+        // 200: LD V0, 1
+        // 202: JP 200
+        let rom = [0x60, 0x01, 0x12, 0x00];
+
+        let detection = detect(&rom);
+
+        assert_eq!(detection.platform, Platform::Chip8);
+        assert_eq!(detection.quirks, Quirks::chip8());
+        assert!(detection.hints.is_empty());
+        assert!(detection.confidence > 0.5 && detection.confidence < 0.6);
+        assert_eq!(detection.to_string(), "chip8 with quirks chip8 (51% confidence)");
+    }
+
+    #[test]
+    fn extension_opcodes_set_the_platform() {
+        // 200: HIGH
+        // 202: SCROLL-DOWN 4
+        // 204: JP 204
+        let schip = detect(&[0x00, 0xff, 0x00, 0xc4, 0x12, 0x04]);
+        // 200: HIGH
+        // 202: SAVE V0 - V1
+        // 204: JP 204
+        let xochip = detect(&[0x00, 0xff, 0x50, 0x12, 0x12, 0x04]);
+
+        assert_eq!(schip.platform, Platform::Schip);
+        assert_eq!(schip.quirks, Quirks::schip());
+        assert_eq!(schip.confidence, 0.875);
+        assert_eq!(xochip.platform, Platform::Xochip);
+        assert_eq!(xochip.hints[1], Hint { address: 0x202, opcode: 0x5012, kind: HintKind::Xochip, weight: 2 });
+        assert_eq!(xochip.hints[1].to_string(), "202 5012 suggests xochip");
+    }
+
+    #[test]
+    fn only_reachable_code_is_scanned() {
+        // 200: JP 206
+        // 202: sprite data that happens to look like HIGH and 5xy2
+        // 206: JP 206
+        let rom = [0x12, 0x06, 0x00, 0xff, 0x50, 0x12, 0x12, 0x06];
+
+        let detection = detect(&rom);
+
+        assert_eq!(detection.platform, Platform::Chip8);
+        assert_eq!(detection.instructions, 2);
+    }
+
+    #[test]
+    fn idioms_hint_at_quirks() {
+        // 200: HIGH
+        // 202: LD V1, 3
+        // 204: SHR V0, V1
+        // 206: LD I, 300
+        // 208: LD V2, [I]
+        // 20A: ADD V2, 1
+        // 20C: LD [I], V2
+        // 20E: LD V0, 4
+        // 210: JP V0, 300 (B300 read as jumping to 300 + V0)
+        let rom = [
+            0x00, 0xff, 0x61, 0x03, 0x80, 0x16, 0xa3, 0x00, 0xf2, 0x65, 0x72, 0x01, 0xf2, 0x55, 0x60, 0x04, 0xb3, 0x00,
+        ];
+
+        let detection = detect(&rom);
+        let kinds: Vec<HintKind> = detection.hints.iter().map(|hint| hint.kind).collect();
+
+        assert_eq!(
+            kinds,
+            [
+                HintKind::Schip,
+                HintKind::ShiftUsesVy(true),
+                HintKind::MemoryIncrementsI(false),
+                HintKind::JumpUsesVx(false),
+            ]
+        );
+        assert_eq!(detection.platform, Platform::Schip);
+        assert!(detection.quirks.shift_uses_vy);
+        assert!(!detection.quirks.jump_uses_vx);
+        assert!(!detection.quirks.memory_increments_i);
+        assert_eq!(detection.quirks.vf_reset, Quirks::schip().vf_reset);
+    }
+}
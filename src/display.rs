@@ -0,0 +1,304 @@
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const PACKED_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT / 8;
+
+// What happens to the part of a sprite that crosses the right or bottom edge of the screen. The
+// starting position always wraps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeMode {
+    Clip,
+    Wrap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// The 64x32 monochrome framebuffer.
+//
+// Each row is stored as a u64 with the most significant bit as the leftmost pixel. A sprite row is
+// a byte, so drawing it is a shift (clip) or rotate (wrap) of that byte into position followed by
+// a single AND to detect collisions and a single XOR to draw it, rather than a loop over pixels.
+//
+// The pixels each draw toggles are also ORed into a per row dirty mask, so front ends can redraw
+// only the rectangles that actually changed since they last looked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Display {
+    rows: [u64; DISPLAY_HEIGHT],
+    dirty: [u64; DISPLAY_HEIGHT],
+    edge_mode: EdgeMode,
+}
+
+impl Display {
+    pub fn new(edge_mode: EdgeMode) -> Display {
+        Display {
+            rows: [0; DISPLAY_HEIGHT],
+            dirty: [0; DISPLAY_HEIGHT],
+            edge_mode,
+        }
+    }
+
+    pub fn edge_mode(&self) -> EdgeMode {
+        self.edge_mode
+    }
+
+    pub fn set_edge_mode(&mut self, edge_mode: EdgeMode) {
+        self.edge_mode = edge_mode;
+    }
+
+    pub fn clear(&mut self) {
+        for (row, dirty) in self.rows.iter_mut().zip(self.dirty.iter_mut()) {
+            *dirty |= *row;
+            *row = 0;
+        }
+    }
+
+    // XORs a sprite onto the screen with its top left corner at (x, y). Returns true if any pixel
+    // that was on got turned off.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let x = x as usize % DISPLAY_WIDTH;
+        let y = y as usize % DISPLAY_HEIGHT;
+        let mut collision = 0;
+
+        for (offset, sprite_row) in sprite.iter().enumerate() {
+            let mut row = y + offset;
+            if row >= DISPLAY_HEIGHT {
+                match self.edge_mode {
+                    EdgeMode::Clip => break,
+                    EdgeMode::Wrap => row %= DISPLAY_HEIGHT,
+                }
+            }
+
+            let sprite_bits = (*sprite_row as u64) << (DISPLAY_WIDTH - 8);
+            let positioned = match self.edge_mode {
+                EdgeMode::Clip => sprite_bits >> x,
+                EdgeMode::Wrap => sprite_bits.rotate_right(x as u32),
+            };
+
+            collision |= self.rows[row] & positioned;
+            self.rows[row] ^= positioned;
+            self.dirty[row] |= positioned;
+        }
+
+        collision != 0
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        (self.rows[y] >> (DISPLAY_WIDTH - 1 - x)) & 1 == 1
+    }
+
+    pub fn rows(&self) -> &[u64; DISPLAY_HEIGHT] {
+        &self.rows
+    }
+
+    pub fn set_rows(&mut self, rows: [u64; DISPLAY_HEIGHT]) {
+        for (index, row) in rows.iter().enumerate() {
+            self.dirty[index] |= self.rows[index] ^ row;
+        }
+        self.rows = rows;
+    }
+
+    // One byte per pixel, 1 for lit and 0 for unlit, rows top to bottom
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(DISPLAY_WIDTH * DISPLAY_HEIGHT);
+        for y in 0..DISPLAY_HEIGHT {
+            for x in 0..DISPLAY_WIDTH {
+                pixels.push(self.pixel(x, y) as u8);
+            }
+        }
+
+        pixels
+    }
+
+    // One bit per pixel, rows top to bottom, 8 pixels per byte with the leftmost in the most
+    // significant bit
+    pub fn packed(&self) -> [u8; PACKED_SIZE] {
+        let mut packed = [0; PACKED_SIZE];
+        for (chunk, row) in packed.chunks_exact_mut(8).zip(self.rows.iter()) {
+            chunk.copy_from_slice(&row.to_be_bytes());
+        }
+
+        packed
+    }
+
+    // Writes 4 bytes per pixel using the given colours. The buffer must hold at least
+    // DISPLAY_WIDTH * DISPLAY_HEIGHT * 4 bytes.
+    pub fn write_rgba(&self, buffer: &mut [u8], on: [u8; 4], off: [u8; 4]) {
+        for (y, row) in self.rows.iter().enumerate() {
+            let line = &mut buffer[y * DISPLAY_WIDTH * 4..(y + 1) * DISPLAY_WIDTH * 4];
+            for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                let lit = (row >> (DISPLAY_WIDTH - 1 - x)) & 1 == 1;
+                pixel.copy_from_slice(if lit { &on } else { &off });
+            }
+        }
+    }
+
+    pub fn to_rgba(&self, on: [u8; 4], off: [u8; 4]) -> Vec<u8> {
+        let mut buffer = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT * 4];
+        self.write_rgba(&mut buffer, on, off);
+
+        buffer
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(|row| *row != 0)
+    }
+
+    // Marks the whole screen as changed, e.g. after a front end resizes its window
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = [u64::MAX; DISPLAY_HEIGHT];
+    }
+
+    // Rectangles covering every pixel that changed since the dirty state was last cleared. Each
+    // run of consecutive changed rows becomes one rectangle spanning the changed columns.
+    pub fn dirty_rects(&self) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        let mut y = 0;
+
+        while y < DISPLAY_HEIGHT {
+            if self.dirty[y] == 0 {
+                y += 1;
+                continue;
+            }
+
+            let start = y;
+            let mut columns = 0;
+            while y < DISPLAY_HEIGHT && self.dirty[y] != 0 {
+                columns |= self.dirty[y];
+                y += 1;
+            }
+
+            let left = columns.leading_zeros() as usize;
+            let right = DISPLAY_WIDTH - columns.trailing_zeros() as usize;
+            rects.push(Rect {
+                x: left,
+                y: start,
+                width: right - left,
+                height: y - start,
+            });
+        }
+
+        rects
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = [0; DISPLAY_HEIGHT];
+    }
+
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        let rects = self.dirty_rects();
+        self.clear_dirty();
+
+        rects
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Display, EdgeMode, Rect, DISPLAY_WIDTH};
+
+    const BOX: [u8; 3] = [0xe0, 0xa0, 0xe0];
+
+    #[test]
+    fn drawing_twice_restores_screen_and_collides() {
+        let mut display = Display::new(EdgeMode::Clip);
+
+        let first = display.draw_sprite(10, 5, &BOX);
+        let second = display.draw_sprite(10, 5, &BOX);
+
+        assert!(!first);
+        assert!(second);
+        assert!(display.rows().iter().all(|row| *row == 0));
+    }
+
+    #[test]
+    fn draw_sprite_places_pixels() {
+        let mut display = Display::new(EdgeMode::Clip);
+
+        display.draw_sprite(10, 5, &BOX);
+
+        assert!(display.pixel(10, 5));
+        assert!(display.pixel(12, 7));
+        assert!(!display.pixel(11, 6));
+        assert!(!display.pixel(13, 5));
+    }
+
+    #[test]
+    fn starting_position_wraps() {
+        let mut display = Display::new(EdgeMode::Clip);
+
+        display.draw_sprite(64 + 3, 32 + 2, &BOX);
+
+        assert!(display.pixel(3, 2));
+    }
+
+    #[test]
+    fn clip_mode_drops_pixels_past_the_edge() {
+        let mut display = Display::new(EdgeMode::Clip);
+
+        display.draw_sprite(62, 30, &BOX);
+
+        assert!(display.pixel(63, 30));
+        assert!(display.pixel(62, 31));
+        assert!(!display.pixel(0, 30));
+        assert!(!display.pixel(62, 0));
+    }
+
+    #[test]
+    fn wrap_mode_carries_pixels_to_the_other_side() {
+        let mut display = Display::new(EdgeMode::Wrap);
+
+        display.draw_sprite(62, 30, &BOX);
+
+        assert!(display.pixel(0, 30));
+        assert!(display.pixel(62, 0));
+        assert!(display.pixel(0, 0));
+    }
+
+    #[test]
+    fn dirty_rects_cover_changed_pixels() {
+        let mut display = Display::new(EdgeMode::Clip);
+
+        display.draw_sprite(10, 5, &BOX);
+        display.draw_sprite(40, 20, &[0xff]);
+
+        assert_eq!(
+            display.take_dirty_rects(),
+            vec![
+                Rect { x: 10, y: 5, width: 3, height: 3 },
+                Rect { x: 40, y: 20, width: 8, height: 1 },
+            ]
+        );
+        assert!(!display.is_dirty());
+    }
+
+    #[test]
+    fn clear_only_dirties_lit_pixels() {
+        let mut display = Display::new(EdgeMode::Clip);
+        display.draw_sprite(0, 0, &[0x80]);
+        display.clear_dirty();
+
+        display.clear();
+
+        assert_eq!(display.dirty_rects(), vec![Rect { x: 0, y: 0, width: 1, height: 1 }]);
+    }
+
+    #[test]
+    fn packed_and_rgba_match_pixels() {
+        let mut display = Display::new(EdgeMode::Clip);
+        display.draw_sprite(8, 1, &[0x81]);
+
+        let packed = display.packed();
+        let rgba = display.to_rgba([255, 255, 255, 255], [0, 0, 0, 255]);
+
+        assert_eq!(packed[8 + 1], 0x81);
+        assert_eq!(packed.iter().filter(|byte| **byte != 0).count(), 1);
+        let pixel_offset = (DISPLAY_WIDTH + 8) * 4;
+        assert_eq!(rgba[pixel_offset..pixel_offset + 4], [255, 255, 255, 255]);
+        assert_eq!(rgba[pixel_offset + 4..pixel_offset + 8], [0, 0, 0, 255]);
+    }
+}
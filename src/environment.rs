@@ -0,0 +1,536 @@
+// Reinforcement learning environment in the style of Gym: reset(seed), then step(action) until it
+// says the episode is done. Observations are the framebuffer, one byte per pixel, and actions are
+// keypad bit masks, bit n for key n. CHIP-8 games have no standard way to report a score, so reward
+// and termination come from a spec per ROM saying where the game keeps them in memory.
+//
+// Frame skip repeats each action for several frames and sums the reward, and sticky actions repeat
+// the previous action instead of the new one with some probability on each frame, as in the Arcade
+// Learning Environment, so agents can't learn to rely on frame exact timing.
+use crate::interpreter::{ExecError, Interpreter, MEMORY_SIZE};
+use crate::rng::Rng;
+use std::fmt;
+
+// Somewhere a game keeps a number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Register(u8),
+    Byte(u16),
+    Word(u16), // big endian, high byte first
+    // One decimal digit per byte, most significant first, the way Fx33 stores them
+    Bcd { address: u16, digits: u8 },
+}
+
+impl Value {
+    pub fn read(&self, interpreter: &Interpreter) -> i64 {
+        let byte = |address: u16| interpreter.memory()[address as usize % MEMORY_SIZE] as i64;
+
+        match *self {
+            Value::Register(register) => interpreter.registers()[register as usize & 0xf] as i64,
+            Value::Byte(address) => byte(address),
+            Value::Word(address) => byte(address) << 8 | byte(address.wrapping_add(1)),
+            // Memory can hold bytes above 9, and a Value built in code can ask for more than 18 digits
+            Value::Bcd { address, digits } => (0..digits as u16).fold(0i64, |value, digit| {
+                value.saturating_mul(10).saturating_add(byte(address.wrapping_add(digit)).min(9))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// A condition that ends the episode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Done {
+    Compare(Value, Comparison, i64),
+    // The program counter reached an address, such as the loop a game sits in after game over
+    Pc(u16),
+}
+
+impl Done {
+    pub fn holds(&self, interpreter: &Interpreter) -> bool {
+        match *self {
+            Done::Compare(value, comparison, expected) => {
+                let value = value.read(interpreter);
+                match comparison {
+                    Comparison::Equal => value == expected,
+                    Comparison::NotEqual => value != expected,
+                    Comparison::Less => value < expected,
+                    Comparison::LessOrEqual => value <= expected,
+                    Comparison::Greater => value > expected,
+                    Comparison::GreaterOrEqual => value >= expected,
+                }
+            }
+            Done::Pc(address) => interpreter.pc() == address,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentSpec {
+    pub score: Option<Value>, // the reward for each step is how much this went up
+    pub done: Vec<Done>,      // the episode ends when any of these holds, or the ROM halts
+    pub actions: Vec<u16>,    // the keypad combinations worth trying, for discrete action spaces
+}
+
+impl Default for EnvironmentSpec {
+    // No reward, episodes end only when the ROM halts, and the actions are no keys or any one key
+    fn default() -> EnvironmentSpec {
+        EnvironmentSpec {
+            score: None,
+            done: Vec::new(),
+            actions: (0..=16).map(|key| if key == 0 { 0 } else { 1 << (key - 1) }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SpecError {
+    Parse { line: usize, message: String },
+    Io(String),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecError::Parse { line, message } => write!(f, "environment spec line {}: {}", line, message),
+            SpecError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+impl EnvironmentSpec {
+    // Reads the spec for one ROM from a file laid out like a key map, with a section per ROM named
+    // after its file name or hash. Addresses are hexadecimal and numbers to compare with decimal:
+    //
+    //     [pong.ch8]
+    //     score = bcd 2F0 3
+    //     done = byte 2F8 == 0
+    //     done = pc 2A4
+    //     actions = none, 1, 4, 1+4
+    //
+    // Values are `bcd <address> <digits>`, `byte <address>`, `word <address>` or a register such as
+    // `v3`. The `default` section applies to every ROM first. A later score or actions line replaces
+    // an earlier one and done lines add up.
+    pub fn from_config(text: &str, rom_name: &str, rom_hash: u64) -> Result<EnvironmentSpec, SpecError> {
+        let hash_section = format!("fnv1a:{:016x}", rom_hash);
+        let mut default_lines = Vec::new();
+        let mut rom_lines = Vec::new();
+        let mut section = String::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                section = name.trim().to_string();
+            } else if section == "default" {
+                default_lines.push((index + 1, line));
+            } else if section.eq_ignore_ascii_case(rom_name) || section.eq_ignore_ascii_case(&hash_section) {
+                rom_lines.push((index + 1, line));
+            }
+        }
+
+        let mut spec = EnvironmentSpec::default();
+        for (line_number, line) in default_lines.into_iter().chain(rom_lines) {
+            let parse_error = |message: String| SpecError::Parse { line: line_number, message };
+            let (name, setting) = line
+                .split_once('=')
+                .ok_or_else(|| parse_error("expected name = setting".to_string()))?;
+
+            match name.trim() {
+                "score" => spec.score = Some(parse_value(setting.trim()).map_err(parse_error)?),
+                "done" => spec.done.push(parse_done(setting.trim()).map_err(parse_error)?),
+                "actions" => spec.actions = parse_actions(setting).map_err(parse_error)?,
+                name => return Err(parse_error(format!("unknown setting {}", name))),
+            }
+        }
+
+        Ok(spec)
+    }
+
+    pub fn load(path: &std::path::Path, rom_name: &str, rom_hash: u64) -> Result<EnvironmentSpec, SpecError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| SpecError::Io(format!("Could not read {}: {}", path.display(), error)))?;
+
+        EnvironmentSpec::from_config(&text, rom_name, rom_hash)
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    match u16::from_str_radix(text, 16) {
+        Ok(address) if (address as usize) < MEMORY_SIZE => Ok(address),
+        _ => Err(format!("{} is not an address 0-FFF", text)),
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["bcd", address, digits] => match digits.parse() {
+            Ok(digits) if (1..=18).contains(&digits) => Ok(Value::Bcd { address: parse_address(address)?, digits }),
+            _ => Err(format!("{} is not a number of digits 1-18", digits)),
+        },
+        ["byte", address] => Ok(Value::Byte(parse_address(address)?)),
+        ["word", address] => Ok(Value::Word(parse_address(address)?)),
+        [register] if register.len() == 2 && register.to_lowercase().starts_with('v') => {
+            u8::from_str_radix(&register[1..], 16)
+                .map(Value::Register)
+                .map_err(|_| format!("{} is not a register V0-VF", register))
+        }
+        _ => Err(format!("{} is not a value, expected e.g. bcd 2F0 3, byte 2F0, word 2F0 or v3", text)),
+    }
+}
+
+fn parse_done(text: &str) -> Result<Done, String> {
+    if let Some(address) = text.strip_prefix("pc ") {
+        return Ok(Done::Pc(parse_address(address.trim())?));
+    }
+
+    // Longest operators first, so <= isn't taken for <
+    let operators = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+    for (operator, comparison) in operators.iter() {
+        if let Some((value, expected)) = text.split_once(operator) {
+            let expected = expected.trim();
+            let expected = expected.parse().map_err(|_| format!("{} is not a number", expected))?;
+            return Ok(Done::Compare(parse_value(value.trim())?, *comparison, expected));
+        }
+    }
+
+    Err(format!("{} is not a condition, expected e.g. byte 2F8 == 0 or pc 2A4", text))
+}
+
+// Keypad combinations separated by commas, each a set of hexadecimal keys joined by +, or none
+fn parse_actions(text: &str) -> Result<Vec<u16>, String> {
+    text.split(',')
+        .map(|action| {
+            let action = action.trim();
+            if action.eq_ignore_ascii_case("none") {
+                return Ok(0);
+            }
+
+            action.split('+').try_fold(0, |keys, key| match u8::from_str_radix(key.trim(), 16) {
+                Ok(key) if key < 16 => Ok(keys | 1 << key),
+                _ => Err(format!("{} is not a keypad key 0-F", key.trim())),
+            })
+        })
+        .collect()
+}
+
+pub struct Environment {
+    interpreter: Interpreter,
+    spec: EnvironmentSpec,
+    frame_skip: u32,
+    sticky_probability: f64,
+    max_frames: Option<u64>,
+    rng: Rng,
+    held: u16, // the keys the game saw on the last frame
+    score: i64,
+    done: bool,
+    halt: Option<ExecError>,
+}
+
+impl Environment {
+    // Takes an interpreter with its ROM loaded and configured the way the game needs. The
+    // environment starts as if reset with the interpreter's seed.
+    pub fn new(interpreter: Interpreter, spec: EnvironmentSpec) -> Environment {
+        let seed = interpreter.seed();
+        let mut environment = Environment {
+            interpreter,
+            spec,
+            frame_skip: 1,
+            sticky_probability: 0.0,
+            max_frames: None,
+            rng: Rng::new(seed),
+            held: 0,
+            score: 0,
+            done: false,
+            halt: None,
+        };
+        environment.reset(seed);
+
+        environment
+    }
+
+    // How many frames each step runs with the same action
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.frame_skip = frames.max(1);
+    }
+
+    // The chance on each frame of the game seeing the previous action instead of the new one
+    pub fn set_sticky_actions(&mut self, probability: f64) {
+        self.sticky_probability = probability.clamp(0.0, 1.0);
+    }
+
+    // Ends episodes after this many frames, for games that can go on forever
+    pub fn set_max_frames(&mut self, frames: Option<u64>) {
+        self.max_frames = frames;
+    }
+
+    // Starts a new episode and returns the first observation. The seed drives both the ROM's random
+    // numbers and the sticky actions, so an episode replays exactly from its seed and actions.
+    pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+        self.interpreter.set_seed(seed);
+        self.interpreter.reset();
+        // A different stream from the ROM's own generator, which starts from the same seed
+        self.rng = Rng::new(!seed);
+        self.held = 0;
+        self.done = false;
+        self.halt = None;
+        self.score = self.read_score();
+
+        self.observation()
+    }
+
+    // Runs the action for frame_skip frames and returns the observation after them, the reward
+    // earned along the way and whether the episode is over. Stepping a finished episode does
+    // nothing until the next reset.
+    pub fn step(&mut self, action: u16) -> (Vec<u8>, i64, bool) {
+        if self.done {
+            return (self.observation(), 0, true);
+        }
+
+        for _ in 0..self.frame_skip {
+            if self.rng.next_f64() >= self.sticky_probability {
+                self.held = action;
+            }
+            self.interpreter.set_keys(self.held);
+            if let Err(error) = self.interpreter.run_frame() {
+                self.halt = Some(error);
+                self.done = true;
+            }
+            if self.done || self.finished() {
+                self.done = true;
+                break;
+            }
+        }
+
+        let score = self.read_score();
+        let reward = score - self.score;
+        self.score = score;
+
+        (self.observation(), reward, self.done)
+    }
+
+    pub fn observation(&self) -> Vec<u8> {
+        self.interpreter.display().pixels()
+    }
+
+    pub fn actions(&self) -> &[u16] {
+        &self.spec.actions
+    }
+
+    pub fn score(&self) -> i64 {
+        self.score
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    // Why the ROM stopped, if an episode ended with an error rather than a done condition
+    pub fn halt(&self) -> Option<&ExecError> {
+        self.halt.as_ref()
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    fn finished(&self) -> bool {
+        let out_of_time = self.max_frames.is_some_and(|max_frames| self.interpreter.frames() >= max_frames);
+
+        out_of_time || self.spec.done.iter().any(|done| done.holds(&self.interpreter))
+    }
+
+    fn read_score(&self) -> i64 {
+        self.spec.score.map_or(0, |score| score.read(&self.interpreter))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Comparison, Done, Environment, EnvironmentSpec, SpecError, Value};
+    use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+    use crate::interpreter::Interpreter;
+    use crate::policy::ErrorClass;
+    use crate::quirks::Quirks;
+
+    // Adds a point each frame key 5 is held and keeps the score as BCD at 300
+    //
+    // 200: LD V1, 5; LD V2, 1
+    // 204: LD DT, V2; LD V3, DT; SE V3, 0; JP 206   wait for the next frame
+    // 20C: SKNP V1; ADD V0, 1; LD I, 300; LD B, V0; JP 204
+    const GAME: [u8; 22] = [
+        0x61, 0x05, 0x62, 0x01, 0xf2, 0x15, 0xf3, 0x07, 0x33, 0x00, 0x12, 0x06, 0xe1, 0xa1, 0x70, 0x01, 0xa3, 0x00,
+        0xf0, 0x33, 0x12, 0x04,
+    ];
+    const KEY_5: u16 = 1 << 5;
+
+    fn environment(spec: &str) -> Environment {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&GAME).unwrap();
+
+        Environment::new(interpreter, EnvironmentSpec::from_config(spec, "game.ch8", 0).unwrap())
+    }
+
+    #[test]
+    fn rewards_follow_the_score_until_done() {
+        let mut environment = environment("[game.ch8]\nscore = bcd 300 3\ndone = bcd 300 3 >= 3\n");
+
+        let (_, first, _) = environment.step(0);
+        let mut total = first;
+        let mut steps = 0;
+        loop {
+            let (observation, reward, done) = environment.step(KEY_5);
+            assert_eq!(observation.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+            total += reward;
+            steps += 1;
+            if done {
+                break;
+            }
+        }
+
+        assert_eq!(first, 0);
+        assert_eq!((total, environment.score()), (3, 3));
+        assert!(steps <= 4);
+        assert_eq!(environment.step(KEY_5).1, 0);
+    }
+
+    #[test]
+    fn frame_skip_repeats_the_action() {
+        let mut environment = environment("[game.ch8]\nscore = bcd 300 3\n");
+        environment.set_frame_skip(4);
+
+        environment.step(KEY_5);
+        let (_, reward, _) = environment.step(KEY_5);
+
+        assert_eq!(environment.interpreter().frames(), 8);
+        assert_eq!(reward, 4);
+    }
+
+    #[test]
+    fn sticky_actions_keep_the_previous_keys() {
+        let mut always = environment("[game.ch8]\nscore = bcd 300 3\n");
+        always.set_sticky_actions(1.0);
+        let mut sometimes = environment("[game.ch8]\nscore = bcd 300 3\n");
+        sometimes.set_sticky_actions(0.5);
+
+        // Alternates between holding and releasing the key as the score goes up
+        let play = |environment: &mut Environment| -> Vec<i64> {
+            (0..20).map(|_| environment.step(if environment.score() % 2 == 0 { KEY_5 } else { 0 }).1).collect()
+        };
+
+        let always_rewards: i64 = (0..20).map(|_| always.step(KEY_5).1).sum();
+        let sometimes_rewards = play(&mut sometimes);
+        sometimes.reset(0);
+        let replayed = play(&mut sometimes);
+
+        assert_eq!(always_rewards, 0);
+        assert_eq!(sometimes_rewards, replayed);
+    }
+
+    #[test]
+    fn max_frames_ends_the_episode() {
+        let mut environment = environment("");
+        environment.set_max_frames(Some(3));
+
+        let done: Vec<bool> = (0..3).map(|_| environment.step(0).2).collect();
+
+        assert_eq!(done, [false, false, true]);
+    }
+
+    #[test]
+    fn reset_starts_a_new_episode() {
+        let mut environment = environment("[game.ch8]\nscore = bcd 300 3\ndone = v1 == 5\n");
+        let first = environment.observation();
+        environment.step(KEY_5);
+        assert!(environment.done());
+
+        let observation = environment.reset(3);
+
+        assert_eq!(observation, first);
+        assert!(!environment.done());
+        assert_eq!((environment.score(), environment.interpreter().frames()), (0, 0));
+        assert_eq!(environment.interpreter().seed(), 3);
+    }
+
+    #[test]
+    fn a_halted_rom_ends_the_episode() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&[0x00, 0xee]).unwrap();
+        let mut environment = Environment::new(interpreter, EnvironmentSpec::default());
+
+        let (_, reward, done) = environment.step(0);
+
+        assert_eq!((reward, done), (0, true));
+        assert_eq!(environment.halt().map(|error| error.class()), Some(ErrorClass::StackUnderflow));
+    }
+
+    #[test]
+    fn bcd_values_clamp_bytes_that_are_not_digits() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&[0xff; 32]).unwrap();
+
+        let eighteen = Value::Bcd { address: 0x200, digits: 18 }.read(&interpreter);
+        let too_many = Value::Bcd { address: 0x200, digits: 255 }.read(&interpreter);
+
+        assert_eq!(eighteen, 999_999_999_999_999_999);
+        assert_eq!(too_many, i64::MAX);
+    }
+
+    #[test]
+    fn spec_reads_the_rom_section_over_the_default() {
+        let text = "
+            [default]
+            done = pc 2A4
+            actions = none, 1, 4, 1+4
+
+            [other.ch8]
+            score = byte 100
+
+            [fnv1a:00000000000000ab]
+            score = word 2F0   # high byte first
+            done = v3 != 0
+        ";
+
+        let spec = EnvironmentSpec::from_config(text, "game.ch8", 0xab).unwrap();
+
+        assert_eq!(spec.score, Some(Value::Word(0x2f0)));
+        assert_eq!(spec.done, vec![Done::Pc(0x2a4), Done::Compare(Value::Register(3), Comparison::NotEqual, 0)]);
+        assert_eq!(spec.actions, vec![0, 1 << 1, 1 << 4, 1 << 1 | 1 << 4]);
+        assert_eq!(EnvironmentSpec::default().actions.len(), 17);
+    }
+
+    #[test]
+    fn spec_rejects_bad_lines() {
+        let error = |text: &str| match EnvironmentSpec::from_config(text, "game.ch8", 0) {
+            Err(SpecError::Parse { line, .. }) => line,
+            result => panic!("{:?}", result),
+        };
+
+        assert_eq!(error("[default]\nscore = bcd 2F0\n"), 2);
+        assert_eq!(error("[default]\n\ndone = byte 2F0 = 3\n"), 3);
+        assert_eq!(error("[default]\nactions = 1+G\n"), 2);
+        assert_eq!(error("[default]\nlives = byte 2F0\n"), 2);
+    }
+}
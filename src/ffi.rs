@@ -0,0 +1,494 @@
+// C ABI for embedding the interpreter in other hosts, built with `--features ffi`. The header in
+// include/chip8.h is generated from this file with cbindgen, see the README.
+//
+// Every function that can fail returns one of the CHIP8_* status codes rather than panicking, and
+// every function accepts a null machine pointer without crashing.
+//
+// Safety: the functions taking pointers are unsafe because they trust the caller for what can't be
+// checked. A machine pointer must be null or come from chip8_new and not yet be freed, a buffer
+// pointer must be valid for the length passed with it, and strings must be nul terminated. The
+// contract is the same for every function so it is written here once rather than on each.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::database::RomInfo;
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::interpreter::{ExecError, Interpreter, LoadError};
+use crate::keypad::KEY_COUNT;
+use crate::loader::RomLoader;
+use crate::quirks::Quirks;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+pub const CHIP8_API_VERSION: u32 = 2;
+
+pub const CHIP8_OK: c_int = 0;
+pub const CHIP8_ERROR_NULL_POINTER: c_int = -1;
+pub const CHIP8_ERROR_ROM_TOO_LARGE: c_int = -2;
+pub const CHIP8_ERROR_BUFFER_TOO_SMALL: c_int = -3;
+pub const CHIP8_ERROR_INVALID_ARGUMENT: c_int = -4;
+pub const CHIP8_ERROR_INVALID_STATE: c_int = -5;
+/// The ROM did something the interpreter can't continue from. The machine stays halted until it is
+/// reset or another ROM is loaded.
+pub const CHIP8_ERROR_HALTED: c_int = -6;
+
+// Written out rather than taken from the display module so the header only exposes CHIP8_* names
+pub const CHIP8_DISPLAY_WIDTH: usize = 64;
+pub const CHIP8_DISPLAY_HEIGHT: usize = 32;
+pub const CHIP8_FRAMEBUFFER_SIZE: usize = 64 * 32;
+
+const _: () = assert!(CHIP8_DISPLAY_WIDTH == DISPLAY_WIDTH && CHIP8_DISPLAY_HEIGHT == DISPLAY_HEIGHT);
+
+/// Opaque to C, only ever handled through a pointer from chip8_new
+pub struct Chip8 {
+    interpreter: Interpreter,
+    // Why the machine halted, while it is halted
+    halt_reason: Option<String>,
+    // The quirks and speed the host chose, which win over the ROM's own when one is loaded
+    settings: RomInfo,
+}
+
+impl Chip8 {
+    // Runs part of the machine, turning an error from a misbehaving ROM into a halted machine. A
+    // panic is caught the same way, since unwinding into C is undefined behaviour.
+    fn execute(&mut self, run: impl FnOnce(&mut Interpreter) -> Result<(), ExecError>) -> c_int {
+        if self.halt_reason.is_some() {
+            return CHIP8_ERROR_HALTED;
+        }
+
+        let interpreter = &mut self.interpreter;
+        let halt_reason = match panic::catch_unwind(AssertUnwindSafe(|| run(interpreter))) {
+            Ok(Ok(())) => return CHIP8_OK,
+            Ok(Err(error)) => error.to_string(),
+            Err(_) => "the interpreter panicked".to_string(),
+        };
+        self.halt_reason = Some(halt_reason);
+
+        CHIP8_ERROR_HALTED
+    }
+}
+
+unsafe fn with_machine(chip8: *mut Chip8, call: impl FnOnce(&mut Chip8) -> c_int) -> c_int {
+    match chip8.as_mut() {
+        Some(chip8) => call(chip8),
+        None => CHIP8_ERROR_NULL_POINTER,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_api_version() -> u32 {
+    CHIP8_API_VERSION
+}
+
+/// Creates a machine with the default quirks and seed 0. Free it with chip8_free.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8 {
+        interpreter: Interpreter::new(Quirks::default(), 0),
+        halt_reason: None,
+        settings: RomInfo::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Copies a ROM into memory and resets the machine. The ROM runs with the quirks and speed the ROM
+/// database has for it, or quirks detected from its code, except where chip8_set_quirks or
+/// chip8_set_cycles_per_frame chose otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, data: *const u8, length: usize) -> c_int {
+    with_machine(chip8, |chip8| {
+        if data.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let rom = slice::from_raw_parts(data, length);
+        let loaded = match RomLoader::new().prepare("", rom.to_vec()) {
+            Ok(loaded) => loaded,
+            Err(_) => return CHIP8_ERROR_INVALID_ARGUMENT,
+        };
+
+        let mut interpreter = chip8.interpreter.clone();
+        chip8.settings.clone().fill_from(loaded.info).configure(&mut interpreter);
+        match interpreter.load_rom(&loaded.rom) {
+            Ok(()) => {
+                chip8.interpreter = interpreter;
+                chip8.halt_reason = None;
+                CHIP8_OK
+            }
+            Err(LoadError::RomTooLarge(_)) => CHIP8_ERROR_ROM_TOO_LARGE,
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(chip8: *mut Chip8) -> c_int {
+    with_machine(chip8, |chip8| {
+        chip8.interpreter.reset();
+        chip8.halt_reason = None;
+        CHIP8_OK
+    })
+}
+
+/// Takes the same values as the --quirks command line option, e.g. "schip" or "vf_reset,clip_sprites"
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_quirks(chip8: *mut Chip8, quirks: *const c_char) -> c_int {
+    with_machine(chip8, |chip8| {
+        if quirks.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let quirks = CStr::from_ptr(quirks);
+
+        match quirks.to_str().ok().and_then(|quirks| quirks.parse().ok()) {
+            Some(quirks) => {
+                chip8.interpreter.set_quirks(quirks);
+                chip8.settings.quirks = Some(quirks);
+                CHIP8_OK
+            }
+            None => CHIP8_ERROR_INVALID_ARGUMENT,
+        }
+    })
+}
+
+/// Takes the same values as the --on-error command line option, e.g. "stack_overflow=wrap" or
+/// "all=ignore". Errors whose policy is halt leave the machine halted with CHIP8_ERROR_HALTED.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_error_policies(chip8: *mut Chip8, policies: *const c_char) -> c_int {
+    with_machine(chip8, |chip8| {
+        if policies.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let policies = CStr::from_ptr(policies);
+
+        match policies.to_str().ok().and_then(|policies| policies.parse().ok()) {
+            Some(policies) => {
+                chip8.interpreter.set_error_policies(policies);
+                CHIP8_OK
+            }
+            None => CHIP8_ERROR_INVALID_ARGUMENT,
+        }
+    })
+}
+
+/// Reseeds the random number generator now, and again with the same seed on every reset or ROM load
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_seed(chip8: *mut Chip8, seed: u64) -> c_int {
+    with_machine(chip8, |chip8| {
+        chip8.interpreter.set_seed(seed);
+        CHIP8_OK
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_cycles_per_frame(chip8: *mut Chip8, cycles_per_frame: u32) -> c_int {
+    with_machine(chip8, |chip8| {
+        chip8.interpreter.set_cycles_per_frame(cycles_per_frame);
+        chip8.settings.cycles_per_frame = Some(cycles_per_frame);
+        CHIP8_OK
+    })
+}
+
+/// Executes a single instruction
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> c_int {
+    with_machine(chip8, |chip8| chip8.execute(|interpreter| interpreter.step()))
+}
+
+/// Executes one 60Hz frame of instructions and ticks the timers
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> c_int {
+    with_machine(chip8, |chip8| chip8.execute(|interpreter| interpreter.run_frame()))
+}
+
+/// Writes one byte per pixel, 1 for lit and 0 for unlit, rows top to bottom. The buffer must hold
+/// at least CHIP8_FRAMEBUFFER_SIZE bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_framebuffer(chip8: *mut Chip8, buffer: *mut u8, length: usize) -> c_int {
+    with_machine(chip8, |chip8| {
+        if buffer.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        if length < CHIP8_FRAMEBUFFER_SIZE {
+            return CHIP8_ERROR_BUFFER_TOO_SMALL;
+        }
+        let buffer = slice::from_raw_parts_mut(buffer, CHIP8_FRAMEBUFFER_SIZE);
+
+        let display = chip8.interpreter.display();
+        for (index, pixel) in buffer.iter_mut().enumerate() {
+            *pixel = display.pixel(index % DISPLAY_WIDTH, index / DISPLAY_WIDTH) as u8;
+        }
+
+        CHIP8_OK
+    })
+}
+
+/// Presses (pressed != 0) or releases one of the keypad keys 0x0-0xF
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: c_int) -> c_int {
+    with_machine(chip8, |chip8| {
+        if key >= KEY_COUNT {
+            return CHIP8_ERROR_INVALID_ARGUMENT;
+        }
+
+        let keypad = chip8.interpreter.keypad_mut();
+        if pressed != 0 {
+            keypad.press(key);
+        } else {
+            keypad.release(key);
+        }
+
+        CHIP8_OK
+    })
+}
+
+/// Returns 1 while the buzzer should sound, 0 when silent, or an error code
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_active(chip8: *mut Chip8) -> c_int {
+    with_machine(chip8, |chip8| chip8.interpreter.sound_active() as c_int)
+}
+
+/// Sets the buzzer tone in Hz and its volume from 0 to 1
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_buzzer(chip8: *mut Chip8, frequency: f32, volume: f32) -> c_int {
+    with_machine(chip8, |chip8| {
+        if !frequency.is_finite() || !volume.is_finite() {
+            return CHIP8_ERROR_INVALID_ARGUMENT;
+        }
+        let buzzer = chip8.interpreter.buzzer_mut();
+        buzzer.set_frequency(frequency);
+        buzzer.set_volume(volume);
+        CHIP8_OK
+    })
+}
+
+/// Writes length mono samples in -1..1 of buzzer output, usually sample_rate / 60 after each frame
+#[no_mangle]
+pub unsafe extern "C" fn chip8_fill_audio(chip8: *mut Chip8, buffer: *mut f32, length: usize, sample_rate: u32) -> c_int {
+    with_machine(chip8, |chip8| {
+        if buffer.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let buffer = slice::from_raw_parts_mut(buffer, length);
+
+        // A halted machine's sound timer never runs down, so it is silenced rather than left beeping
+        if chip8.halt_reason.is_some() {
+            chip8.interpreter.buzzer_mut().fill(buffer, sample_rate, false);
+        } else {
+            chip8.interpreter.fill_audio(buffer, sample_rate);
+        }
+        CHIP8_OK
+    })
+}
+
+/// Writes why a halted machine stopped as a nul terminated string, starting with the address of the
+/// instruction, e.g. "0204: return with an empty stack". Returns CHIP8_ERROR_INVALID_STATE if the
+/// machine isn't halted.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_halt_reason(chip8: *mut Chip8, buffer: *mut c_char, length: usize) -> c_int {
+    with_machine(chip8, |chip8| {
+        if buffer.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let reason = match &chip8.halt_reason {
+            Some(reason) => reason,
+            None => return CHIP8_ERROR_INVALID_STATE,
+        };
+        if length < reason.len() + 1 {
+            return CHIP8_ERROR_BUFFER_TOO_SMALL;
+        }
+
+        ptr::copy_nonoverlapping(reason.as_ptr(), buffer as *mut u8, reason.len());
+        *buffer.add(reason.len()) = 0;
+
+        CHIP8_OK
+    })
+}
+
+/// Size in bytes of the buffer chip8_save_state needs, or 0 for a null machine
+#[no_mangle]
+pub unsafe extern "C" fn chip8_state_size(chip8: *mut Chip8) -> usize {
+    match chip8.as_ref() {
+        Some(chip8) => chip8.interpreter.save_state().len(),
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: *mut Chip8, buffer: *mut u8, length: usize) -> c_int {
+    with_machine(chip8, |chip8| {
+        if buffer.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let state = chip8.interpreter.save_state();
+        if length < state.len() {
+            return CHIP8_ERROR_BUFFER_TOO_SMALL;
+        }
+
+        ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+
+        CHIP8_OK
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, data: *const u8, length: usize) -> c_int {
+    with_machine(chip8, |chip8| {
+        if data.is_null() {
+            return CHIP8_ERROR_NULL_POINTER;
+        }
+        let state = slice::from_raw_parts(data, length);
+
+        match chip8.interpreter.load_state(state) {
+            Ok(()) => {
+                chip8.halt_reason = None;
+                CHIP8_OK
+            }
+            Err(_) => CHIP8_ERROR_INVALID_STATE,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    // LD V0, 1; LD F, V0; DRW V0, V0, 5; JP 206
+    const ROM: [u8; 8] = [0x60, 0x01, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06];
+
+    #[test]
+    fn runs_a_rom_and_reads_the_framebuffer() {
+        unsafe {
+            let chip8 = chip8_new();
+            let mut framebuffer = [0; CHIP8_FRAMEBUFFER_SIZE];
+
+            assert_eq!(chip8_load_rom(chip8, ROM.as_ptr(), ROM.len()), CHIP8_OK);
+            assert_eq!(chip8_run_frame(chip8), CHIP8_OK);
+            assert_eq!(chip8_get_framebuffer(chip8, framebuffer.as_mut_ptr(), framebuffer.len()), CHIP8_OK);
+
+            assert_eq!(framebuffer[DISPLAY_WIDTH + 3], 1);
+            assert_eq!(framebuffer[DISPLAY_WIDTH + 1], 0);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn loading_picks_the_quirks_unless_the_host_chose_them() {
+        // LD I, 200; LD AUDIO, [I]; JP 204, which only XO-CHIP has
+        let rom = [0xa2, 0x00, 0xf0, 0x02, 0x12, 0x04];
+        unsafe {
+            let detected = chip8_new();
+            let chosen = chip8_new();
+            let schip = CString::new("schip").unwrap();
+            chip8_set_quirks(chosen, schip.as_ptr());
+            chip8_set_cycles_per_frame(chosen, 33);
+
+            chip8_load_rom(detected, rom.as_ptr(), rom.len());
+            chip8_load_rom(chosen, rom.as_ptr(), rom.len());
+
+            assert_eq!((*detected).interpreter.quirks(), Quirks::xochip());
+            assert_eq!((*chosen).interpreter.quirks(), Quirks::schip());
+            assert_eq!((*chosen).interpreter.cycles_per_frame(), 33);
+            chip8_free(detected);
+            chip8_free(chosen);
+        }
+    }
+
+    #[test]
+    fn null_pointers_return_error_codes() {
+        unsafe {
+            let mut framebuffer = [0; CHIP8_FRAMEBUFFER_SIZE];
+
+            assert_eq!(chip8_run_frame(ptr::null_mut()), CHIP8_ERROR_NULL_POINTER);
+            assert_eq!(chip8_get_framebuffer(ptr::null_mut(), framebuffer.as_mut_ptr(), 0), CHIP8_ERROR_NULL_POINTER);
+            assert_eq!(chip8_state_size(ptr::null_mut()), 0);
+            chip8_free(ptr::null_mut());
+
+            let chip8 = chip8_new();
+            assert_eq!(chip8_load_rom(chip8, ptr::null(), 10), CHIP8_ERROR_NULL_POINTER);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_return_error_codes() {
+        unsafe {
+            let chip8 = chip8_new();
+            let big_rom = vec![0; 4000];
+            let mut small_buffer = [0; 16];
+            let bad_quirks = CString::new("warp_drive").unwrap();
+
+            assert_eq!(chip8_load_rom(chip8, big_rom.as_ptr(), big_rom.len()), CHIP8_ERROR_ROM_TOO_LARGE);
+            assert_eq!(chip8_get_framebuffer(chip8, small_buffer.as_mut_ptr(), small_buffer.len()), CHIP8_ERROR_BUFFER_TOO_SMALL);
+            assert_eq!(chip8_set_key(chip8, 16, 1), CHIP8_ERROR_INVALID_ARGUMENT);
+            assert_eq!(chip8_set_quirks(chip8, bad_quirks.as_ptr()), CHIP8_ERROR_INVALID_ARGUMENT);
+            assert_eq!(chip8_load_state(chip8, small_buffer.as_ptr(), small_buffer.len()), CHIP8_ERROR_INVALID_STATE);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn bad_rom_halts_instead_of_panicking() {
+        unsafe {
+            // 0x5001 isn't an instruction
+            let rom = [0x50, 0x01];
+            let chip8 = chip8_new();
+            chip8_load_rom(chip8, rom.as_ptr(), rom.len());
+
+            let first = chip8_step(chip8);
+
+            assert_eq!(first, CHIP8_ERROR_HALTED);
+            assert_eq!(chip8_run_frame(chip8), CHIP8_ERROR_HALTED);
+            let mut reason = [0; 64];
+            assert_eq!(chip8_get_halt_reason(chip8, reason.as_mut_ptr(), reason.len()), CHIP8_OK);
+            assert_eq!(CStr::from_ptr(reason.as_ptr()).to_str(), Ok("0200: 5001 is not an instruction"));
+            assert_eq!(chip8_reset(chip8), CHIP8_OK);
+            assert_eq!(chip8_get_halt_reason(chip8, reason.as_mut_ptr(), reason.len()), CHIP8_ERROR_INVALID_STATE);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn error_policies_keep_a_bad_rom_running() {
+        unsafe {
+            let rom = [0x50, 0x01];
+            let chip8 = chip8_new();
+            let policies = CString::new("invalid_instruction=ignore").unwrap();
+            let bad_policies = CString::new("invalid_instruction=explode").unwrap();
+            chip8_load_rom(chip8, rom.as_ptr(), rom.len());
+
+            assert_eq!(chip8_set_error_policies(chip8, bad_policies.as_ptr()), CHIP8_ERROR_INVALID_ARGUMENT);
+            assert_eq!(chip8_set_error_policies(chip8, policies.as_ptr()), CHIP8_OK);
+            assert_eq!(chip8_run_frame(chip8), CHIP8_OK);
+            chip8_free(chip8);
+        }
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        unsafe {
+            let chip8 = chip8_new();
+            chip8_load_rom(chip8, ROM.as_ptr(), ROM.len());
+            chip8_run_frame(chip8);
+            let mut state = vec![0; chip8_state_size(chip8)];
+            assert_eq!(chip8_save_state(chip8, state.as_mut_ptr(), state.len()), CHIP8_OK);
+
+            let restored = chip8_new();
+            assert_eq!(chip8_load_state(restored, state.as_ptr(), state.len()), CHIP8_OK);
+
+            let mut framebuffer = [0; CHIP8_FRAMEBUFFER_SIZE];
+            let mut restored_framebuffer = [0; CHIP8_FRAMEBUFFER_SIZE];
+            chip8_get_framebuffer(chip8, framebuffer.as_mut_ptr(), framebuffer.len());
+            chip8_get_framebuffer(restored, restored_framebuffer.as_mut_ptr(), restored_framebuffer.len());
+            assert_eq!(framebuffer[..], restored_framebuffer[..]);
+            chip8_free(chip8);
+            chip8_free(restored);
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// Minimal GIF reader and writer for Octo cartridges, which keep their payload in the colour indices
+// of the image. Only what that needs is here: every frame is read as its raw palette indices, with
+// interlaced frames put back in row order, and extensions such as frame delays are skipped.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+const MAX_CODES: u16 = 4096;
+const MAX_SUB_BLOCK: usize = 255;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub palette: Option<Vec<[u8; 3]>>, // the frame's own palette, if it has one
+    pub pixels: Vec<u8>, // palette indices, rows top to bottom
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u16,
+    pub height: u16,
+    pub palette: Vec<[u8; 3]>,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GifError {
+    NotAGif,
+    Truncated,
+    Malformed(String),
+}
+
+impl fmt::Display for GifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GifError::NotAGif => write!(f, "not a GIF image"),
+            GifError::Truncated => write!(f, "the image ends early"),
+            GifError::Malformed(message) => write!(f, "malformed GIF: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GifError {}
+
+impl Image {
+    // An image with full size frames of these indices into the palette
+    pub fn new(width: u16, height: u16, palette: Vec<[u8; 3]>, frames: Vec<Vec<u8>>) -> Image {
+        let frames = frames
+            .into_iter()
+            .map(|pixels| Frame { left: 0, top: 0, width, height, palette: None, pixels })
+            .collect();
+
+        Image { width, height, palette, frames }
+    }
+}
+
+pub fn read(bytes: &[u8]) -> Result<Image, GifError> {
+    if !(bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")) {
+        return Err(GifError::NotAGif);
+    }
+    let mut reader = Reader { bytes, offset: 6 };
+
+    let width = reader.u16_le()?;
+    let height = reader.u16_le()?;
+    let flags = reader.byte()?;
+    reader.take(2)?; // background colour and aspect ratio
+    let palette = match flags & 0x80 {
+        0 => Vec::new(),
+        _ => reader.palette(flags)?,
+    };
+
+    let mut frames = Vec::new();
+    loop {
+        match reader.byte()? {
+            0x3b => break, // trailer
+            0x21 => {
+                reader.byte()?; // extension label
+                reader.sub_blocks()?;
+            }
+            0x2c => frames.push(reader.frame()?),
+            block => return Err(GifError::Malformed(format!("unknown block {:02x}", block))),
+        }
+    }
+
+    Ok(Image { width, height, palette, frames })
+}
+
+pub fn write(writer: &mut impl Write, image: &Image) -> io::Result<()> {
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&image.width.to_le_bytes())?;
+    writer.write_all(&image.height.to_le_bytes())?;
+    let (flags, palette) = palette_bytes(&image.palette)?;
+    writer.write_all(&[flags, 0, 0])?;
+    writer.write_all(&palette)?;
+    let global_bits = (flags & 0x07) + 1;
+
+    for frame in image.frames.iter() {
+        if frame.pixels.len() != frame.width as usize * frame.height as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel data doesn't match the frame size"));
+        }
+        writer.write_all(&[0x2c])?;
+        for value in [frame.left, frame.top, frame.width, frame.height] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        let bits = match &frame.palette {
+            Some(palette) => {
+                let (flags, palette) = palette_bytes(palette)?;
+                writer.write_all(&[flags])?;
+                writer.write_all(&palette)?;
+                (flags & 0x07) + 1
+            }
+            None => {
+                writer.write_all(&[0])?;
+                global_bits
+            }
+        };
+        writer.write_all(&[bits])?;
+
+        let data = lzw_encode(&frame.pixels, bits);
+        for block in data.chunks(MAX_SUB_BLOCK) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])?;
+    }
+
+    writer.write_all(&[0x3b])
+}
+
+// The flags announcing a palette and its entries padded to a power of two, with at least the 4
+// entries LZW's minimum code size needs
+fn palette_bytes(palette: &[[u8; 3]]) -> io::Result<(u8, Vec<u8>)> {
+    if palette.len() > 256 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "a GIF palette has at most 256 colours"));
+    }
+    let bits = (2..=8).find(|bits| 1usize << bits >= palette.len()).unwrap_or(8);
+
+    let mut bytes = vec![0; 3 << bits];
+    for (entry, colour) in bytes.chunks_mut(3).zip(palette.iter()) {
+        entry.copy_from_slice(colour);
+    }
+
+    Ok((0x80 | (bits - 1), bytes))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], GifError> {
+        let bytes = self.bytes.get(self.offset..self.offset + length).ok_or(GifError::Truncated)?;
+        self.offset += length;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, GifError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_le(&mut self) -> Result<u16, GifError> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    // The size of a palette is in the low 3 bits of the flags before it
+    fn palette(&mut self, flags: u8) -> Result<Vec<[u8; 3]>, GifError> {
+        let entries = 2 << (flags & 0x07);
+        let bytes = self.take(entries * 3)?;
+
+        Ok(bytes.chunks(3).map(|entry| [entry[0], entry[1], entry[2]]).collect())
+    }
+
+    // Data blocks are split into sub-blocks of up to 255 bytes, each after its length
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, GifError> {
+        let mut data = Vec::new();
+        loop {
+            let length = self.byte()? as usize;
+            if length == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.take(length)?);
+        }
+    }
+
+    fn frame(&mut self) -> Result<Frame, GifError> {
+        let left = self.u16_le()?;
+        let top = self.u16_le()?;
+        let width = self.u16_le()?;
+        let height = self.u16_le()?;
+        let flags = self.byte()?;
+        let palette = match flags & 0x80 {
+            0 => None,
+            _ => Some(self.palette(flags)?),
+        };
+
+        let minimum_size = self.byte()?;
+        if !(2..=11).contains(&minimum_size) {
+            return Err(GifError::Malformed(format!("LZW minimum code size {}", minimum_size)));
+        }
+        let size = width as usize * height as usize;
+        let mut pixels = lzw_decode(&self.sub_blocks()?, minimum_size, size)?;
+        if pixels.len() < size {
+            return Err(GifError::Malformed("a frame has fewer pixels than its size".to_string()));
+        }
+        pixels.truncate(size);
+        if flags & 0x40 != 0 {
+            pixels = deinterlace(&pixels, width as usize, height as usize);
+        }
+
+        Ok(Frame { left, top, width, height, palette, pixels })
+    }
+}
+
+// Interlaced frames store every 8th row from 0, every 8th from 4, every 4th from 2 then every 2nd
+// from 1
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let passes = [(0, 8), (4, 8), (2, 4), (1, 2)];
+    let order = passes.iter().flat_map(|&(start, step)| (start..height).step_by(step));
+
+    let mut rows = vec![0; pixels.len()];
+    for (stored, row) in pixels.chunks(width.max(1)).zip(order) {
+        rows[row * width..(row + 1) * width].copy_from_slice(stored);
+    }
+
+    rows
+}
+
+// Codes are packed least significant bit first, growing from minimum_size + 1 bits up to 12 as the
+// table fills. The table is only reset by a clear code, so a full table just stops growing.
+fn lzw_decode(data: &[u8], minimum_size: u8, limit: usize) -> Result<Vec<u8>, GifError> {
+    let clear = 1u16 << minimum_size;
+    let end = clear + 1;
+    let mut prefixes = vec![0u16; MAX_CODES as usize];
+    let mut suffixes = vec![0u8; MAX_CODES as usize];
+    let mut lengths = vec![0usize; MAX_CODES as usize];
+    for code in 0..clear {
+        suffixes[code as usize] = code as u8;
+        lengths[code as usize] = 1;
+    }
+
+    // The limit comes from the frame size in the file, so it only caps the output, the data sizes it
+    let mut pixels = Vec::with_capacity(limit.min(data.len()));
+    let mut size = minimum_size + 1;
+    let mut next = end + 1;
+    let mut previous: Option<u16> = None;
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    let mut bytes = data.iter();
+
+    while pixels.len() < limit {
+        while bit_count < size {
+            match bytes.next() {
+                Some(byte) => bits |= (*byte as u32) << bit_count,
+                None => return Ok(pixels), // some encoders leave out the end code
+            }
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << size) - 1)) as u16;
+        bits >>= size;
+        bit_count -= size;
+
+        if code == clear {
+            size = minimum_size + 1;
+            next = end + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        let previous_code = match previous {
+            Some(previous_code) => previous_code,
+            None if code < clear => {
+                pixels.push(code as u8);
+                previous = Some(code);
+                continue;
+            }
+            None => return Err(GifError::Malformed(format!("code {} before any pixels", code))),
+        };
+
+        // A code one past the table is the previous string followed by its own first byte
+        let first = match code {
+            _ if code < next => first_byte(&prefixes, &suffixes, &lengths, code),
+            _ if code == next => first_byte(&prefixes, &suffixes, &lengths, previous_code),
+            _ => return Err(GifError::Malformed(format!("code {} is past the table", code))),
+        };
+        if next < MAX_CODES {
+            prefixes[next as usize] = previous_code;
+            suffixes[next as usize] = first;
+            lengths[next as usize] = lengths[previous_code as usize] + 1;
+            next += 1;
+            if next == 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+
+        let start = pixels.len();
+        pixels.resize(start + lengths[code as usize], 0);
+        let mut string = code;
+        for pixel in pixels[start..].iter_mut().rev() {
+            *pixel = suffixes[string as usize];
+            string = prefixes[string as usize];
+        }
+        previous = Some(code);
+    }
+
+    Ok(pixels)
+}
+
+fn first_byte(prefixes: &[u16], suffixes: &[u8], lengths: &[usize], mut code: u16) -> u8 {
+    for _ in 1..lengths[code as usize] {
+        code = prefixes[code as usize];
+    }
+
+    suffixes[code as usize]
+}
+
+fn lzw_encode(pixels: &[u8], minimum_size: u8) -> Vec<u8> {
+    let clear = 1u16 << minimum_size;
+    let end = clear + 1;
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = minimum_size + 1;
+    let mut next = end + 1;
+
+    writer.write(clear, size);
+    let mut current: Option<u16> = None;
+    for &pixel in pixels {
+        let prefix = match current {
+            Some(prefix) => prefix,
+            None => {
+                current = Some(pixel as u16);
+                continue;
+            }
+        };
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            current = Some(code);
+            continue;
+        }
+
+        writer.write(prefix, size);
+        if next < MAX_CODES {
+            table.insert((prefix, pixel), next);
+            if next == 1 << size {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            writer.write(clear, size);
+            table.clear();
+            size = minimum_size + 1;
+            next = end + 1;
+        }
+        current = Some(pixel as u16);
+    }
+    if let Some(code) = current {
+        writer.write(code, size);
+    }
+    writer.write(end, size);
+
+    writer.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lzw_decode, lzw_encode, read, write, GifError, Image};
+    use crate::rng::Rng;
+
+    #[test]
+    fn reads_reference_images() {
+        // The well known 43 byte transparent pixel
+        let pixel = [
+            0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff,
+            0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+            0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+        ];
+        // 3x2 with a 4 colour palette, the LZW codes 4 0 1 2 3 6 5 worked out by hand
+        let gif = [
+            0x47, 0x49, 0x46, 0x38, 0x37, 0x61, 0x03, 0x00, 0x02, 0x00, 0x81, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00,
+            0xff, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00,
+            0x00, 0x02, 0x03, 0x44, 0x34, 0x56, 0x00, 0x3b,
+        ];
+
+        let pixel = read(&pixel).unwrap();
+        let image = read(&gif).unwrap();
+
+        assert_eq!(pixel.palette, [[0, 0, 0], [0xff, 0xff, 0xff]]);
+        assert_eq!(pixel.frames[0].pixels, [0]);
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.palette, [[0xff, 0, 0], [0, 0xff, 0], [0, 0, 0xff], [0xff, 0xff, 0xff]]);
+        assert_eq!(image.frames.len(), 1);
+        assert_eq!(image.frames[0].pixels, [0, 1, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let mut rng = Rng::new(7);
+        let palette: Vec<[u8; 3]> = (0..=255).map(|index| [index, 255 - index, 0]).collect();
+        // Long enough to fill the code table and clear it again
+        let noisy: Vec<u8> = (0..100 * 100).map(|_| rng.next_u8()).collect();
+        let flat = vec![3; 100 * 100];
+        let image = Image::new(100, 100, palette, vec![noisy, flat]);
+        let mut gif = Vec::new();
+
+        write(&mut gif, &image).unwrap();
+
+        assert_eq!(read(&gif).unwrap(), image);
+    }
+
+    #[test]
+    fn lzw_handles_strings_that_repeat_themselves() {
+        let pixels = [0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 0, 2, 3, 2];
+
+        let decoded = lzw_decode(&lzw_encode(&pixels, 2), 2, pixels.len()).unwrap();
+
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn lzw_stops_at_the_end_of_the_data_whatever_the_limit() {
+        let pixels = [1, 2, 3, 0, 1, 2];
+
+        let decoded = lzw_decode(&lzw_encode(&pixels, 2), 2, usize::MAX).unwrap();
+
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn rejects_broken_images() {
+        let image = Image::new(2, 1, vec![[0, 0, 0], [255, 255, 255]], vec![vec![0, 1]]);
+        let mut gif = Vec::new();
+        write(&mut gif, &image).unwrap();
+
+        assert_eq!(read(b"PNG"), Err(GifError::NotAGif));
+        assert_eq!(read(&gif[..gif.len() - 1]), Err(GifError::Truncated));
+        assert!(matches!(read(&[&gif[..gif.len() - 1], &[0x99]].concat()), Err(GifError::Malformed(_))));
+    }
+}
@@ -1,10 +1,15 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NoArgInstructionType {
     ClearDisplay, // 00E0 - CLS
     Return, // 00EE - RET
+    LoadAudio, // F002 - AUDIO (XO-CHIP)
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressInstructionType {
     SYS, // 0nnn - SYS
     JumpDirect, // 1nnn - JP
@@ -13,13 +18,13 @@ pub enum AddressInstructionType {
     JumpAddV0, // Bnnn JP V0
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AddressInstruction {
-    instruction_type: AddressInstructionType,
-    address: u16,
+    pub instruction_type: AddressInstructionType,
+    pub address: u16,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RegisterByteInstructionType {
     SkipEqual, // 3xkk - SE
     SkipNotEqual, // 4xkk - SNE
@@ -28,14 +33,14 @@ pub enum RegisterByteInstructionType {
     RandAnd, // cxkk - RND
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RegisterByteInstruction {
-    instruction_type: RegisterByteInstructionType,
-    register: u8,
-    byte: u8
+    pub instruction_type: RegisterByteInstructionType,
+    pub register: u8,
+    pub byte: u8
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SingleRegisterInstructionType {
     SkipPressed, // Ex9E - SKP Vx
     SkipNotPressed, // ExA1 - SKNP Vx
@@ -48,15 +53,16 @@ pub enum SingleRegisterInstructionType {
     StoreBCD, // Fx33 - LD B Vx
     StoreRegisters, // Fx55 - LD [I] Vx
     ReadToRegisters, // Fx65 - LD Vx [I]
+    SetPitch, // Fx3A - LD PITCH Vx (XO-CHIP)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SingleRegisterInstruction {
-    instruction_type: SingleRegisterInstructionType,
-    register: u8,
+    pub instruction_type: SingleRegisterInstructionType,
+    pub register: u8,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TwoRegisterInstructionType {
     SkipEqual, // 5xy0 - SE Vx, Vy
     Set, // 8xy0 - LD Vx, Vy
@@ -71,21 +77,25 @@ pub enum TwoRegisterInstructionType {
     SkipNotEqual, // 9xy0 - SNE Vx, Vy
 }
 
-#[derive(Debug, PartialEq)]
+// Register fields keep the Vx/Vy naming used by the technical reference
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TwoRegisterInstruction {
-    instruction_type: TwoRegisterInstructionType,
-    Vx: u8,
-    Vy: u8,
+    pub instruction_type: TwoRegisterInstructionType,
+    pub Vx: u8,
+    pub Vy: u8,
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DrawInstruction {
-    Vx: u8,
-    Vy: u8,
-    height: u8,
+    pub Vx: u8,
+    pub Vy: u8,
+    pub height: u8,
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     NoArgInstruction(NoArgInstructionType),
     AddressInstruction(AddressInstruction),
@@ -95,11 +105,93 @@ pub enum Instruction {
     DrawInstruction(DrawInstruction),
 }
 
+// Which Instruction variant an instruction is, without its operands
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionFamily {
+    NoArgInstruction,
+    AddressInstruction,
+    RegisterByteInstruction,
+    SingleRegisterInstruction,
+    TwoRegisterInstruction,
+    DrawInstruction,
+}
+
+pub const INSTRUCTION_FAMILIES: [InstructionFamily; 6] = [
+    InstructionFamily::NoArgInstruction,
+    InstructionFamily::AddressInstruction,
+    InstructionFamily::RegisterByteInstruction,
+    InstructionFamily::SingleRegisterInstruction,
+    InstructionFamily::TwoRegisterInstruction,
+    InstructionFamily::DrawInstruction,
+];
+
+impl fmt::Display for InstructionFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseFamilyError(pub String);
+
+impl fmt::Display for ParseFamilyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown instruction family: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseFamilyError {}
+
+// An opcode that doesn't decode to any instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidInstruction(pub u16);
+
+impl fmt::Display for InvalidInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} is not an instruction", self.0)
+    }
+}
+
+impl std::error::Error for InvalidInstruction {}
+
+// Text that doesn't assemble to an instruction
+#[derive(Debug, PartialEq)]
+pub struct AssembleError(pub String);
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not an instruction, expected a mnemonic like LD I, 0x2F0", self.0)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+// Accepts the variant name with or without the Instruction suffix, ignoring case, so both
+// "TwoRegisterInstruction" and "tworegister" work
+impl FromStr for InstructionFamily {
+    type Err = ParseFamilyError;
+
+    fn from_str(s: &str) -> Result<InstructionFamily, ParseFamilyError> {
+        let name = s.trim().to_lowercase();
+        let name = name.strip_suffix("instruction").unwrap_or(&name);
 
+        INSTRUCTION_FAMILIES
+            .iter()
+            .find(|family| family.to_string().to_lowercase().strip_suffix("instruction") == Some(name))
+            .copied()
+            .ok_or_else(|| ParseFamilyError(s.to_string()))
+    }
+}
 
 impl Instruction {
-    // TODO: Replace panics with a Result
-    pub fn parse(raw: (u8, u8)) -> Instruction {
+    pub fn parse(raw: (u8, u8)) -> Result<Instruction, InvalidInstruction> {
+        Instruction::decode(raw).ok_or_else(|| InvalidInstruction(u16::from_be_bytes([raw.0, raw.1])))
+    }
+
+    // Same as parse, but returns None for byte pairs that aren't instructions. Useful when the
+    // bytes may be data, e.g. when disassembling around the program counter.
+    pub fn decode(raw: (u8, u8)) -> Option<Instruction> {
         let (upper_byte, lower_byte) = raw;
 
         // first 4 bits are easiest to group instructions by
@@ -114,7 +206,7 @@ impl Instruction {
 
         let last_four_bit_values: u8 = lower_byte & 0b1111;
 
-        match first_four_bit_value {
+        let instruction = match first_four_bit_value {
             0x0 => {
                 match lower_byte {
                     0xe0 => Instruction::NoArgInstruction(NoArgInstructionType::ClearDisplay),
@@ -150,7 +242,7 @@ impl Instruction {
                         Vx: second_four_bit_values,
                         Vy: third_four_bit_values,
                     }),
-                    _ => panic!("Invalid Instruction")
+                    _ => return None
                 }
             }
             0x6 => Instruction::RegisterByteInstruction(RegisterByteInstruction {
@@ -210,7 +302,7 @@ impl Instruction {
                         Vx: second_four_bit_values,
                         Vy: third_four_bit_values,
                     }),
-                    _ => panic!("Invalid Instruction")
+                    _ => return None
                 }
             },
             0x9 => {
@@ -220,7 +312,7 @@ impl Instruction {
                         Vx: second_four_bit_values,
                         Vy: third_four_bit_values,
                     }),
-                    _ => panic!("Invalid Instruction")
+                    _ => return None
                 }
             }
             0xa => Instruction::AddressInstruction(AddressInstruction {
@@ -251,11 +343,12 @@ impl Instruction {
                         instruction_type: SingleRegisterInstructionType::SkipNotPressed,
                         register: second_four_bit_values,
                     }),
-                    _  => panic!("Invalid Instruction"),
+                    _ => return None,
                 }
             },
             0xf => {
                 match lower_byte {
+                    0x02 if second_four_bit_values == 0 => Instruction::NoArgInstruction(NoArgInstructionType::LoadAudio),
                     0x07 => Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
                         instruction_type: SingleRegisterInstructionType::ReadDelayTimer,
                         register: second_four_bit_values,
@@ -292,14 +385,277 @@ impl Instruction {
                         instruction_type: SingleRegisterInstructionType::ReadToRegisters,
                         register: second_four_bit_values,
                     }),
-                    _ => panic!("Invalid Instruction")
+                    0x3a => Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
+                        instruction_type: SingleRegisterInstructionType::SetPitch,
+                        register: second_four_bit_values,
+                    }),
+                    _ => return None
                 }
             }
             _ => {
                 // It shouldn't be possible to get here, but the type we match on is a u8, even
                 // though the values are created with a bitwise and that would make it impossible to
                 // be more than 2^4
-                panic!("Invalid Instruction")
+                return None
+            }
+        };
+
+        Some(instruction)
+    }
+
+    // The two bytes of the instruction, the reverse of parse
+    pub fn encode(&self) -> (u8, u8) {
+        let opcode: u16 = match *self {
+            Instruction::NoArgInstruction(instruction_type) => match instruction_type {
+                NoArgInstructionType::ClearDisplay => 0x00e0,
+                NoArgInstructionType::Return => 0x00ee,
+                NoArgInstructionType::LoadAudio => 0xf002,
+            },
+            Instruction::AddressInstruction(AddressInstruction { instruction_type, address }) => {
+                let high = match instruction_type {
+                    AddressInstructionType::SYS => 0x0000,
+                    AddressInstructionType::JumpDirect => 0x1000,
+                    AddressInstructionType::Call => 0x2000,
+                    AddressInstructionType::SetI => 0xa000,
+                    AddressInstructionType::JumpAddV0 => 0xb000,
+                };
+                high | (address & 0xfff)
+            }
+            Instruction::RegisterByteInstruction(RegisterByteInstruction { instruction_type, register, byte }) => {
+                let high = match instruction_type {
+                    RegisterByteInstructionType::SkipEqual => 0x3000,
+                    RegisterByteInstructionType::SkipNotEqual => 0x4000,
+                    RegisterByteInstructionType::Set => 0x6000,
+                    RegisterByteInstructionType::Add => 0x7000,
+                    RegisterByteInstructionType::RandAnd => 0xc000,
+                };
+                high | (register as u16 & 0xf) << 8 | byte as u16
+            }
+            Instruction::SingleRegisterInstruction(SingleRegisterInstruction { instruction_type, register }) => {
+                let (high, low) = match instruction_type {
+                    SingleRegisterInstructionType::SkipPressed => (0xe000, 0x9e),
+                    SingleRegisterInstructionType::SkipNotPressed => (0xe000, 0xa1),
+                    SingleRegisterInstructionType::ReadDelayTimer => (0xf000, 0x07),
+                    SingleRegisterInstructionType::WaitForKeyPress => (0xf000, 0x0a),
+                    SingleRegisterInstructionType::SetDelayTimer => (0xf000, 0x15),
+                    SingleRegisterInstructionType::SetSoundTimer => (0xf000, 0x18),
+                    SingleRegisterInstructionType::AddI => (0xf000, 0x1e),
+                    SingleRegisterInstructionType::LoadSprite => (0xf000, 0x29),
+                    SingleRegisterInstructionType::StoreBCD => (0xf000, 0x33),
+                    SingleRegisterInstructionType::StoreRegisters => (0xf000, 0x55),
+                    SingleRegisterInstructionType::ReadToRegisters => (0xf000, 0x65),
+                    SingleRegisterInstructionType::SetPitch => (0xf000, 0x3a),
+                };
+                high | (register as u16 & 0xf) << 8 | low
+            }
+            Instruction::TwoRegisterInstruction(TwoRegisterInstruction { instruction_type, Vx, Vy }) => {
+                let (high, low) = match instruction_type {
+                    TwoRegisterInstructionType::SkipEqual => (0x5000, 0x0),
+                    TwoRegisterInstructionType::Set => (0x8000, 0x0),
+                    TwoRegisterInstructionType::Or => (0x8000, 0x1),
+                    TwoRegisterInstructionType::And => (0x8000, 0x2),
+                    TwoRegisterInstructionType::ExclusiveOr => (0x8000, 0x3),
+                    TwoRegisterInstructionType::Add => (0x8000, 0x4),
+                    TwoRegisterInstructionType::SubtractBorrow => (0x8000, 0x5),
+                    TwoRegisterInstructionType::ShiftRight => (0x8000, 0x6),
+                    TwoRegisterInstructionType::SubtractNotBorrow => (0x8000, 0x7),
+                    TwoRegisterInstructionType::ShiftLeft => (0x8000, 0xe),
+                    TwoRegisterInstructionType::SkipNotEqual => (0x9000, 0x0),
+                };
+                high | (Vx as u16 & 0xf) << 8 | (Vy as u16 & 0xf) << 4 | low
+            }
+            Instruction::DrawInstruction(DrawInstruction { Vx, Vy, height }) => {
+                0xd000 | (Vx as u16 & 0xf) << 8 | (Vy as u16 & 0xf) << 4 | (height as u16 & 0xf)
+            }
+        };
+
+        let [upper_byte, lower_byte] = opcode.to_be_bytes();
+        (upper_byte, lower_byte)
+    }
+
+    // Reads one instruction written the way Display writes it, e.g. "LD I, 0x2F0" or "DRW V0, V1, 5".
+    // Case and commas don't matter, numbers are decimal unless they start with 0x, and SHR and SHL
+    // also take a single register, which is shifted in place.
+    pub fn assemble(text: &str) -> Result<Instruction, AssembleError> {
+        let error = || AssembleError(text.trim().to_string());
+        let upper = text.to_uppercase().replace(',', " ");
+        let words: Vec<&str> = upper.split_whitespace().collect();
+        let (mnemonic, operands) = words.split_first().ok_or_else(error)?;
+        let operands: Option<Vec<Operand>> = operands.iter().map(|word| Operand::parse(word)).collect();
+        let operands = operands.ok_or_else(error)?;
+
+        let address = |instruction_type, address: u16| {
+            let instruction = AddressInstruction { instruction_type, address };
+            (address <= 0xfff).then_some(Instruction::AddressInstruction(instruction))
+        };
+        let register_byte = |instruction_type, register, byte: u16| {
+            let instruction = RegisterByteInstruction { instruction_type, register, byte: byte as u8 };
+            (byte <= 0xff).then_some(Instruction::RegisterByteInstruction(instruction))
+        };
+        let single = |instruction_type, register| {
+            Some(Instruction::SingleRegisterInstruction(SingleRegisterInstruction { instruction_type, register }))
+        };
+        let two = |instruction_type, x, y| {
+            Some(Instruction::TwoRegisterInstruction(TwoRegisterInstruction { instruction_type, Vx: x, Vy: y }))
+        };
+
+        use Operand::*;
+        let instruction = match (*mnemonic, operands.as_slice()) {
+            ("CLS", []) => Some(Instruction::NoArgInstruction(NoArgInstructionType::ClearDisplay)),
+            ("RET", []) => Some(Instruction::NoArgInstruction(NoArgInstructionType::Return)),
+            ("AUDIO", []) => Some(Instruction::NoArgInstruction(NoArgInstructionType::LoadAudio)),
+            ("SYS", [Number(n)]) => address(AddressInstructionType::SYS, *n),
+            ("JP", [Number(n)]) => address(AddressInstructionType::JumpDirect, *n),
+            ("CALL", [Number(n)]) => address(AddressInstructionType::Call, *n),
+            ("LD", [I, Number(n)]) => address(AddressInstructionType::SetI, *n),
+            ("JP", [Register(0), Number(n)]) => address(AddressInstructionType::JumpAddV0, *n),
+            ("SE", [Register(x), Number(n)]) => register_byte(RegisterByteInstructionType::SkipEqual, *x, *n),
+            ("SNE", [Register(x), Number(n)]) => register_byte(RegisterByteInstructionType::SkipNotEqual, *x, *n),
+            ("LD", [Register(x), Number(n)]) => register_byte(RegisterByteInstructionType::Set, *x, *n),
+            ("ADD", [Register(x), Number(n)]) => register_byte(RegisterByteInstructionType::Add, *x, *n),
+            ("RND", [Register(x), Number(n)]) => register_byte(RegisterByteInstructionType::RandAnd, *x, *n),
+            ("SKP", [Register(x)]) => single(SingleRegisterInstructionType::SkipPressed, *x),
+            ("SKNP", [Register(x)]) => single(SingleRegisterInstructionType::SkipNotPressed, *x),
+            ("LD", [Register(x), DelayTimer]) => single(SingleRegisterInstructionType::ReadDelayTimer, *x),
+            ("LD", [Register(x), Key]) => single(SingleRegisterInstructionType::WaitForKeyPress, *x),
+            ("LD", [DelayTimer, Register(x)]) => single(SingleRegisterInstructionType::SetDelayTimer, *x),
+            ("LD", [SoundTimer, Register(x)]) => single(SingleRegisterInstructionType::SetSoundTimer, *x),
+            ("ADD", [I, Register(x)]) => single(SingleRegisterInstructionType::AddI, *x),
+            ("LD", [Font, Register(x)]) => single(SingleRegisterInstructionType::LoadSprite, *x),
+            ("LD", [Bcd, Register(x)]) => single(SingleRegisterInstructionType::StoreBCD, *x),
+            ("LD", [AtI, Register(x)]) => single(SingleRegisterInstructionType::StoreRegisters, *x),
+            ("LD", [Register(x), AtI]) => single(SingleRegisterInstructionType::ReadToRegisters, *x),
+            ("LD", [Pitch, Register(x)]) => single(SingleRegisterInstructionType::SetPitch, *x),
+            ("SE", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::SkipEqual, *x, *y),
+            ("LD", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::Set, *x, *y),
+            ("OR", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::Or, *x, *y),
+            ("AND", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::And, *x, *y),
+            ("XOR", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::ExclusiveOr, *x, *y),
+            ("ADD", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::Add, *x, *y),
+            ("SUB", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::SubtractBorrow, *x, *y),
+            ("SHR", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::ShiftRight, *x, *y),
+            ("SHR", [Register(x)]) => two(TwoRegisterInstructionType::ShiftRight, *x, *x),
+            ("SUBN", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::SubtractNotBorrow, *x, *y),
+            ("SHL", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::ShiftLeft, *x, *y),
+            ("SHL", [Register(x)]) => two(TwoRegisterInstructionType::ShiftLeft, *x, *x),
+            ("SNE", [Register(x), Register(y)]) => two(TwoRegisterInstructionType::SkipNotEqual, *x, *y),
+            ("DRW", [Register(x), Register(y), Number(n)]) if *n <= 0xf => {
+                Some(Instruction::DrawInstruction(DrawInstruction { Vx: *x, Vy: *y, height: *n as u8 }))
+            }
+            _ => None,
+        };
+
+        instruction.ok_or_else(error)
+    }
+
+    pub fn family(&self) -> InstructionFamily {
+        match self {
+            Instruction::NoArgInstruction(_) => InstructionFamily::NoArgInstruction,
+            Instruction::AddressInstruction(_) => InstructionFamily::AddressInstruction,
+            Instruction::RegisterByteInstruction(_) => InstructionFamily::RegisterByteInstruction,
+            Instruction::SingleRegisterInstruction(_) => InstructionFamily::SingleRegisterInstruction,
+            Instruction::TwoRegisterInstruction(_) => InstructionFamily::TwoRegisterInstruction,
+            Instruction::DrawInstruction(_) => InstructionFamily::DrawInstruction,
+        }
+    }
+}
+
+// What an operand of an assembled instruction can be, after upper casing
+enum Operand {
+    Register(u8),
+    Number(u16),
+    I,
+    AtI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Pitch,
+}
+
+impl Operand {
+    fn parse(word: &str) -> Option<Operand> {
+        let operand = match word {
+            "I" => Operand::I,
+            "[I]" => Operand::AtI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "B" => Operand::Bcd,
+            "PITCH" => Operand::Pitch,
+            _ => match (word.strip_prefix('V'), word.strip_prefix("0X")) {
+                (Some(register), _) if register.len() == 1 => Operand::Register(u8::from_str_radix(register, 16).ok()?),
+                (_, Some(hex)) => Operand::Number(u16::from_str_radix(hex, 16).ok()?),
+                _ => Operand::Number(word.parse().ok()?),
+            },
+        };
+
+        Some(operand)
+    }
+}
+
+// Mnemonics follow Cowgod's reference, with addresses and bytes written in hexadecimal
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::NoArgInstruction(instruction_type) => match instruction_type {
+                NoArgInstructionType::ClearDisplay => write!(f, "CLS"),
+                NoArgInstructionType::Return => write!(f, "RET"),
+                NoArgInstructionType::LoadAudio => write!(f, "AUDIO"),
+            },
+            Instruction::AddressInstruction(AddressInstruction { instruction_type, address }) => match instruction_type {
+                AddressInstructionType::SYS => write!(f, "SYS 0x{:03X}", address),
+                AddressInstructionType::JumpDirect => write!(f, "JP 0x{:03X}", address),
+                AddressInstructionType::Call => write!(f, "CALL 0x{:03X}", address),
+                AddressInstructionType::SetI => write!(f, "LD I, 0x{:03X}", address),
+                AddressInstructionType::JumpAddV0 => write!(f, "JP V0, 0x{:03X}", address),
+            },
+            Instruction::RegisterByteInstruction(RegisterByteInstruction { instruction_type, register, byte }) => {
+                let mnemonic = match instruction_type {
+                    RegisterByteInstructionType::SkipEqual => "SE",
+                    RegisterByteInstructionType::SkipNotEqual => "SNE",
+                    RegisterByteInstructionType::Set => "LD",
+                    RegisterByteInstructionType::Add => "ADD",
+                    RegisterByteInstructionType::RandAnd => "RND",
+                };
+                write!(f, "{} V{:X}, 0x{:02X}", mnemonic, register, byte)
+            }
+            Instruction::SingleRegisterInstruction(SingleRegisterInstruction { instruction_type, register }) => {
+                match instruction_type {
+                    SingleRegisterInstructionType::SkipPressed => write!(f, "SKP V{:X}", register),
+                    SingleRegisterInstructionType::SkipNotPressed => write!(f, "SKNP V{:X}", register),
+                    SingleRegisterInstructionType::ReadDelayTimer => write!(f, "LD V{:X}, DT", register),
+                    SingleRegisterInstructionType::WaitForKeyPress => write!(f, "LD V{:X}, K", register),
+                    SingleRegisterInstructionType::SetDelayTimer => write!(f, "LD DT, V{:X}", register),
+                    SingleRegisterInstructionType::SetSoundTimer => write!(f, "LD ST, V{:X}", register),
+                    SingleRegisterInstructionType::AddI => write!(f, "ADD I, V{:X}", register),
+                    SingleRegisterInstructionType::LoadSprite => write!(f, "LD F, V{:X}", register),
+                    SingleRegisterInstructionType::StoreBCD => write!(f, "LD B, V{:X}", register),
+                    SingleRegisterInstructionType::StoreRegisters => write!(f, "LD [I], V{:X}", register),
+                    SingleRegisterInstructionType::ReadToRegisters => write!(f, "LD V{:X}, [I]", register),
+                    SingleRegisterInstructionType::SetPitch => write!(f, "LD PITCH, V{:X}", register),
+                }
+            }
+            Instruction::TwoRegisterInstruction(TwoRegisterInstruction { instruction_type, Vx, Vy }) => {
+                let mnemonic = match instruction_type {
+                    TwoRegisterInstructionType::SkipEqual => "SE",
+                    TwoRegisterInstructionType::Set => "LD",
+                    TwoRegisterInstructionType::Or => "OR",
+                    TwoRegisterInstructionType::And => "AND",
+                    TwoRegisterInstructionType::ExclusiveOr => "XOR",
+                    TwoRegisterInstructionType::Add => "ADD",
+                    TwoRegisterInstructionType::SubtractBorrow => "SUB",
+                    TwoRegisterInstructionType::ShiftRight => "SHR",
+                    TwoRegisterInstructionType::SubtractNotBorrow => "SUBN",
+                    TwoRegisterInstructionType::ShiftLeft => "SHL",
+                    TwoRegisterInstructionType::SkipNotEqual => "SNE",
+                };
+                write!(f, "{} V{:X}, V{:X}", mnemonic, Vx, Vy)
+            }
+            Instruction::DrawInstruction(DrawInstruction { Vx, Vy, height }) => {
+                write!(f, "DRW V{:X}, V{:X}, {}", Vx, Vy, height)
             }
         }
     }
@@ -308,7 +664,11 @@ impl Instruction {
 #[cfg(test)]
 mod test {
     use super::{
+        AssembleError,
         Instruction,
+        InstructionFamily,
+        InvalidInstruction,
+        ParseFamilyError,
         NoArgInstructionType,
         AddressInstruction,
         AddressInstructionType,
@@ -321,11 +681,55 @@ mod test {
     };
     use crate::instruction::DrawInstruction;
 
+    #[test]
+    fn decode_returns_none_for_invalid_instructions() {
+        assert_eq!(Instruction::decode((0x51, 0x21)), None);
+        assert_eq!(Instruction::decode((0x81, 0x2f)), None);
+        assert_eq!(Instruction::decode((0xe1, 0x00)), None);
+        assert_eq!(Instruction::decode((0xf1, 0xff)), None);
+        assert_eq!(Instruction::decode((0xf1, 0x02)), None);
+    }
+
+    #[test]
+    fn parse_returns_the_opcode_for_invalid_instructions() {
+        let result = Instruction::parse((0x51, 0x21));
+
+        assert_eq!(result, Err(InvalidInstruction(0x5121)));
+        assert_eq!(result.unwrap_err().to_string(), "5121 is not an instruction");
+    }
+
+    #[test]
+    fn display_uses_reference_mnemonics() {
+        let mnemonics: Vec<String> = [(0x00, 0xe0), (0x2a, 0xbc), (0xa1, 0x23), (0x3c, 0x0f), (0xf5, 0x65), (0x8a, 0xb6), (0xd1, 0x2f)]
+            .iter()
+            .map(|raw| Instruction::parse(*raw).unwrap().to_string())
+            .collect();
+
+        assert_eq!(mnemonics, vec![
+            "CLS",
+            "CALL 0xABC",
+            "LD I, 0x123",
+            "SE VC, 0x0F",
+            "LD V5, [I]",
+            "SHR VA, VB",
+            "DRW V1, V2, 15",
+        ]);
+    }
+
+    #[test]
+    fn family_names_parse_with_or_without_suffix() {
+        assert_eq!("TwoRegisterInstruction".parse(), Ok(InstructionFamily::TwoRegisterInstruction));
+        assert_eq!("draw".parse(), Ok(InstructionFamily::DrawInstruction));
+        assert_eq!(InstructionFamily::NoArgInstruction.to_string(), "NoArgInstruction");
+        assert_eq!("Jump".parse::<InstructionFamily>(), Err(ParseFamilyError("Jump".to_string())));
+        assert_eq!(Instruction::parse((0x8a, 0xb6)).unwrap().family(), InstructionFamily::TwoRegisterInstruction);
+    }
+
     #[test]
     fn parse_handles_clear_display() {
         let raw_instruction = (0x0, 0xe0);
 
-        let parse_instruction = Instruction::parse(raw_instruction);
+        let parse_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parse_instruction, Instruction::NoArgInstruction(NoArgInstructionType::ClearDisplay))
     }
@@ -334,7 +738,7 @@ mod test {
     fn parse_handles_return() {
         let raw_instruction = (0x0, 0xee);
 
-        let parse_instruction = Instruction::parse(raw_instruction);
+        let parse_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parse_instruction, Instruction::NoArgInstruction(NoArgInstructionType::Return))
     }
@@ -343,7 +747,7 @@ mod test {
     fn parse_handles_sys() {
         let raw_instruction = (0x0a, 0xbc);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::AddressInstruction(AddressInstruction {
             instruction_type:  AddressInstructionType::SYS,
@@ -355,7 +759,7 @@ mod test {
     fn parse_handles_jump_direct() {
         let raw_instruction = (0x12, 0x34);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::AddressInstruction(AddressInstruction {
             instruction_type: AddressInstructionType::JumpDirect,
@@ -367,7 +771,7 @@ mod test {
     fn parse_handles_call() {
         let raw_instruction = (0x23, 0x45);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::AddressInstruction(AddressInstruction {
             instruction_type: AddressInstructionType::Call,
@@ -379,7 +783,7 @@ mod test {
     fn parse_handles_set_i() {
         let raw_instruction = (0xab, 0xcd);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::AddressInstruction(AddressInstruction {
             instruction_type: AddressInstructionType::SetI,
@@ -391,7 +795,7 @@ mod test {
     fn parse_handles_jump_add_v0() {
         let raw_instruction = (0xbc, 0xde);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::AddressInstruction(AddressInstruction {
             instruction_type: AddressInstructionType::JumpAddV0,
@@ -403,7 +807,7 @@ mod test {
     fn parse_handles_register_byte_skip_equal() {
         let raw_instruction = (0x34,0x56);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::RegisterByteInstruction(RegisterByteInstruction {
             instruction_type: RegisterByteInstructionType::SkipEqual,
@@ -416,7 +820,7 @@ mod test {
     fn parse_handles_register_byte_skip_not_equal() {
         let raw_instruction = (0x45, 0x67);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::RegisterByteInstruction(RegisterByteInstruction {
             instruction_type: RegisterByteInstructionType::SkipNotEqual,
@@ -429,7 +833,7 @@ mod test {
     fn parse_handles_register_byte_set() {
         let raw_instruction = (0x67 ,0x89);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::RegisterByteInstruction(RegisterByteInstruction {
             instruction_type: RegisterByteInstructionType::Set,
@@ -442,7 +846,7 @@ mod test {
     fn parse_handles_register_byte_add() {
         let raw_instruction = (0x78, 0x9a);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::RegisterByteInstruction(RegisterByteInstruction {
             instruction_type: RegisterByteInstructionType::Add,
@@ -455,7 +859,7 @@ mod test {
     fn parse_handles_register_byte_rand_and() {
         let raw_instruction = (0xcd,0xef);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::RegisterByteInstruction(RegisterByteInstruction {
             instruction_type: RegisterByteInstructionType::RandAnd,
@@ -468,7 +872,7 @@ mod test {
     fn parse_handles_skip_pressed() {
         let raw_instruction = (0xef, 0x9e);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::SkipPressed,
@@ -480,7 +884,7 @@ mod test {
     fn parse_handles_skip_not_pressed() {
         let raw_instruction = (0xef, 0xa1);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::SkipNotPressed,
@@ -492,7 +896,7 @@ mod test {
     fn parse_handles_read_delay_timer() {
         let raw_instruction = (0xf0, 0x07);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::ReadDelayTimer,
@@ -504,7 +908,7 @@ mod test {
     fn parse_handles_wait_for_key_press() {
         let raw_instruction = (0xf1, 0x0a);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::WaitForKeyPress,
@@ -516,7 +920,7 @@ mod test {
     fn parse_handles_set_delay_timer() {
         let raw_instruction = (0xf2, 0x15);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::SetDelayTimer,
@@ -528,7 +932,7 @@ mod test {
     fn parse_handles_set_sound_timer() {
         let raw_instruction = (0xf2, 0x18);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::SetSoundTimer,
@@ -540,7 +944,7 @@ mod test {
     fn parse_handles_single_register_add() {
         let raw_instruction = (0xf3, 0x1e);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::AddI,
@@ -552,7 +956,7 @@ mod test {
     fn parse_handles_load_sprite()  {
         let raw_instruction = (0xf4, 0x29);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::LoadSprite,
@@ -564,7 +968,7 @@ mod test {
     fn parse_handles_store_bsd() {
         let raw_instruction = (0xf5, 0x33);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::StoreBCD,
@@ -576,7 +980,7 @@ mod test {
     fn parse_handles_store_registers() {
         let raw_instruction = (0xf6, 0x55);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::StoreRegisters,
@@ -588,7 +992,7 @@ mod test {
     fn parse_handles_read_to_registers() {
         let raw_instruction = (0xf7, 0x65);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
             instruction_type: SingleRegisterInstructionType::ReadToRegisters,
@@ -596,11 +1000,32 @@ mod test {
         }))
     }
 
+    #[test]
+    fn parse_handles_load_audio() {
+        let raw_instruction = (0xf0, 0x02);
+
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
+
+        assert_eq!(parsed_instruction, Instruction::NoArgInstruction(NoArgInstructionType::LoadAudio))
+    }
+
+    #[test]
+    fn parse_handles_set_pitch() {
+        let raw_instruction = (0xf8, 0x3a);
+
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
+
+        assert_eq!(parsed_instruction, Instruction::SingleRegisterInstruction(SingleRegisterInstruction {
+            instruction_type: SingleRegisterInstructionType::SetPitch,
+            register: 0x8
+        }))
+    }
+
     #[test]
     fn parse_handles_two_register_skip_equal() {
         let raw_instruction = (0x56, 0x70);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::SkipEqual,
@@ -613,7 +1038,7 @@ mod test {
     fn parse_handles_two_register_set() {
         let raw_instruction = (0x89, 0xa0);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction{
             instruction_type: TwoRegisterInstructionType::Set,
@@ -626,7 +1051,7 @@ mod test {
     fn parse_handles_two_register_or() {
         let raw_instruction = (0x8a, 0xb1);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::Or,
@@ -639,7 +1064,7 @@ mod test {
     fn parse_handles_two_register_and() {
         let raw_instruction = (0x8b, 0xc2);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::And,
@@ -652,7 +1077,7 @@ mod test {
     fn parse_handles_two_register_xor() {
         let raw_instruction = (0x8c, 0xd3);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::ExclusiveOr,
@@ -665,7 +1090,7 @@ mod test {
     fn parse_handles_two_register_add() {
         let raw_instruction = (0x8d, 0xe4);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::Add,
@@ -678,7 +1103,7 @@ mod test {
     fn parse_handles_two_register_subtract_borrow() {
         let raw_instruction = (0x8e, 0xf5);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::SubtractBorrow,
@@ -691,7 +1116,7 @@ mod test {
     fn parse_handles_two_register_shift_right() {
         let raw_instruction = (0x8f, 0x06);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::ShiftRight,
@@ -704,7 +1129,7 @@ mod test {
     fn parse_handles_two_register_subtract_not_borrow() {
         let raw_instruction = (0x80, 0x17);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::SubtractNotBorrow,
@@ -717,7 +1142,7 @@ mod test {
     fn parse_handles_two_register_shift_left() {
         let raw_instruction = (0x81, 0x2e);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::ShiftLeft,
@@ -730,7 +1155,7 @@ mod test {
     fn parse_handles_two_register_skip_not_equal() {
         let raw_instruction = (0x92, 0x30);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::TwoRegisterInstruction(TwoRegisterInstruction {
             instruction_type: TwoRegisterInstructionType::SkipNotEqual,
@@ -743,7 +1168,7 @@ mod test {
     fn parse_handles_draw() {
         let raw_instruction = (0xd0, 0x12);
 
-        let parsed_instruction = Instruction::parse(raw_instruction);
+        let parsed_instruction = Instruction::parse(raw_instruction).unwrap();
 
         assert_eq!(parsed_instruction, Instruction::DrawInstruction(DrawInstruction {
             Vx: 0x0,
@@ -751,4 +1176,37 @@ mod test {
             height: 0x2
         }))
     }
+
+    #[test]
+    fn encode_and_assemble_round_trip_every_instruction() {
+        for opcode in 0..=0xffffu16 {
+            let instruction = match Instruction::decode((opcode.to_be_bytes()[0], opcode.to_be_bytes()[1])) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+
+            let assembled = Instruction::assemble(&instruction.to_string());
+
+            // 0xE0 and 0xEE low bytes decode as CLS and RET whatever the high byte, so compare decoded
+            assert_eq!(Instruction::decode(instruction.encode()), Some(instruction), "{:04X}", opcode);
+            assert_eq!(assembled, Ok(instruction), "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn assemble_accepts_loose_spelling() {
+        let assembled: Vec<(u8, u8)> = ["cls", "jp 512", "ld i,0x2f0", "drw v0 v1 15", "shr v3", "ld [i], vf"]
+            .iter()
+            .map(|text| Instruction::assemble(text).unwrap().encode())
+            .collect();
+
+        assert_eq!(assembled, vec![(0x00, 0xe0), (0x12, 0x00), (0xa2, 0xf0), (0xd0, 0x1f), (0x83, 0x36), (0xff, 0x55)]);
+    }
+
+    #[test]
+    fn assemble_rejects_bad_operands() {
+        for text in ["", "JP 0x1000", "LD V0, 256", "DRW V0, V1, 16", "JP V1, 0x200", "LD VG, 1", "NOP"] {
+            assert_eq!(Instruction::assemble(text), Err(AssembleError(text.to_string())));
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,1727 @@
+use crate::audio::{Buzzer, DEFAULT_PITCH, PATTERN_SIZE};
+use crate::decode_cache::DecodeCache;
+use crate::display::{Display, EdgeMode, DISPLAY_HEIGHT};
+use crate::hash::Fnv1a;
+use crate::instruction::{
+    AddressInstruction,
+    AddressInstructionType,
+    DrawInstruction,
+    Instruction,
+    NoArgInstructionType,
+    RegisterByteInstruction,
+    RegisterByteInstructionType,
+    SingleRegisterInstruction,
+    SingleRegisterInstructionType,
+    TwoRegisterInstruction,
+    TwoRegisterInstructionType,
+};
+use crate::keypad::Keypad;
+use crate::policy::{ErrorClass, ErrorPolicies, ErrorPolicy};
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::snapshot::Snapshot;
+use std::fmt;
+use std::str::FromStr;
+use threaded::Blocks;
+
+mod threaded;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const PROGRAM_START: u16 = 0x200;
+pub const FONT_START: u16 = 0x050;
+pub const STACK_SIZE: usize = 16;
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - PROGRAM_START as usize;
+
+// Hexadecimal digit sprites 0-F, 5 bytes each, as listed in section 2.4 of Cowgod's reference
+const FONT: [u8; 80] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+    0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    RomTooLarge(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::RomTooLarge(size) => write!(
+                f,
+                "ROM is {} bytes but at most {} bytes fit in memory",
+                size, MAX_ROM_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    NotAState,
+    UnsupportedVersion(u8),
+    Truncated,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "data is not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported", version),
+            StateError::Truncated => write!(f, "save state is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Something a ROM did that the machine can't carry on from under its error policy. The
+// instruction that caused it hasn't taken effect, and each error carries the address of that
+// instruction and a snapshot of the machine as it was.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecError {
+    StackOverflow { pc: u16, snapshot: Box<Snapshot> },
+    StackUnderflow { pc: u16, snapshot: Box<Snapshot> },
+    // An instruction reached through I past the end of memory
+    MemoryOutOfBounds { pc: u16, i: u16, length: usize, snapshot: Box<Snapshot> },
+    // The program counter ran off the end of memory
+    PcOutOfBounds { pc: u16, snapshot: Box<Snapshot> },
+    UnsupportedSys { pc: u16, address: u16, snapshot: Box<Snapshot> },
+    InvalidInstruction { pc: u16, opcode: u16, snapshot: Box<Snapshot> },
+}
+
+impl ExecError {
+    pub fn pc(&self) -> u16 {
+        match self {
+            ExecError::StackOverflow { pc, .. }
+            | ExecError::StackUnderflow { pc, .. }
+            | ExecError::MemoryOutOfBounds { pc, .. }
+            | ExecError::PcOutOfBounds { pc, .. }
+            | ExecError::UnsupportedSys { pc, .. }
+            | ExecError::InvalidInstruction { pc, .. } => *pc,
+        }
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        match self {
+            ExecError::StackOverflow { snapshot, .. }
+            | ExecError::StackUnderflow { snapshot, .. }
+            | ExecError::MemoryOutOfBounds { snapshot, .. }
+            | ExecError::PcOutOfBounds { snapshot, .. }
+            | ExecError::UnsupportedSys { snapshot, .. }
+            | ExecError::InvalidInstruction { snapshot, .. } => snapshot,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            ExecError::StackOverflow { .. } => ErrorClass::StackOverflow,
+            ExecError::StackUnderflow { .. } => ErrorClass::StackUnderflow,
+            ExecError::MemoryOutOfBounds { .. } => ErrorClass::MemoryOutOfBounds,
+            ExecError::PcOutOfBounds { .. } => ErrorClass::PcOutOfBounds,
+            ExecError::UnsupportedSys { .. } => ErrorClass::UnsupportedSys,
+            ExecError::InvalidInstruction { .. } => ErrorClass::InvalidInstruction,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}: ", self.pc())?;
+        match self {
+            ExecError::StackOverflow { .. } => write!(f, "stack overflow, more than {} nested calls", STACK_SIZE),
+            ExecError::StackUnderflow { .. } => write!(f, "return with an empty stack"),
+            ExecError::MemoryOutOfBounds { i, length, .. } => {
+                write!(f, "I is {:04X} but the instruction accesses {} bytes, past the end of memory", i, length)
+            }
+            ExecError::PcOutOfBounds { .. } => write!(f, "program counter is past the end of memory"),
+            ExecError::UnsupportedSys { address, .. } => write!(f, "SYS {:03X} calls a machine code routine", address),
+            ExecError::InvalidInstruction { opcode, .. } => write!(f, "{:04X} is not an instruction", opcode),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+// An error as the execute functions find it, before it is placed in an ExecError with a snapshot
+enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { length: usize },
+    PcOutOfBounds,
+    UnsupportedSys(u16),
+    InvalidInstruction(u16),
+}
+
+impl Fault {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Fault::StackOverflow => ErrorClass::StackOverflow,
+            Fault::StackUnderflow => ErrorClass::StackUnderflow,
+            Fault::MemoryOutOfBounds { .. } => ErrorClass::MemoryOutOfBounds,
+            Fault::PcOutOfBounds => ErrorClass::PcOutOfBounds,
+            Fault::UnsupportedSys(_) => ErrorClass::UnsupportedSys,
+            Fault::InvalidInstruction(_) => ErrorClass::InvalidInstruction,
+        }
+    }
+}
+
+// How instructions are run. Interpreter fetches and decodes one instruction at a time. Threaded
+// translates the code a basic block at a time into threaded code first, see threaded.rs, which is
+// faster for long runs and behaves the same instruction for instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Interpreter,
+    Threaded,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Interpreter => write!(f, "interpreter"),
+            Backend::Threaded => write!(f, "threaded"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s {
+            "interpreter" => Ok(Backend::Interpreter),
+            "threaded" => Ok(Backend::Threaded),
+            _ => Err(format!("unknown backend {}, expected interpreter or threaded", s)),
+        }
+    }
+}
+
+// Hooks for tools that watch the machine run, such as the instruction trace. The interpreter is
+// generic over its observer, so running without one costs nothing.
+pub trait Observer {
+    // Called before each instruction executes, with the machine still in its state from before it
+    fn before_step(&mut self, _interpreter: &Interpreter, _opcode: u16) {}
+
+    // Called for each byte an instruction reads or writes through I, such as a sprite row drawn by
+    // Dxyn or a register stored by Fx55. Instruction fetches aren't included.
+    fn memory_read(&mut self, _address: u16, _value: u8) {}
+    fn memory_written(&mut self, _address: u16, _value: u8) {}
+}
+
+impl Observer for () {}
+
+// Optional observers and pairs of observers, so tools can be switched on independently and run
+// together
+impl<T: Observer> Observer for Option<T> {
+    fn before_step(&mut self, interpreter: &Interpreter, opcode: u16) {
+        if let Some(observer) = self {
+            observer.before_step(interpreter, opcode);
+        }
+    }
+
+    fn memory_read(&mut self, address: u16, value: u8) {
+        if let Some(observer) = self {
+            observer.memory_read(address, value);
+        }
+    }
+
+    fn memory_written(&mut self, address: u16, value: u8) {
+        if let Some(observer) = self {
+            observer.memory_written(address, value);
+        }
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before_step(&mut self, interpreter: &Interpreter, opcode: u16) {
+        self.0.before_step(interpreter, opcode);
+        self.1.before_step(interpreter, opcode);
+    }
+
+    fn memory_read(&mut self, address: u16, value: u8) {
+        self.0.memory_read(address, value);
+        self.1.memory_read(address, value);
+    }
+
+    fn memory_written(&mut self, address: u16, value: u8) {
+        self.0.memory_written(address, value);
+        self.1.memory_written(address, value);
+    }
+}
+
+const STATE_MAGIC: &[u8; 4] = b"C8SS";
+const STATE_VERSION: u8 = 3;
+
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    memory: [u8; MEMORY_SIZE],
+    registers: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: [u16; STACK_SIZE],
+    sp: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    keypad: Keypad,
+    display: Display,
+    buzzer: Buzzer,
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    rom: Vec<u8>,
+    quirks: Quirks,
+    seed: u64,
+    rng: Rng,
+    cycles_per_frame: u32,
+    error_policies: ErrorPolicies,
+    decode_cache: Option<DecodeCache>,
+    blocks: Option<Blocks>, // translated code when the backend is Threaded
+    cycles: u64,
+    frames: u64,
+    drew_this_frame: bool,
+}
+
+impl Interpreter {
+    pub fn new(quirks: Quirks, seed: u64) -> Interpreter {
+        let mut interpreter = Interpreter {
+            memory: [0; MEMORY_SIZE],
+            registers: [0; 16],
+            i: 0,
+            pc: PROGRAM_START,
+            stack: [0; STACK_SIZE],
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            keypad: Keypad::new(),
+            display: Display::new(edge_mode(quirks)),
+            buzzer: Buzzer::new(),
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rom: Vec::new(),
+            quirks,
+            seed,
+            rng: Rng::new(seed),
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            error_policies: ErrorPolicies::default(),
+            decode_cache: Some(DecodeCache::new()),
+            blocks: None,
+            cycles: 0,
+            frames: 0,
+            drew_this_frame: false,
+        };
+        interpreter.reset();
+
+        interpreter
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(LoadError::RomTooLarge(rom.len()));
+        }
+
+        self.rom = rom.to_vec();
+        self.reset();
+
+        Ok(())
+    }
+
+    // Returns the machine to its power on state with the current ROM loaded. Quirks, seed, cycles
+    // per frame and error policies are configuration rather than machine state so they are kept.
+    pub fn reset(&mut self) {
+        self.memory = [0; MEMORY_SIZE];
+        self.forget_code();
+        let font_start = FONT_START as usize;
+        self.memory[font_start..font_start + FONT.len()].copy_from_slice(&FONT);
+        let program_start = PROGRAM_START as usize;
+        self.memory[program_start..program_start + self.rom.len()].copy_from_slice(&self.rom);
+
+        self.registers = [0; 16];
+        self.i = 0;
+        self.pc = PROGRAM_START;
+        self.stack = [0; STACK_SIZE];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.keypad = Keypad::new();
+        self.display = Display::new(edge_mode(self.quirks));
+        self.display.mark_all_dirty();
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.rng = Rng::new(self.seed);
+        self.cycles = 0;
+        self.frames = 0;
+        self.drew_this_frame = false;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.display.set_edge_mode(edge_mode(quirks));
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.cycles_per_frame = cycles_per_frame;
+    }
+
+    pub fn set_error_policies(&mut self, error_policies: ErrorPolicies) {
+        self.error_policies = error_policies;
+    }
+
+    // Instructions are decoded once per address and cached until something writes over them. Only
+    // worth turning off to measure what the cache saves.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled { Some(DecodeCache::new()) } else { None };
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.blocks = match backend {
+            Backend::Interpreter => None,
+            Backend::Threaded => Some(Blocks::new()),
+        };
+    }
+
+    // Sets every key at once as a bit mask, bit n for key n
+    pub fn set_keys(&mut self, keys: u16) {
+        self.keypad.set_state(keys);
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut Keypad {
+        &mut self.keypad
+    }
+
+    // Writes a byte from outside the program, e.g. a cheat or a debugger. Goes through the same path
+    // as the ROM's own writes so cached and translated code over the byte is thrown away.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.write_memory(address as usize, &[value], &mut ());
+    }
+
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn keys(&self) -> u16 {
+        self.keypad.state()
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    // Front ends take the dirty rectangles through this once they have redrawn
+    pub fn display_mut(&mut self) -> &mut Display {
+        &mut self.display
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    pub fn error_policies(&self) -> ErrorPolicies {
+        self.error_policies
+    }
+
+    pub fn backend(&self) -> Backend {
+        if self.blocks.is_some() {
+            Backend::Threaded
+        } else {
+            Backend::Interpreter
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn buzzer(&self) -> &Buzzer {
+        &self.buzzer
+    }
+
+    pub fn buzzer_mut(&mut self) -> &mut Buzzer {
+        &mut self.buzzer
+    }
+
+    // The XO-CHIP pattern loaded by F002, or None while the ROM hasn't loaded one and the plain
+    // buzzer is used
+    pub fn audio_pattern(&self) -> Option<&[u8; PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Produces the buzzer's output for the current sound timer. Hosts call this between frames with
+    // however many samples their audio device wants, usually sample_rate / 60 per frame.
+    pub fn fill_audio(&mut self, buffer: &mut [f32], sample_rate: u32) {
+        let active = self.sound_active();
+        match &self.audio_pattern {
+            Some(pattern) => self.buzzer.fill_pattern(buffer, sample_rate, active, pattern, self.pitch),
+            None => self.buzzer.fill(buffer, sample_rate, active),
+        }
+    }
+
+    // Hash of everything that affects future execution. Two machines with the same state hash
+    // will behave identically given the same inputs.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write(&self.memory);
+        hasher.write(&self.registers);
+        hasher.write(&self.i.to_le_bytes());
+        hasher.write(&self.pc.to_le_bytes());
+        for address in self.stack() {
+            hasher.write(&address.to_le_bytes());
+        }
+        hasher.write(&[self.sp as u8, self.delay_timer, self.sound_timer, self.keypad.wait_state()]);
+        for row in self.display.rows().iter() {
+            hasher.write(&row.to_le_bytes());
+        }
+        hasher.write(&self.rng.state().to_le_bytes());
+        hasher.write(&[self.audio_pattern.is_some() as u8, self.pitch]);
+        hasher.write(&self.audio_pattern.unwrap_or([0; PATTERN_SIZE]));
+
+        hasher.finish()
+    }
+
+    // Serializes the whole machine, including the loaded ROM and configuration, so a front end can
+    // restore it later with load_state. All values are little endian.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(MEMORY_SIZE * 2 + 512);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_le_bytes());
+        }
+        state.extend_from_slice(&[self.sp as u8, self.delay_timer, self.sound_timer]);
+        state.extend_from_slice(&self.keypad.state().to_le_bytes());
+        state.push(self.keypad.wait_state());
+        for row in self.display.rows().iter() {
+            state.extend_from_slice(&row.to_le_bytes());
+        }
+        state.extend_from_slice(&self.rng.state().to_le_bytes());
+        state.extend_from_slice(&self.seed.to_le_bytes());
+        state.push(self.quirks.to_bits());
+        state.extend_from_slice(&self.cycles_per_frame.to_le_bytes());
+        state.extend_from_slice(&self.cycles.to_le_bytes());
+        state.extend_from_slice(&self.frames.to_le_bytes());
+        state.extend_from_slice(&(self.rom.len() as u16).to_le_bytes());
+        state.extend_from_slice(&self.rom);
+        state.push(self.audio_pattern.is_some() as u8);
+        state.extend_from_slice(&self.audio_pattern.unwrap_or([0; PATTERN_SIZE]));
+        state.push(self.pitch);
+        for mask in self.keypad.press_masks().iter() {
+            state.extend_from_slice(&mask.to_le_bytes());
+        }
+
+        state
+    }
+
+    // Restores a state from save_state. The machine is left untouched if the state is invalid.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader { data: state };
+        if reader.take(4)? != STATE_MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut loaded = self.clone();
+        loaded.memory.copy_from_slice(reader.take(MEMORY_SIZE)?);
+        loaded.registers.copy_from_slice(reader.take(16)?);
+        loaded.i = reader.u16()?;
+        loaded.pc = reader.u16()?;
+        for address in loaded.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        loaded.sp = (reader.u8()? as usize).min(STACK_SIZE);
+        loaded.delay_timer = reader.u8()?;
+        loaded.sound_timer = reader.u8()?;
+        loaded.keypad.set_state(reader.u16()?);
+        loaded.keypad.set_wait_state(reader.u8()?);
+        let mut rows = [0; DISPLAY_HEIGHT];
+        for row in rows.iter_mut() {
+            *row = reader.u64()?;
+        }
+        loaded.display.set_rows(rows);
+        loaded.rng = Rng::from_state(reader.u64()?);
+        loaded.seed = reader.u64()?;
+        loaded.set_quirks(Quirks::from_bits(reader.u8()?));
+        loaded.cycles_per_frame = reader.u32()?;
+        loaded.cycles = reader.u64()?;
+        loaded.frames = reader.u64()?;
+        let rom_length = reader.u16()? as usize;
+        if rom_length > MAX_ROM_SIZE {
+            return Err(StateError::NotAState);
+        }
+        loaded.rom = reader.take(rom_length)?.to_vec();
+        // Version 1 states predate XO-CHIP audio
+        if version >= 2 {
+            let has_pattern = reader.u8()? != 0;
+            let mut pattern = [0; PATTERN_SIZE];
+            pattern.copy_from_slice(reader.take(PATTERN_SIZE)?);
+            loaded.audio_pattern = if has_pattern { Some(pattern) } else { None };
+            loaded.pitch = reader.u8()?;
+        } else {
+            loaded.audio_pattern = None;
+            loaded.pitch = DEFAULT_PITCH;
+        }
+        // Version 2 states predate keys pressed between frames
+        let mut press_masks = [0; 3];
+        if version >= 3 {
+            for mask in press_masks.iter_mut() {
+                *mask = reader.u16()?;
+            }
+        }
+        loaded.keypad.set_press_masks(press_masks);
+        loaded.drew_this_frame = false;
+        loaded.forget_code();
+
+        *self = loaded;
+
+        Ok(())
+    }
+
+    // Runs one 60Hz frame: a batch of instructions followed by a timer tick. An error ends the
+    // frame early, without the tick.
+    pub fn run_frame(&mut self) -> Result<(), ExecError> {
+        self.run_frame_with(&mut ())
+    }
+
+    pub fn run_frame_with(&mut self, observer: &mut impl Observer) -> Result<(), ExecError> {
+        self.drew_this_frame = false;
+
+        if self.blocks.is_some() {
+            self.run_threaded(self.cycles_per_frame, observer)?;
+        } else {
+            for _ in 0..self.cycles_per_frame {
+                self.step_interpreted(observer)?;
+
+                // The VIP waits for the vertical blank interrupt before drawing, which limits a ROM
+                // to one sprite per frame
+                if self.quirks.display_wait && self.drew_this_frame {
+                    break;
+                }
+            }
+        }
+
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.keypad.end_frame();
+        self.frames = self.frames.wrapping_add(1);
+
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        self.step_with(&mut ())
+    }
+
+    pub fn step_with(&mut self, observer: &mut impl Observer) -> Result<(), ExecError> {
+        if self.blocks.is_some() {
+            return self.run_threaded(1, observer);
+        }
+
+        self.step_interpreted(observer)
+    }
+
+    fn step_interpreted(&mut self, observer: &mut impl Observer) -> Result<(), ExecError> {
+        let (pc, cycles) = (self.pc, self.cycles);
+        let result = self.fetch_and_execute(observer);
+
+        // The execute functions check before changing anything, so only the fetch needs undoing
+        result.map_err(|fault| {
+            self.pc = pc;
+            self.cycles = cycles;
+            self.exec_error(fault)
+        })
+    }
+
+    fn fetch_and_execute(&mut self, observer: &mut impl Observer) -> Result<(), Fault> {
+        if self.pc as usize + 1 >= MEMORY_SIZE {
+            match self.recover(Fault::PcOutOfBounds)? {
+                ErrorPolicy::Wrap => self.pc %= MEMORY_SIZE as u16,
+                _ => {
+                    self.cycles = self.cycles.wrapping_add(1);
+                    return Ok(());
+                }
+            }
+        }
+        let pc = self.pc as usize;
+        let raw_instruction = (self.memory[pc], self.memory[(pc + 1) % MEMORY_SIZE]);
+        observer.before_step(self, u16::from_be_bytes([raw_instruction.0, raw_instruction.1]));
+        let instruction = match &mut self.decode_cache {
+            Some(decode_cache) => decode_cache.get(&self.memory, pc),
+            None => Instruction::parse(raw_instruction),
+        };
+        if let Err(error) = instruction {
+            self.recover(Fault::InvalidInstruction(error.0))?;
+        }
+
+        self.pc += 2;
+        // The counters come from save states too, which can hold anything
+        self.cycles = self.cycles.wrapping_add(1);
+        match instruction {
+            Ok(instruction) => self.execute(instruction, observer),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction, observer: &mut impl Observer) -> Result<(), Fault> {
+        match instruction {
+            Instruction::NoArgInstruction(instruction_type) => self.execute_no_arg(instruction_type, observer),
+            Instruction::AddressInstruction(instruction) => self.execute_address(instruction),
+            Instruction::RegisterByteInstruction(instruction) => {
+                self.execute_register_byte(instruction);
+                Ok(())
+            }
+            Instruction::SingleRegisterInstruction(instruction) => self.execute_single_register(instruction, observer),
+            Instruction::TwoRegisterInstruction(instruction) => {
+                self.execute_two_register(instruction);
+                Ok(())
+            }
+            Instruction::DrawInstruction(instruction) => self.execute_draw(instruction, observer),
+        }
+    }
+
+    // Looks up the policy for a fault. Halt comes back as the fault itself so it can be returned
+    // with ?, leaving the caller to handle Wrap and Ignore.
+    fn recover(&self, fault: Fault) -> Result<ErrorPolicy, Fault> {
+        match self.error_policies.get(fault.class()) {
+            ErrorPolicy::Halt => Err(fault),
+            policy => Ok(policy),
+        }
+    }
+
+    fn exec_error(&self, fault: Fault) -> ExecError {
+        let pc = self.pc;
+        let snapshot = Box::new(Snapshot::of(self));
+
+        match fault {
+            Fault::StackOverflow => ExecError::StackOverflow { pc, snapshot },
+            Fault::StackUnderflow => ExecError::StackUnderflow { pc, snapshot },
+            Fault::MemoryOutOfBounds { length } => ExecError::MemoryOutOfBounds { pc, i: self.i, length, snapshot },
+            Fault::PcOutOfBounds => ExecError::PcOutOfBounds { pc, snapshot },
+            Fault::UnsupportedSys(address) => ExecError::UnsupportedSys { pc, address, snapshot },
+            Fault::InvalidInstruction(opcode) => ExecError::InvalidInstruction { pc, opcode, snapshot },
+        }
+    }
+
+    // Where the bytes an instruction reads or writes starting at I begin, or None if the
+    // instruction should be skipped. Go through read_memory and write_memory with it so the
+    // addresses wrap when the policy says to.
+    fn memory_at_i(&self, length: usize) -> Result<Option<usize>, Fault> {
+        let start = self.i as usize;
+        if start + length <= MEMORY_SIZE {
+            return Ok(Some(start));
+        }
+
+        match self.recover(Fault::MemoryOutOfBounds { length })? {
+            ErrorPolicy::Wrap => Ok(Some(start % MEMORY_SIZE)),
+            _ => Ok(None),
+        }
+    }
+
+    fn read_memory(&self, start: usize, buffer: &mut [u8], observer: &mut (impl Observer + ?Sized)) {
+        for (offset, byte) in buffer.iter_mut().enumerate() {
+            let address = (start + offset) % MEMORY_SIZE;
+            *byte = self.memory[address];
+            observer.memory_read(address as u16, *byte);
+        }
+    }
+
+    fn write_memory(&mut self, start: usize, data: &[u8], observer: &mut (impl Observer + ?Sized)) {
+        for (offset, byte) in data.iter().enumerate() {
+            let address = (start + offset) % MEMORY_SIZE;
+            self.memory[address] = *byte;
+            if let Some(decode_cache) = &mut self.decode_cache {
+                decode_cache.invalidate(address);
+            }
+            if let Some(blocks) = &mut self.blocks {
+                blocks.invalidate(address);
+            }
+            observer.memory_written(address as u16, *byte);
+        }
+    }
+
+    // For when all of memory changes at once
+    fn forget_code(&mut self) {
+        if let Some(decode_cache) = &mut self.decode_cache {
+            decode_cache.clear();
+        }
+        if let Some(blocks) = &mut self.blocks {
+            blocks.clear();
+        }
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc += 2;
+        }
+    }
+
+    fn execute_no_arg(&mut self, instruction_type: NoArgInstructionType, observer: &mut impl Observer) -> Result<(), Fault> {
+        match instruction_type {
+            NoArgInstructionType::ClearDisplay => self.display.clear(),
+            NoArgInstructionType::Return => self.return_from_call()?,
+            NoArgInstructionType::LoadAudio => {
+                if let Some(start) = self.memory_at_i(PATTERN_SIZE)? {
+                    let mut pattern = [0; PATTERN_SIZE];
+                    self.read_memory(start, &mut pattern, observer);
+                    self.audio_pattern = Some(pattern);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_address(&mut self, instruction: AddressInstruction) -> Result<(), Fault> {
+        let address = instruction.address;
+
+        match instruction.instruction_type {
+            // Machine code routines only existed on the original hardware, so there is nothing to
+            // run whatever the policy
+            AddressInstructionType::SYS => {
+                self.recover(Fault::UnsupportedSys(address))?;
+            }
+            AddressInstructionType::JumpDirect => self.pc = address,
+            AddressInstructionType::Call => self.call(address)?,
+            AddressInstructionType::SetI => self.i = address,
+            AddressInstructionType::JumpAddV0 => {
+                let register = if self.quirks.jump_uses_vx {
+                    (address >> 8) as usize
+                } else {
+                    0
+                };
+                self.pc = address + self.registers[register] as u16;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, address: u16) -> Result<(), Fault> {
+        if self.sp == STACK_SIZE {
+            if self.recover(Fault::StackOverflow)? == ErrorPolicy::Ignore {
+                return Ok(());
+            }
+            self.stack.rotate_left(1);
+            self.sp -= 1;
+        }
+        self.stack[self.sp] = self.pc;
+        self.sp += 1;
+        self.pc = address;
+
+        Ok(())
+    }
+
+    fn return_from_call(&mut self) -> Result<(), Fault> {
+        if self.sp == 0 {
+            if self.recover(Fault::StackUnderflow)? == ErrorPolicy::Ignore {
+                return Ok(());
+            }
+            self.sp = STACK_SIZE;
+        }
+        self.sp -= 1;
+        self.pc = self.stack[self.sp];
+
+        Ok(())
+    }
+
+    fn execute_register_byte(&mut self, instruction: RegisterByteInstruction) {
+        let register = instruction.register as usize;
+        let byte = instruction.byte;
+
+        match instruction.instruction_type {
+            RegisterByteInstructionType::SkipEqual => self.skip_if(self.registers[register] == byte),
+            RegisterByteInstructionType::SkipNotEqual => self.skip_if(self.registers[register] != byte),
+            RegisterByteInstructionType::Set => self.registers[register] = byte,
+            RegisterByteInstructionType::Add => {
+                self.registers[register] = self.registers[register].wrapping_add(byte)
+            }
+            RegisterByteInstructionType::RandAnd => self.registers[register] = self.rng.next_u8() & byte,
+        }
+    }
+
+    fn execute_single_register(
+        &mut self,
+        instruction: SingleRegisterInstruction,
+        observer: &mut impl Observer,
+    ) -> Result<(), Fault> {
+        let register = instruction.register as usize;
+        let value = self.registers[register];
+
+        match instruction.instruction_type {
+            SingleRegisterInstructionType::SkipPressed => self.skip_if(self.keypad.is_down(value)),
+            SingleRegisterInstructionType::SkipNotPressed => self.skip_if(!self.keypad.is_down(value)),
+            SingleRegisterInstructionType::ReadDelayTimer => self.registers[register] = self.delay_timer,
+            SingleRegisterInstructionType::WaitForKeyPress => match self.keypad.poll_wait() {
+                Some(key) => self.registers[register] = key,
+                // Execute this instruction again until a key has been pressed and released
+                None => self.pc -= 2,
+            },
+            SingleRegisterInstructionType::SetDelayTimer => self.delay_timer = value,
+            SingleRegisterInstructionType::SetSoundTimer => self.sound_timer = value,
+            SingleRegisterInstructionType::AddI => self.i = self.i.wrapping_add(value as u16),
+            SingleRegisterInstructionType::LoadSprite => self.i = FONT_START + (value & 0xf) as u16 * 5,
+            SingleRegisterInstructionType::StoreBCD => self.store_bcd(value, observer)?,
+            SingleRegisterInstructionType::StoreRegisters => self.store_registers(register, observer)?,
+            SingleRegisterInstructionType::ReadToRegisters => self.read_registers(register, observer)?,
+            SingleRegisterInstructionType::SetPitch => self.pitch = value,
+        }
+
+        Ok(())
+    }
+
+    // The memory instructions are shared with the threaded backend, which calls them with its
+    // observer behind a dyn
+    fn store_bcd(&mut self, value: u8, observer: &mut (impl Observer + ?Sized)) -> Result<(), Fault> {
+        if let Some(start) = self.memory_at_i(3)? {
+            self.write_memory(start, &[value / 100, (value / 10) % 10, value % 10], observer);
+        }
+
+        Ok(())
+    }
+
+    fn store_registers(&mut self, last: usize, observer: &mut (impl Observer + ?Sized)) -> Result<(), Fault> {
+        if let Some(start) = self.memory_at_i(last + 1)? {
+            let registers = self.registers;
+            self.write_memory(start, &registers[..=last], observer);
+            if self.quirks.memory_increments_i {
+                self.i = self.i.wrapping_add(last as u16 + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_registers(&mut self, last: usize, observer: &mut (impl Observer + ?Sized)) -> Result<(), Fault> {
+        if let Some(start) = self.memory_at_i(last + 1)? {
+            let mut registers = self.registers;
+            self.read_memory(start, &mut registers[..=last], observer);
+            self.registers = registers;
+            if self.quirks.memory_increments_i {
+                self.i = self.i.wrapping_add(last as u16 + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_two_register(&mut self, instruction: TwoRegisterInstruction) {
+        let x = instruction.Vx as usize;
+        let y = instruction.Vy as usize;
+        let vx = self.registers[x];
+        let vy = self.registers[y];
+
+        match instruction.instruction_type {
+            TwoRegisterInstructionType::SkipEqual => self.skip_if(vx == vy),
+            TwoRegisterInstructionType::SkipNotEqual => self.skip_if(vx != vy),
+            TwoRegisterInstructionType::Set => self.registers[x] = vy,
+            TwoRegisterInstructionType::Or => self.logic_result(x, vx | vy),
+            TwoRegisterInstructionType::And => self.logic_result(x, vx & vy),
+            TwoRegisterInstructionType::ExclusiveOr => self.logic_result(x, vx ^ vy),
+            TwoRegisterInstructionType::Add => {
+                let (sum, carry) = vx.overflowing_add(vy);
+                self.flag_result(x, sum, carry);
+            }
+            TwoRegisterInstructionType::SubtractBorrow => self.flag_result(x, vx.wrapping_sub(vy), vx >= vy),
+            TwoRegisterInstructionType::SubtractNotBorrow => self.flag_result(x, vy.wrapping_sub(vx), vy >= vx),
+            TwoRegisterInstructionType::ShiftRight => self.shift_right(x, y),
+            TwoRegisterInstructionType::ShiftLeft => self.shift_left(x, y),
+        }
+    }
+
+    fn shift_right(&mut self, x: usize, y: usize) {
+        let source = if self.quirks.shift_uses_vy { self.registers[y] } else { self.registers[x] };
+        self.flag_result(x, source >> 1, source & 1 == 1);
+    }
+
+    fn shift_left(&mut self, x: usize, y: usize) {
+        let source = if self.quirks.shift_uses_vy { self.registers[y] } else { self.registers[x] };
+        self.flag_result(x, source << 1, source >> 7 == 1);
+    }
+
+    fn logic_result(&mut self, register: usize, value: u8) {
+        self.registers[register] = value;
+        if self.quirks.vf_reset {
+            self.registers[0xf] = 0;
+        }
+    }
+
+    // VF is written after the result so the flag wins when VF is also the destination
+    fn flag_result(&mut self, register: usize, value: u8, flag: bool) {
+        self.registers[register] = value;
+        self.registers[0xf] = flag as u8;
+    }
+
+    fn execute_draw(&mut self, instruction: DrawInstruction, observer: &mut impl Observer) -> Result<(), Fault> {
+        self.draw(instruction.Vx as usize, instruction.Vy as usize, instruction.height as usize, observer)
+    }
+
+    fn draw(
+        &mut self,
+        vx: usize,
+        vy: usize,
+        height: usize,
+        observer: &mut (impl Observer + ?Sized),
+    ) -> Result<(), Fault> {
+        let x = self.registers[vx];
+        let y = self.registers[vy];
+        let start = match self.memory_at_i(height)? {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let mut sprite = [0; 16];
+        self.read_memory(start, &mut sprite[..height], observer);
+
+        let collision = self.display.draw_sprite(x, y, &sprite[..height]);
+
+        self.registers[0xf] = collision as u8;
+        self.drew_this_frame = true;
+
+        Ok(())
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < length {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+fn edge_mode(quirks: Quirks) -> EdgeMode {
+    if quirks.clip_sprites {
+        EdgeMode::Clip
+    } else {
+        EdgeMode::Wrap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExecError, Interpreter, LoadError, Observer, StateError, FONT_START, MAX_ROM_SIZE, PROGRAM_START, STACK_SIZE};
+    use crate::audio::PATTERN_SIZE;
+    use crate::policy::ErrorClass;
+    use crate::quirks::Quirks;
+    use crate::snapshot::Snapshot;
+
+    fn interpreter_with_program(program: &[u8], quirks: Quirks) -> Interpreter {
+        let mut interpreter = Interpreter::new(quirks, 1);
+        interpreter.load_rom(program).unwrap();
+
+        interpreter
+    }
+
+    fn run_steps(interpreter: &mut Interpreter, steps: usize) {
+        for _ in 0..steps {
+            interpreter.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn load_rom_places_program_after_reserved_memory() {
+        let interpreter = interpreter_with_program(&[0x12, 0x34], Quirks::chip8());
+
+        assert_eq!(interpreter.pc(), PROGRAM_START);
+        assert_eq!(interpreter.memory()[0x200..0x202], [0x12, 0x34]);
+        assert_eq!(interpreter.memory()[FONT_START as usize], 0xf0);
+    }
+
+    #[test]
+    fn load_rom_rejects_oversized_rom() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 1);
+
+        let result = interpreter.load_rom(&vec![0; MAX_ROM_SIZE + 1]);
+
+        assert_eq!(result, Err(LoadError::RomTooLarge(MAX_ROM_SIZE + 1)));
+    }
+
+    fn with_policy(interpreter: &mut Interpreter, policies: &str) {
+        interpreter.set_error_policies(policies.parse().unwrap());
+    }
+
+    #[test]
+    fn stack_errors_leave_the_machine_as_it_was() {
+        // CALL 200 forever
+        let mut overflow = interpreter_with_program(&[0x22, 0x00], Quirks::chip8());
+        let mut underflow = interpreter_with_program(&[0x00, 0xee], Quirks::chip8());
+
+        run_steps(&mut overflow, 16);
+
+        assert_eq!(overflow.step().unwrap_err().class(), ErrorClass::StackOverflow);
+        assert_eq!((overflow.pc(), overflow.cycles(), overflow.sp()), (0x200, 16, 16));
+        assert_eq!(underflow.step().unwrap_err().class(), ErrorClass::StackUnderflow);
+        assert_eq!((underflow.pc(), underflow.cycles()), (0x200, 0));
+    }
+
+    #[test]
+    fn errors_carry_the_pc_and_a_snapshot() {
+        // LD V3, 7; then 5001 isn't an instruction
+        let mut interpreter = interpreter_with_program(&[0x63, 0x07, 0x50, 0x01], Quirks::chip8());
+        interpreter.step().unwrap();
+
+        let error = interpreter.step().unwrap_err();
+
+        assert_eq!(error.pc(), 0x202);
+        assert_eq!(error.snapshot(), &Snapshot::of(&interpreter));
+        assert_eq!(error.snapshot().registers[3], 7);
+        assert_eq!(error.to_string(), "0202: 5001 is not an instruction");
+    }
+
+    #[test]
+    fn memory_past_the_end_through_i_is_an_error() {
+        // LD I, FFE; LD V5, 99; LD B, V5
+        let mut interpreter = interpreter_with_program(&[0xaf, 0xfe, 0x65, 0x63, 0xf5, 0x33], Quirks::chip8());
+
+        run_steps(&mut interpreter, 2);
+
+        match interpreter.step() {
+            Err(ExecError::MemoryOutOfBounds { pc, i, length, .. }) => assert_eq!((pc, i, length), (0x204, 0xffe, 3)),
+            result => panic!("expected MemoryOutOfBounds, got {:?}", result),
+        }
+        assert_eq!(interpreter.memory()[0xffe..], [0, 0]);
+        assert_eq!(interpreter.pc(), 0x204);
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_is_an_error() {
+        // JP FFE, which holds 0000, a SYS that runs on into 1000
+        let mut interpreter = interpreter_with_program(&[0x1f, 0xfe], Quirks::chip8());
+
+        run_steps(&mut interpreter, 2);
+
+        let error = interpreter.step().unwrap_err();
+        assert_eq!((error.class(), error.pc()), (ErrorClass::PcOutOfBounds, 0x1000));
+        assert_eq!(interpreter.run_frame(), Err(error));
+        assert_eq!(interpreter.frames(), 0);
+    }
+
+    #[test]
+    fn invalid_instructions_are_an_error() {
+        let mut interpreter = interpreter_with_program(&[0x50, 0x01], Quirks::chip8());
+
+        let result = interpreter.step();
+
+        match result {
+            Err(ExecError::InvalidInstruction { pc, opcode, .. }) => assert_eq!((pc, opcode), (0x200, 0x5001)),
+            result => panic!("expected InvalidInstruction, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn sys_is_ignored_unless_the_policy_halts() {
+        // SYS 123
+        let mut interpreter = interpreter_with_program(&[0x01, 0x23, 0x01, 0x23], Quirks::chip8());
+
+        interpreter.step().unwrap();
+        with_policy(&mut interpreter, "unsupported_sys=halt");
+
+        match interpreter.step() {
+            Err(ExecError::UnsupportedSys { pc, address, .. }) => assert_eq!((pc, address), (0x202, 0x123)),
+            result => panic!("expected UnsupportedSys, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn wrapped_stack_overflow_forgets_the_oldest_return_address() {
+        // CALL 202; CALL 204; ... seventeen deep
+        let program: Vec<u8> = (1..=17u16).flat_map(|n| (0x2200 + n * 2).to_be_bytes().to_vec()).collect();
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+        with_policy(&mut interpreter, "stack_overflow=wrap");
+
+        run_steps(&mut interpreter, 17);
+
+        assert_eq!(interpreter.sp(), 16);
+        assert_eq!(interpreter.stack()[0], 0x204);
+        assert_eq!(interpreter.stack()[15], 0x222);
+        assert_eq!(interpreter.pc(), 0x222);
+    }
+
+    #[test]
+    fn wrapped_stack_underflow_returns_to_the_top_slot() {
+        // CALL 206; RET; RET; then the subroutine at 206 is RET
+        let mut interpreter = interpreter_with_program(&[0x22, 0x06, 0x00, 0xee, 0x00, 0xee, 0x00, 0xee], Quirks::chip8());
+        with_policy(&mut interpreter, "stack_underflow=wrap");
+        // Leave a return address in the top slot
+        interpreter.stack[STACK_SIZE - 1] = 0x206;
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!((interpreter.pc(), interpreter.sp()), (0x206, 15));
+    }
+
+    #[test]
+    fn ignored_stack_errors_skip_the_instruction() {
+        let mut interpreter = interpreter_with_program(&[0x00, 0xee, 0x63, 0x07], Quirks::chip8());
+        with_policy(&mut interpreter, "stack_underflow=ignore");
+
+        run_steps(&mut interpreter, 2);
+
+        assert_eq!((interpreter.pc(), interpreter.sp(), interpreter.registers()[3]), (0x204, 0, 7));
+    }
+
+    #[test]
+    fn wrapped_memory_access_continues_at_address_zero() {
+        // LD I, FFE; LD V5, 99; LD B, V5; LD I, FFF; DRW V0, V0, 2
+        let program = [0xaf, 0xfe, 0x65, 0x63, 0xf5, 0x33, 0xaf, 0xff, 0xd0, 0x02];
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+        with_policy(&mut interpreter, "memory_out_of_bounds=wrap");
+
+        run_steps(&mut interpreter, 5);
+
+        assert_eq!(interpreter.memory()[0xffe..], [0, 9]);
+        assert_eq!(interpreter.memory()[0], 9);
+        assert_eq!(interpreter.display().rows()[0] >> 56, 0x09);
+        assert_eq!(interpreter.display().rows()[1] >> 56, 0x09);
+    }
+
+    #[test]
+    fn code_rewritten_after_it_ran_executes_the_new_instruction() {
+        // CALL 20E; LD V0, 72; LD V1, 10; LD I, 20E; LD [I], V1; CALL 20E; JP 20C
+        // 20E: ADD V2, 1; RET
+        let program = [
+            0x22, 0x0e, 0x60, 0x72, 0x61, 0x10, 0xa2, 0x0e, 0xf1, 0x55, 0x22, 0x0e, 0x12, 0x0c, 0x72, 0x01, 0x00, 0xee,
+        ];
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+
+        run_steps(&mut interpreter, 10);
+
+        // ADD V2, 1 then the ADD V2, 10 written over it
+        assert_eq!(interpreter.registers()[2], 0x11);
+    }
+
+    #[test]
+    fn load_state_replaces_cached_instructions() {
+        // ADD V2, 1; JP 200
+        let mut interpreter = interpreter_with_program(&[0x72, 0x01, 0x12, 0x00], Quirks::chip8());
+        let mut other = interpreter_with_program(&[0x72, 0x05, 0x12, 0x00], Quirks::chip8());
+        run_steps(&mut interpreter, 2);
+
+        interpreter.load_state(&other.save_state()).unwrap();
+        run_steps(&mut interpreter, 1);
+        run_steps(&mut other, 1);
+
+        assert_eq!(interpreter.registers()[2], 5);
+        assert_eq!(interpreter.state_hash(), other.state_hash());
+    }
+
+    #[test]
+    fn ignored_memory_access_does_nothing() {
+        // LD I, FFE; LD V5, 99; LD B, V5; LD V0, [I]
+        let program = [0xaf, 0xfe, 0x65, 0x63, 0xf5, 0x33, 0xff, 0x65];
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+        with_policy(&mut interpreter, "memory_out_of_bounds=ignore");
+
+        run_steps(&mut interpreter, 4);
+
+        assert_eq!(interpreter.memory()[0xffe..], [0, 0]);
+        assert_eq!(interpreter.registers()[5], 99);
+        assert_eq!((interpreter.i(), interpreter.pc()), (0xffe, 0x208));
+    }
+
+    #[test]
+    fn wrapped_pc_continues_at_address_zero() {
+        // JP FFE, which holds 0000, a SYS that runs on into 1000
+        let mut interpreter = interpreter_with_program(&[0x1f, 0xfe], Quirks::chip8());
+        with_policy(&mut interpreter, "pc_out_of_bounds=wrap");
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!(interpreter.pc(), 0x002);
+    }
+
+    #[test]
+    fn ignored_pc_idles_with_the_timers_running() {
+        // LD V0, 5; LD DT, V0; JP FFE
+        let mut interpreter = interpreter_with_program(&[0x60, 0x05, 0xf0, 0x15, 0x1f, 0xfe], Quirks::chip8());
+        with_policy(&mut interpreter, "pc_out_of_bounds=ignore");
+
+        for _ in 0..3 {
+            interpreter.run_frame().unwrap();
+        }
+
+        assert_eq!(interpreter.pc(), 0x1000);
+        assert_eq!(interpreter.delay_timer(), 2);
+        assert_eq!(interpreter.cycles(), 30);
+    }
+
+    #[test]
+    fn ignored_invalid_instructions_are_skipped() {
+        let mut interpreter = interpreter_with_program(&[0x50, 0x01, 0x63, 0x07], Quirks::chip8());
+        with_policy(&mut interpreter, "invalid_instruction=ignore");
+
+        run_steps(&mut interpreter, 2);
+
+        assert_eq!((interpreter.pc(), interpreter.registers()[3]), (0x204, 7));
+    }
+
+    #[test]
+    fn optional_and_paired_observers_all_see_each_step() {
+        struct Counter(usize);
+        impl Observer for Counter {
+            fn before_step(&mut self, _interpreter: &Interpreter, _opcode: u16) {
+                self.0 += 1;
+            }
+        }
+        let mut interpreter = interpreter_with_program(&[0x12, 0x00], Quirks::chip8());
+        let mut observers = (Counter(0), (Some(Counter(0)), None::<Counter>));
+
+        interpreter.run_frame_with(&mut observers).unwrap();
+
+        assert_eq!(observers.0 .0, 10);
+        assert_eq!((observers.1 .0).unwrap().0, 10);
+    }
+
+    #[test]
+    fn call_and_return_use_the_stack() {
+        let mut interpreter = interpreter_with_program(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xee], Quirks::chip8());
+
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.pc(), 0x204);
+        assert_eq!(interpreter.stack(), &[0x202]);
+
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.pc(), 0x202);
+        assert_eq!(interpreter.sp(), 0);
+    }
+
+    #[test]
+    fn skip_equal_skips_next_instruction() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x05, 0x30, 0x05], Quirks::chip8());
+
+        run_steps(&mut interpreter, 2);
+
+        assert_eq!(interpreter.pc(), 0x206);
+    }
+
+    #[test]
+    fn add_sets_carry_flag() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0xff, 0x61, 0x02, 0x80, 0x14], Quirks::chip8());
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!(interpreter.registers()[0x0], 0x01);
+        assert_eq!(interpreter.registers()[0xf], 1);
+    }
+
+    #[test]
+    fn subtract_sets_not_borrow_flag() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x01, 0x61, 0x02, 0x80, 0x15], Quirks::chip8());
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!(interpreter.registers()[0x0], 0xff);
+        assert_eq!(interpreter.registers()[0xf], 0);
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        let mut interpreter = interpreter_with_program(&[0x6f, 0xff, 0x61, 0x02, 0x8f, 0x14], Quirks::chip8());
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!(interpreter.registers()[0xf], 1);
+    }
+
+    #[test]
+    fn shift_right_respects_shift_quirk() {
+        let program = [0x60, 0x04, 0x61, 0x03, 0x80, 0x16];
+        let mut uses_vy = interpreter_with_program(&program, Quirks::chip8());
+        let mut in_place = interpreter_with_program(&program, Quirks::schip());
+
+        run_steps(&mut uses_vy, 3);
+        run_steps(&mut in_place, 3);
+
+        assert_eq!((uses_vy.registers()[0x0], uses_vy.registers()[0xf]), (0x01, 1));
+        assert_eq!((in_place.registers()[0x0], in_place.registers()[0xf]), (0x02, 0));
+    }
+
+    #[test]
+    fn logic_respects_vf_reset_quirk() {
+        let program = [0x6f, 0x07, 0x80, 0x11];
+        let mut reset = interpreter_with_program(&program, Quirks::chip8());
+        let mut kept = interpreter_with_program(&program, Quirks::schip());
+
+        run_steps(&mut reset, 2);
+        run_steps(&mut kept, 2);
+
+        assert_eq!(reset.registers()[0xf], 0);
+        assert_eq!(kept.registers()[0xf], 7);
+    }
+
+    #[test]
+    fn jump_add_respects_jump_quirk() {
+        let program = [0x60, 0x10, 0x63, 0x20, 0xb3, 0x00];
+        let mut uses_v0 = interpreter_with_program(&program, Quirks::chip8());
+        let mut uses_vx = interpreter_with_program(&program, Quirks::schip());
+
+        run_steps(&mut uses_v0, 3);
+        run_steps(&mut uses_vx, 3);
+
+        assert_eq!(uses_v0.pc(), 0x310);
+        assert_eq!(uses_vx.pc(), 0x320);
+    }
+
+    #[test]
+    fn store_bcd_writes_decimal_digits() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0xfe, 0xa3, 0x00, 0xf0, 0x33], Quirks::chip8());
+
+        run_steps(&mut interpreter, 3);
+
+        assert_eq!(interpreter.memory()[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn store_and_read_registers_respect_memory_quirk() {
+        let program = [0x60, 0x0a, 0x61, 0x0b, 0xa3, 0x00, 0xf1, 0x55];
+        let mut increments = interpreter_with_program(&program, Quirks::chip8());
+        let mut unchanged = interpreter_with_program(&program, Quirks::schip());
+
+        run_steps(&mut increments, 4);
+        run_steps(&mut unchanged, 4);
+
+        assert_eq!(increments.memory()[0x300..0x302], [0x0a, 0x0b]);
+        assert_eq!(increments.i(), 0x302);
+        assert_eq!(unchanged.i(), 0x300);
+    }
+
+    #[test]
+    fn draw_xors_sprite_and_reports_collision() {
+        // Draw the font sprite for 0 twice at the same spot
+        let mut interpreter = interpreter_with_program(&[0xf0, 0x29, 0xd0, 0x05, 0xd0, 0x05], Quirks::chip8());
+
+        run_steps(&mut interpreter, 2);
+        assert!(interpreter.display().pixel(0, 0));
+        assert!(!interpreter.display().pixel(1, 1));
+        assert_eq!(interpreter.registers()[0xf], 0);
+
+        interpreter.step().unwrap();
+        assert!(interpreter.display().rows().iter().all(|row| *row == 0));
+        assert_eq!(interpreter.registers()[0xf], 1);
+    }
+
+    #[test]
+    fn draw_clips_or_wraps_at_the_edge() {
+        let program = [0x60, 0x3e, 0x61, 0x1e, 0xf2, 0x29, 0xd0, 0x15];
+        let mut clipped = interpreter_with_program(&program, Quirks::chip8());
+        let mut wrapped = interpreter_with_program(&program, Quirks::xochip());
+
+        run_steps(&mut clipped, 4);
+        run_steps(&mut wrapped, 4);
+
+        assert!(clipped.display().pixel(63, 30));
+        assert!(!clipped.display().pixel(0, 30));
+        assert!(!clipped.display().pixel(62, 0));
+        assert!(wrapped.display().pixel(0, 30));
+        assert!(wrapped.display().pixel(62, 0));
+    }
+
+    #[test]
+    fn skip_pressed_reads_keys() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x0a, 0xe0, 0x9e], Quirks::chip8());
+        interpreter.set_keys(1 << 0xa);
+
+        run_steps(&mut interpreter, 2);
+
+        assert_eq!(interpreter.pc(), 0x206);
+    }
+
+    #[test]
+    fn wait_for_key_press_blocks_until_a_key_is_released() {
+        let mut interpreter = interpreter_with_program(&[0xf3, 0x0a], Quirks::chip8());
+
+        run_steps(&mut interpreter, 5);
+        assert_eq!(interpreter.pc(), 0x200);
+
+        interpreter.keypad_mut().press(0x7);
+        run_steps(&mut interpreter, 5);
+        assert_eq!(interpreter.pc(), 0x200);
+
+        // Still down until the frame it was pressed in is over
+        interpreter.keypad_mut().release(0x7);
+        interpreter.run_frame().unwrap();
+        assert_eq!(interpreter.pc(), 0x200);
+
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.pc(), 0x202);
+        assert_eq!(interpreter.registers()[0x3], 0x7);
+    }
+
+    #[test]
+    fn skip_pressed_sees_keys_tapped_between_frames() {
+        // 200: LD V0, A
+        // 202: SKNP V0
+        // 204: LD V1, 1
+        // 206: JP 206
+        let program = [0x60, 0x0a, 0xe0, 0xa1, 0x61, 0x01, 0x12, 0x06];
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+
+        interpreter.keypad_mut().press(0xa);
+        interpreter.keypad_mut().release(0xa);
+        interpreter.run_frame().unwrap();
+
+        assert_eq!(interpreter.registers()[0x1], 1);
+        assert!(!interpreter.keypad().is_down(0xa));
+    }
+
+    #[test]
+    fn run_frame_ticks_timers_once() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x03, 0xf0, 0x15, 0xf0, 0x18, 0x12, 0x06], Quirks::chip8());
+
+        interpreter.run_frame().unwrap();
+
+        assert_eq!(interpreter.delay_timer(), 2);
+        assert_eq!(interpreter.sound_timer(), 2);
+        assert!(interpreter.sound_active());
+        assert_eq!(interpreter.frames(), 1);
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame_after_a_draw() {
+        let program = [0xd0, 0x01, 0xd0, 0x01, 0x12, 0x00];
+        let mut waits = interpreter_with_program(&program, Quirks::chip8());
+        let mut no_wait = interpreter_with_program(&program, Quirks::xochip());
+
+        waits.run_frame().unwrap();
+        no_wait.run_frame().unwrap();
+
+        assert_eq!(waits.cycles(), 1);
+        assert_eq!(no_wait.cycles(), 10);
+    }
+
+    #[test]
+    fn random_numbers_follow_the_seed() {
+        let program = [0xc0, 0xff, 0xc1, 0xff];
+        let mut first = Interpreter::new(Quirks::chip8(), 42);
+        let mut second = Interpreter::new(Quirks::chip8(), 42);
+        first.load_rom(&program).unwrap();
+        second.load_rom(&program).unwrap();
+
+        run_steps(&mut first, 2);
+        run_steps(&mut second, 2);
+
+        assert_eq!(first.registers(), second.registers());
+        assert_eq!(first.state_hash(), second.state_hash());
+    }
+
+    #[test]
+    fn reset_restores_power_on_state() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x05, 0xc1, 0xff], Quirks::chip8());
+        let initial_hash = interpreter.state_hash();
+
+        run_steps(&mut interpreter, 2);
+        assert_ne!(interpreter.state_hash(), initial_hash);
+
+        interpreter.reset();
+        assert_eq!(interpreter.state_hash(), initial_hash);
+    }
+
+    #[test]
+    fn fill_audio_sounds_while_the_sound_timer_runs() {
+        // LD V0, 2; LD ST, V0; JP 204
+        let mut interpreter = interpreter_with_program(&[0x60, 0x02, 0xf0, 0x18, 0x12, 0x04], Quirks::chip8());
+        let mut buffer = [0.0; 735];
+
+        interpreter.fill_audio(&mut buffer, 44100);
+        assert!(buffer.iter().all(|sample| *sample == 0.0));
+
+        interpreter.run_frame().unwrap();
+        interpreter.fill_audio(&mut buffer, 44100);
+        assert!(buffer.iter().any(|sample| *sample != 0.0));
+
+        interpreter.run_frame().unwrap();
+        interpreter.fill_audio(&mut buffer, 44100);
+        assert_eq!(buffer[734], 0.0);
+    }
+
+    #[test]
+    fn fill_audio_plays_the_xo_chip_pattern() {
+        // LD I, 20A; AUDIO; LD V0, 112; LD PITCH, V0; LD ST, V0; then the pattern at 0x20A
+        let mut program = vec![0xa2, 0x0a, 0xf0, 0x02, 0x60, 0x70, 0xf0, 0x3a, 0xf0, 0x18];
+        program.extend_from_slice(&[0xf0; PATTERN_SIZE]);
+        let mut interpreter = interpreter_with_program(&program, Quirks::xochip());
+        run_steps(&mut interpreter, 5);
+        let mut buffer = [0.0; 400];
+
+        interpreter.fill_audio(&mut buffer, 4000);
+
+        assert_eq!(interpreter.audio_pattern(), Some(&[0xf0; PATTERN_SIZE]));
+        assert_eq!(interpreter.pitch(), 112);
+        // Two bits a sample once the start ramp is done, so the 1111 0000 pattern is a 1kHz square
+        let volume = interpreter.buzzer().volume();
+        assert_eq!(&buffer[100..104], &[volume, volume, -volume, -volume]);
+    }
+
+    #[test]
+    fn load_state_restores_saved_machine() {
+        let program = [0x60, 0x05, 0xc1, 0xff, 0xf0, 0x29, 0xd0, 0x15, 0x12, 0x02];
+        let mut interpreter = interpreter_with_program(&program, Quirks::schip());
+        interpreter.run_frame().unwrap();
+        let state = interpreter.save_state();
+        let saved_hash = interpreter.state_hash();
+        interpreter.run_frame().unwrap();
+
+        let mut restored = Interpreter::new(Quirks::chip8(), 7);
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.state_hash(), saved_hash);
+        assert_eq!(restored.quirks(), Quirks::schip());
+        restored.run_frame().unwrap();
+        assert_eq!(restored.state_hash(), interpreter.state_hash());
+    }
+
+    #[test]
+    fn load_state_accepts_version_1_states() {
+        let mut interpreter = interpreter_with_program(&[0x60, 0x05, 0x12, 0x02], Quirks::chip8());
+        interpreter.run_frame().unwrap();
+        let mut state = interpreter.save_state();
+        state.truncate(state.len() - 6 - PATTERN_SIZE - 2);
+        state[4] = 1;
+
+        let mut restored = Interpreter::new(Quirks::chip8(), 0);
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.state_hash(), interpreter.state_hash());
+    }
+
+    #[test]
+    fn load_state_keeps_keys_tapped_between_frames() {
+        let program = [0x60, 0x0a, 0xe0, 0xa1, 0x61, 0x01, 0x12, 0x06];
+        let mut interpreter = interpreter_with_program(&program, Quirks::chip8());
+        interpreter.keypad_mut().press(0xa);
+        interpreter.keypad_mut().release(0xa);
+        let state = interpreter.save_state();
+        let mut version_2 = state.clone();
+        version_2.truncate(state.len() - 6);
+        version_2[4] = 2;
+
+        let mut restored = Interpreter::new(Quirks::chip8(), 0);
+        restored.load_state(&state).unwrap();
+        restored.run_frame().unwrap();
+        let mut old = Interpreter::new(Quirks::chip8(), 0);
+        old.load_state(&version_2).unwrap();
+        old.run_frame().unwrap();
+
+        assert_eq!(restored.registers()[0x1], 1);
+        assert_eq!(old.registers()[0x1], 0);
+    }
+
+    #[test]
+    fn load_state_rejects_bad_data() {
+        let mut interpreter = interpreter_with_program(&[0x12, 0x00], Quirks::chip8());
+        let state = interpreter.save_state();
+        let hash = interpreter.state_hash();
+
+        assert_eq!(interpreter.load_state(b"nope"), Err(StateError::NotAState));
+        assert_eq!(interpreter.load_state(&state[..100]), Err(StateError::Truncated));
+        assert_eq!(interpreter.state_hash(), hash);
+    }
+}
@@ -0,0 +1,601 @@
+// Threaded code backend. Instead of fetching and decoding one instruction at a time, the code is
+// translated a basic block at a time into a list of ops with their operands already pulled out.
+// Most instructions get a handler of their own, called through a function pointer, which shares the
+// interpreter's methods for anything with quirks, error policies or memory access. The rare ones,
+// such as waiting for a key, go through the interpreter's execute. Blocks are found by following
+// jumps, calls and both sides of every skip from the block being entered, and a write to memory
+// inside a block throws it away.
+use super::{ExecError, Fault, Interpreter, Observer, FONT_START, MEMORY_SIZE};
+use crate::instruction::{
+    AddressInstructionType,
+    Instruction,
+    NoArgInstructionType,
+    RegisterByteInstructionType,
+    SingleRegisterInstructionType,
+    TwoRegisterInstructionType,
+};
+use std::ops::Range;
+
+// Long straight runs of code are split so a jump into the middle doesn't retranslate all of it
+const MAX_BLOCK_LENGTH: usize = 64;
+// Self-modifying code leaves stale ops behind, so start again once this many have built up
+const MAX_OPS: usize = 1 << 16;
+const NO_BLOCK: u32 = u32::MAX;
+
+type Handler = fn(&mut Interpreter, &Op, &mut dyn Observer) -> Result<(), Fault>;
+
+#[derive(Clone, Copy)]
+struct Op {
+    instruction: Instruction,
+    opcode: u16,
+    handler: Option<Handler>,
+    x: usize,
+    y: usize,
+    value: u16,
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    start: usize,
+    end: usize,
+    ops: Range<usize>,
+}
+
+#[derive(Clone)]
+pub(super) struct Blocks {
+    ops: Vec<Op>,
+    blocks: Vec<Option<Block>>,
+    starts: Vec<u32>,   // the block starting at each address
+    coverage: Vec<u16>, // how many blocks each byte of memory is part of
+    generation: u64,    // changes whenever blocks are thrown away
+}
+
+impl Blocks {
+    pub(super) fn new() -> Blocks {
+        Blocks {
+            ops: Vec::new(),
+            blocks: Vec::new(),
+            starts: vec![NO_BLOCK; MEMORY_SIZE],
+            coverage: vec![0; MEMORY_SIZE],
+            generation: 0,
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.ops.clear();
+        self.blocks.clear();
+        self.starts.fill(NO_BLOCK);
+        self.coverage.fill(0);
+        self.generation += 1;
+    }
+
+    // Throws away every block containing the byte
+    pub(super) fn invalidate(&mut self, address: usize) {
+        if self.coverage[address] == 0 {
+            return;
+        }
+
+        for slot in self.blocks.iter_mut() {
+            if let Some(block) = slot {
+                if (block.start..block.end).contains(&address) {
+                    self.starts[block.start] = NO_BLOCK;
+                    for byte in block.start..block.end {
+                        self.coverage[byte] -= 1;
+                    }
+                    *slot = None;
+                }
+            }
+        }
+        self.generation += 1;
+    }
+
+    // The ops of the block starting at address, translating it and the blocks reachable from it if
+    // it hasn't been seen yet. None where there is nothing to translate, such as an invalid opcode
+    // or the end of memory, which the interpreter deals with instead.
+    fn lookup(&mut self, address: usize, memory: &[u8; MEMORY_SIZE]) -> Option<Range<usize>> {
+        if address + 1 >= MEMORY_SIZE {
+            return None;
+        }
+        if self.starts[address] == NO_BLOCK {
+            if self.ops.len() > MAX_OPS {
+                self.clear();
+            }
+            self.translate(address, memory);
+        }
+
+        match self.starts[address] {
+            NO_BLOCK => None,
+            index => self.blocks[index as usize].as_ref().map(|block| block.ops.clone()),
+        }
+    }
+
+    fn translate(&mut self, address: usize, memory: &[u8; MEMORY_SIZE]) {
+        let mut pending = vec![address];
+
+        while let Some(start) = pending.pop() {
+            if start + 1 >= MEMORY_SIZE || self.starts[start] != NO_BLOCK {
+                continue;
+            }
+
+            let first_op = self.ops.len();
+            let mut address = start;
+            while address + 1 < MEMORY_SIZE && self.ops.len() - first_op < MAX_BLOCK_LENGTH {
+                let instruction = match Instruction::decode((memory[address], memory[address + 1])) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                self.ops.push(Op::new(instruction, u16::from_be_bytes([memory[address], memory[address + 1]])));
+                address += 2;
+
+                if let Some(successors) = successors(&instruction, address) {
+                    pending.extend(successors);
+                    break;
+                }
+            }
+            if address == start {
+                continue;
+            }
+            // A block cut short by its length or an invalid opcode carries on from where it stopped
+            if !ends_block(&self.ops[self.ops.len() - 1].instruction) {
+                pending.push(address);
+            }
+
+            self.starts[start] = self.blocks.len() as u32;
+            self.blocks.push(Some(Block { start, end: address, ops: first_op..self.ops.len() }));
+            for byte in start..address {
+                self.coverage[byte] += 1;
+            }
+        }
+    }
+}
+
+// Printing every op would bury the rest of the interpreter in a Debug dump
+impl std::fmt::Debug for Blocks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let blocks = self.blocks.iter().filter(|block| block.is_some()).count();
+        write!(f, "Blocks {{ blocks: {}, ops: {} }}", blocks, self.ops.len())
+    }
+}
+
+impl Op {
+    fn new(instruction: Instruction, opcode: u16) -> Op {
+        let mut op = Op { instruction, opcode, handler: None, x: 0, y: 0, value: 0 };
+
+        match instruction {
+            Instruction::AddressInstruction(instruction) => {
+                op.value = instruction.address;
+                op.handler = match instruction.instruction_type {
+                    AddressInstructionType::JumpDirect => Some(jump),
+                    AddressInstructionType::Call => Some(call),
+                    AddressInstructionType::SetI => Some(set_i),
+                    _ => None,
+                };
+            }
+            Instruction::RegisterByteInstruction(instruction) => {
+                op.x = instruction.register as usize;
+                op.value = instruction.byte as u16;
+                op.handler = match instruction.instruction_type {
+                    RegisterByteInstructionType::SkipEqual => Some(skip_equal_byte),
+                    RegisterByteInstructionType::SkipNotEqual => Some(skip_not_equal_byte),
+                    RegisterByteInstructionType::Set => Some(set_byte),
+                    RegisterByteInstructionType::Add => Some(add_byte),
+                    RegisterByteInstructionType::RandAnd => Some(rand_and),
+                };
+            }
+            Instruction::TwoRegisterInstruction(instruction) => {
+                op.x = instruction.Vx as usize;
+                op.y = instruction.Vy as usize;
+                op.handler = match instruction.instruction_type {
+                    TwoRegisterInstructionType::SkipEqual => Some(skip_equal_registers),
+                    TwoRegisterInstructionType::SkipNotEqual => Some(skip_not_equal_registers),
+                    TwoRegisterInstructionType::Set => Some(set_register),
+                    TwoRegisterInstructionType::Or => Some(or),
+                    TwoRegisterInstructionType::And => Some(and),
+                    TwoRegisterInstructionType::ExclusiveOr => Some(exclusive_or),
+                    TwoRegisterInstructionType::Add => Some(add_registers),
+                    TwoRegisterInstructionType::SubtractBorrow => Some(subtract),
+                    TwoRegisterInstructionType::SubtractNotBorrow => Some(subtract_reversed),
+                    TwoRegisterInstructionType::ShiftRight => Some(shift_right),
+                    TwoRegisterInstructionType::ShiftLeft => Some(shift_left),
+                };
+            }
+            Instruction::SingleRegisterInstruction(instruction) => {
+                op.x = instruction.register as usize;
+                op.handler = match instruction.instruction_type {
+                    SingleRegisterInstructionType::SkipPressed => Some(skip_pressed),
+                    SingleRegisterInstructionType::SkipNotPressed => Some(skip_not_pressed),
+                    SingleRegisterInstructionType::ReadDelayTimer => Some(read_delay_timer),
+                    SingleRegisterInstructionType::SetDelayTimer => Some(set_delay_timer),
+                    SingleRegisterInstructionType::SetSoundTimer => Some(set_sound_timer),
+                    SingleRegisterInstructionType::AddI => Some(add_i),
+                    SingleRegisterInstructionType::LoadSprite => Some(load_sprite),
+                    SingleRegisterInstructionType::StoreBCD => Some(store_bcd),
+                    SingleRegisterInstructionType::StoreRegisters => Some(store_registers),
+                    SingleRegisterInstructionType::ReadToRegisters => Some(read_registers),
+                    _ => None,
+                };
+            }
+            Instruction::DrawInstruction(instruction) => {
+                op.x = instruction.Vx as usize;
+                op.y = instruction.Vy as usize;
+                op.value = instruction.height as u16;
+                op.handler = Some(draw);
+            }
+            Instruction::NoArgInstruction(instruction_type) => {
+                if instruction_type == NoArgInstructionType::Return {
+                    op.handler = Some(return_from_call);
+                }
+            }
+        }
+
+        op
+    }
+}
+
+fn jump(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.pc = op.value;
+    Ok(())
+}
+
+fn call(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.call(op.value)
+}
+
+fn return_from_call(interpreter: &mut Interpreter, _: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.return_from_call()
+}
+
+fn set_i(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.i = op.value;
+    Ok(())
+}
+
+fn skip_equal_byte(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(interpreter.registers[op.x] as u16 == op.value);
+    Ok(())
+}
+
+fn skip_not_equal_byte(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(interpreter.registers[op.x] as u16 != op.value);
+    Ok(())
+}
+
+fn set_byte(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.registers[op.x] = op.value as u8;
+    Ok(())
+}
+
+fn add_byte(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.registers[op.x] = interpreter.registers[op.x].wrapping_add(op.value as u8);
+    Ok(())
+}
+
+fn rand_and(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.registers[op.x] = interpreter.rng.next_u8() & op.value as u8;
+    Ok(())
+}
+
+fn skip_equal_registers(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(interpreter.registers[op.x] == interpreter.registers[op.y]);
+    Ok(())
+}
+
+fn skip_not_equal_registers(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(interpreter.registers[op.x] != interpreter.registers[op.y]);
+    Ok(())
+}
+
+fn set_register(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.registers[op.x] = interpreter.registers[op.y];
+    Ok(())
+}
+
+fn add_registers(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    let (sum, carry) = interpreter.registers[op.x].overflowing_add(interpreter.registers[op.y]);
+    interpreter.flag_result(op.x, sum, carry);
+    Ok(())
+}
+
+fn or(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.logic_result(op.x, interpreter.registers[op.x] | interpreter.registers[op.y]);
+    Ok(())
+}
+
+fn and(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.logic_result(op.x, interpreter.registers[op.x] & interpreter.registers[op.y]);
+    Ok(())
+}
+
+fn exclusive_or(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.logic_result(op.x, interpreter.registers[op.x] ^ interpreter.registers[op.y]);
+    Ok(())
+}
+
+fn subtract(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    let (vx, vy) = (interpreter.registers[op.x], interpreter.registers[op.y]);
+    interpreter.flag_result(op.x, vx.wrapping_sub(vy), vx >= vy);
+    Ok(())
+}
+
+fn subtract_reversed(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    let (vx, vy) = (interpreter.registers[op.x], interpreter.registers[op.y]);
+    interpreter.flag_result(op.x, vy.wrapping_sub(vx), vy >= vx);
+    Ok(())
+}
+
+fn shift_right(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.shift_right(op.x, op.y);
+    Ok(())
+}
+
+fn shift_left(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.shift_left(op.x, op.y);
+    Ok(())
+}
+
+fn skip_pressed(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(interpreter.keypad.is_down(interpreter.registers[op.x]));
+    Ok(())
+}
+
+fn skip_not_pressed(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.skip_if(!interpreter.keypad.is_down(interpreter.registers[op.x]));
+    Ok(())
+}
+
+fn read_delay_timer(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.registers[op.x] = interpreter.delay_timer;
+    Ok(())
+}
+
+fn set_delay_timer(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.delay_timer = interpreter.registers[op.x];
+    Ok(())
+}
+
+fn set_sound_timer(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.sound_timer = interpreter.registers[op.x];
+    Ok(())
+}
+
+fn add_i(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.i = interpreter.i.wrapping_add(interpreter.registers[op.x] as u16);
+    Ok(())
+}
+
+fn load_sprite(interpreter: &mut Interpreter, op: &Op, _: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.i = FONT_START + (interpreter.registers[op.x] & 0xf) as u16 * 5;
+    Ok(())
+}
+
+fn store_bcd(interpreter: &mut Interpreter, op: &Op, observer: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.store_bcd(interpreter.registers[op.x], observer)
+}
+
+fn store_registers(interpreter: &mut Interpreter, op: &Op, observer: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.store_registers(op.x, observer)
+}
+
+fn read_registers(interpreter: &mut Interpreter, op: &Op, observer: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.read_registers(op.x, observer)
+}
+
+fn draw(interpreter: &mut Interpreter, op: &Op, observer: &mut dyn Observer) -> Result<(), Fault> {
+    interpreter.draw(op.x, op.y, op.value as usize, observer)
+}
+
+// Where control can go after an instruction that ends a block, or None if execution carries on
+// to the next instruction
+fn successors(instruction: &Instruction, next: usize) -> Option<Vec<usize>> {
+    if !ends_block(instruction) {
+        return None;
+    }
+
+    let successors = match instruction {
+        Instruction::AddressInstruction(instruction) => match instruction.instruction_type {
+            AddressInstructionType::JumpDirect => vec![instruction.address as usize],
+            AddressInstructionType::Call => vec![instruction.address as usize, next],
+            _ => vec![],
+        },
+        Instruction::SingleRegisterInstruction(instruction)
+            if instruction.instruction_type == SingleRegisterInstructionType::WaitForKeyPress =>
+        {
+            vec![next]
+        }
+        // The skips, which go to the next instruction or the one after
+        Instruction::RegisterByteInstruction(_) | Instruction::TwoRegisterInstruction(_) | Instruction::SingleRegisterInstruction(_) => {
+            vec![next, next + 2]
+        }
+        _ => vec![],
+    };
+
+    Some(successors)
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::NoArgInstruction(instruction_type) => *instruction_type == NoArgInstructionType::Return,
+        Instruction::AddressInstruction(instruction) => matches!(
+            instruction.instruction_type,
+            AddressInstructionType::JumpDirect | AddressInstructionType::Call | AddressInstructionType::JumpAddV0
+        ),
+        Instruction::RegisterByteInstruction(instruction) => matches!(
+            instruction.instruction_type,
+            RegisterByteInstructionType::SkipEqual | RegisterByteInstructionType::SkipNotEqual
+        ),
+        Instruction::TwoRegisterInstruction(instruction) => matches!(
+            instruction.instruction_type,
+            TwoRegisterInstructionType::SkipEqual | TwoRegisterInstructionType::SkipNotEqual
+        ),
+        Instruction::SingleRegisterInstruction(instruction) => matches!(
+            instruction.instruction_type,
+            SingleRegisterInstructionType::SkipPressed
+                | SingleRegisterInstructionType::SkipNotPressed
+                | SingleRegisterInstructionType::WaitForKeyPress
+        ),
+        Instruction::DrawInstruction(_) => false,
+    }
+}
+
+impl Interpreter {
+    // Runs up to budget instructions through the blocks, stopping early for a draw when the
+    // display_wait quirk is on, the same as the interpreter's frame loop
+    pub(super) fn run_threaded(&mut self, budget: u32, observer: &mut impl Observer) -> Result<(), ExecError> {
+        let mut remaining = budget;
+
+        while remaining > 0 {
+            remaining -= self.run_block(remaining, observer)?;
+            if self.quirks.display_wait && self.drew_this_frame {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the block at pc, or one instruction through the interpreter if there is no block there.
+    // Returns how many instructions ran.
+    fn run_block(&mut self, budget: u32, observer: &mut impl Observer) -> Result<u32, ExecError> {
+        let blocks = self.blocks.as_mut().unwrap();
+        let generation = blocks.generation;
+        let ops = match blocks.lookup(self.pc as usize, &self.memory) {
+            Some(ops) => ops,
+            None => {
+                self.step_interpreted(observer)?;
+                return Ok(1);
+            }
+        };
+
+        // The ops are taken out while the block runs so they aren't borrowed from self. Running ops
+        // can throw blocks away but never touches the ops themselves.
+        let all_ops = std::mem::take(&mut self.blocks.as_mut().unwrap().ops);
+        let result = self.run_ops(&all_ops[ops], budget, generation, observer);
+        self.blocks.as_mut().unwrap().ops = all_ops;
+
+        result
+    }
+
+    fn run_ops(
+        &mut self,
+        ops: &[Op],
+        budget: u32,
+        generation: u64,
+        observer: &mut impl Observer,
+    ) -> Result<u32, ExecError> {
+        let mut executed = 0;
+        for op in ops {
+            let (pc, cycles) = (self.pc, self.cycles);
+            observer.before_step(self, op.opcode);
+            self.pc += 2;
+            self.cycles = self.cycles.wrapping_add(1);
+            let result = match op.handler {
+                Some(handler) => handler(self, op, observer),
+                None => self.execute(op.instruction, observer),
+            };
+            if let Err(fault) = result {
+                self.pc = pc;
+                self.cycles = cycles;
+                return Err(self.exec_error(fault));
+            }
+
+            executed += 1;
+            // Stop if this block wrote over itself, as the rest of it may no longer be right
+            let ended = executed == budget || self.blocks.as_ref().unwrap().generation != generation;
+            if ended || (self.quirks.display_wait && self.drew_this_frame) {
+                break;
+            }
+        }
+
+        Ok(executed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Blocks;
+    use crate::interpreter::{Backend, Interpreter, Observer, MEMORY_SIZE};
+    use crate::quirks::Quirks;
+
+    fn memory_with(program: &[u8]) -> [u8; MEMORY_SIZE] {
+        let mut memory = [0; MEMORY_SIZE];
+        memory[0x200..0x200 + program.len()].copy_from_slice(program);
+
+        memory
+    }
+
+    #[test]
+    fn blocks_are_found_by_following_jumps_calls_and_skips() {
+        // 200: SE V0, 0; JP 208; CALL 20C; JP 200
+        // 20C: ADD V1, 1; RET
+        let memory = memory_with(&[0x30, 0x00, 0x12, 0x08, 0x22, 0x0c, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x71, 0x01, 0x00, 0xee]);
+        let mut blocks = Blocks::new();
+
+        assert_eq!(blocks.lookup(0x200, &memory), Some(0..1));
+
+        let starts: Vec<usize> = (0..MEMORY_SIZE).filter(|address| blocks.starts[*address] != super::NO_BLOCK).collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20c]);
+    }
+
+    #[test]
+    fn writing_into_a_block_throws_it_away() {
+        // ADD V0, 1; JP 200
+        let memory = memory_with(&[0x70, 0x01, 0x12, 0x00]);
+        let mut blocks = Blocks::new();
+        blocks.lookup(0x200, &memory);
+
+        blocks.invalidate(0x300);
+        assert!(blocks.starts[0x200] != super::NO_BLOCK);
+        blocks.invalidate(0x203);
+
+        assert_eq!(blocks.starts[0x200], super::NO_BLOCK);
+        assert_eq!(blocks.coverage[0x200], 0);
+    }
+
+    #[test]
+    fn a_block_that_rewrites_its_own_next_instruction_runs_the_new_one() {
+        // LD V0, 72; LD V1, 05; LD I, 208; LD [I], V1; ADD V2, 1; JP 20A
+        let program = [0x60, 0x72, 0x61, 0x05, 0xa2, 0x08, 0xf1, 0x55, 0x72, 0x01, 0x12, 0x0a];
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 1);
+        interpreter.set_backend(Backend::Threaded);
+        interpreter.load_rom(&program).unwrap();
+
+        interpreter.run_frame().unwrap();
+
+        // The store put ADD V2, 5 where ADD V2, 1 was
+        assert_eq!(interpreter.registers()[2], 5);
+    }
+
+    #[test]
+    fn memory_handlers_report_to_the_observer() {
+        #[derive(Debug, Default, PartialEq)]
+        struct Accesses {
+            read: Vec<u16>,
+            written: Vec<u16>,
+        }
+        impl Observer for Accesses {
+            fn memory_read(&mut self, address: u16, _value: u8) {
+                self.read.push(address);
+            }
+
+            fn memory_written(&mut self, address: u16, _value: u8) {
+                self.written.push(address);
+            }
+        }
+        // LD V0, FF; LD I, 300; LD B, V0; LD V1, [I]; LD [I], V1; DRW V0, V0, 3; JP 20C
+        let program = [0x60, 0xff, 0xa3, 0x00, 0xf0, 0x33, 0xf1, 0x65, 0xf1, 0x55, 0xd0, 0x03, 0x12, 0x0c];
+        let mut accesses = Vec::new();
+
+        for backend in [Backend::Interpreter, Backend::Threaded].iter() {
+            let mut interpreter = Interpreter::new(Quirks::schip(), 1);
+            interpreter.set_backend(*backend);
+            interpreter.load_rom(&program).unwrap();
+            let mut observer = Accesses::default();
+            interpreter.run_frame_with(&mut observer).unwrap();
+            accesses.push(observer);
+        }
+
+        assert_eq!(accesses[0].written, [0x300, 0x301, 0x302, 0x300, 0x301]);
+        assert_eq!(accesses[0].read.len(), 5);
+        assert_eq!(accesses[1], accesses[0]);
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::fmt;
+
+pub const KEY_COUNT: u8 = 16;
+
+// Progress of an Fx0A instruction. Only a key pressed after the instruction started counts, and the
+// VIP only continues once that key has been released again, so holding a key down can't satisfy
+// several Fx0A instructions in a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyWait {
+    Idle,
+    WaitingForPress,
+    WaitingForRelease(u8),
+}
+
+// The 16 key hexadecimal keypad
+//
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keypad {
+    // Bit n is set while key n is held down
+    state: u16,
+    // Keys pressed since the last frame ended. They count as down for the whole frame, so a key
+    // pressed and released between two frames is still seen by the ROM.
+    pending_presses: u16,
+    // Keys that were down during the last frame, pressing one again straight away isn't a new press
+    last_frame: u16,
+    // New presses since Fx0A started waiting
+    wait_presses: u16,
+    wait: KeyWait,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            state: 0,
+            pending_presses: 0,
+            last_frame: 0,
+            wait_presses: 0,
+            wait: KeyWait::Idle,
+        }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.set_state(self.state | 1 << (key & 0xf));
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.state &= !(1 << (key & 0xf));
+    }
+
+    // Whether the key is held right now
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.state & (1 << (key & 0xf)) != 0
+    }
+
+    // Whether the key is held or was pressed at some point this frame, which is what the ROM sees
+    pub fn is_down(&self, key: u8) -> bool {
+        self.down() & (1 << (key & 0xf)) != 0
+    }
+
+    pub fn state(&self) -> u16 {
+        self.state
+    }
+
+    // The keys the ROM sees as down this frame, as a mask that can be played back with set_state
+    pub fn down(&self) -> u16 {
+        self.state | self.pending_presses
+    }
+
+    // Replaces every key at once, used when inputs come from a recording. Keys that weren't held
+    // before are presses.
+    pub fn set_state(&mut self, state: u16) {
+        let pressed = state & !self.state;
+        self.pending_presses |= pressed;
+        self.wait_presses |= pressed & !self.last_frame;
+        self.state = state;
+    }
+
+    pub fn release_all(&mut self) {
+        self.state = 0;
+    }
+
+    // Called by the interpreter after each frame
+    pub fn end_frame(&mut self) {
+        self.last_frame = self.down();
+        self.pending_presses = 0;
+    }
+
+    // Translates a front end key event. Returns false when the host key isn't mapped.
+    pub fn host_key_down(&mut self, key_map: &KeyMap, host_key: &str) -> bool {
+        match key_map.key(host_key) {
+            Some(key) => {
+                self.press(key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn host_key_up(&mut self, key_map: &KeyMap, host_key: &str) -> bool {
+        match key_map.key(host_key) {
+            Some(key) => {
+                self.release(key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Called each time Fx0A executes. Returns the key once it has been pressed and released,
+    // otherwise the instruction should be repeated.
+    pub fn poll_wait(&mut self) -> Option<u8> {
+        match self.wait {
+            KeyWait::Idle => {
+                self.wait = KeyWait::WaitingForPress;
+                self.wait_presses = 0;
+                None
+            }
+            KeyWait::WaitingForPress => {
+                if self.wait_presses != 0 {
+                    self.wait = KeyWait::WaitingForRelease(self.wait_presses.trailing_zeros() as u8);
+                }
+                None
+            }
+            KeyWait::WaitingForRelease(key) => {
+                if self.is_down(key) {
+                    return None;
+                }
+                self.wait = KeyWait::Idle;
+                Some(key)
+            }
+        }
+    }
+
+    // Compact form of the wait state for hashing and save states
+    pub fn wait_state(&self) -> u8 {
+        match self.wait {
+            KeyWait::Idle => 0xff,
+            KeyWait::WaitingForPress => 0xfe,
+            KeyWait::WaitingForRelease(key) => key,
+        }
+    }
+
+    pub fn set_wait_state(&mut self, wait_state: u8) {
+        self.wait = match wait_state {
+            0xff => KeyWait::Idle,
+            0xfe => KeyWait::WaitingForPress,
+            key => KeyWait::WaitingForRelease(key & 0xf),
+        };
+    }
+
+    // The pending, last frame and Fx0A press masks, for save states
+    pub fn press_masks(&self) -> [u16; 3] {
+        [self.pending_presses, self.last_frame, self.wait_presses]
+    }
+
+    pub fn set_press_masks(&mut self, masks: [u16; 3]) {
+        self.pending_presses = masks[0];
+        self.last_frame = masks[1];
+        self.wait_presses = masks[2];
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Keypad {
+        Keypad::new()
+    }
+}
+
+// Maps host key names to keypad keys. Key names are whatever the front end calls its keys, compared
+// case insensitively, e.g. "w", "space" or "up".
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    bindings: HashMap<String, u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeyMapError {
+    Parse { line: usize, message: String },
+    Io(String),
+}
+
+impl fmt::Display for KeyMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyMapError::Parse { line, message } => write!(f, "key map line {}: {}", line, message),
+            KeyMapError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for KeyMapError {}
+
+impl KeyMap {
+    pub fn empty() -> KeyMap {
+        KeyMap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    // The usual layout, with the keypad on the left hand side of a QWERTY keyboard
+    //
+    //     1 2 3 4        1 2 3 C
+    //     q w e r        4 5 6 D
+    //     a s d f   ->   7 8 9 E
+    //     z x c v        A 0 B F
+    pub fn qwerty() -> KeyMap {
+        let layout = [
+            ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xc),
+            ("q", 0x4), ("w", 0x5), ("e", 0x6), ("r", 0xd),
+            ("a", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xe),
+            ("z", 0xa), ("x", 0x0), ("c", 0xb), ("v", 0xf),
+        ];
+
+        let mut key_map = KeyMap::empty();
+        for (host_key, key) in layout.iter() {
+            key_map.bind(host_key, *key);
+        }
+
+        key_map
+    }
+
+    pub fn bind(&mut self, host_key: &str, key: u8) {
+        self.bindings.insert(host_key.to_lowercase(), key & 0xf);
+    }
+
+    pub fn unbind(&mut self, host_key: &str) {
+        self.bindings.remove(&host_key.to_lowercase());
+    }
+
+    pub fn key(&self, host_key: &str) -> Option<u8> {
+        self.bindings.get(&host_key.to_lowercase()).copied()
+    }
+
+    // Host keys bound to a keypad key, sorted so they can be shown to the user
+    pub fn host_keys(&self, key: u8) -> Vec<&str> {
+        let mut host_keys: Vec<&str> = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| **bound == key)
+            .map(|(host_key, _)| host_key.as_str())
+            .collect();
+        host_keys.sort_unstable();
+
+        host_keys
+    }
+
+    // Reads a key map config file and returns the mapping for one ROM. The file is split into
+    // sections, each holding `host_key = keypad_key` lines:
+    //
+    //     [default]
+    //     up = 5
+    //
+    //     [pong.ch8]
+    //     w = 1
+    //     s = 4
+    //
+    //     [fnv1a:85944171f73967e8]
+    //     space = a
+    //
+    // The mapping starts as the QWERTY layout, then the `default` section is applied, then any
+    // section named after the ROM's file name or hash. Binding a host key replaces its previous
+    // binding and `host_key = none` removes it.
+    pub fn from_config(text: &str, rom_name: &str, rom_hash: u64) -> Result<KeyMap, KeyMapError> {
+        let hash_section = format!("fnv1a:{:016x}", rom_hash);
+        let mut default_bindings = Vec::new();
+        let mut rom_bindings = Vec::new();
+        let mut section = String::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let (host_key, key) = parse_binding(line, line_number)?;
+            if section == "default" {
+                default_bindings.push((host_key, key));
+            } else if section.eq_ignore_ascii_case(rom_name) || section.eq_ignore_ascii_case(&hash_section) {
+                rom_bindings.push((host_key, key));
+            }
+        }
+
+        let mut key_map = KeyMap::qwerty();
+        for (host_key, key) in default_bindings.into_iter().chain(rom_bindings) {
+            match key {
+                Some(key) => key_map.bind(host_key, key),
+                None => key_map.unbind(host_key),
+            }
+        }
+
+        Ok(key_map)
+    }
+
+    pub fn load(path: &std::path::Path, rom_name: &str, rom_hash: u64) -> Result<KeyMap, KeyMapError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| KeyMapError::Io(format!("Could not read {}: {}", path.display(), error)))?;
+
+        KeyMap::from_config(&text, rom_name, rom_hash)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::qwerty()
+    }
+}
+
+fn parse_binding(line: &str, line_number: usize) -> Result<(&str, Option<u8>), KeyMapError> {
+    let parse_error = |message: &str| KeyMapError::Parse {
+        line: line_number,
+        message: message.to_string(),
+    };
+
+    let (host_key, key) = line
+        .split_once('=')
+        .ok_or_else(|| parse_error("expected host_key = keypad_key"))?;
+    let host_key = host_key.trim();
+    let key = key.trim();
+    if host_key.is_empty() {
+        return Err(parse_error("missing host key"));
+    }
+    if key.eq_ignore_ascii_case("none") {
+        return Ok((host_key, None));
+    }
+
+    match u8::from_str_radix(key, 16) {
+        Ok(key) if key < KEY_COUNT => Ok((host_key, Some(key))),
+        _ => Err(parse_error(&format!("{} is not a keypad key 0-F", key))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyMap, KeyMapError, Keypad};
+
+    #[test]
+    fn press_and_release_update_state() {
+        let mut keypad = Keypad::new();
+
+        keypad.press(0x3);
+        keypad.press(0xf);
+        keypad.release(0x3);
+
+        assert_eq!(keypad.state(), 1 << 0xf);
+        assert!(keypad.is_pressed(0xf));
+        assert!(!keypad.is_pressed(0x3));
+    }
+
+    #[test]
+    fn poll_wait_returns_key_after_release() {
+        let mut keypad = Keypad::new();
+        assert_eq!(keypad.poll_wait(), None);
+
+        keypad.press(0x7);
+        assert_eq!(keypad.poll_wait(), None);
+        assert_eq!(keypad.poll_wait(), None);
+
+        keypad.release(0x7);
+        keypad.end_frame();
+        assert_eq!(keypad.poll_wait(), Some(0x7));
+        assert_eq!(keypad.poll_wait(), None);
+    }
+
+    #[test]
+    fn taps_between_frames_count_for_the_next_frame() {
+        let mut keypad = Keypad::new();
+
+        keypad.press(0x4);
+        keypad.release(0x4);
+        let during = keypad.is_down(0x4);
+        keypad.end_frame();
+        let after = keypad.is_down(0x4);
+
+        assert!(during);
+        assert!(!after);
+        assert!(!keypad.is_pressed(0x4));
+    }
+
+    #[test]
+    fn poll_wait_ignores_keys_held_before_it_started() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x2);
+        keypad.end_frame();
+
+        assert_eq!(keypad.poll_wait(), None);
+        assert_eq!(keypad.poll_wait(), None);
+        keypad.release(0x2);
+        keypad.end_frame();
+
+        assert_eq!(keypad.poll_wait(), None);
+        assert_eq!(keypad.wait_state(), 0xfe);
+    }
+
+    #[test]
+    fn pressing_a_key_again_straight_away_is_not_a_new_press() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x3);
+        keypad.release(0x3);
+        keypad.end_frame();
+        keypad.poll_wait();
+
+        keypad.press(0x3);
+        keypad.poll_wait();
+        let again = keypad.wait_state();
+        keypad.release(0x3);
+        keypad.end_frame();
+        keypad.end_frame();
+        keypad.press(0x3);
+        keypad.poll_wait();
+        let later = keypad.wait_state();
+
+        assert_eq!(again, 0xfe);
+        assert_eq!(later, 0x3);
+    }
+
+    #[test]
+    fn host_keys_follow_the_map() {
+        let key_map = KeyMap::qwerty();
+        let mut keypad = Keypad::new();
+
+        assert!(keypad.host_key_down(&key_map, "W"));
+        assert!(!keypad.host_key_down(&key_map, "p"));
+
+        assert_eq!(keypad.state(), 1 << 0x5);
+        assert_eq!(key_map.host_keys(0x5), vec!["w"]);
+    }
+
+    #[test]
+    fn config_applies_default_then_rom_sections() {
+        let config = "
+            # arrows for everything
+            [default]
+            up = 5
+
+            [pong.ch8]
+            up = 1
+            w = none
+
+            [tetris.ch8]
+            up = 4
+        ";
+
+        let key_map = KeyMap::from_config(config, "pong.ch8", 0).unwrap();
+
+        assert_eq!(key_map.key("up"), Some(0x1));
+        assert_eq!(key_map.key("w"), None);
+        assert_eq!(key_map.key("s"), Some(0x8));
+    }
+
+    #[test]
+    fn config_matches_rom_hash_sections() {
+        let config = "[fnv1a:00000000000000ab]\nspace = a\n";
+
+        let matched = KeyMap::from_config(config, "game.ch8", 0xab).unwrap();
+        let unmatched = KeyMap::from_config(config, "game.ch8", 0xcd).unwrap();
+
+        assert_eq!(matched.key("space"), Some(0xa));
+        assert_eq!(unmatched.key("space"), None);
+    }
+
+    #[test]
+    fn config_rejects_invalid_keys() {
+        let result = KeyMap::from_config("[default]\nup = 10\n", "game.ch8", 0);
+
+        assert_eq!(
+            result,
+            Err(KeyMapError::Parse {
+                line: 2,
+                message: "10 is not a keypad key 0-F".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,38 @@
+pub mod audio;
+pub mod cartridge;
+pub mod cheats;
+pub mod cli;
+pub mod coverage;
+pub mod database;
+pub mod decode_cache;
+pub mod detect;
+pub mod display;
+pub mod environment;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gif;
+pub mod hash;
+pub mod instruction;
+pub mod interpreter;
+pub mod keypad;
+/// cbindgen:ignore
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod loader;
+pub mod lockstep;
+pub mod movie;
+pub mod octo;
+pub mod patch;
+pub mod png;
+pub mod policy;
+pub mod pool;
+pub mod profiler;
+pub mod quirks;
+pub mod rng;
+pub mod snapshot;
+pub mod trace;
+
+// Has its own Chip8 type, which would clash with the C one in the generated header
+/// cbindgen:ignore
+#[cfg(feature = "wasm")]
+pub mod wasm;
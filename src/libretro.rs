@@ -0,0 +1,444 @@
+// libretro core, built with `--features libretro`. Frontends such as RetroArch load the cdylib and
+// drive it through the retro_* functions below, see https://docs.libretro.com for the API.
+//
+// libretro has no handle to pass around, a core is a single global instance. The frontend calls
+// every function from the same thread, the mutexes are only there to make the globals safe to share.
+//
+// Safety: pointers passed in by the frontend are trusted to be valid for the sizes passed with them,
+// as the libretro API requires.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::audio;
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::interpreter::Interpreter;
+use crate::keypad::{KeyMap, KEY_COUNT};
+use crate::loader::RomLoader;
+use crate::quirks::Quirks;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+pub const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+
+pub const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+pub const RETRO_DEVICE_ID_JOYPAD_Y: c_uint = 1;
+pub const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+pub const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+pub const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+pub const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+pub const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+pub const RETRO_DEVICE_ID_JOYPAD_X: c_uint = 9;
+pub const RETRO_DEVICE_ID_JOYPAD_L: c_uint = 10;
+pub const RETRO_DEVICE_ID_JOYPAD_R: c_uint = 11;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+pub const RETRO_REGION_NTSC: c_uint = 0;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+
+const PIXEL_ON: u32 = 0x00ff_ffff;
+const PIXEL_OFF: u32 = 0x0000_0000;
+
+// The d-pad sits on the keys under WASD in the QWERTY layout, which is what most modern ROMs use.
+// The face and shoulder buttons cover the keys games use most often. Start and select are left for
+// the frontend's own menus, the keyboard reaches every key.
+const JOYPAD_KEYS: [(c_uint, u8); 10] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x7),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x9),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0xc),
+];
+
+pub type RetroEnvironment = unsafe extern "C" fn(command: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = unsafe extern "C" fn();
+pub type RetroInputState = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+struct Core {
+    interpreter: Interpreter,
+    key_map: KeyMap,
+    halted: bool,
+    video: Vec<u32>,
+    samples: Vec<f32>,
+    audio: Vec<i16>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn with_core<T>(default: T, call: impl FnOnce(&mut Core) -> T) -> T {
+    let mut core = CORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match core.as_mut() {
+        Some(core) => call(core),
+        None => default,
+    }
+}
+
+impl Core {
+    fn new() -> Core {
+        Core {
+            interpreter: Interpreter::new(Quirks::default(), 0),
+            key_map: KeyMap::qwerty(),
+            halted: false,
+            video: vec![PIXEL_OFF; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            samples: vec![0.0; SAMPLES_PER_FRAME],
+            audio: vec![0; SAMPLES_PER_FRAME * 2],
+        }
+    }
+
+    unsafe fn poll_input(&mut self, input_state: RetroInputState) {
+        let mut keys = 0u16;
+        for (id, key) in JOYPAD_KEYS.iter() {
+            if input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) != 0 {
+                keys |= 1 << key;
+            }
+        }
+
+        // retro_key codes for letters and digits are their lowercase ASCII values, which are also
+        // the host key names the QWERTY key map uses
+        for key in 0..KEY_COUNT {
+            for host_key in self.key_map.host_keys(key) {
+                let code = match host_key.as_bytes() {
+                    [code] => *code as c_uint,
+                    _ => continue,
+                };
+                if input_state(0, RETRO_DEVICE_KEYBOARD, 0, code) != 0 {
+                    keys |= 1 << key;
+                }
+            }
+        }
+
+        self.interpreter.set_keys(keys);
+    }
+
+    // Runs one frame, turning an error or a panic from a misbehaving ROM into a halted machine that
+    // keeps presenting its last frame
+    fn run_frame(&mut self) {
+        if self.halted {
+            return;
+        }
+
+        let interpreter = &mut self.interpreter;
+        if !matches!(panic::catch_unwind(AssertUnwindSafe(|| interpreter.run_frame())), Ok(Ok(()))) {
+            self.halted = true;
+        }
+    }
+
+    fn render_video(&mut self) {
+        let display = self.interpreter.display();
+        for y in 0..DISPLAY_HEIGHT {
+            for x in 0..DISPLAY_WIDTH {
+                self.video[y * DISPLAY_WIDTH + x] = if display.pixel(x, y) { PIXEL_ON } else { PIXEL_OFF };
+            }
+        }
+    }
+
+    // The buzzer as interleaved stereo. A halted machine's sound timer never runs down, so it is
+    // silenced rather than left beeping.
+    fn render_audio(&mut self) {
+        if self.halted {
+            self.interpreter.buzzer_mut().fill(&mut self.samples, SAMPLE_RATE, false);
+        } else {
+            self.interpreter.fill_audio(&mut self.samples, SAMPLE_RATE);
+        }
+
+        for (frame, sample) in self.audio.chunks_mut(2).zip(self.samples.iter()) {
+            let sample = audio::to_i16(*sample);
+            frame[0] = sample;
+            frame[1] = sample;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: Option<RetroEnvironment>) {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).environment = environment;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: Option<RetroVideoRefresh>) {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).video_refresh = video_refresh;
+}
+
+// Only the batch callback is used, single samples would be one call per sample
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: Option<RetroAudioSample>) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: Option<RetroAudioSampleBatch>) {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).audio_sample_batch = audio_sample_batch;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: Option<RetroInputPoll>) {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).input_poll = input_poll;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: Option<RetroInputState>) {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).input_state = input_state;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    *CORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Core::new());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if let Some(info) = info.as_mut() {
+        *info = RetroSystemInfo {
+            library_name: b"chip-8-rust\0".as_ptr() as *const c_char,
+            library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+            valid_extensions: b"ch8|c8|gif\0".as_ptr() as *const c_char,
+            need_fullpath: false,
+            block_extract: false,
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    if let Some(info) = info.as_mut() {
+        *info = RetroSystemAvInfo {
+            geometry: RetroGameGeometry {
+                base_width: DISPLAY_WIDTH as c_uint,
+                base_height: DISPLAY_HEIGHT as c_uint,
+                max_width: DISPLAY_WIDTH as c_uint,
+                max_height: DISPLAY_HEIGHT as c_uint,
+                aspect_ratio: DISPLAY_WIDTH as f32 / DISPLAY_HEIGHT as f32,
+            },
+            timing: RetroSystemTiming {
+                fps: FRAMES_PER_SECOND as f64,
+                sample_rate: SAMPLE_RATE as f64,
+            },
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| {
+        core.interpreter.reset();
+        core.halted = false;
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    if let Some(input_poll) = callbacks.input_poll {
+        input_poll();
+    }
+
+    with_core((), |core| {
+        if let Some(input_state) = callbacks.input_state {
+            core.poll_input(input_state);
+        }
+        core.run_frame();
+        core.render_video();
+        core.render_audio();
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            let pitch = DISPLAY_WIDTH * 4;
+            video_refresh(core.video.as_ptr() as *const c_void, DISPLAY_WIDTH as c_uint, DISPLAY_HEIGHT as c_uint, pitch);
+        }
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            audio_sample_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME);
+        }
+    });
+}
+
+// Save states include the ROM, so the size is fixed once a game is loaded
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.interpreter.save_state().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.interpreter.save_state();
+        if data.is_null() || size < state.len() {
+            return false;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        if data.is_null() {
+            return false;
+        }
+        let state = slice::from_raw_parts(data as *const u8, size);
+        if core.interpreter.load_state(state).is_err() {
+            return false;
+        }
+        core.halted = false;
+
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let game = match game.as_ref() {
+        Some(game) if !game.data.is_null() => game,
+        _ => return false,
+    };
+
+    if let Some(environment) = callbacks().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+            return false;
+        }
+    }
+
+    // The path is only used to tell cartridges apart, the data is always passed in
+    let name = match game.path.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(game.path).to_string_lossy().into_owned(),
+    };
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size);
+    let loaded = match RomLoader::new().prepare(&name, rom.to_vec()) {
+        Ok(loaded) => loaded,
+        Err(_) => return false,
+    };
+
+    with_core(false, |core| {
+        let mut interpreter = core.interpreter.clone();
+        loaded.info.configure(&mut interpreter);
+        if interpreter.load_rom(&loaded.rom).is_err() {
+            return false;
+        }
+        core.interpreter = interpreter;
+        core.key_map = loaded.info.key_map();
+        core.halted = false;
+
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core((), |core| *core = Core::new());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
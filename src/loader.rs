@@ -0,0 +1,209 @@
+// Everything between a ROM file and a machine ready to run it, shared by the front ends. An Octo
+// cartridge is unpacked to its program, the patches are applied in order, then the settings come
+// from the cartridge, the ROM database or, when neither has any quirks, what the code looks like
+// it needs. Front ends apply the result under whatever was given on the command line.
+use crate::cartridge::{self, CartridgeError};
+use crate::database::{Database, DatabaseError, RomInfo};
+use crate::detect::{detect, Detection};
+use crate::patch::{self, PatchError};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    Io(String),
+    Database(DatabaseError),
+    Cartridge { name: String, error: CartridgeError },
+    Patch { name: String, error: PatchError },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(message) => write!(f, "{}", message),
+            LoadError::Database(error) => write!(f, "{}", error),
+            LoadError::Cartridge { name, error } => write!(f, "Could not load {}: {}", name, error),
+            LoadError::Patch { name, error } => write!(f, "Could not apply {}: {}", name, error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedRom {
+    pub rom: Vec<u8>,  // with the patches applied
+    pub info: RomInfo, // the cartridge's settings, then the database's, then detected quirks
+    pub entry: Option<RomInfo>,
+    pub detection: Option<Detection>, // only run when nothing said which quirks to use
+}
+
+#[derive(Debug, Clone)]
+pub struct RomLoader {
+    database: Database,
+    patches: Vec<(String, Vec<u8>)>,
+}
+
+impl RomLoader {
+    // Looks ROMs up in the embedded database and applies no patches
+    pub fn new() -> RomLoader {
+        RomLoader::with_database(Database::embedded())
+    }
+
+    pub fn with_database(database: Database) -> RomLoader {
+        RomLoader { database, patches: Vec::new() }
+    }
+
+    // The embedded database with a database file over it, and patch files to apply in order
+    pub fn from_files(
+        database_path: Option<impl AsRef<Path>>,
+        patch_paths: &[impl AsRef<Path>],
+    ) -> Result<RomLoader, LoadError> {
+        let mut loader = RomLoader::new();
+        if let Some(path) = database_path {
+            loader.load_database(path.as_ref())?;
+        }
+        for path in patch_paths.iter() {
+            loader.load_patch(path.as_ref())?;
+        }
+
+        Ok(loader)
+    }
+
+    // Adds the entries in a database file, replacing any for the same ROM
+    pub fn load_database(&mut self, path: &Path) -> Result<(), LoadError> {
+        self.database.merge(Database::load(path).map_err(LoadError::Database)?);
+
+        Ok(())
+    }
+
+    pub fn load_patch(&mut self, path: &Path) -> Result<(), LoadError> {
+        let patch = read(path)?;
+        self.add_patch(&path.display().to_string(), patch);
+
+        Ok(())
+    }
+
+    // Patches apply in the order they were added, the name is for errors
+    pub fn add_patch(&mut self, name: &str, patch: Vec<u8>) {
+        self.patches.push((name.to_string(), patch));
+    }
+
+    pub fn load(&self, path: &Path) -> Result<LoadedRom, LoadError> {
+        self.prepare(&path.display().to_string(), read(path)?)
+    }
+
+    // Prepares ROM bytes read from somewhere else, as a cartridge when the name ends in .gif
+    pub fn prepare(&self, name: &str, bytes: Vec<u8>) -> Result<LoadedRom, LoadError> {
+        let (rom, cartridge_info) = match name.to_lowercase().ends_with(".gif") {
+            true => {
+                let cartridge = cartridge::read(&bytes)
+                    .map_err(|error| LoadError::Cartridge { name: name.to_string(), error })?;
+                let info = cartridge.rom_info();
+                (cartridge.rom, Some(info))
+            }
+            false => (bytes, None),
+        };
+
+        let mut patched = rom.clone();
+        for (name, patch) in self.patches.iter() {
+            patched =
+                patch::apply(&patched, patch).map_err(|error| LoadError::Patch { name: name.clone(), error })?;
+        }
+
+        // A patched ROM is usually still the game in the database, so its entry comes first
+        let entry = self.database.lookup(&rom).or_else(|| self.database.lookup(&patched)).cloned();
+        let mut info = cartridge_info.unwrap_or_default().fill_from(entry.clone().unwrap_or_default());
+        let detection = info.quirks().is_none().then(|| detect(&patched));
+        if let Some(detection) = &detection {
+            info.quirks = Some(detection.quirks);
+        }
+
+        Ok(LoadedRom { rom: patched, info, entry, detection })
+    }
+}
+
+impl Default for RomLoader {
+    fn default() -> RomLoader {
+        RomLoader::new()
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, LoadError> {
+    fs::read(path).map_err(|error| LoadError::Io(format!("Could not read {}: {}", path.display(), error)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoadError, RomLoader};
+    use crate::cartridge::{self, Cartridge};
+    use crate::database::Database;
+    use crate::display::{Display, EdgeMode};
+    use crate::hash::sha1;
+    use crate::patch::{self, PatchError, PatchFormat};
+    use crate::quirks::Quirks;
+
+    const ROM: [u8; 4] = [0x60, 0x03, 0x12, 0x00];
+    const FIXED: [u8; 4] = [0x60, 0x09, 0x12, 0x00];
+
+    fn database(rom: &[u8], settings: &str) -> Database {
+        let hex: String = sha1(rom).iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        Database::from_config(&format!("[sha1:{}]\ntitle = Loop\n{}", hex, settings)).unwrap()
+    }
+
+    #[test]
+    fn patched_roms_keep_the_original_entry() {
+        let mut loader = RomLoader::with_database(database(&ROM, "platform = schip\n"));
+        loader.add_patch("fix.bps", patch::create(PatchFormat::Bps, &ROM, &FIXED));
+
+        let loaded = loader.prepare("loop.ch8", ROM.to_vec()).unwrap();
+
+        assert_eq!(loaded.rom, FIXED);
+        assert_eq!(loaded.entry.map(|entry| entry.title), Some("Loop".to_string()));
+        assert_eq!(loaded.info.quirks(), Some(Quirks::schip()));
+        assert_eq!(loaded.detection, None);
+    }
+
+    #[test]
+    fn cartridge_settings_come_before_the_database() {
+        let cartridge =
+            Cartridge { quirks: Quirks::xochip(), cycles_per_frame: Some(7), ..Cartridge::new(ROM.to_vec()) };
+        let mut gif = Vec::new();
+        cartridge::write(&mut gif, &cartridge, &Display::new(EdgeMode::Clip)).unwrap();
+        let loader = RomLoader::with_database(database(&ROM, "platform = schip\ncycles_per_frame = 30\n"));
+
+        let loaded = loader.prepare("loop.GIF", gif).unwrap();
+
+        assert_eq!(loaded.rom, ROM);
+        assert_eq!(loaded.info.quirks(), Some(Quirks::xochip()));
+        assert_eq!(loaded.info.cycles_per_frame, Some(7));
+        assert!(loaded.entry.is_some());
+    }
+
+    #[test]
+    fn unknown_roms_get_detected_quirks() {
+        let loader = RomLoader::with_database(Database::new());
+
+        let loaded = loader.prepare("loop.ch8", ROM.to_vec()).unwrap();
+
+        let detection = loaded.detection.unwrap();
+        assert_eq!(loaded.info.quirks(), Some(detection.quirks));
+        assert_eq!(loaded.entry, None);
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let mut loader = RomLoader::with_database(Database::new());
+        loader.add_patch("other.bps", patch::create(PatchFormat::Bps, &FIXED, &ROM));
+
+        let patch_error = loader.prepare("loop.ch8", ROM.to_vec()).unwrap_err();
+        let cartridge_error = loader.prepare("loop.gif", ROM.to_vec()).unwrap_err();
+
+        let mismatch = matches!(&patch_error, LoadError::Patch { error: PatchError::SourceMismatch { .. }, .. });
+        assert!(mismatch, "{:?}", patch_error);
+        assert!(patch_error.to_string().starts_with("Could not apply other.bps: "));
+        assert!(cartridge_error.to_string().starts_with("Could not load loop.gif: "));
+    }
+}
@@ -0,0 +1,286 @@
+// Differential testing. Runs two machines side by side on the same inputs, for example the same ROM
+// under two quirk profiles, or one build against a known-good trace, and reports the first point
+// where they disagree along with what each machine had just executed.
+use crate::interpreter::{ExecError, Interpreter, Observer};
+use crate::snapshot::Snapshot;
+use crate::trace::{self, TraceFilter, Tracer};
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_HISTORY: usize = 16;
+
+// Collects the state before every instruction in a frame, so two frames can be compared step by
+// step after they have run
+struct StepRecorder {
+    steps: Vec<(Snapshot, String)>,
+}
+
+impl Observer for StepRecorder {
+    fn before_step(&mut self, interpreter: &Interpreter, opcode: u16) {
+        self.steps.push((Snapshot::of(interpreter), trace::format_line(interpreter, opcode)));
+    }
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub frame: u64,
+    pub left: Box<Snapshot>,
+    pub right: Box<Snapshot>,
+    pub differences: Vec<String>,
+    // Trace lines for the instructions each machine executed leading up to the divergence, oldest
+    // first. The last one is the instruction that caused it.
+    pub left_history: Vec<String>,
+    pub right_history: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "machines diverged in frame {}:", self.frame)?;
+        for difference in self.differences.iter() {
+            writeln!(f, "  {}", difference)?;
+        }
+
+        for (name, history, snapshot) in [("left", &self.left_history, &self.left), ("right", &self.right_history, &self.right)].iter() {
+            writeln!(f, "\n{} executed:", name)?;
+            writeln!(f, "{}", trace::HEADER)?;
+            for line in history.iter() {
+                writeln!(f, "{}", line)?;
+            }
+            write!(f, "{} state:\n{}", name, snapshot)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Lockstep {
+    left: Interpreter,
+    right: Interpreter,
+    history: usize,
+    left_history: VecDeque<String>,
+    right_history: VecDeque<String>,
+}
+
+impl Lockstep {
+    pub fn new(left: Interpreter, right: Interpreter) -> Lockstep {
+        Lockstep {
+            left,
+            right,
+            history: DEFAULT_HISTORY,
+            left_history: VecDeque::new(),
+            right_history: VecDeque::new(),
+        }
+    }
+
+    // How many instructions before a divergence to report
+    pub fn set_history(&mut self, history: usize) {
+        self.history = history;
+    }
+
+    pub fn left(&self) -> &Interpreter {
+        &self.left
+    }
+
+    pub fn right(&self) -> &Interpreter {
+        &self.right
+    }
+
+    // Runs both machines for one frame with the same keys held. The machines are compared before
+    // every instruction and again at the end of the frame.
+    pub fn run_frame(&mut self, keys: u16) -> Result<(), Divergence> {
+        self.left.set_keys(keys);
+        self.right.set_keys(keys);
+        // Taken before running, as a frame that stops with an error isn't counted
+        let frame = self.left.frames();
+        let mut left = StepRecorder { steps: Vec::new() };
+        let mut right = StepRecorder { steps: Vec::new() };
+
+        let left_result = self.left.run_frame_with(&mut left);
+        let right_result = self.right.run_frame_with(&mut right);
+
+        for ((left_snapshot, left_line), (right_snapshot, right_line)) in left.steps.into_iter().zip(right.steps) {
+            let differences = left_snapshot.differences(&right_snapshot);
+            if !differences.is_empty() {
+                return Err(self.divergence(frame, left_snapshot, right_snapshot, differences));
+            }
+            remember(&mut self.left_history, left_line, self.history);
+            remember(&mut self.right_history, right_line, self.history);
+        }
+
+        // Catches the last instruction of the frame, and machines that ran a different number of
+        // instructions without differing along the way. Machines that stop with the same error
+        // still agree.
+        let left_snapshot = Snapshot::of(&self.left);
+        let right_snapshot = Snapshot::of(&self.right);
+        let mut differences = left_snapshot.differences(&right_snapshot);
+        if left_result != right_result {
+            differences.push(format!("result {} != {}", outcome(&left_result), outcome(&right_result)));
+        }
+        if !differences.is_empty() {
+            return Err(self.divergence(frame, left_snapshot, right_snapshot, differences));
+        }
+
+        Ok(())
+    }
+
+    // Runs a frame for each entry of inputs, returning how many frames ran before a divergence
+    pub fn run(&mut self, inputs: &[u16]) -> Result<usize, Divergence> {
+        for keys in inputs.iter() {
+            self.run_frame(*keys)?;
+        }
+
+        Ok(inputs.len())
+    }
+
+    fn divergence(&self, frame: u64, left: Snapshot, right: Snapshot, differences: Vec<String>) -> Divergence {
+        Divergence {
+            frame,
+            left: Box::new(left),
+            right: Box::new(right),
+            differences,
+            left_history: self.left_history.iter().cloned().collect(),
+            right_history: self.right_history.iter().cloned().collect(),
+        }
+    }
+}
+
+fn outcome(result: &Result<(), ExecError>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(error) => error.to_string(),
+    }
+}
+
+fn remember(history: &mut VecDeque<String>, line: String, length: usize) {
+    history.push_back(line);
+    while history.len() > length {
+        history.pop_front();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TraceMismatch {
+    // 1 based line number in the expected trace, counting comment lines, so it can be found in the file
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "trace differs at line {}:", self.line)?;
+        for line in self.context.iter() {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "- {}", self.expected.as_deref().unwrap_or("(end of trace)"))?;
+        writeln!(f, "+ {}", self.actual.as_deref().unwrap_or("(end of trace)"))
+    }
+}
+
+// Runs a machine for a frame per entry of inputs and compares its trace against a recorded one,
+// e.g. from a known-good emulator. Comment lines in the recording are skipped. Returns the number
+// of lines that matched. The trace ends early if the machine stops with an error.
+pub fn compare_trace(interpreter: &mut Interpreter, inputs: &[u16], expected: &str, history: usize) -> Result<usize, TraceMismatch> {
+    let mut tracer = Tracer::new(Vec::new(), TraceFilter::default());
+    for keys in inputs.iter() {
+        interpreter.set_keys(*keys);
+        if interpreter.run_frame_with(&mut tracer).is_err() {
+            break;
+        }
+    }
+    let output = tracer.finish().expect("writing to a Vec can't fail");
+    let actual = String::from_utf8_lossy(&output);
+
+    let mut expected_lines = expected
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('#') && !line.trim().is_empty());
+    let mut actual_lines = actual.lines();
+    let mut context = VecDeque::new();
+    let mut matched = 0;
+
+    loop {
+        let (number, expected_line) = match expected_lines.next() {
+            Some((index, line)) => (index + 1, Some(line)),
+            None => (expected.lines().count() + 1, None),
+        };
+        let actual_line = actual_lines.next();
+
+        if expected_line.is_none() && actual_line.is_none() {
+            return Ok(matched);
+        }
+        if expected_line.map(str::trim_end) != actual_line {
+            return Err(TraceMismatch {
+                line: number,
+                expected: expected_line.map(str::to_string),
+                actual: actual_line.map(str::to_string),
+                context: context.into_iter().collect(),
+            });
+        }
+
+        remember(&mut context, actual_line.unwrap_or_default().to_string(), history);
+        matched += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compare_trace, Lockstep};
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+    use crate::trace::{TraceFilter, Tracer};
+
+    fn machine(program: &[u8], quirks: Quirks) -> Interpreter {
+        let mut interpreter = Interpreter::new(quirks, 3);
+        interpreter.load_rom(program).unwrap();
+
+        interpreter
+    }
+
+    fn record_trace(interpreter: &mut Interpreter) -> String {
+        let mut tracer = Tracer::new(Vec::new(), TraceFilter::default());
+        interpreter.run_frame_with(&mut tracer).unwrap();
+
+        String::from_utf8(tracer.finish().unwrap()).unwrap()
+    }
+
+    // LD V0, 3; LD V1, 5; RND V2, FF; SHR V0, V1; JP 200
+    const SHIFT: [u8; 10] = [0x60, 0x03, 0x61, 0x05, 0xc2, 0xff, 0x80, 0x16, 0x12, 0x00];
+
+    #[test]
+    fn identical_machines_never_diverge() {
+        let mut lockstep = Lockstep::new(machine(&SHIFT, Quirks::chip8()), machine(&SHIFT, Quirks::chip8()));
+
+        let frames = lockstep.run(&[0; 30]);
+
+        assert_eq!(frames.unwrap(), 30);
+    }
+
+    #[test]
+    fn reports_the_instruction_that_diverged() {
+        let mut lockstep = Lockstep::new(machine(&SHIFT, Quirks::chip8()), machine(&SHIFT, Quirks::schip()));
+        lockstep.set_history(2);
+
+        let divergence = lockstep.run(&[0; 30]).unwrap_err();
+
+        assert_eq!(divergence.frame, 0);
+        assert_eq!(divergence.differences, vec!["V0 02 != 01"]);
+        assert_eq!(divergence.left_history.len(), 2);
+        assert!(divergence.left_history[1].ends_with("SHR V0, V1"));
+        assert!(divergence.to_string().contains("left executed:"));
+    }
+
+    #[test]
+    fn compare_trace_finds_the_first_different_line() {
+        let expected = record_trace(&mut machine(&SHIFT, Quirks::chip8()));
+        let tampered = expected.replacen("SHR V0, V1", "SHR V0, V0", 1);
+
+        let matched = compare_trace(&mut machine(&SHIFT, Quirks::chip8()), &[0], &format!("# header\n{}", expected), 4);
+        let mismatch = compare_trace(&mut machine(&SHIFT, Quirks::chip8()), &[0], &tampered, 2).unwrap_err();
+
+        assert_eq!(matched, Ok(10));
+        assert_eq!(mismatch.line, 4);
+        assert_eq!(mismatch.context.len(), 2);
+        assert!(mismatch.actual.unwrap().ends_with("SHR V0, V1"));
+    }}
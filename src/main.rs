@@ -1,47 +1,398 @@
-mod interpreter;
-mod instruction;
+use chip_8_rust::audio::{self, DEFAULT_FREQUENCY, DEFAULT_VOLUME};
+use chip_8_rust::cartridge::{self, Cartridge};
+use chip_8_rust::cheats::Cheats;
+use chip_8_rust::cli::{exit_with, option_value, parse_number};
+use chip_8_rust::coverage::Coverage;
+use chip_8_rust::database::RomInfo;
+use chip_8_rust::detect::{detect, Detection};
+use chip_8_rust::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip_8_rust::interpreter::{Backend, Interpreter, DEFAULT_CYCLES_PER_FRAME};
+use chip_8_rust::loader::RomLoader;
+use chip_8_rust::lockstep::Lockstep;
+use chip_8_rust::movie::Movie;
+use chip_8_rust::policy::ErrorPolicies;
+use chip_8_rust::profiler::Profiler;
+use chip_8_rust::quirks::Quirks;
+use chip_8_rust::trace::{TraceFilter, Tracer};
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
 
-use instruction::Instruction;
-use std::io;
-use std::u8;
+const USAGE: &str = "Usage: chip-8-rust <rom> [options]
+
+Runs a ROM headless and prints the final display. The ROM can also be an Octo cartridge (.gif).
+
+Options:
+  --quirks <quirks>          chip8, schip, xochip, none or a comma separated list of quirk flags,
+                             by default what the ROM database says, or what the ROM looks like
+                             it needs when it isn't in the database
+  --seed <n>                 seed for the random number generator (default 0)
+  --cycles-per-frame <n>     instructions executed per 60Hz frame, by default what the ROM
+                             database says or 10
+  --frames <n>               number of frames to run (default 600)
+  --backend <backend>        interpreter, or threaded to translate the ROM into threaded code first
+  --on-error <policies>      what to do when the ROM hits an error, e.g. stack_overflow=wrap or
+                             all=ignore, with halt, wrap or ignore for each class of error
+  --database <file>          ROM metadata to use along with the embedded database, see data/roms.txt
+  --detect                   print the platform and quirks the ROM looks like it needs, with the
+                             instructions that suggest them, instead of running it
+  --patch <file>             apply an IPS or BPS patch to the ROM as it loads, can be repeated
+  --freeze <cheats>          hold memory at these values every frame, e.g. 2F0=03,2F1=09 with
+                             hexadecimal addresses and values
+  --play <movie>             replay a movie recorded against this ROM and check it for desyncs
+  --wav <file>               write the buzzer output to a WAV file
+  --tone <hz>                buzzer frequency (default 440)
+  --volume <0-1>             buzzer volume (default 0.25)
+  --trace <file>             write a line per executed instruction, see src/trace.rs for the format
+  --trace-range <start-end>  only trace instructions at these hexadecimal addresses, e.g. 200-2FF
+  --trace-family <families>  only trace these instruction families, e.g. TwoRegisterInstruction
+  --profile <file>           write a report of the hottest loops, most called routines and opcode mix
+  --folded <file>            write the call stacks in the folded format flamegraph tools read
+  --coverage <file>          write a disassembly of the ROM marking which bytes ran, were read or
+                             were written, also after --play to see what a recorded session reached
+  --heatmap <file>           write a PNG heatmap of how the whole 4 KiB of memory was used
+  --save-cartridge <file>    pack the ROM and its settings into an Octo cartridge (.gif) labelled
+                             with the final display
+  --lockstep <quirks>        run the ROM under --quirks and these quirks side by side and report
+                             the first instruction where they diverge
+  --lockstep-backend <name>  the same with the second machine on this backend, e.g. to check the
+                             threaded backend against the interpreter";
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+
+struct Options {
+    rom_path: String,
+    quirks: Option<Quirks>, // None until the ROM database or detection has had a say
+    seed: u64,
+    cycles_per_frame: Option<u32>,
+    frames: u64,
+    backend: Backend,
+    error_policies: ErrorPolicies,
+    patch_paths: Vec<String>,
+    database_path: Option<String>,
+    detect: bool,
+    cheats: Cheats,
+    movie_path: Option<String>,
+    wav_path: Option<String>,
+    tone: f32,
+    volume: f32,
+    trace_path: Option<String>,
+    trace_filter: TraceFilter,
+    profile_path: Option<String>,
+    folded_path: Option<String>,
+    coverage_path: Option<String>,
+    heatmap_path: Option<String>,
+    cartridge_path: Option<String>,
+    on_colour: Option<[u8; 3]>, // only shown in saved cartridges
+    off_colour: Option<[u8; 3]>,
+    lockstep_quirks: Option<Quirks>,
+    lockstep_backend: Option<Backend>,
+}
+
+// The tools that can watch a headless run or a movie playing
+type Observers = (Option<Tracer<BufWriter<File>>>, (Option<Profiler>, Option<Coverage>));
+
+impl Options {
+    fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or_default()
+    }
+
+    fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME)
+    }
+
+    // Anything not given on the command line comes from the ROM's database entry
+    fn apply_rom_info(&mut self, info: &RomInfo) {
+        self.quirks = self.quirks.or_else(|| info.quirks());
+        self.cycles_per_frame = self.cycles_per_frame.or(info.cycles_per_frame);
+        self.on_colour = self.on_colour.or(info.on_colour);
+        self.off_colour = self.off_colour.or(info.off_colour);
+    }
+}
 
-// This main function is a placeholder to give the project some functionality until the interpreter is done.
-// It reads in a 2 byte value in hexadecimal and prints the resulting instruction.
 fn main() {
-    let keep_going = true;
+    let mut options = parse_options(env::args().skip(1)).unwrap_or_else(|message| exit_with(&message));
+    let loaded = RomLoader::from_files(options.database_path.as_ref(), &options.patch_paths)
+        .and_then(|loader| loader.load(Path::new(&options.rom_path)))
+        .unwrap_or_else(|error| exit_with(&error.to_string()));
+    let rom = loaded.rom;
+    if options.detect {
+        print_detection(&detect(&rom));
+        return;
+    }
+    if let Some(entry) = &loaded.entry {
+        eprintln!("Recognised {}", entry);
+    } else if let (None, Some(detection)) = (options.quirks, &loaded.detection) {
+        eprintln!("Detected {}", detection);
+    }
+    options.apply_rom_info(&loaded.info);
 
-    while keep_going {
-        let mut input = String::new();
+    let lockstep = options.lockstep_quirks.is_some() || options.lockstep_backend.is_some();
+    let interpreter = match &options.movie_path {
+        Some(movie_path) => play_movie(&options, movie_path, &rom),
+        None if lockstep => run_lockstep(&options, &rom),
+        None => run_headless(&options, &rom),
+    };
 
-        println!("Enter 2 byte hexadecimal instruction with space between bytes. Example: F5 07");
-        io::stdin().read_line(&mut input).ok().expect("Could not read input");
+    if let Some(cartridge_path) = &options.cartridge_path {
+        save_cartridge(&options, cartridge_path, &rom, &interpreter);
+    }
+
+    print_display(&interpreter);
+    println!("frames: {}  cycles: {}  state: {:016x}", interpreter.frames(), interpreter.cycles(), interpreter.state_hash());
+}
+
+fn run_headless(options: &Options, rom: &[u8]) -> Interpreter {
+    let mut interpreter = Interpreter::new(options.quirks(), options.seed);
+    interpreter.set_cycles_per_frame(options.cycles_per_frame());
+    interpreter.set_backend(options.backend);
+    interpreter.set_error_policies(options.error_policies);
+    interpreter.buzzer_mut().set_frequency(options.tone);
+    interpreter.buzzer_mut().set_volume(options.volume);
+    interpreter.load_rom(rom).unwrap_or_else(|error| exit_with(&error.to_string()));
+    let mut observers = create_observers(options);
 
-        let raw_instruction = to_raw_instruction(&input.trim()).expect("Could not parse instruction");
+    let samples_per_frame = (WAV_SAMPLE_RATE / 60) as usize;
+    let mut samples = Vec::new();
+    for _ in 0..options.frames {
+        options.cheats.apply(&mut interpreter);
+        let result = interpreter.run_frame_with(&mut observers);
+        // Keep what ran so far, the display and trace are the most useful things to look at
+        if let Err(error) = result {
+            eprintln!("ROM stopped during frame {}: {}", interpreter.frames() + 1, error);
+            break;
+        }
 
-        println!("{:?}\n\n", Instruction::parse(raw_instruction));
+        if options.wav_path.is_some() {
+            let start = samples.len();
+            samples.resize(start + samples_per_frame, 0.0);
+            interpreter.fill_audio(&mut samples[start..], WAV_SAMPLE_RATE);
+        }
     }
+
+    if let Some(wav_path) = &options.wav_path {
+        write_wav(wav_path, &samples);
+    }
+    finish_observers(options, observers, &interpreter);
+
+    interpreter
 }
 
-fn to_raw_instruction(input_string: &str) -> Option<(u8, u8)>  {
-    if input_string.len() != 5 {
-        return None;
+fn create_observers(options: &Options) -> Observers {
+    let tracer = options.trace_path.as_ref().map(|trace_path| {
+        let file = File::create(trace_path)
+            .unwrap_or_else(|error| exit_with(&format!("Could not create {}: {}", trace_path, error)));
+        let mut tracer = Tracer::new(BufWriter::new(file), options.trace_filter.clone());
+        tracer.write_header();
+
+        tracer
+    });
+    let profiler = (options.profile_path.is_some() || options.folded_path.is_some()).then(Profiler::new);
+    let coverage = (options.coverage_path.is_some() || options.heatmap_path.is_some()).then(Coverage::new);
+
+    (tracer, (profiler, coverage))
+}
+
+fn finish_observers(options: &Options, observers: Observers, interpreter: &Interpreter) {
+    let (tracer, (profiler, coverage)) = observers;
+    if let Some(tracer) = tracer {
+        tracer.finish().unwrap_or_else(|error| exit_with(&format!("Could not write the trace: {}", error)));
     }
+    if let Some(profiler) = profiler {
+        if let Some(profile_path) = &options.profile_path {
+            write_file(profile_path, &profiler.report());
+        }
+        if let Some(folded_path) = &options.folded_path {
+            write_file(folded_path, &profiler.folded_stacks());
+        }
+    }
+    if let Some(coverage) = coverage {
+        if let Some(coverage_path) = &options.coverage_path {
+            write_file(coverage_path, &coverage.annotated_disassembly(interpreter));
+        }
+        if let Some(heatmap_path) = &options.heatmap_path {
+            let file = File::create(heatmap_path)
+                .unwrap_or_else(|error| exit_with(&format!("Could not create {}: {}", heatmap_path, error)));
+            coverage
+                .write_heatmap(&mut BufWriter::new(file), interpreter)
+                .unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", heatmap_path, error)));
+        }
+    }
+}
 
-    let bytes_as_str: Vec<&str> = input_string.split(" ").collect();
+fn write_wav(wav_path: &str, samples: &[f32]) {
+    let file = File::create(wav_path)
+        .unwrap_or_else(|error| exit_with(&format!("Could not create {}: {}", wav_path, error)));
 
-    if bytes_as_str.len() != 2 {
-        return None;
+    audio::write_wav(&mut BufWriter::new(file), samples, WAV_SAMPLE_RATE)
+        .unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", wav_path, error)));
+}
+
+fn write_file(path: &str, contents: &str) {
+    fs::write(path, contents).unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", path, error)));
+}
+
+// The second machine takes its quirks and backend from the lockstep options, and anything not given
+// there from the first
+fn run_lockstep(options: &Options, rom: &[u8]) -> Interpreter {
+    let lockstep_quirks = options.lockstep_quirks.unwrap_or(options.quirks());
+    let lockstep_backend = options.lockstep_backend.unwrap_or(options.backend);
+    let machine = |quirks, backend| {
+        let mut interpreter = Interpreter::new(quirks, options.seed);
+        interpreter.set_cycles_per_frame(options.cycles_per_frame());
+        interpreter.set_backend(backend);
+        interpreter.set_error_policies(options.error_policies);
+        interpreter.load_rom(rom).unwrap_or_else(|error| exit_with(&error.to_string()));
+
+        interpreter
+    };
+
+    let mut lockstep = Lockstep::new(
+        machine(options.quirks(), options.backend),
+        machine(lockstep_quirks, lockstep_backend),
+    );
+    let inputs = vec![0; options.frames as usize];
+    match lockstep.run(&inputs) {
+        Ok(frames) => println!(
+            "{} on the {} and {} on the {} agreed for {} frames",
+            options.quirks(), options.backend, lockstep_quirks, lockstep_backend, frames
+        ),
+        Err(divergence) => exit_with(&divergence.to_string()),
+    }
+
+    lockstep.left().clone()
+}
+
+fn play_movie(options: &Options, movie_path: &str, rom: &[u8]) -> Interpreter {
+    let text = fs::read_to_string(movie_path)
+        .unwrap_or_else(|error| exit_with(&format!("Could not read {}: {}", movie_path, error)));
+    let movie = Movie::parse(&text).unwrap_or_else(|error| exit_with(&error.to_string()));
+
+    let mut observers = create_observers(options);
+    let interpreter = movie.play_with(rom, &mut observers).unwrap_or_else(|error| exit_with(&error.to_string()));
+    println!("Played {} frames with no desyncs", movie.inputs.len());
+    finish_observers(options, observers, &interpreter);
+
+    interpreter
+}
+
+fn print_display(interpreter: &Interpreter) {
+    for y in 0..DISPLAY_HEIGHT {
+        let row: String = (0..DISPLAY_WIDTH)
+            .map(|x| if interpreter.display().pixel(x, y) { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
+}
+
+fn print_detection(detection: &Detection) {
+    println!("{}", detection);
+    println!("{} reachable instructions", detection.instructions);
+    for hint in &detection.hints {
+        println!("{}", hint);
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: None,
+        seed: 0,
+        cycles_per_frame: None,
+        frames: 600,
+        backend: Backend::Interpreter,
+        error_policies: ErrorPolicies::default(),
+        patch_paths: Vec::new(),
+        database_path: None,
+        detect: false,
+        cheats: Cheats::new(),
+        movie_path: None,
+        wav_path: None,
+        tone: DEFAULT_FREQUENCY,
+        volume: DEFAULT_VOLUME,
+        trace_path: None,
+        trace_filter: TraceFilter::default(),
+        profile_path: None,
+        folded_path: None,
+        coverage_path: None,
+        heatmap_path: None,
+        cartridge_path: None,
+        on_colour: None,
+        off_colour: None,
+        lockstep_quirks: None,
+        lockstep_backend: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            "--quirks" => options.quirks = Some(option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?),
+            "--seed" => options.seed = parse_number(&option_value(&mut args, &arg)?)?,
+            "--cycles-per-frame" => options.cycles_per_frame = Some(parse_number(&option_value(&mut args, &arg)?)? as u32),
+            "--frames" => options.frames = parse_number(&option_value(&mut args, &arg)?)?,
+            "--backend" => options.backend = option_value(&mut args, &arg)?.parse()?,
+            "--on-error" => options.error_policies = option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?,
+            "--database" => options.database_path = Some(option_value(&mut args, &arg)?),
+            "--detect" => options.detect = true,
+            "--patch" => options.patch_paths.push(option_value(&mut args, &arg)?),
+            "--freeze" => options.cheats = option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?,
+            "--play" => options.movie_path = Some(option_value(&mut args, &arg)?),
+            "--wav" => options.wav_path = Some(option_value(&mut args, &arg)?),
+            "--tone" => options.tone = parse_decimal(&option_value(&mut args, &arg)?)?,
+            "--volume" => options.volume = parse_decimal(&option_value(&mut args, &arg)?)?,
+            "--trace" => options.trace_path = Some(option_value(&mut args, &arg)?),
+            "--profile" => options.profile_path = Some(option_value(&mut args, &arg)?),
+            "--folded" => options.folded_path = Some(option_value(&mut args, &arg)?),
+            "--coverage" => options.coverage_path = Some(option_value(&mut args, &arg)?),
+            "--heatmap" => options.heatmap_path = Some(option_value(&mut args, &arg)?),
+            "--save-cartridge" => options.cartridge_path = Some(option_value(&mut args, &arg)?),
+            "--lockstep" => options.lockstep_quirks = Some(option_value(&mut args, &arg)?.parse().map_err(|error| format!("{}", error))?),
+            "--lockstep-backend" => options.lockstep_backend = Some(option_value(&mut args, &arg)?.parse()?),
+            "--trace-range" => {
+                let range = TraceFilter::parse_range(&option_value(&mut args, &arg)?).map_err(|error| error.to_string())?;
+                options.trace_filter.addresses = Some(range);
+            }
+            "--trace-family" => {
+                for family in option_value(&mut args, &arg)?.split(',') {
+                    options.trace_filter.families.push(family.parse().map_err(|error| format!("{}", error))?);
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ => options.rom_path = arg,
+        }
     }
 
-    // TODO: This could have better error handling, but this implementation probably won't be used
-    // when reading from a binary file so it is fine for now.
-    let number_values: Vec<u8> = bytes_as_str
-        .into_iter()
-        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
-        .collect();
+    if options.rom_path.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    // A movie only replays against the machine it was recorded on, and lockstep compares machines
+    // running the ROM as it is
+    let other_run = options.movie_path.is_some() || options.lockstep_quirks.is_some() || options.lockstep_backend.is_some();
+    if other_run && !options.cheats.is_empty() {
+        return Err("--freeze can't be used with --play or --lockstep".to_string());
+    }
+
+    Ok(options)
+}
 
-    Some((
-            number_values[0],
-            number_values[1],
-        ))
+fn save_cartridge(options: &Options, cartridge_path: &str, rom: &[u8], interpreter: &Interpreter) {
+    let cartridge = Cartridge {
+        rom: rom.to_vec(),
+        quirks: interpreter.quirks(),
+        cycles_per_frame: Some(interpreter.cycles_per_frame()),
+        on_colour: options.on_colour,
+        off_colour: options.off_colour,
+    };
+    let file = File::create(cartridge_path)
+        .unwrap_or_else(|error| exit_with(&format!("Could not create {}: {}", cartridge_path, error)));
+    cartridge::write(&mut BufWriter::new(file), &cartridge, interpreter.display())
+        .unwrap_or_else(|error| exit_with(&format!("Could not write {}: {}", cartridge_path, error)));
+}
+
+fn parse_decimal(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok(number),
+        _ => Err(format!("{} is not a number", value)),
+    }
 }
@@ -0,0 +1,606 @@
+use crate::hash::fnv1a;
+use crate::interpreter::{ExecError, Interpreter, LoadError, Observer};
+use crate::policy::ErrorPolicies;
+use crate::quirks::Quirks;
+use std::fmt;
+
+// A movie is everything needed to reproduce a run exactly: the ROM it was recorded against, the
+// machine configuration, and the keys held during every frame. Periodic state hashes let playback
+// notice the moment it stops matching the original run.
+//
+// Movies are stored as plain text so they can be attached to bug reports and diffed:
+//
+//     chip8-movie 1
+//     rom fnv1a:8c3e1d4f05a7b2c9
+//     quirks chip8
+//     seed 1234
+//     cycles-per-frame 10
+//     checkpoint-interval 60
+//     frames
+//     0000 x59
+//     0010
+//     = 60 1f2e3d4c5b6a7988
+//
+// Each frame line is the held keys as a 16 bit hex mask (bit n for key n), optionally followed by
+// `xN` to repeat it N times. A `= frame hash` line records the state hash after that many frames.
+// Lines starting with `#` are comments. An `on-error` header line, in the form the --on-error option
+// takes, is only written when the error policies aren't the defaults.
+
+pub const MOVIE_VERSION: u32 = 1;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 60;
+// A day at 60 frames a second. Repeat counts are expanded as a movie is parsed, so a longer movie is
+// far more likely to be a corrupt count than a real recording.
+pub const MAX_FRAMES: u64 = 60 * 60 * 60 * 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    pub frame: u64,
+    pub state_hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    pub error_policies: ErrorPolicies,
+    pub checkpoint_interval: u64,
+    pub inputs: Vec<u16>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+// Frames are counted from 1, so frame n is the nth frame played and a desync at frame n means the
+// state after it didn't match
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    RomMismatch { expected: u64, actual: u64 },
+    Load(LoadError),
+    Desync { frame: u64, expected: u64, actual: u64 },
+    Exec { frame: u64, error: ExecError },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "movie line {}: {}", line, message),
+            MovieError::RomMismatch { expected, actual } => write!(
+                f,
+                "movie was recorded against ROM fnv1a:{:016x} but this ROM is fnv1a:{:016x}",
+                expected, actual
+            ),
+            MovieError::Load(error) => write!(f, "{}", error),
+            MovieError::Desync { frame, expected, actual } => write!(
+                f,
+                "desync after frame {}: expected state {:016x} but found {:016x}",
+                frame, expected, actual
+            ),
+            MovieError::Exec { frame, error } => write!(f, "ROM stopped during frame {}: {}", frame, error),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<LoadError> for MovieError {
+    fn from(error: LoadError) -> MovieError {
+        MovieError::Load(error)
+    }
+}
+
+impl Movie {
+    pub fn new(interpreter: &Interpreter, checkpoint_interval: u64) -> Movie {
+        Movie {
+            rom_hash: fnv1a(interpreter.rom()),
+            quirks: interpreter.quirks(),
+            seed: interpreter.seed(),
+            cycles_per_frame: interpreter.cycles_per_frame(),
+            error_policies: interpreter.error_policies(),
+            checkpoint_interval,
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    // Builds a freshly reset interpreter configured the way the movie was recorded
+    pub fn interpreter(&self, rom: &[u8]) -> Result<Interpreter, MovieError> {
+        let rom_hash = fnv1a(rom);
+        if rom_hash != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                actual: rom_hash,
+            });
+        }
+
+        let mut interpreter = Interpreter::new(self.quirks, self.seed);
+        interpreter.set_cycles_per_frame(self.cycles_per_frame);
+        interpreter.set_error_policies(self.error_policies);
+        interpreter.load_rom(rom)?;
+
+        Ok(interpreter)
+    }
+
+    // Plays the whole movie against the ROM, returning the final machine
+    pub fn play(&self, rom: &[u8]) -> Result<Interpreter, MovieError> {
+        self.play_with(rom, &mut ())
+    }
+
+    // Plays the movie with an observer watching, e.g. to see what a recorded session covered
+    pub fn play_with(&self, rom: &[u8], observer: &mut impl Observer) -> Result<Interpreter, MovieError> {
+        let mut interpreter = self.interpreter(rom)?;
+        let mut player = Player::new(self);
+        while player.play_frame_with(&mut interpreter, observer)? {}
+
+        Ok(interpreter)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let (line, magic) = lines.next().unwrap_or((1, ""));
+        if magic != format!("chip8-movie {}", MOVIE_VERSION) {
+            return Err(parse_error(line, "not a version 1 chip8 movie"));
+        }
+
+        let mut rom_hash = None;
+        let mut quirks = None;
+        let mut seed = None;
+        let mut cycles_per_frame = None;
+        let mut error_policies = ErrorPolicies::default();
+        let mut checkpoint_interval = DEFAULT_CHECKPOINT_INTERVAL;
+
+        for (line, text) in lines.by_ref() {
+            if text == "frames" {
+                break;
+            }
+
+            let (key, value) = split_pair(text).ok_or_else(|| parse_error(line, "expected a key and value"))?;
+            match key {
+                "rom" => {
+                    let hex = value
+                        .strip_prefix("fnv1a:")
+                        .ok_or_else(|| parse_error(line, "ROM hash must be fnv1a:<hex>"))?;
+                    rom_hash = Some(parse_hex(hex, line)?);
+                }
+                "quirks" => quirks = Some(value.parse().map_err(|error| parse_error(line, &format!("{}", error)))?),
+                "seed" => seed = Some(parse_decimal(value, line)?),
+                "cycles-per-frame" => {
+                    let cycles = parse_decimal(value, line)?;
+                    if cycles > u32::MAX as u64 {
+                        return Err(parse_error(line, &format!("cycles-per-frame can be at most {}", u32::MAX)));
+                    }
+                    cycles_per_frame = Some(cycles as u32);
+                }
+                "on-error" => error_policies = value.parse().map_err(|error| parse_error(line, &format!("{}", error)))?,
+                "checkpoint-interval" => checkpoint_interval = parse_decimal(value, line)?,
+                _ => return Err(parse_error(line, &format!("unknown header field {}", key))),
+            }
+        }
+
+        let mut movie = Movie {
+            rom_hash: rom_hash.ok_or_else(|| parse_error(0, "missing rom"))?,
+            quirks: quirks.ok_or_else(|| parse_error(0, "missing quirks"))?,
+            seed: seed.ok_or_else(|| parse_error(0, "missing seed"))?,
+            cycles_per_frame: cycles_per_frame.ok_or_else(|| parse_error(0, "missing cycles-per-frame"))?,
+            error_policies,
+            checkpoint_interval,
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        };
+
+        // The player checks checkpoints in order, one out of order or past the end would never be checked
+        let mut last_checkpoint_line = 0;
+        for (line, text) in lines {
+            if let Some(checkpoint) = text.strip_prefix('=') {
+                let (frame, state_hash) =
+                    split_pair(checkpoint.trim()).ok_or_else(|| parse_error(line, "expected a frame and hash"))?;
+                let frame = parse_decimal(frame, line)?;
+                let previous = movie.checkpoints.last().map_or(0, |checkpoint| checkpoint.frame);
+                if frame <= previous {
+                    return Err(parse_error(line, &format!("checkpoint frame must come after frame {}", previous)));
+                }
+                movie.checkpoints.push(Checkpoint {
+                    frame,
+                    state_hash: parse_hex(state_hash, line)?,
+                });
+                last_checkpoint_line = line;
+                continue;
+            }
+
+            let (keys, count) = match split_pair(text) {
+                Some((keys, repeat)) => {
+                    let count = repeat
+                        .strip_prefix('x')
+                        .ok_or_else(|| parse_error(line, "repeat count must look like x<count>"))?;
+                    (keys, parse_decimal(count, line)?)
+                }
+                None => (text, 1),
+            };
+            let keys = u16::from_str_radix(keys, 16).map_err(|_| parse_error(line, "invalid key mask"))?;
+            if count > MAX_FRAMES - movie.inputs.len() as u64 {
+                return Err(parse_error(line, &format!("a movie can be at most {} frames long", MAX_FRAMES)));
+            }
+            movie.inputs.extend(std::iter::repeat(keys).take(count as usize));
+        }
+
+        if let Some(checkpoint) = movie.checkpoints.last() {
+            if checkpoint.frame > movie.inputs.len() as u64 {
+                let frames = movie.inputs.len();
+                let message = format!("checkpoint after frame {} but the movie has {}", checkpoint.frame, frames);
+                return Err(parse_error(last_checkpoint_line, &message));
+            }
+        }
+
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "chip8-movie {}", MOVIE_VERSION)?;
+        writeln!(f, "rom fnv1a:{:016x}", self.rom_hash)?;
+        writeln!(f, "quirks {}", self.quirks)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "cycles-per-frame {}", self.cycles_per_frame)?;
+        if self.error_policies != ErrorPolicies::default() {
+            writeln!(f, "on-error {}", self.error_policies)?;
+        }
+        writeln!(f, "checkpoint-interval {}", self.checkpoint_interval)?;
+        writeln!(f, "frames")?;
+
+        // Runs of identical input are collapsed, but never across a checkpoint so that the
+        // checkpoint lines stay next to the frame they describe
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        let mut frame = 0;
+        while frame < self.inputs.len() {
+            let keys = self.inputs[frame];
+            let run_limit = checkpoints
+                .peek()
+                .map(|checkpoint| checkpoint.frame as usize)
+                .filter(|checkpoint_frame| *checkpoint_frame > frame)
+                .unwrap_or(self.inputs.len());
+            let mut count = 1;
+            while frame + count < run_limit && self.inputs[frame + count] == keys {
+                count += 1;
+            }
+
+            if count == 1 {
+                writeln!(f, "{:04x}", keys)?;
+            } else {
+                writeln!(f, "{:04x} x{}", keys, count)?;
+            }
+            frame += count;
+
+            while let Some(checkpoint) = checkpoints.next_if(|checkpoint| checkpoint.frame as usize <= frame) {
+                writeln!(f, "= {} {:016x}", checkpoint.frame, checkpoint.state_hash)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn split_pair(text: &str) -> Option<(&str, &str)> {
+    let mut parts = text.splitn(2, char::is_whitespace);
+    let key = parts.next()?;
+    let value = parts.next()?.trim();
+
+    Some((key, value))
+}
+
+fn parse_decimal(text: &str, line: usize) -> Result<u64, MovieError> {
+    text.parse().map_err(|_| parse_error(line, &format!("invalid number {}", text)))
+}
+
+fn parse_hex(text: &str, line: usize) -> Result<u64, MovieError> {
+    u64::from_str_radix(text, 16).map_err(|_| parse_error(line, &format!("invalid hash {}", text)))
+}
+
+// Records the keys held during each frame. The interpreter must be freshly reset when recording
+// starts, since playback begins from power on.
+pub struct Recorder {
+    movie: Movie,
+}
+
+impl Recorder {
+    pub fn new(interpreter: &Interpreter, checkpoint_interval: u64) -> Recorder {
+        Recorder {
+            movie: Movie::new(interpreter, checkpoint_interval),
+        }
+    }
+
+    // Runs one frame with whatever keys the front end has set and records them. A frame that stops
+    // with an error is recorded too, so playing the movie back stops with the same error.
+    pub fn record_frame(&mut self, interpreter: &mut Interpreter) -> Result<(), ExecError> {
+        self.movie.inputs.push(interpreter.keypad().down());
+        interpreter.run_frame()?;
+
+        let frame = self.movie.inputs.len() as u64;
+        if self.movie.checkpoint_interval > 0 && frame % self.movie.checkpoint_interval == 0 {
+            self.movie.checkpoints.push(Checkpoint {
+                frame,
+                state_hash: interpreter.state_hash(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Feeds a movie's inputs back into an interpreter one frame at a time
+pub struct Player<'a> {
+    movie: &'a Movie,
+    frame: usize,
+    next_checkpoint: usize,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(movie: &'a Movie) -> Player<'a> {
+        Player {
+            movie,
+            frame: 0,
+            next_checkpoint: 0,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.inputs.len()
+    }
+
+    // Plays the next frame. Returns false once every recorded frame has been played.
+    pub fn play_frame(&mut self, interpreter: &mut Interpreter) -> Result<bool, MovieError> {
+        self.play_frame_with(interpreter, &mut ())
+    }
+
+    pub fn play_frame_with(&mut self, interpreter: &mut Interpreter, observer: &mut impl Observer) -> Result<bool, MovieError> {
+        if self.finished() {
+            return Ok(false);
+        }
+
+        interpreter.set_keys(self.movie.inputs[self.frame]);
+        interpreter
+            .run_frame_with(observer)
+            .map_err(|error| MovieError::Exec { frame: self.frame as u64 + 1, error })?;
+        self.frame += 1;
+
+        while let Some(checkpoint) = self.movie.checkpoints.get(self.next_checkpoint) {
+            if checkpoint.frame > self.frame as u64 {
+                break;
+            }
+            self.next_checkpoint += 1;
+
+            let state_hash = interpreter.state_hash();
+            if checkpoint.frame == self.frame as u64 && checkpoint.state_hash != state_hash {
+                return Err(MovieError::Desync {
+                    frame: checkpoint.frame,
+                    expected: checkpoint.state_hash,
+                    actual: state_hash,
+                });
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Movie, MovieError, Player, Recorder, MAX_FRAMES};
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    // Draws a random sprite when key 5 is held, otherwise spins
+    const ROM: [u8; 18] = [
+        0x60, 0x05, // LD V0, 5
+        0xe0, 0xa1, // SKNP V0
+        0x12, 0x0a, // JP 20A
+        0x12, 0x00, // JP 200
+        0x00, 0x00,
+        0xc1, 0x3f, // RND V1, 3F
+        0xf1, 0x29, // LD F, V1
+        0xd1, 0x15, // DRW V1, V1, 5
+        0x12, 0x00, // JP 200
+    ];
+
+    fn record(inputs: &[u16]) -> (Movie, Interpreter) {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 99);
+        interpreter.load_rom(&ROM).unwrap();
+        let mut recorder = Recorder::new(&interpreter, 4);
+
+        for keys in inputs {
+            interpreter.set_keys(*keys);
+            recorder.record_frame(&mut interpreter).unwrap();
+        }
+
+        (recorder.finish(), interpreter)
+    }
+
+    #[test]
+    fn playback_reproduces_recorded_run() {
+        let (movie, recorded) = record(&[0, 0, 1 << 5, 1 << 5, 0, 1 << 5, 0, 0, 0, 1 << 5]);
+
+        let played = movie.play(&ROM).unwrap();
+
+        assert_eq!(played.state_hash(), recorded.state_hash());
+        assert_eq!(movie.checkpoints.len(), 2);
+    }
+
+    #[test]
+    fn playback_reproduces_keys_tapped_between_frames() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 99);
+        interpreter.load_rom(&ROM).unwrap();
+        let mut recorder = Recorder::new(&interpreter, 1);
+
+        for frame in 0..6 {
+            if frame % 2 == 1 {
+                interpreter.keypad_mut().press(0x5);
+                interpreter.keypad_mut().release(0x5);
+            }
+            recorder.record_frame(&mut interpreter).unwrap();
+        }
+        let movie = recorder.finish();
+
+        let played = movie.play(&ROM).unwrap();
+
+        assert_eq!(movie.inputs, vec![0, 1 << 5, 0, 1 << 5, 0, 1 << 5]);
+        assert_eq!(played.state_hash(), interpreter.state_hash());
+    }
+
+    #[test]
+    fn text_format_round_trips() {
+        let (movie, _) = record(&[0, 0, 0, 0, 0, 0x20, 0x20, 0, 0]);
+
+        let text = movie.to_string();
+        let parsed = Movie::parse(&text).unwrap();
+
+        assert_eq!(parsed, movie);
+        assert!(text.contains("\n0000 x4\n= 4 "));
+    }
+
+    #[test]
+    fn error_policies_round_trip() {
+        let (mut movie, _) = record(&[0, 0]);
+        movie.error_policies = "stack_overflow=wrap".parse().unwrap();
+
+        let parsed = Movie::parse(&movie.to_string()).unwrap();
+
+        assert!(movie.to_string().contains("\non-error stack_overflow=wrap\n"));
+        assert_eq!(parsed.error_policies, movie.error_policies);
+        assert_eq!(parsed.interpreter(&ROM).unwrap().error_policies(), movie.error_policies);
+    }
+
+    #[test]
+    fn playback_detects_desync() {
+        let (mut movie, _) = record(&[0, 1 << 5, 0, 1 << 5, 0, 0, 0, 0]);
+        movie.inputs[2] = 1 << 5;
+
+        let mut interpreter = movie.interpreter(&ROM).unwrap();
+        let mut player = Player::new(&movie);
+        let mut result = Ok(true);
+        while let Ok(true) = result {
+            result = player.play_frame(&mut interpreter);
+        }
+
+        match result {
+            Err(error @ MovieError::Desync { .. }) => {
+                assert!(error.to_string().starts_with("desync after frame 4: "), "{}", error)
+            }
+            other => panic!("expected a desync, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn playback_rejects_a_different_rom() {
+        let (movie, _) = record(&[0]);
+
+        let result = movie.interpreter(&[0x12, 0x00]);
+
+        assert!(matches!(result, Err(MovieError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn playback_stops_with_the_recorded_error() {
+        // 200: ADD V0, 1
+        // 202: SE V0, 3
+        // 204: JP 200
+        // 206: RET with nothing on the stack
+        let rom = [0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x00, 0xee];
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.set_cycles_per_frame(4);
+        interpreter.load_rom(&rom).unwrap();
+        let mut recorder = Recorder::new(&interpreter, 1);
+        let mut recorded = Ok(());
+        while recorded.is_ok() {
+            recorded = recorder.record_frame(&mut interpreter);
+        }
+        let movie = Movie::parse(&recorder.finish().to_string()).unwrap();
+
+        let played = movie.play(&rom);
+
+        let error = recorded.unwrap_err();
+        let played = played.unwrap_err();
+        assert_eq!(movie.inputs.len(), 3);
+        assert_eq!(played, MovieError::Exec { frame: 3, error });
+        assert!(played.to_string().starts_with("ROM stopped during frame 3: "), "{}", played);
+    }
+
+    #[test]
+    fn parse_rejects_huge_repeat_counts() {
+        let header = "chip8-movie 1\nrom fnv1a:00\nquirks chip8\nseed 1\ncycles-per-frame 10\nframes\n";
+
+        let overflow = Movie::parse(&format!("{}0000 x18446744073709551615\n", header));
+        let too_long = Movie::parse(&format!("{}0000 x5184000\n0000\n", header));
+        let longest = Movie::parse(&format!("{}0000 x5183999\n0000\n", header));
+
+        assert!(matches!(overflow, Err(MovieError::Parse { line: 7, .. })));
+        assert!(matches!(too_long, Err(MovieError::Parse { line: 8, .. })));
+        assert_eq!(longest.unwrap().inputs.len() as u64, MAX_FRAMES);
+    }
+
+    #[test]
+    fn parse_rejects_checkpoints_that_would_never_be_checked() {
+        let header = "chip8-movie 1\nrom fnv1a:00\nquirks chip8\nseed 1\ncycles-per-frame 10\nframes\n";
+
+        let repeated = Movie::parse(&format!("{}0000 x2\n= 2 aa\n= 2 bb\n", header));
+        let backwards = Movie::parse(&format!("{}0000\n= 1 aa\n0000\n= 0 bb\n", header));
+        let past_the_end = Movie::parse(&format!("{}0000 x2\n= 2 aa\n= 3 bb\n", header));
+        let last_frame = Movie::parse(&format!("{}0000 x2\n= 1 aa\n= 2 bb\n", header));
+
+        assert!(matches!(repeated, Err(MovieError::Parse { line: 9, .. })));
+        assert!(matches!(backwards, Err(MovieError::Parse { line: 10, .. })));
+        assert!(matches!(past_the_end, Err(MovieError::Parse { line: 9, .. })));
+        assert_eq!(last_frame.unwrap().checkpoints.len(), 2);
+    }
+
+    #[test]
+    fn parse_rejects_cycles_per_frame_that_dont_fit() {
+        let movie = |cycles: u64| {
+            Movie::parse(&format!("chip8-movie 1\nrom fnv1a:00\nquirks chip8\nseed 1\ncycles-per-frame {}\n", cycles))
+        };
+
+        let too_many = movie(u32::MAX as u64 + 1);
+        let most = movie(u32::MAX as u64);
+
+        assert!(matches!(too_many, Err(MovieError::Parse { line: 5, .. })));
+        assert_eq!(most.unwrap().cycles_per_frame, u32::MAX);
+    }
+
+    #[test]
+    fn parse_reports_bad_lines() {
+        let text = "chip8-movie 1\nrom fnv1a:00\nquirks chip8\nseed 1\ncycles-per-frame 10\nframes\n0000\nzzzz\n";
+
+        let result = Movie::parse(text);
+
+        assert_eq!(
+            result,
+            Err(MovieError::Parse {
+                line: 8,
+                message: "invalid key mask".to_string()
+            })
+        );
+    }
+}
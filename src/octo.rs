@@ -0,0 +1,808 @@
+// A compiler for the part of Octo, John Earnest's CHIP-8 assembly language, that most programs use,
+// so cartridges can be run from the source they carry. Programs are whitespace separated tokens
+// with # comments:
+//
+//     : main
+//         i := ball
+//         loop
+//             v0 += 1
+//             if v0 == 60 then v0 := 0
+//             sprite v0 v1 4
+//         again
+//     : ball 0x60 0xF0 0xF0 0x60
+//
+// This covers the statements for CHIP-8, SUPER-CHIP and XO-CHIP, labels, :const, :alias, :org,
+// :next, :unpack, :byte, :pointer and :call, with if/then, if/begin/else/end and loop/while/again.
+// Comparisons other than == and != go through vf, the way Octo compiles them. Macros, :calc,
+// :stringmode and anything else evaluated while compiling need Octo itself and are errors here.
+//
+// A program starts at main. When main comes before any other label or code the program runs
+// straight into it, otherwise the first instruction is a jump there.
+use crate::interpreter::PROGRAM_START;
+use std::collections::HashMap;
+use std::fmt;
+
+const MAX_ADDRESS: usize = 0xffff;
+const RESERVED: &[&str] = &[
+    ":=", "|=", "&=", "^=", "-=", "=-", "+=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "-", ";", "key",
+    "-key", "hex", "bighex", "long", "random", "delay", "buzzer", "pitch", "i", "if", "then", "begin", "else",
+    "end", "loop", "while", "again", "return", "clear", "sprite", "jump", "jump0", "native", "save", "load",
+    "saveflags", "loadflags", "bcd", "hires", "lores", "exit", "scroll-down", "scroll-up", "scroll-left",
+    "scroll-right", "plane", "audio",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctoError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for OctoError {}
+
+pub fn compile(source: &str) -> Result<Vec<u8>, OctoError> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |token| (index + 1, token))
+        })
+        .collect();
+
+    Compiler::new(tokens).compile()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Name {
+    Label(usize),
+    Const(i32),
+    Alias(u8),
+}
+
+// How an address is filled in once the label it names is known
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    Opcode(u16), // the low 12 bits of an instruction such as jump or i :=
+    Word,        // all 16 bits, as for i := long
+    UnpackHigh(u8),
+    UnpackLow,
+}
+
+enum Block {
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+struct Compiler<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    position: usize,
+    rom: Vec<u8>, // from PROGRAM_START
+    here: usize,
+    names: HashMap<&'a str, Name>,
+    fixups: Vec<(usize, usize, &'a str, Fixup)>, // address, line, name and how to write it
+    blocks: Vec<(usize, Block)>,                 // open ifs and loops with the line they started on
+    jump_to_main: Option<bool>,                  // decided by the first label or code
+}
+
+impl<'a> Compiler<'a> {
+    fn new(tokens: Vec<(usize, &'a str)>) -> Compiler<'a> {
+        Compiler {
+            tokens,
+            position: 0,
+            rom: Vec::new(),
+            here: PROGRAM_START as usize,
+            names: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            jump_to_main: None,
+        }
+    }
+
+    fn compile(mut self) -> Result<Vec<u8>, OctoError> {
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some((line, _)) = self.blocks.last() {
+            return Err(OctoError { line: *line, message: "this block has no end or again".to_string() });
+        }
+        let last_line = self.tokens.last().map_or(1, |(line, _)| *line);
+        if self.jump_to_main != Some(false) {
+            self.fixups.push((PROGRAM_START as usize, last_line, "main", Fixup::Opcode(0x1000)));
+        }
+        for (address, line, name, fixup) in std::mem::take(&mut self.fixups) {
+            let target = match self.names.get(name) {
+                Some(Name::Label(target)) => *target,
+                _ => return Err(OctoError { line, message: format!("{} is not defined", name) }),
+            };
+            self.here = address;
+            self.write_address(target, fixup).map_err(|error| OctoError { line, ..error })?;
+        }
+
+        Ok(self.rom)
+    }
+
+    fn error(&self, message: String) -> OctoError {
+        let index = self.position.saturating_sub(1).min(self.tokens.len().saturating_sub(1));
+        let line = self.tokens.get(index).map_or(1, |(line, _)| *line);
+
+        OctoError { line, message }
+    }
+
+    fn next(&mut self) -> Result<&'a str, OctoError> {
+        let (_, token) =
+            self.tokens.get(self.position).ok_or_else(|| self.error("the program ends early".to_string()))?;
+        self.position += 1;
+
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|(_, token)| *token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), OctoError> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(self.error(format!("expected {}, found {}", expected, token))),
+        }
+    }
+
+    // Leaves room for a jump to main unless main is the first thing in the program
+    fn place_main(&mut self, main_here: bool) {
+        if self.jump_to_main.is_none() {
+            self.jump_to_main = Some(!main_here);
+            if !main_here && self.here == PROGRAM_START as usize {
+                self.here += 2;
+            }
+        }
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), OctoError> {
+        self.place_main(false);
+        let offset = self
+            .here
+            .checked_sub(PROGRAM_START as usize)
+            .filter(|_| self.here <= MAX_ADDRESS)
+            .ok_or_else(|| self.error(format!("{:X} is outside the program", self.here)))?;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), OctoError> {
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    fn write_address(&mut self, address: usize, fixup: Fixup) -> Result<(), OctoError> {
+        let too_far = |limit: usize| match address > limit {
+            true => Err(self.error(format!("{:X} is too far away, the most this can reach is {:X}", address, limit))),
+            false => Ok(()),
+        };
+        match fixup {
+            Fixup::Opcode(opcode) => {
+                too_far(0xfff)?;
+                self.emit(opcode | address as u16)
+            }
+            Fixup::Word => {
+                too_far(MAX_ADDRESS)?;
+                self.emit(address as u16)
+            }
+            Fixup::UnpackHigh(nybble) => {
+                too_far(0xfff)?;
+                self.emit(0x6000 | (nybble as u16) << 4 | (address >> 8) as u16)
+            }
+            Fixup::UnpackLow => self.emit(0x6100 | (address & 0xff) as u16),
+        }
+    }
+
+    // Writes an address now if it's known, or leaves room for it until the label turns up
+    fn address(&mut self, fixup: Fixup) -> Result<(), OctoError> {
+        let token = self.next()?;
+        match self.resolve(token)? {
+            Some(address) => self.write_address(address, fixup),
+            None => {
+                let line = self.tokens[self.position - 1].0;
+                self.fixups.push((self.here, line, token, fixup));
+                self.emit(0)
+            }
+        }
+    }
+
+    // The address a token stands for, or None for a label that isn't defined yet
+    fn resolve(&self, token: &str) -> Result<Option<usize>, OctoError> {
+        match (parse_number(token), self.names.get(token)) {
+            (Some(number), _) | (None, Some(&Name::Const(number))) if number >= 0 => Ok(Some(number as usize)),
+            (None, Some(&Name::Label(address))) => Ok(Some(address)),
+            (None, None) if !is_reserved(token) && parse_register(token).is_none() => Ok(None),
+            _ => Err(self.error(format!("{} is not an address", token))),
+        }
+    }
+
+    fn number(&mut self) -> Result<i32, OctoError> {
+        let token = self.next()?;
+        match (parse_number(token), self.names.get(token)) {
+            (Some(number), _) => Ok(number),
+            (None, Some(Name::Const(number))) => Ok(*number),
+            (None, Some(Name::Label(address))) => Ok(*address as i32),
+            _ => Err(self.error(format!("{} is not a number or a constant", token))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError> {
+        match self.number()? {
+            number if (-128..=255).contains(&number) => Ok(number as u8),
+            number => Err(self.error(format!("{} doesn't fit in a byte", number))),
+        }
+    }
+
+    fn nybble(&mut self) -> Result<u16, OctoError> {
+        match self.number()? {
+            number if (0..=15).contains(&number) => Ok(number as u16),
+            number => Err(self.error(format!("{} is not a number from 0 to 15", number))),
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, OctoError> {
+        let token = self.next()?;
+        self.as_register(token).ok_or_else(|| self.error(format!("{} is not a register", token)))
+    }
+
+    fn as_register(&self, token: &str) -> Option<u16> {
+        match self.names.get(token) {
+            Some(Name::Alias(register)) => Some(*register as u16),
+            _ => parse_register(token).map(u16::from),
+        }
+    }
+
+    fn define(&mut self, name: &'a str, value: Name) -> Result<(), OctoError> {
+        if is_reserved(name) || parse_number(name).is_some() || parse_register(name).is_some() {
+            return Err(self.error(format!("{} can't be used as a name", name)));
+        }
+        if self.names.insert(name, value).is_some() {
+            return Err(self.error(format!("{} is already defined", name)));
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if let Some(x) = self.as_register(token) {
+            return self.assignment(x);
+        }
+
+        match token {
+            ":" => {
+                let name = self.next()?;
+                self.place_main(name == "main");
+                self.define(name, Name::Label(self.here))
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.place_main(false);
+                self.define(name, Name::Label(self.here + 1))
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.number()?;
+                self.define(name, Name::Const(value))
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()? as u8;
+                self.define(name, Name::Alias(register))
+            }
+            ":org" => match self.number()? {
+                address if (PROGRAM_START as i32..=MAX_ADDRESS as i32).contains(&address) => {
+                    self.here = address as usize;
+                    Ok(())
+                }
+                address => Err(self.error(format!("{:X} is outside the program", address))),
+            },
+            // v0 := the nybble and the high bits of the address, v1 := the low byte
+            ":unpack" => {
+                let nybble = self.nybble()? as u8;
+                let token = self.next()?;
+                for fixup in [Fixup::UnpackHigh(nybble), Fixup::UnpackLow] {
+                    match self.resolve(token)? {
+                        Some(address) => self.write_address(address, fixup)?,
+                        None => {
+                            let line = self.tokens[self.position - 1].0;
+                            self.fixups.push((self.here, line, token, fixup));
+                            self.emit(0)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte)
+            }
+            ":pointer" => self.address(Fixup::Word),
+            ":call" => self.address(Fixup::Opcode(0x2000)),
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => self.next().and_then(|_| self.next()).map(|_| ()),
+            "clear" => self.emit(0x00e0),
+            "return" | ";" => self.emit(0x00ee),
+            "hires" => self.emit(0x00ff),
+            "lores" => self.emit(0x00fe),
+            "exit" => self.emit(0x00fd),
+            "scroll-down" => self.nybble().and_then(|n| self.emit(0x00c0 | n)),
+            "scroll-up" => self.nybble().and_then(|n| self.emit(0x00d0 | n)),
+            "scroll-right" => self.emit(0x00fb),
+            "scroll-left" => self.emit(0x00fc),
+            "audio" => self.emit(0xf002),
+            "plane" => self.nybble().and_then(|n| self.emit(0xf001 | n << 8)),
+            "jump" => self.address(Fixup::Opcode(0x1000)),
+            "jump0" => self.address(Fixup::Opcode(0xb000)),
+            "native" => self.address(Fixup::Opcode(0x0000)),
+            "bcd" => self.register().and_then(|x| self.emit(0xf033 | x << 8)),
+            "saveflags" => self.register().and_then(|x| self.emit(0xf075 | x << 8)),
+            "loadflags" => self.register().and_then(|x| self.emit(0xf085 | x << 8)),
+            "save" | "load" => {
+                let x = self.register()?;
+                // XO-CHIP's save vx - vy and load vx - vy work on a range without touching i
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let opcode = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(opcode | x << 8 | y << 4)
+                } else {
+                    let opcode = if token == "save" { 0xf055 } else { 0xf065 };
+                    self.emit(opcode | x << 8)
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let height = self.nybble()?;
+                self.emit(0xd000 | x << 8 | y << 4 | height)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match token {
+                    "delay" => 0xf015,
+                    "buzzer" => 0xf018,
+                    _ => 0xf03a,
+                };
+                self.emit(opcode | x << 8)
+            }
+            "i" => match self.next()? {
+                ":=" => match self.peek() {
+                    Some("hex") | Some("bighex") => {
+                        let opcode = if self.next()? == "hex" { 0xf029 } else { 0xf030 };
+                        let x = self.register()?;
+                        self.emit(opcode | x << 8)
+                    }
+                    Some("long") => {
+                        self.next()?;
+                        self.emit(0xf000)?;
+                        self.address(Fixup::Word)
+                    }
+                    _ => self.address(Fixup::Opcode(0xa000)),
+                },
+                "+=" => self.register().and_then(|x| self.emit(0xf01e | x << 8)),
+                operator => Err(self.error(format!("{} can't be used with i", operator))),
+            },
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()? {
+                    "then" => {
+                        self.skip_unless(condition)?;
+                        self.statement()
+                    }
+                    "begin" => {
+                        self.skip_unless(condition.negated())?;
+                        let (line, jump) = (self.tokens[self.position - 1].0, self.here);
+                        self.blocks.push((line, Block::If { jump }));
+                        self.emit(0x1000)
+                    }
+                    token => Err(self.error(format!("expected then or begin, found {}", token))),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((line, Block::If { jump })) => {
+                    let else_jump = self.here;
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump, self.here)?;
+                    self.blocks.push((line, Block::Else { jump: else_jump }));
+                    Ok(())
+                }
+                _ => Err(self.error("else without if ... begin".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some((_, Block::If { jump })) | Some((_, Block::Else { jump })) => self.patch_jump(jump, self.here),
+                _ => Err(self.error("end without if ... begin".to_string())),
+            },
+            "loop" => {
+                let line = self.tokens[self.position - 1].0;
+                self.blocks.push((line, Block::Loop { start: self.here, exits: Vec::new() }));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_unless(condition.negated())?;
+                let jump = self.here;
+                match self.blocks.iter_mut().rev().find_map(|(_, block)| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(self.error("while outside a loop".to_string())),
+                }
+                self.emit(0x1000)
+            }
+            "again" => match self.blocks.pop() {
+                Some((_, Block::Loop { start, exits })) => {
+                    self.write_address(start, Fixup::Opcode(0x1000))?;
+                    let end = self.here;
+                    exits.into_iter().try_for_each(|jump| self.patch_jump(jump, end))
+                }
+                _ => Err(self.error("again without loop".to_string())),
+            },
+            token if token.starts_with(':') || token.starts_with('{') => {
+                Err(self.error(format!("{} needs Octo's own compiler", token)))
+            }
+            token => match (parse_number(token), self.names.get(token)) {
+                (Some(_), _) | (None, Some(Name::Const(_))) => {
+                    self.position -= 1;
+                    let byte = self.byte()?;
+                    self.emit_byte(byte)
+                }
+                _ => {
+                    // Any other name is a subroutine call, perhaps to a label further down
+                    self.position -= 1;
+                    self.address(Fixup::Opcode(0x2000))
+                }
+            },
+        }
+    }
+
+    fn patch_jump(&mut self, jump: usize, target: usize) -> Result<(), OctoError> {
+        let here = self.here;
+        self.here = jump;
+        let result = self.write_address(target, Fixup::Opcode(0x1000));
+        self.here = here;
+
+        result
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), OctoError> {
+        let operator = self.next()?;
+        let source = self.peek().and_then(|token| self.as_register(token));
+        if let Some(y) = source {
+            self.next()?;
+            let operation = match operator {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xe,
+                _ => return Err(self.error(format!("{} can't be used between registers", operator))),
+            };
+            return self.emit(0x8000 | x << 8 | y << 4 | operation);
+        }
+
+        match (operator, self.peek()) {
+            (":=", Some("random")) => {
+                self.next()?;
+                let mask = self.byte()?;
+                self.emit(0xc000 | x << 8 | mask as u16)
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                self.emit(0xf00a | x << 8)
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                self.emit(0xf007 | x << 8)
+            }
+            (":=", _) => {
+                let byte = self.byte()?;
+                self.emit(0x6000 | x << 8 | byte as u16)
+            }
+            ("+=", _) => {
+                let byte = self.byte()?;
+                self.emit(0x7000 | x << 8 | byte as u16)
+            }
+            ("-=", _) => {
+                let byte = self.byte()?;
+                self.emit(0x7000 | x << 8 | byte.wrapping_neg() as u16)
+            }
+            (operator, _) => Err(self.error(format!("{} needs a register on the right", operator))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let x = self.register()?;
+        let comparison = match self.next()? {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => return Ok(Condition::Key { x, pressed: true }),
+            "-key" => return Ok(Condition::Key { x, pressed: false }),
+            token => return Err(self.error(format!("{} is not a comparison", token))),
+        };
+        let operand = match self.peek().and_then(|token| self.as_register(token)) {
+            Some(y) => {
+                self.next()?;
+                Operand::Register(y)
+            }
+            None => Operand::Byte(self.byte()? as u16),
+        };
+
+        Ok(Condition::Compare { x, comparison, operand })
+    }
+
+    // Emits what skips the next instruction when the condition doesn't hold
+    fn skip_unless(&mut self, condition: Condition) -> Result<(), OctoError> {
+        let (x, comparison, operand) = match condition {
+            Condition::Key { x, pressed: true } => return self.emit(0xe0a1 | x << 8),
+            Condition::Key { x, pressed: false } => return self.emit(0xe09e | x << 8),
+            Condition::Compare { x, comparison, operand } => (x, comparison, operand),
+        };
+        match (comparison, operand) {
+            (Comparison::Equal, Operand::Byte(byte)) => self.emit(0x4000 | x << 8 | byte),
+            (Comparison::NotEqual, Operand::Byte(byte)) => self.emit(0x3000 | x << 8 | byte),
+            (Comparison::Equal, Operand::Register(y)) => self.emit(0x9000 | x << 8 | y << 4),
+            (Comparison::NotEqual, Operand::Register(y)) => self.emit(0x5000 | x << 8 | y << 4),
+            (comparison, operand) => {
+                // vf := operand, then subtract one way or the other so the flag says which is larger
+                match operand {
+                    Operand::Register(y) => self.emit(0x8f00 | y << 4)?,
+                    Operand::Byte(byte) => self.emit(0x6f00 | byte)?,
+                }
+                let (subtraction, flag) = match comparison {
+                    Comparison::Greater => (0x5, 0),
+                    Comparison::LessOrEqual => (0x5, 1),
+                    Comparison::Less => (0x7, 0),
+                    _ => (0x7, 1),
+                };
+                self.emit(0x8f00 | x << 4 | subtraction)?;
+                self.emit(0x4f00 | flag)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u16),
+    Byte(u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Key { x: u16, pressed: bool },
+    Compare { x: u16, comparison: Comparison, operand: Operand },
+}
+
+impl Condition {
+    fn negated(self) -> Condition {
+        match self {
+            Condition::Key { x, pressed } => Condition::Key { x, pressed: !pressed },
+            Condition::Compare { x, comparison, operand } => {
+                let comparison = match comparison {
+                    Comparison::Equal => Comparison::NotEqual,
+                    Comparison::NotEqual => Comparison::Equal,
+                    Comparison::Less => Comparison::GreaterOrEqual,
+                    Comparison::GreaterOrEqual => Comparison::Less,
+                    Comparison::Greater => Comparison::LessOrEqual,
+                    Comparison::LessOrEqual => Comparison::Greater,
+                };
+                Condition::Compare { x, comparison, operand }
+            }
+        }
+    }
+}
+
+// Octo's number forms: decimal, which may be negative, 0x hexadecimal and 0b binary
+fn parse_number(token: &str) -> Option<i32> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i32::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|character: char| character.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    if negative {
+        value.checked_neg()
+    } else {
+        Some(value)
+    }
+}
+
+fn parse_register(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+// Words that mean something on their own, so they can't name a label or be called
+fn is_reserved(token: &str) -> bool {
+    RESERVED.contains(&token) || token.starts_with(':') || token.starts_with('{')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, OctoError};
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    // Runs a program until it reaches the label stop, which should be a jump to itself
+    fn run(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new(Quirks::xochip(), 0);
+        interpreter.load_rom(&compile(source).unwrap()).unwrap();
+        for _ in 0..1000 {
+            interpreter.step().unwrap();
+        }
+
+        interpreter
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        match compile(source) {
+            Err(OctoError { line, message }) => (line, message),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn compiles_statements_to_opcodes() {
+        let source = "
+            : main
+                clear  v1 := 0x12  v1 += 3  v1 -= 1  v2 := v1  v2 ^= v1  v2 >>= v1  v2 =- v1
+                v3 := random 0x0F  v4 := key  v5 := delay  delay := v5  buzzer := v5
+                i := 0x345  i += v6  i := hex v7  i := bighex v7  i := long 0x1234
+                sprite v1 v2 5  bcd v8  save v9  load v9  save v1 - v3  load v1 - v3
+                hires lores scroll-down 4 scroll-up 2 scroll-left scroll-right plane 3 audio pitch := v2
+                saveflags v4  loadflags v4  jump0 0x300  native 0x123  exit  return ;
+        ";
+
+        let rom = compile(source).unwrap();
+
+        let opcodes: Vec<u16> = rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(
+            opcodes,
+            [
+                0x00e0, 0x6112, 0x7103, 0x71ff, 0x8210, 0x8213, 0x8216, 0x8217, 0xc30f, 0xf40a, 0xf507, 0xf515,
+                0xf518, 0xa345, 0xf61e, 0xf729, 0xf730, 0xf000, 0x1234, 0xd125, 0xf833, 0xf955, 0xf965, 0x5132,
+                0x5133, 0x00ff, 0x00fe, 0x00c4, 0x00d2, 0x00fc, 0x00fb, 0xf301, 0xf002, 0xf23a, 0xf475, 0xf485,
+                0xb300, 0x0123, 0x00fd, 0x00ee, 0x00ee,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_constants_and_aliases_resolve_in_either_order() {
+        let source = "
+            :const SPEED 3
+            :alias x v4
+            : main
+                x := SPEED
+                i := ball
+                draw
+                jump main
+            : draw
+                sprite x x SPEED
+                return
+            : ball 0xF0 0x90 0xF0
+        ";
+
+        let rom = compile(source).unwrap();
+
+        assert_eq!(rom, [0x64, 0x03, 0xa2, 0x0c, 0x22, 0x08, 0x12, 0x00, 0xd4, 0x43, 0x00, 0xee, 0xf0, 0x90, 0xf0]);
+    }
+
+    #[test]
+    fn main_after_other_code_gets_a_jump() {
+        let source = ": data 1 2 : main :next target v0 := 0 :unpack 0xA data :org 0x300 : spot jump spot";
+
+        let rom = compile(source).unwrap();
+
+        assert_eq!(&rom[..10], [0x12, 0x04, 0x01, 0x02, 0x60, 0x00, 0x60, 0xa2, 0x61, 0x02]);
+        assert_eq!(rom.len(), 0x102);
+        assert_eq!(&rom[0x100..], [0x13, 0x00]);
+        assert_eq!(compile(": main v1 := target : target").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn ifs_and_loops_run_as_written() {
+        // Counts v0 up to 10, v1 gets the even numbers seen and v2 the odd ones
+        let source = "
+            : main
+                loop
+                    while v0 != 10
+                    v0 += 1
+                    v3 := v0
+                    v4 := 1
+                    v3 &= v4
+                    if v3 == 0 begin
+                        v1 += 1
+                    else
+                        v2 += 1
+                    end
+                    if v0 == 10 then v5 := 0xAA
+                again
+            : stop jump stop
+        ";
+
+        let interpreter = run(source);
+
+        assert_eq!(&interpreter.registers()[..6], [10, 5, 5, 0, 1, 0xaa]);
+    }
+
+    #[test]
+    fn comparisons_hold_for_each_order() {
+        let mut results = Vec::new();
+        for (a, b) in [(1, 2), (2, 2), (3, 2)] {
+            let source = format!(
+                ": main v0 := {} v1 := {}
+                  if v0 < v1 then v2 := 1
+                  if v0 > 2 then v3 := 1
+                  if v0 <= v1 then v4 := 1
+                  if v0 >= 2 then v5 := 1
+                  if v0 != v1 then v6 := 1
+                  if v0 == 2 then v7 := 1
+                  : stop jump stop",
+                a, b
+            );
+            results.push(run(&source).registers()[2..8].to_vec());
+        }
+
+        assert_eq!(results, [[1, 0, 1, 0, 1, 0], [0, 0, 1, 1, 0, 1], [0, 1, 0, 1, 1, 0]]);
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        assert_eq!(error(": main\n  v0 := 300\n"), (2, "300 doesn't fit in a byte".to_string()));
+        assert_eq!(error(": main\n  loop\n  v0 += 1\n"), (2, "this block has no end or again".to_string()));
+        assert_eq!(error(": main\n\n  missing\n"), (3, "missing is not defined".to_string()));
+        assert_eq!(error(": main\n  :macro twice { }\n"), (2, ":macro needs Octo's own compiler".to_string()));
+        assert_eq!(error(": main else"), (1, "else without if ... begin".to_string()));
+        assert_eq!(error(": start clear"), (1, "main is not defined".to_string()));
+        assert_eq!(error(": main : main").1, "main is already defined");
+        assert_eq!(error(": main i := long").1, "the program ends early");
+    }
+}
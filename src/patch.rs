@@ -0,0 +1,492 @@
+// ROM patches in the two formats ROM hackers share fixes in. IPS is a list of (offset, bytes)
+// records and carries no checksums. BPS describes the patched ROM as runs copied from the original
+// or the patch itself, and ends with CRC-32s of the original, the result and the patch, so applying
+// it to the wrong ROM is an error rather than a broken game.
+use crate::hash::crc32;
+use crate::instruction::{AssembleError, Instruction};
+use crate::interpreter::{MAX_ROM_SIZE, PROGRAM_START};
+use std::fmt;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_END: &[u8] = b"EOF";
+// IPS offsets are 24 bits and records at most 64 KiB, far more than any CHIP-8 ROM needs
+const IPS_MAX_RECORD: usize = 0xffff;
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+}
+
+impl PatchFormat {
+    // Recognises a patch by its header
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+
+    // By file extension, for choosing which format to write
+    pub fn from_path(path: &str) -> Option<PatchFormat> {
+        let extension = path.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "ips" => Some(PatchFormat::Ips),
+            "bps" => Some(PatchFormat::Bps),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    Truncated,
+    Malformed(String),
+    SourceMismatch { expected: u32, actual: u32 }, // CRC-32s of the ROM the patch was made for and the one given
+    TargetMismatch { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 },
+    OutOfRange(u16), // an address outside the ROM
+    Assemble(AssembleError),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "the patch ends early"),
+            PatchError::Malformed(message) => write!(f, "malformed patch: {}", message),
+            PatchError::SourceMismatch { expected, actual } => write!(
+                f,
+                "the patch is for a ROM with CRC-32 {:08x}, this one has {:08x}",
+                expected, actual
+            ),
+            PatchError::TargetMismatch { expected, actual } => write!(
+                f,
+                "the patched ROM should have CRC-32 {:08x} but has {:08x}",
+                expected, actual
+            ),
+            PatchError::PatchChecksum { expected, actual } => {
+                write!(f, "the patch is corrupt, CRC-32 {:08x} where it says {:08x}", actual, expected)
+            }
+            PatchError::OutOfRange(address) => write!(f, "address {:03X} is outside the ROM", address),
+            PatchError::Assemble(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+// Applies an IPS or BPS patch, whichever it is
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+// A patch that turns original into modified
+pub fn create(format: PatchFormat, original: &[u8], modified: &[u8]) -> Vec<u8> {
+    match format {
+        PatchFormat::Ips => create_ips(original, modified),
+        PatchFormat::Bps => create_bps(original, modified),
+    }
+}
+
+// A copy of the ROM with the instruction at address replaced by text assembled, e.g.
+// patch_instruction(rom, 0x2f4, "LD V5, 0x09"). Addresses are where the ROM is loaded in memory, as
+// the disassembly shows them.
+pub fn patch_instruction(rom: &[u8], address: u16, text: &str) -> Result<Vec<u8>, PatchError> {
+    let instruction = Instruction::assemble(text).map_err(PatchError::Assemble)?;
+    let offset = (address as usize).checked_sub(PROGRAM_START as usize).ok_or(PatchError::OutOfRange(address))?;
+    if offset + 2 > rom.len() {
+        return Err(PatchError::OutOfRange(address));
+    }
+
+    let mut patched = rom.to_vec();
+    let (upper_byte, lower_byte) = instruction.encode();
+    patched[offset] = upper_byte;
+    patched[offset + 1] = lower_byte;
+
+    Ok(patched)
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(patch);
+    if reader.take(IPS_MAGIC.len())? != IPS_MAGIC {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut patched = rom.to_vec();
+
+    loop {
+        let offset = reader.take(3)?;
+        if offset == IPS_END {
+            break;
+        }
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let size = reader.u16_be()? as usize;
+        // A zero size is a run of one repeated byte
+        let data = match size {
+            0 => {
+                let count = reader.u16_be()? as usize;
+                vec![reader.take(1)?[0]; count]
+            }
+            _ => reader.take(size)?.to_vec(),
+        };
+
+        let end = offset + data.len();
+        if end > MAX_ROM_SIZE.max(patched.len()) {
+            return Err(PatchError::Malformed(format!("makes a {} byte ROM, too big to load", end)));
+        }
+        if patched.len() < end {
+            patched.resize(end, 0);
+        }
+        patched[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Some tools add the length to truncate the result to after EOF
+    if let Ok(length) = reader.take(3) {
+        patched.truncate(u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize);
+    }
+
+    Ok(patched)
+}
+
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = IPS_MAGIC.to_vec();
+    let differs = |offset: usize| original.get(offset) != modified.get(offset);
+
+    let mut offset = 0;
+    while offset < modified.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+        // A record header costs 5 bytes, so short unchanged gaps are cheaper to include
+        let mut end = offset + 1;
+        while end < modified.len() && end - offset < IPS_MAX_RECORD {
+            let gap = (end..modified.len().min(end + 5)).take_while(|offset| !differs(*offset)).count();
+            if gap == 5 || end + gap == modified.len() {
+                break;
+            }
+            end = (end + gap + 1).min(offset + IPS_MAX_RECORD);
+        }
+
+        patch.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - offset) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[offset..end]);
+        offset = end;
+    }
+
+    patch.extend_from_slice(IPS_END);
+    if modified.len() < original.len() {
+        patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+
+    patch
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_SIZE);
+    let checksum = |index: usize| {
+        u32::from_le_bytes([footer[index], footer[index + 1], footer[index + 2], footer[index + 3]])
+    };
+    let (source_crc, target_crc, patch_crc) = (checksum(0), checksum(4), checksum(8));
+
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if actual != patch_crc {
+        return Err(PatchError::PatchChecksum { expected: patch_crc, actual });
+    }
+    let actual = crc32(rom);
+    if actual != source_crc {
+        return Err(PatchError::SourceMismatch { expected: source_crc, actual });
+    }
+
+    let mut reader = Reader::new(body);
+    if reader.take(BPS_MAGIC.len())? != BPS_MAGIC {
+        return Err(PatchError::UnknownFormat);
+    }
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.take(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(PatchError::Malformed(format!("made for a {} byte ROM", source_size)));
+    }
+    if target_size > MAX_ROM_SIZE {
+        return Err(PatchError::Malformed(format!("makes a {} byte ROM, too big to load", target_size)));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    let relative = |offset: usize, delta: usize| {
+        let magnitude = delta >> 1;
+        match delta & 1 {
+            0 => offset.checked_add(magnitude),
+            _ => offset.checked_sub(magnitude),
+        }
+    };
+
+    while !reader.is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(PatchError::Malformed("writes past the end of the ROM".to_string()));
+        }
+        let malformed = || PatchError::Malformed("copies from outside the ROM".to_string());
+
+        match action & 3 {
+            // Source read, the original's bytes at the same offset
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..start + length).ok_or_else(malformed)?);
+            }
+            // Target read, bytes from the patch
+            1 => target.extend_from_slice(reader.take(length)?),
+            // Source copy from anywhere in the original
+            2 => {
+                source_offset = relative(source_offset, reader.number()?).ok_or_else(malformed)?;
+                target.extend_from_slice(rom.get(source_offset..source_offset + length).ok_or_else(malformed)?);
+                source_offset += length;
+            }
+            // Target copy from what has been written so far, byte by byte so runs can overlap
+            _ => {
+                target_offset = relative(target_offset, reader.number()?).ok_or_else(malformed)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or_else(malformed)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    let actual = crc32(&target);
+    if target.len() != target_size || actual != target_crc {
+        return Err(PatchError::TargetMismatch { expected: target_crc, actual });
+    }
+
+    Ok(target)
+}
+
+// Runs that match the original at the same offset are read from it and everything else is stored
+// in the patch. ROMs are small enough that searching for moved code isn't worth it.
+pub fn create_bps(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_number(&mut patch, original.len());
+    write_number(&mut patch, modified.len());
+    write_number(&mut patch, 0);
+
+    let same = |offset: usize| original.get(offset) == modified.get(offset);
+    let mut offset = 0;
+    while offset < modified.len() {
+        let from_source = same(offset);
+        let length = (offset..modified.len()).take_while(|offset| same(*offset) == from_source).count();
+
+        write_number(&mut patch, ((length - 1) << 2) | if from_source { 0 } else { 1 });
+        if !from_source {
+            patch.extend_from_slice(&modified[offset..offset + length]);
+        }
+        offset += length;
+    }
+
+    patch.extend_from_slice(&crc32(original).to_le_bytes());
+    patch.extend_from_slice(&crc32(modified).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+    patch
+}
+
+// BPS numbers are little endian groups of 7 bits, with the top bit set on the last. Each group
+// after the first also counts one more, so every number has exactly one encoding.
+fn write_number(patch: &mut Vec<u8>, mut number: usize) {
+    loop {
+        let bits = (number & 0x7f) as u8;
+        number >>= 7;
+        if number == 0 {
+            patch.push(0x80 | bits);
+            return;
+        }
+        patch.push(bits);
+        number -= 1;
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self.bytes.get(self.position..self.position + length).ok_or(PatchError::Truncated)?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn u16_be(&mut self) -> Result<u16, PatchError> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let overflow = || PatchError::Malformed("number too large".to_string());
+        let (mut number, mut shift) = (0u64, 1u64);
+        loop {
+            let byte = self.take(1)?[0];
+            number += (byte & 0x7f) as u64 * shift;
+            if byte & 0x80 != 0 {
+                return (number <= usize::MAX as u64).then_some(number as usize).ok_or_else(overflow);
+            }
+            // Anything past 49 bits isn't a ROM size or offset
+            if shift >= 1 << 42 {
+                return Err(overflow());
+            }
+            shift <<= 7;
+            number += shift;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply, create, patch_instruction, write_number, PatchError, PatchFormat, Reader, BPS_MAGIC, IPS_END, IPS_MAGIC,
+    };
+    use crate::hash::crc32;
+    use crate::interpreter::MAX_ROM_SIZE;
+
+    fn roms() -> (Vec<u8>, Vec<u8>) {
+        let original: Vec<u8> = (0..200u8).collect();
+        let mut modified = original.clone();
+        modified[3] = 0xff;
+        modified[5] = 0xfe;
+        modified[150..160].fill(0);
+        modified.extend_from_slice(&[1, 2, 3]);
+
+        (original, modified)
+    }
+
+    #[test]
+    fn created_patches_apply_back_to_the_modified_rom() {
+        let (original, modified) = roms();
+        let shorter = original[..120].to_vec();
+
+        for format in [PatchFormat::Ips, PatchFormat::Bps] {
+            for target in [&modified, &shorter, &original] {
+                let patch = create(format, &original, target);
+
+                assert_eq!(PatchFormat::detect(&patch), Some(format));
+                assert_eq!(apply(&original, &patch).as_ref(), Ok(target), "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn ips_records_merge_short_gaps_and_expand_runs() {
+        let (original, modified) = roms();
+        // PATCH, a run at 000200 of 4 bytes of 0x11, then EOF
+        let run = b"PATCH\x00\x02\x00\x00\x00\x00\x04\x11EOF";
+
+        let patch = create(PatchFormat::Ips, &original, &modified);
+        let patched = apply(&[0; 4], run).unwrap();
+
+        // One record for 3..=5, one for 150..160 and one for the bytes added at the end
+        assert_eq!(patch.len(), 5 + (5 + 3) + (5 + 10) + (5 + 3) + 3);
+        assert_eq!(patched.len(), 0x204);
+        assert_eq!(&patched[0x200..], &[0x11; 4]);
+    }
+
+    #[test]
+    fn bps_checks_the_rom_and_the_patch() {
+        let (original, modified) = roms();
+        let patch = create(PatchFormat::Bps, &original, &modified);
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+
+        let wrong_rom = apply(&modified, &patch);
+        let corrupt = apply(&original, &corrupt);
+
+        assert_eq!(wrong_rom, Err(PatchError::SourceMismatch { expected: crc32(&original), actual: crc32(&modified) }));
+        assert!(matches!(corrupt, Err(PatchError::PatchChecksum { .. })));
+        assert_eq!(apply(&original, b"nope"), Err(PatchError::UnknownFormat));
+        assert_eq!(apply(&original, &patch[..8]), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn bps_rejects_targets_too_big_to_load() {
+        let rom = [0x12, 0x00];
+        let mut patch = BPS_MAGIC.to_vec();
+        for number in [rom.len(), MAX_ROM_SIZE + 1, 0] {
+            write_number(&mut patch, number);
+        }
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        let result = apply(&rom, &patch);
+
+        assert_eq!(result, Err(PatchError::Malformed(format!("makes a {} byte ROM, too big to load", MAX_ROM_SIZE + 1))));
+    }
+
+    #[test]
+    fn ips_rejects_records_past_what_can_be_loaded() {
+        let rom = [0x12, 0x00];
+        let mut patch = IPS_MAGIC.to_vec();
+        // A run of 16 zeros at 0xffffff
+        patch.extend_from_slice(&[0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x10, 0x00]);
+        patch.extend_from_slice(IPS_END);
+        let mut longest = IPS_MAGIC.to_vec();
+        longest.extend_from_slice(&(MAX_ROM_SIZE as u32 - 1).to_be_bytes()[1..]);
+        longest.extend_from_slice(&[0x00, 0x01, 0xaa]);
+        longest.extend_from_slice(IPS_END);
+
+        let result = apply(&rom, &patch);
+        let longest = apply(&rom, &longest).unwrap();
+
+        assert_eq!(result, Err(PatchError::Malformed(format!("makes a {} byte ROM, too big to load", 0xffffff + 16))));
+        assert_eq!(longest.len(), MAX_ROM_SIZE);
+    }
+
+    #[test]
+    fn bps_numbers_round_trip() {
+        for number in [0, 1, 127, 128, 16511, 16512, 1 << 30] {
+            let mut bytes = Vec::new();
+            write_number(&mut bytes, number);
+
+            assert_eq!(Reader::new(&bytes).number(), Ok(number));
+        }
+    }
+
+    #[test]
+    fn patch_instruction_replaces_two_bytes_at_the_memory_address() {
+        let rom = [0x60, 0x03, 0x12, 0x00];
+
+        let patched = patch_instruction(&rom, 0x200, "LD V0, 0x09");
+
+        assert_eq!(patched, Ok(vec![0x60, 0x09, 0x12, 0x00]));
+        assert_eq!(patch_instruction(&rom, 0x203, "CLS"), Err(PatchError::OutOfRange(0x203)));
+        assert_eq!(patch_instruction(&rom, 0x1fe, "CLS"), Err(PatchError::OutOfRange(0x1fe)));
+        assert!(matches!(patch_instruction(&rom, 0x200, "LD V0"), Err(PatchError::Assemble(_))));
+    }
+}
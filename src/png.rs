@@ -0,0 +1,108 @@
+// Minimal PNG writer for images the tools produce, such as the coverage heatmap. The image data is
+// stored uncompressed inside the zlib stream, which every PNG reader accepts and which keeps the
+// writer a few dozen lines instead of a deflate implementation.
+use crate::hash::{adler32, crc32};
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// Writes 8 bit RGB pixels, three bytes per pixel, rows top to bottom
+pub fn write_rgb(writer: &mut impl Write, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let row_size = width as usize * 3;
+    if pixels.len() != row_size * height as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixel data doesn't match the image size"));
+    }
+
+    writer.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, RGB, deflate, no filter, no interlace
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // Each row starts with its filter type, 0 for none
+    let mut filtered = Vec::with_capacity((row_size + 1) * height as usize);
+    for row in pixels.chunks(row_size.max(1)).take(height as usize) {
+        filtered.push(0);
+        filtered.extend_from_slice(row);
+    }
+    write_chunk(writer, b"IDAT", &zlib_stored(&filtered))?;
+
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    writer.write_all(&crc32(&checked).to_be_bytes())
+}
+
+// A zlib stream of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+
+    stream
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_rgb, zlib_stored};
+    use crate::hash::crc32;
+
+    #[test]
+    fn writes_chunks_with_valid_checksums() {
+        let pixels = [0xff, 0, 0, 0, 0xff, 0];
+        let mut png = Vec::new();
+
+        write_rgb(&mut png, 2, 1, &pixels).unwrap();
+
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+        let mut offset = 8;
+        let mut kinds = Vec::new();
+        while offset < png.len() {
+            let length = u32::from_be_bytes([png[offset], png[offset + 1], png[offset + 2], png[offset + 3]]) as usize;
+            let body = &png[offset + 4..offset + 8 + length];
+            let crc = &png[offset + 8 + length..offset + 12 + length];
+            assert_eq!(crc, crc32(body).to_be_bytes());
+            kinds.push(String::from_utf8(body[..4].to_vec()).unwrap());
+            offset += 12 + length;
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn stored_blocks_split_long_data() {
+        let data = vec![7; 70000];
+
+        let stream = zlib_stored(&data);
+
+        // Header, two block headers of five bytes, the data and the Adler-32
+        assert_eq!(stream.len(), 2 + 5 + 5 + 70000 + 4);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + 0xffff], 1);
+    }
+
+    #[test]
+    fn rejects_pixels_of_the_wrong_size() {
+        assert!(write_rgb(&mut Vec::new(), 2, 2, &[0; 6]).is_err());
+    }
+}
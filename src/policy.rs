@@ -0,0 +1,201 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The kinds of ExecError a ROM can run into. Each class has its own policy for what the
+// interpreter does about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    StackOverflow,      // 2nnn with the stack full
+    StackUnderflow,     // 00EE with the stack empty
+    MemoryOutOfBounds,  // an instruction reading or writing through I past the end of memory
+    PcOutOfBounds,      // the program counter running off the end of memory
+    UnsupportedSys,     // 0nnn, a call to a machine code routine
+    InvalidInstruction, // an opcode that doesn't decode
+}
+
+pub const ERROR_CLASSES: [ErrorClass; 6] = [
+    ErrorClass::StackOverflow,
+    ErrorClass::StackUnderflow,
+    ErrorClass::MemoryOutOfBounds,
+    ErrorClass::PcOutOfBounds,
+    ErrorClass::UnsupportedSys,
+    ErrorClass::InvalidInstruction,
+];
+
+impl ErrorClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorClass::StackOverflow => "stack_overflow",
+            ErrorClass::StackUnderflow => "stack_underflow",
+            ErrorClass::MemoryOutOfBounds => "memory_out_of_bounds",
+            ErrorClass::PcOutOfBounds => "pc_out_of_bounds",
+            ErrorClass::UnsupportedSys => "unsupported_sys",
+            ErrorClass::InvalidInstruction => "invalid_instruction",
+        }
+    }
+
+    fn index(&self) -> usize {
+        ERROR_CLASSES.iter().position(|class| class == self).unwrap()
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// What the interpreter does when a ROM runs into an error:
+//
+// Halt stops with an ExecError and leaves the machine as it was before the instruction.
+//
+// Wrap carries on the way hardware with wrapping counters would. A call on a full stack forgets the
+// oldest return address, a return on an empty stack returns to the top slot, addresses through I
+// wrap round to 0 and so does the program counter. Classes with nothing to wrap treat it as Ignore.
+//
+// Ignore skips the instruction as if it were a no-op. A program counter past the end of memory has
+// no next instruction, so the machine idles there with the timers still running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+    Halt,
+    Wrap,
+    Ignore,
+}
+
+impl fmt::Display for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorPolicy::Halt => write!(f, "halt"),
+            ErrorPolicy::Wrap => write!(f, "wrap"),
+            ErrorPolicy::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+// A policy for each error class. By default everything halts except SYS, which modern
+// interpreters have always ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorPolicies {
+    policies: [ErrorPolicy; 6],
+}
+
+impl ErrorPolicies {
+    pub fn get(&self, class: ErrorClass) -> ErrorPolicy {
+        self.policies[class.index()]
+    }
+
+    pub fn set(&mut self, class: ErrorClass, policy: ErrorPolicy) {
+        self.policies[class.index()] = policy;
+    }
+}
+
+impl Default for ErrorPolicies {
+    fn default() -> ErrorPolicies {
+        let mut policies = ErrorPolicies { policies: [ErrorPolicy::Halt; 6] };
+        policies.set(ErrorClass::UnsupportedSys, ErrorPolicy::Ignore);
+
+        policies
+    }
+}
+
+// Written as a comma separated list of class=policy for the classes that differ from the default,
+// e.g. stack_overflow=wrap,invalid_instruction=ignore. This is the form used on the command line.
+impl fmt::Display for ErrorPolicies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let default = ErrorPolicies::default();
+        let changed: Vec<String> = ERROR_CLASSES
+            .iter()
+            .filter(|class| self.get(**class) != default.get(**class))
+            .map(|class| format!("{}={}", class, self.get(*class)))
+            .collect();
+
+        write!(f, "{}", changed.join(","))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParsePoliciesError(pub String);
+
+impl fmt::Display for ParsePoliciesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not an error policy, expected e.g. stack_overflow=wrap", self.0)
+    }
+}
+
+impl std::error::Error for ParsePoliciesError {}
+
+// Settings not in the list keep their default. "all" sets every class at once.
+impl FromStr for ErrorPolicies {
+    type Err = ParsePoliciesError;
+
+    fn from_str(s: &str) -> Result<ErrorPolicies, ParsePoliciesError> {
+        let mut policies = ErrorPolicies::default();
+
+        for setting in s.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let error = || ParsePoliciesError(setting.to_string());
+            let (name, policy) = setting.split_once('=').ok_or_else(error)?;
+            let policy = match policy.trim() {
+                "halt" => ErrorPolicy::Halt,
+                "wrap" => ErrorPolicy::Wrap,
+                "ignore" => ErrorPolicy::Ignore,
+                _ => return Err(error()),
+            };
+
+            match name.trim() {
+                "all" => policies.policies = [policy; 6],
+                name => {
+                    let class = ERROR_CLASSES.iter().find(|class| class.name() == name).ok_or_else(error)?;
+                    policies.set(*class, policy);
+                }
+            }
+        }
+
+        Ok(policies)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ErrorClass, ErrorPolicies, ErrorPolicy, ParsePoliciesError, ERROR_CLASSES};
+
+    #[test]
+    fn everything_but_sys_halts_by_default() {
+        let policies = ErrorPolicies::default();
+
+        for class in ERROR_CLASSES.iter() {
+            let expected = if *class == ErrorClass::UnsupportedSys { ErrorPolicy::Ignore } else { ErrorPolicy::Halt };
+            assert_eq!(policies.get(*class), expected, "{}", class);
+        }
+        assert_eq!(policies.to_string(), "");
+    }
+
+    #[test]
+    fn policies_round_trip() {
+        let mut policies = ErrorPolicies::default();
+        policies.set(ErrorClass::StackOverflow, ErrorPolicy::Wrap);
+        policies.set(ErrorClass::UnsupportedSys, ErrorPolicy::Halt);
+
+        let text = policies.to_string();
+
+        assert_eq!(text, "stack_overflow=wrap,unsupported_sys=halt");
+        assert_eq!(text.parse::<ErrorPolicies>(), Ok(policies));
+    }
+
+    #[test]
+    fn all_sets_every_class() {
+        let policies: ErrorPolicies = "all=ignore,pc_out_of_bounds=wrap".parse().unwrap();
+
+        assert_eq!(policies.get(ErrorClass::StackUnderflow), ErrorPolicy::Ignore);
+        assert_eq!(policies.get(ErrorClass::PcOutOfBounds), ErrorPolicy::Wrap);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_classes_and_policies() {
+        assert_eq!(
+            "stack_overflow=explode".parse::<ErrorPolicies>(),
+            Err(ParsePoliciesError("stack_overflow=explode".to_string()))
+        );
+        assert_eq!("warp_drive=halt".parse::<ErrorPolicies>(), Err(ParsePoliciesError("warp_drive=halt".to_string())));
+        assert_eq!("halt".parse::<ErrorPolicies>(), Err(ParsePoliciesError("halt".to_string())));
+    }
+}
@@ -0,0 +1,194 @@
+// Runs many independent machines across threads, for search, reinforcement learning and genetic
+// programming loops that evaluate thousands of ROMs or input sequences at a time. Every machine
+// starts as a copy of a template interpreter, so quirks, cycles per frame, error policies and the
+// backend are configured once on the template, then gets its job's ROM, seed and inputs.
+use crate::hash::fnv1a;
+use crate::interpreter::{ExecError, Interpreter, LoadError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub rom: Vec<u8>,
+    pub seed: u64,
+    pub inputs: Vec<u16>, // the keys held on each frame, one frame per entry
+}
+
+impl Job {
+    pub fn new(rom: Vec<u8>, seed: u64, inputs: Vec<u16>) -> Job {
+        Job { rom, seed, inputs }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineResult {
+    pub frames: u64, // frames completed, fewer than the inputs if the machine halted
+    pub cycles: u64,
+    pub framebuffer_hash: u64,
+    pub memory_digest: u64,
+    pub state_hash: u64,
+    pub halt: Option<ExecError>,
+}
+
+impl MachineResult {
+    pub fn of(interpreter: &Interpreter, halt: Option<ExecError>) -> MachineResult {
+        let rows: Vec<u8> = interpreter.display().rows().iter().flat_map(|row| row.to_le_bytes()).collect();
+
+        MachineResult {
+            frames: interpreter.frames(),
+            cycles: interpreter.cycles(),
+            framebuffer_hash: fnv1a(&rows),
+            memory_digest: fnv1a(interpreter.memory()),
+            state_hash: interpreter.state_hash(),
+            halt,
+        }
+    }
+}
+
+pub struct MachinePool {
+    template: Interpreter,
+    threads: usize,
+}
+
+impl MachinePool {
+    // Uses as many threads as the machine has cores
+    pub fn new(template: Interpreter) -> MachinePool {
+        let threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+
+        MachinePool { template, threads }
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    // Runs every job and returns their results in the same order. A job whose ROM doesn't fit in
+    // memory gets the LoadError instead.
+    pub fn run(&self, jobs: &[Job]) -> Vec<Result<MachineResult, LoadError>> {
+        self.run_with(jobs, MachineResult::of)
+    }
+
+    // Same as run, but each machine is summed up by evaluate once its job has finished, e.g. to
+    // read a score out of memory for a fitness function
+    pub fn run_with<T: Send>(
+        &self,
+        jobs: &[Job],
+        evaluate: impl Fn(&Interpreter, Option<ExecError>) -> T + Sync,
+    ) -> Vec<Result<T, LoadError>> {
+        // Jobs are handed out one at a time, so a thread that draws short jobs takes more of them
+        let next_job = AtomicUsize::new(0);
+        let mut results: Vec<Option<Result<T, LoadError>>> = (0..jobs.len()).map(|_| None).collect();
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(jobs.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut finished = Vec::new();
+                        loop {
+                            let index = next_job.fetch_add(1, Ordering::Relaxed);
+                            match jobs.get(index) {
+                                Some(job) => finished.push((index, self.run_job(job, &evaluate))),
+                                None => return finished,
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            for worker in workers {
+                for (index, result) in worker.join().unwrap() {
+                    results[index] = Some(result);
+                }
+            }
+        });
+
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+
+    fn run_job<T>(&self, job: &Job, evaluate: &impl Fn(&Interpreter, Option<ExecError>) -> T) -> Result<T, LoadError> {
+        let mut interpreter = self.template.clone();
+        interpreter.set_seed(job.seed);
+        interpreter.load_rom(&job.rom)?;
+
+        let mut halt = None;
+        for keys in job.inputs.iter() {
+            interpreter.set_keys(*keys);
+            if let Err(error) = interpreter.run_frame() {
+                halt = Some(error);
+                break;
+            }
+        }
+
+        Ok(evaluate(&interpreter, halt))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Job, MachinePool, MachineResult};
+    use crate::interpreter::{Interpreter, LoadError, MAX_ROM_SIZE};
+    use crate::policy::ErrorClass;
+    use crate::quirks::Quirks;
+
+    // RND V0, FF; LD I, 300; LD [I], V0; ADD V1, 1; JP 206
+    const RANDOM_BYTE: [u8; 10] = [0xc0, 0xff, 0xa3, 0x00, 0xf0, 0x55, 0x71, 0x01, 0x12, 0x06];
+
+    fn jobs(count: u64) -> Vec<Job> {
+        (0..count).map(|seed| Job::new(RANDOM_BYTE.to_vec(), seed, vec![0; 5])).collect()
+    }
+
+    #[test]
+    fn results_match_running_each_job_alone_in_job_order() {
+        let mut pool = MachinePool::new(Interpreter::new(Quirks::chip8(), 0));
+        pool.set_threads(4);
+        let jobs = jobs(20);
+
+        let results = pool.run(&jobs);
+
+        for (job, result) in jobs.iter().zip(results) {
+            let mut interpreter = Interpreter::new(Quirks::chip8(), job.seed);
+            interpreter.load_rom(&job.rom).unwrap();
+            for _ in 0..5 {
+                interpreter.run_frame().unwrap();
+            }
+            assert_eq!(result, Ok(MachineResult::of(&interpreter, None)));
+        }
+    }
+
+    #[test]
+    fn reports_why_a_machine_halted() {
+        let pool = MachinePool::new(Interpreter::new(Quirks::chip8(), 0));
+        // RET with nothing on the stack
+        let jobs = [Job::new(vec![0x00, 0xee], 0, vec![0; 3]), Job::new(vec![0; MAX_ROM_SIZE + 1], 0, vec![0])];
+
+        let results = pool.run(&jobs);
+
+        let halted = results[0].as_ref().unwrap();
+        assert_eq!(halted.frames, 0);
+        assert_eq!(halted.halt.as_ref().map(|error| error.class()), Some(ErrorClass::StackUnderflow));
+        assert_eq!(results[1], Err(LoadError::RomTooLarge(MAX_ROM_SIZE + 1)));
+    }
+
+    #[test]
+    fn run_with_evaluates_each_machine() {
+        let mut template = Interpreter::new(Quirks::chip8(), 0);
+        template.set_cycles_per_frame(4);
+        let pool = MachinePool::new(template);
+
+        let bytes = pool.run_with(&jobs(3), |interpreter, _| interpreter.memory()[0x300]);
+
+        let expected: Vec<Result<u8, LoadError>> = (0..3)
+            .map(|seed| {
+                let mut interpreter = Interpreter::new(Quirks::chip8(), seed);
+                interpreter.load_rom(&RANDOM_BYTE).unwrap();
+                interpreter.step().unwrap();
+                Ok(interpreter.registers()[0])
+            })
+            .collect();
+        assert_eq!(bytes, expected);
+    }
+}
@@ -0,0 +1,359 @@
+// Execution profiler for finding where a ROM spends its time, e.g.
+// interpreter.run_frame_with(&mut profiler). Time is counted in instructions executed, the only
+// clock the machine has, so profiles are the same on every host and from run to run.
+//
+// Subroutines are followed through the stack pointer rather than by decoding CALL and RET, so a
+// call that fails or is ignored under the error policy isn't counted. Each subroutine is known by
+// the address it was called at, and everything outside a subroutine belongs to main.
+use crate::instruction::{AddressInstruction, AddressInstructionType, Instruction};
+use crate::interpreter::{Interpreter, Observer, MEMORY_SIZE};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// How many entries each table in the report shows
+const REPORT_ROWS: usize = 10;
+
+// A backward jump and the instructions it repeats
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotLoop {
+    pub start: u16,
+    pub end: u16, // the address of the jump back to start
+    pub iterations: u64,
+    pub cycles: u64, // instructions executed between start and end, not counting subroutines
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutineProfile {
+    pub address: u16,
+    pub calls: u64,
+    pub cycles: u64, // including the subroutines it calls
+    pub self_cycles: u64,
+}
+
+pub struct Profiler {
+    address_counts: Vec<u64>,
+    opcode_counts: Vec<u64>,
+    opcodes: Vec<u16>, // the last opcode executed at each address
+    routines: HashMap<u16, RoutineProfile>,
+    main_cycles: u64,
+    // Entry addresses of the subroutines being run, innermost last
+    stack: Vec<u16>,
+    // Cycles per distinct stack, with the current stack's cycles held back until it changes
+    stacks: HashMap<Vec<u16>, u64>,
+    stack_cycles: u64,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            address_counts: vec![0; MEMORY_SIZE],
+            opcode_counts: vec![0; 1 << 16],
+            opcodes: vec![0; MEMORY_SIZE],
+            routines: HashMap::new(),
+            main_cycles: 0,
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            stack_cycles: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Executions of each address in memory
+    pub fn address_counts(&self) -> &[u64] {
+        &self.address_counts
+    }
+
+    // Backward jumps, the most time consuming first
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = (0..MEMORY_SIZE)
+            .filter(|address| self.address_counts[*address] > 0)
+            .filter_map(|address| match decode(self.opcodes[address]) {
+                Some(Instruction::AddressInstruction(AddressInstruction {
+                    instruction_type: AddressInstructionType::JumpDirect,
+                    address: target,
+                })) if target as usize <= address => Some(HotLoop {
+                    start: target,
+                    end: address as u16,
+                    iterations: self.address_counts[address],
+                    cycles: self.address_counts[target as usize..=address].iter().sum(),
+                }),
+                _ => None,
+            })
+            .collect();
+        loops.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+
+        loops
+    }
+
+    // Subroutines, the most called first
+    pub fn routines(&self) -> Vec<RoutineProfile> {
+        let mut routines: Vec<RoutineProfile> = self.routines.values().cloned().collect();
+        routines.sort_by(|a, b| b.calls.cmp(&a.calls).then(b.cycles.cmp(&a.cycles)).then(a.address.cmp(&b.address)));
+
+        routines
+    }
+
+    // Executions of each kind of instruction, such as TwoRegisterInstruction::Add, the most
+    // common first. Opcodes that don't decode are counted as invalid.
+    pub fn opcode_mix(&self) -> Vec<(String, u64)> {
+        let mut mix: HashMap<String, u64> = HashMap::new();
+        for (opcode, count) in self.opcode_counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let name = match decode(opcode as u16) {
+                Some(instruction) => variant_name(&instruction),
+                None => "invalid".to_string(),
+            };
+            *mix.entry(name).or_insert(0) += count;
+        }
+
+        let mut mix: Vec<(String, u64)> = mix.into_iter().collect();
+        mix.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        mix
+    }
+
+    // One line per call stack, in the folded format flamegraph.pl and inferno read, e.g.
+    // main;sub_2A4;sub_31C 1200
+    pub fn folded_stacks(&self) -> String {
+        let mut stacks = self.stacks.clone();
+        if self.stack_cycles > 0 {
+            *stacks.entry(self.stack.clone()).or_insert(0) += self.stack_cycles;
+        }
+        let mut lines: Vec<String> = stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let mut line = "main".to_string();
+                for address in stack {
+                    write!(line, ";sub_{:03X}", address).unwrap();
+                }
+                format!("{} {}", line, cycles)
+            })
+            .collect();
+        lines.sort();
+
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let share = |cycles: u64| 100.0 * cycles as f64 / self.cycles.max(1) as f64;
+
+        writeln!(report, "{} instructions, {} in main", self.cycles, self.main_cycles).unwrap();
+
+        writeln!(report, "\nHottest loops").unwrap();
+        writeln!(report, "  {:>10} {:>6} {:>10}  range", "cycles", "share", "iterations").unwrap();
+        for hot_loop in self.hot_loops().iter().take(REPORT_ROWS) {
+            writeln!(
+                report,
+                "  {:>10} {:>5.1}% {:>10}  {:03X}-{:03X}",
+                hot_loop.cycles,
+                share(hot_loop.cycles),
+                hot_loop.iterations,
+                hot_loop.start,
+                hot_loop.end
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nMost called routines").unwrap();
+        writeln!(report, "  {:>10} {:>10} {:>6} {:>10}  routine", "calls", "cycles", "share", "self").unwrap();
+        for routine in self.routines().iter().take(REPORT_ROWS) {
+            writeln!(
+                report,
+                "  {:>10} {:>10} {:>5.1}% {:>10}  sub_{:03X}",
+                routine.calls,
+                routine.cycles,
+                share(routine.cycles),
+                routine.self_cycles,
+                routine.address
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nOpcode mix").unwrap();
+        writeln!(report, "  {:>10} {:>6}  instruction", "count", "share").unwrap();
+        for (name, count) in self.opcode_mix() {
+            writeln!(report, "  {:>10} {:>5.1}%  {}", count, share(count), name).unwrap();
+        }
+
+        writeln!(report, "\nHottest addresses").unwrap();
+        writeln!(report, "  {:>10} {:>6}  address", "count", "share").unwrap();
+        let mut addresses: Vec<usize> = (0..MEMORY_SIZE).filter(|address| self.address_counts[*address] > 0).collect();
+        addresses.sort_by(|a, b| self.address_counts[*b].cmp(&self.address_counts[*a]).then(a.cmp(b)));
+        for address in addresses.into_iter().take(REPORT_ROWS) {
+            let count = self.address_counts[address];
+            let mnemonic = match decode(self.opcodes[address]) {
+                Some(instruction) => instruction.to_string(),
+                None => "???".to_string(),
+            };
+            writeln!(report, "  {:>10} {:>5.1}%  {:03X} {}", count, share(count), address, mnemonic).unwrap();
+        }
+
+        report
+    }
+
+    // Brings the shadow stack in line with the interpreter's stack depth
+    fn follow_stack(&mut self, pc: u16, depth: usize) {
+        if depth == self.stack.len() {
+            return;
+        }
+
+        if self.stack_cycles > 0 {
+            *self.stacks.entry(self.stack.clone()).or_insert(0) += self.stack_cycles;
+            self.stack_cycles = 0;
+        }
+        self.stack.truncate(depth);
+        while self.stack.len() < depth {
+            self.stack.push(pc);
+            let routine = self.routines.entry(pc).or_insert_with(|| RoutineProfile {
+                address: pc,
+                ..RoutineProfile::default()
+            });
+            routine.calls += 1;
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl Observer for Profiler {
+    fn before_step(&mut self, interpreter: &Interpreter, opcode: u16) {
+        let pc = interpreter.pc();
+        self.follow_stack(pc, interpreter.sp());
+
+        let address = pc as usize % MEMORY_SIZE;
+        self.address_counts[address] += 1;
+        self.opcodes[address] = opcode;
+        self.opcode_counts[opcode as usize] += 1;
+        self.cycles += 1;
+        self.stack_cycles += 1;
+
+        match self.stack.last() {
+            Some(innermost) => self.routines.get_mut(innermost).unwrap().self_cycles += 1,
+            None => self.main_cycles += 1,
+        }
+        // A recursive routine is only charged once for each instruction
+        for (depth, routine) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(routine) {
+                self.routines.get_mut(routine).unwrap().cycles += 1;
+            }
+        }
+    }
+}
+
+fn decode(opcode: u16) -> Option<Instruction> {
+    Instruction::decode(((opcode >> 8) as u8, opcode as u8))
+}
+
+// The Instruction variant and its instruction type, without operands
+fn variant_name(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::NoArgInstruction(instruction_type) => format!("{}::{:?}", instruction.family(), instruction_type),
+        Instruction::AddressInstruction(instruction) => {
+            format!("AddressInstruction::{:?}", instruction.instruction_type)
+        }
+        Instruction::RegisterByteInstruction(instruction) => {
+            format!("RegisterByteInstruction::{:?}", instruction.instruction_type)
+        }
+        Instruction::SingleRegisterInstruction(instruction) => {
+            format!("SingleRegisterInstruction::{:?}", instruction.instruction_type)
+        }
+        Instruction::TwoRegisterInstruction(instruction) => {
+            format!("TwoRegisterInstruction::{:?}", instruction.instruction_type)
+        }
+        Instruction::DrawInstruction(_) => "DrawInstruction".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HotLoop, Profiler, RoutineProfile};
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    // 200: LD V0, 3
+    // 202: CALL 20C
+    // 204: ADD V0, FF
+    // 206: SE V0, 0
+    // 208: JP 202
+    // 20A: JP 20A
+    // 20C: CALL 210
+    // 20E: RET
+    // 210: RET
+    const PROGRAM: [u8; 18] = [
+        0x60, 0x03, 0x22, 0x0c, 0x70, 0xff, 0x30, 0x00, 0x12, 0x02, 0x12, 0x0a, 0x22, 0x10, 0x00, 0xee, 0x00, 0xee,
+    ];
+
+    fn profile(steps: usize) -> Profiler {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&PROGRAM).unwrap();
+        let mut profiler = Profiler::new();
+
+        for _ in 0..steps {
+            interpreter.step_with(&mut profiler).unwrap();
+        }
+
+        profiler
+    }
+
+    #[test]
+    fn counts_executions_per_address_and_instruction() {
+        // Twice round the loop of seven instructions, out through the skip, then four spins
+        let profiler = profile(1 + 7 + 7 + 6 + 4);
+
+        assert_eq!(profiler.cycles(), 25);
+        assert_eq!(profiler.address_counts()[0x202], 3);
+        assert_eq!(profiler.address_counts()[0x208], 2);
+        assert_eq!(profiler.address_counts()[0x20a], 4);
+        assert_eq!(profiler.opcode_mix()[0], ("AddressInstruction::Call".to_string(), 6));
+        assert!(profiler.opcode_mix().contains(&("NoArgInstruction::Return".to_string(), 6)));
+    }
+
+    #[test]
+    fn times_called_subroutines() {
+        let profiler = profile(1 + 7 + 7 + 6 + 4);
+
+        let routines = profiler.routines();
+
+        assert_eq!(
+            routines,
+            vec![
+                RoutineProfile { address: 0x20c, calls: 3, cycles: 9, self_cycles: 6 },
+                RoutineProfile { address: 0x210, calls: 3, cycles: 3, self_cycles: 3 },
+            ]
+        );
+        assert_eq!(
+            profiler.folded_stacks(),
+            "main 16\nmain;sub_20C 6\nmain;sub_20C;sub_210 3\n"
+        );
+    }
+
+    #[test]
+    fn finds_loops_from_backward_jumps() {
+        let profiler = profile(1 + 7 + 7 + 6 + 4);
+
+        let loops = profiler.hot_loops();
+
+        assert_eq!(loops[0], HotLoop { start: 0x202, end: 0x208, iterations: 2, cycles: 11 });
+        assert_eq!(loops[1], HotLoop { start: 0x20a, end: 0x20a, iterations: 4, cycles: 4 });
+    }
+
+    #[test]
+    fn report_lists_each_table() {
+        let report = profile(40).report();
+
+        assert!(report.starts_with("40 instructions"));
+        assert!(report.contains("\nHottest loops\n"));
+        assert!(report.contains("  sub_20C\n"));
+        assert!(report.contains("  RegisterByteInstruction::Add\n"));
+        assert!(report.contains(" 20A JP 0x20A\n"));
+    }
+}
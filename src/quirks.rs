@@ -0,0 +1,226 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The original CHIP-8 interpreter and its later descendants disagree on a handful of instructions.
+// Each flag here selects one side of a disagreement so the same interpreter can run ROMs written
+// for any of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    pub vf_reset: bool, // 8xy1, 8xy2 and 8xy3 reset VF to 0
+    pub memory_increments_i: bool, // Fx55 and Fx65 leave I pointing past the last register
+    pub display_wait: bool, // Dxyn waits for the next frame before drawing
+    pub clip_sprites: bool, // sprites are clipped at the screen edge instead of wrapping
+    pub shift_uses_vy: bool, // 8xy6 and 8xyE shift Vy into Vx instead of shifting Vx in place
+    pub jump_uses_vx: bool, // Bnnn jumps to xnn + Vx instead of nnn + V0
+}
+
+const FLAG_NAMES: [&str; 6] = [
+    "vf_reset",
+    "memory_increments_i",
+    "display_wait",
+    "clip_sprites",
+    "shift_uses_vy",
+    "jump_uses_vx",
+];
+
+impl Quirks {
+    // Behaviour of the original COSMAC VIP interpreter
+    pub fn chip8() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            memory_increments_i: true,
+            display_wait: true,
+            clip_sprites: true,
+            shift_uses_vy: true,
+            jump_uses_vx: false,
+        }
+    }
+
+    // Behaviour of SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increments_i: false,
+            display_wait: false,
+            clip_sprites: true,
+            shift_uses_vy: false,
+            jump_uses_vx: true,
+        }
+    }
+
+    // Behaviour of XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increments_i: true,
+            display_wait: false,
+            clip_sprites: false,
+            shift_uses_vy: true,
+            jump_uses_vx: false,
+        }
+    }
+
+    pub fn none() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            memory_increments_i: false,
+            display_wait: false,
+            clip_sprites: false,
+            shift_uses_vy: false,
+            jump_uses_vx: false,
+        }
+    }
+
+    fn flags(&self) -> [bool; 6] {
+        [
+            self.vf_reset,
+            self.memory_increments_i,
+            self.display_wait,
+            self.clip_sprites,
+            self.shift_uses_vy,
+            self.jump_uses_vx,
+        ]
+    }
+
+    // One bit per flag in declaration order, used by save states
+    pub fn to_bits(&self) -> u8 {
+        self.flags()
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (index, enabled)| bits | ((*enabled as u8) << index))
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        let mut quirks = Quirks::none();
+        for (index, name) in FLAG_NAMES.iter().enumerate() {
+            if bits & (1 << index) != 0 {
+                quirks.set_flag(name);
+            }
+        }
+
+        quirks
+    }
+
+    fn set_flag(&mut self, name: &str) -> bool {
+        match name {
+            "vf_reset" => self.vf_reset = true,
+            "memory_increments_i" => self.memory_increments_i = true,
+            "display_wait" => self.display_wait = true,
+            "clip_sprites" => self.clip_sprites = true,
+            "shift_uses_vy" => self.shift_uses_vy = true,
+            "jump_uses_vx" => self.jump_uses_vx = true,
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::chip8()
+    }
+}
+
+// Quirks are written as a profile name when they match one exactly, otherwise as a comma separated
+// list of the enabled flags. This is the form used on the command line and in movie files.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Quirks::chip8() {
+            return write!(f, "chip8");
+        }
+        if *self == Quirks::schip() {
+            return write!(f, "schip");
+        }
+        if *self == Quirks::xochip() {
+            return write!(f, "xochip");
+        }
+        if *self == Quirks::none() {
+            return write!(f, "none");
+        }
+
+        let enabled: Vec<&str> = FLAG_NAMES
+            .iter()
+            .zip(self.flags().iter())
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| *name)
+            .collect();
+
+        write!(f, "{}", enabled.join(","))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseQuirksError(pub String);
+
+impl fmt::Display for ParseQuirksError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown quirk or profile: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseQuirksError {}
+
+impl FromStr for Quirks {
+    type Err = ParseQuirksError;
+
+    fn from_str(s: &str) -> Result<Quirks, ParseQuirksError> {
+        match s.trim() {
+            "chip8" => return Ok(Quirks::chip8()),
+            "schip" => return Ok(Quirks::schip()),
+            "xochip" => return Ok(Quirks::xochip()),
+            "none" | "" => return Ok(Quirks::none()),
+            _ => {}
+        }
+
+        let mut quirks = Quirks::none();
+        for name in s.split(',').map(str::trim) {
+            if !quirks.set_flag(name) {
+                return Err(ParseQuirksError(name.to_string()));
+            }
+        }
+
+        Ok(quirks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParseQuirksError, Quirks};
+
+    #[test]
+    fn profiles_display_as_their_name() {
+        assert_eq!(Quirks::chip8().to_string(), "chip8");
+        assert_eq!(Quirks::schip().to_string(), "schip");
+        assert_eq!(Quirks::xochip().to_string(), "xochip");
+        assert_eq!(Quirks::none().to_string(), "none");
+    }
+
+    #[test]
+    fn custom_quirks_round_trip() {
+        let quirks = Quirks {
+            shift_uses_vy: true,
+            jump_uses_vx: true,
+            ..Quirks::none()
+        };
+
+        let text = quirks.to_string();
+
+        assert_eq!(text, "shift_uses_vy,jump_uses_vx");
+        assert_eq!(text.parse::<Quirks>(), Ok(quirks));
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for quirks in [Quirks::chip8(), Quirks::schip(), Quirks::xochip(), Quirks::none()].iter() {
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), *quirks);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unknown_flags() {
+        let parsed = "vf_reset,warp_drive".parse::<Quirks>();
+
+        assert_eq!(parsed, Err(ParseQuirksError("warp_drive".to_string())));
+    }
+}
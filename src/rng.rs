@@ -0,0 +1,76 @@
+// Seeded xorshift64* generator used by Cxkk. The interpreter owns its own generator rather than
+// using a global one so that a run can be reproduced exactly from its seed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift never leaves the all zero state, so a zero seed is swapped for a fixed constant
+        let state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+
+        Rng { state }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // Resumes a generator from a state previously returned by state()
+    pub fn from_state(state: u64) -> Rng {
+        Rng::new(state)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    // Uniform in [0, 1), from the top 53 bits so every value is exactly representable
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rng;
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut first = Rng::new(1234);
+        let mut second = Rng::new(1234);
+
+        let first_values: Vec<u8> = (0..32).map(|_| first.next_u8()).collect();
+        let second_values: Vec<u8> = (0..32).map(|_| second.next_u8()).collect();
+
+        assert_eq!(first_values, second_values);
+    }
+
+    #[test]
+    fn zero_seed_still_produces_values() {
+        let mut rng = Rng::new(0);
+
+        let values: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+
+        assert!(values.iter().all(|value| *value != 0));
+    }
+
+    #[test]
+    fn next_f64_stays_in_the_unit_interval() {
+        let mut rng = Rng::new(7);
+
+        let values: Vec<f64> = (0..1000).map(|_| rng.next_f64()).collect();
+
+        assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+        assert!(values.iter().any(|value| *value < 0.1) && values.iter().any(|value| *value > 0.9));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::interpreter::{Interpreter, MEMORY_SIZE};
+use std::fmt;
+
+// A copy of everything a ROM can observe about the machine, taken at one point in time. Used to
+// compare machines and to show what a machine looked like when something went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub cycles: u64,
+    pub pc: u16,
+    pub i: u16,
+    pub registers: [u8; 16],
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: Vec<u8>,
+    pub display: [u64; DISPLAY_HEIGHT],
+}
+
+impl Snapshot {
+    pub fn of(interpreter: &Interpreter) -> Snapshot {
+        Snapshot {
+            cycles: interpreter.cycles(),
+            pc: interpreter.pc(),
+            i: interpreter.i(),
+            registers: *interpreter.registers(),
+            stack: interpreter.stack().to_vec(),
+            delay_timer: interpreter.delay_timer(),
+            sound_timer: interpreter.sound_timer(),
+            memory: interpreter.memory().to_vec(),
+            display: *interpreter.display().rows(),
+        }
+    }
+
+    // Describes each way this snapshot differs from another, one line per difference. The cycle
+    // count isn't compared, machines can reach the same state at different times.
+    pub fn differences(&self, other: &Snapshot) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if self.pc != other.pc {
+            differences.push(format!("PC {:04X} != {:04X}", self.pc, other.pc));
+        }
+        if self.i != other.i {
+            differences.push(format!("I {:04X} != {:04X}", self.i, other.i));
+        }
+        for (register, (ours, theirs)) in self.registers.iter().zip(other.registers.iter()).enumerate() {
+            if ours != theirs {
+                differences.push(format!("V{:X} {:02X} != {:02X}", register, ours, theirs));
+            }
+        }
+        if self.stack != other.stack {
+            differences.push(format!("stack {} != {}", format_stack(&self.stack), format_stack(&other.stack)));
+        }
+        if self.delay_timer != other.delay_timer {
+            differences.push(format!("DT {:02X} != {:02X}", self.delay_timer, other.delay_timer));
+        }
+        if self.sound_timer != other.sound_timer {
+            differences.push(format!("ST {:02X} != {:02X}", self.sound_timer, other.sound_timer));
+        }
+        for address in 0..MEMORY_SIZE.min(self.memory.len()).min(other.memory.len()) {
+            if self.memory[address] != other.memory[address] {
+                differences.push(format!("memory {:03X} {:02X} != {:02X}", address, self.memory[address], other.memory[address]));
+            }
+        }
+        for (y, (ours, theirs)) in self.display.iter().zip(other.display.iter()).enumerate() {
+            if ours != theirs {
+                differences.push(format!("display row {} {:016X} != {:016X}", y, ours, theirs));
+            }
+        }
+
+        differences
+    }
+}
+
+fn format_stack(stack: &[u16]) -> String {
+    let addresses: Vec<String> = stack.iter().map(|address| format!("{:03X}", address)).collect();
+
+    format!("[{}]", addresses.join(" "))
+}
+
+// Registers on one line and the display as # and . below, memory is left out as it would drown
+// everything else
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "cycle {}  PC {:04X}  I {:04X}  DT {:02X}  ST {:02X}  stack {}",
+            self.cycles, self.pc, self.i, self.delay_timer, self.sound_timer, format_stack(&self.stack))?;
+        for (register, value) in self.registers.iter().enumerate() {
+            write!(f, "V{:X} {:02X}{}", register, value, if register == 15 { "\n" } else { "  " })?;
+        }
+        for row in self.display.iter() {
+            let pixels: String = (0..DISPLAY_WIDTH).map(|x| if row & (1 << (DISPLAY_WIDTH - 1 - x)) != 0 { '#' } else { '.' }).collect();
+            writeln!(f, "{}", pixels)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Snapshot;
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn differences_lists_each_changed_part() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        // LD V3, 7; LD I, 300; LD [I], V3
+        interpreter.load_rom(&[0x63, 0x07, 0xa3, 0x00, 0xf3, 0x55]).unwrap();
+        let before = Snapshot::of(&interpreter);
+
+        for _ in 0..3 {
+            interpreter.step().unwrap();
+        }
+        let after = Snapshot::of(&interpreter);
+
+        assert_eq!(before.differences(&before), Vec::<String>::new());
+        assert_eq!(before.differences(&after), vec![
+            "PC 0200 != 0206",
+            "I 0000 != 0304",
+            "V3 00 != 07",
+            "memory 303 00 != 07",
+        ]);
+    }
+}
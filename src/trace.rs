@@ -0,0 +1,226 @@
+// Instruction trace, one line per executed instruction, for diffing a run against another emulator
+// or another build of this one. Every line shows the machine as it was just before the instruction
+// executed, in fixed width columns separated by single spaces:
+//
+//     cycle      pc   op   v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf i    sp dt st mnemonic
+//     0000000012 0218 8014 05 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 ADD V0, V1
+//
+// cycle is the number of instructions executed before this one, in decimal. Everything else is
+// upper case hexadecimal: pc and the opcode, V0 to VF, I, the stack depth and the delay and sound
+// timers. The mnemonic is the disassembly of the opcode as shown by Instruction's Display, or ???
+// when the opcode doesn't decode. Lines starting with # are comments.
+use crate::instruction::{Instruction, InstructionFamily};
+use crate::interpreter::{Interpreter, Observer};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+pub const HEADER: &str = "# cycle    pc   op   v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf i    sp dt st mnemonic";
+
+pub fn format_line(interpreter: &Interpreter, opcode: u16) -> String {
+    let mut line = format!("{:010} {:04X} {:04X}", interpreter.cycles(), interpreter.pc(), opcode);
+    for register in interpreter.registers().iter() {
+        line.push_str(&format!(" {:02X}", register));
+    }
+    line.push_str(&format!(
+        " {:04X} {:02X} {:02X} {:02X} ",
+        interpreter.i(),
+        interpreter.sp(),
+        interpreter.delay_timer(),
+        interpreter.sound_timer()
+    ));
+
+    match Instruction::decode(((opcode >> 8) as u8, opcode as u8)) {
+        Some(instruction) => line.push_str(&instruction.to_string()),
+        None => line.push_str("???"),
+    }
+
+    line
+}
+
+// Limits a trace to instructions at some addresses or of some families. An empty filter traces
+// everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub families: Vec<InstructionFamily>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseRangeError(pub String);
+
+impl fmt::Display for ParseRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not an address range, expected e.g. 200-2FF", self.0)
+    }
+}
+
+impl std::error::Error for ParseRangeError {}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        if let Some(addresses) = &self.addresses {
+            if !addresses.contains(&pc) {
+                return false;
+            }
+        }
+
+        if self.families.is_empty() {
+            return true;
+        }
+        match Instruction::decode(((opcode >> 8) as u8, opcode as u8)) {
+            Some(instruction) => self.families.contains(&instruction.family()),
+            None => false,
+        }
+    }
+
+    // Parses an inclusive range of hexadecimal addresses such as 200-2FF, or a single address
+    pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, ParseRangeError> {
+        let error = || ParseRangeError(text.to_string());
+        let address = |part: &str| u16::from_str_radix(part.trim().trim_start_matches("0x"), 16).map_err(|_| error());
+
+        let (start, end) = match text.split_once('-') {
+            Some((start, end)) => (address(start)?, address(end)?),
+            None => (address(text)?, address(text)?),
+        };
+        if start > end {
+            return Err(error());
+        }
+
+        Ok(start..=end)
+    }
+}
+
+// Writes trace lines as the interpreter runs, e.g. interpreter.run_frame_with(&mut tracer). Write
+// errors stop the trace and are returned from finish.
+pub struct Tracer<W: Write> {
+    writer: W,
+    filter: TraceFilter,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, filter: TraceFilter) -> Tracer<W> {
+        Tracer {
+            writer,
+            filter,
+            error: None,
+        }
+    }
+
+    pub fn write_header(&mut self) {
+        self.write_line(HEADER);
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", line) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn before_step(&mut self, interpreter: &Interpreter, opcode: u16) {
+        if self.error.is_none() && self.filter.matches(interpreter.pc(), opcode) {
+            self.write_line(&format_line(interpreter, opcode));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_line, TraceFilter, Tracer, HEADER};
+    use crate::instruction::InstructionFamily;
+    use crate::interpreter::Interpreter;
+    use crate::quirks::Quirks;
+
+    // LD V0, 5; LD V1, 10; ADD V0, V1; LD I, 300; JP 208
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0x61, 0x0a, 0x80, 0x14, 0xa3, 0x00, 0x12, 0x08];
+
+    fn trace(filter: TraceFilter, steps: usize) -> Vec<String> {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&PROGRAM).unwrap();
+        let mut tracer = Tracer::new(Vec::new(), filter);
+
+        for _ in 0..steps {
+            interpreter.step_with(&mut tracer).unwrap();
+        }
+
+        let output = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn lines_show_the_state_before_each_instruction() {
+        let lines = trace(TraceFilter::default(), 4);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "0000000000 0200 6005 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 LD V0, 0x05");
+        assert_eq!(lines[2], "0000000002 0204 8014 05 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 ADD V0, V1");
+        assert_eq!(lines[3], "0000000003 0206 A300 0F 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 LD I, 0x300");
+    }
+
+    #[test]
+    fn header_lines_up_with_the_columns() {
+        let line = &trace(TraceFilter::default(), 1)[0];
+
+        assert_eq!(HEADER.find("pc").unwrap(), line.find("0200").unwrap());
+        assert_eq!(HEADER.find("vf").unwrap(), line.find(" 0000 ").unwrap() - 2);
+        assert_eq!(HEADER.find("mnemonic").unwrap(), line.find("LD").unwrap());
+    }
+
+    #[test]
+    fn filter_by_address_range() {
+        let filter = TraceFilter {
+            addresses: Some(TraceFilter::parse_range("204-206").unwrap()),
+            ..TraceFilter::default()
+        };
+
+        let lines = trace(filter, 5);
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0000000002 0204"));
+        assert!(lines[1].starts_with("0000000003 0206"));
+    }
+
+    #[test]
+    fn filter_by_instruction_family() {
+        let filter = TraceFilter {
+            families: vec![InstructionFamily::TwoRegisterInstruction, InstructionFamily::AddressInstruction],
+            ..TraceFilter::default()
+        };
+
+        let lines = trace(filter, 6);
+
+        let mnemonics: Vec<&str> = lines.iter().map(|line| &line[83..]).collect();
+        assert_eq!(mnemonics, vec!["ADD V0, V1", "LD I, 0x300", "JP 0x208", "JP 0x208"]);
+    }
+
+    #[test]
+    fn parse_range_accepts_single_addresses_and_rejects_backwards_ranges() {
+        assert_eq!(TraceFilter::parse_range("0x2A0"), Ok(0x2a0..=0x2a0));
+        assert!(TraceFilter::parse_range("300-200").is_err());
+        assert!(TraceFilter::parse_range("zz").is_err());
+    }
+
+    #[test]
+    fn undecodable_opcodes_are_traced_as_unknown() {
+        let mut interpreter = Interpreter::new(Quirks::chip8(), 0);
+        interpreter.load_rom(&[0x51, 0x21]).unwrap();
+
+        let line = format_line(&interpreter, 0x5121);
+
+        assert!(line.ends_with(" 5121 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 00 00 00 ???"));
+    }
+}
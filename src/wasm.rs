@@ -0,0 +1,147 @@
+// JavaScript bindings, built with `--features wasm` for wasm32-unknown-unknown. Byte buffers cross
+// the boundary as Uint8Arrays and errors are thrown as JavaScript Errors.
+
+use crate::database::RomInfo;
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::interpreter::Interpreter;
+use crate::loader::RomLoader;
+use crate::quirks::Quirks;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Chip8 {
+    interpreter: Interpreter,
+    // The quirks and speed chosen from JavaScript, which win over the ROM's own when one is loaded
+    settings: RomInfo,
+}
+
+#[wasm_bindgen]
+impl Chip8 {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Chip8 {
+        Chip8 {
+            interpreter: Interpreter::new(Quirks::default(), 0),
+            settings: RomInfo::default(),
+        }
+    }
+
+    // Runs the ROM with the quirks and speed the ROM database has for it, or quirks detected from
+    // its code, except where set_quirks or set_cycles_per_frame chose otherwise. Pass the file name
+    // to load an Octo cartridge GIF.
+    pub fn load_rom(&mut self, bytes: &[u8], name: Option<String>) -> Result<(), JsError> {
+        let loaded = RomLoader::new().prepare(name.as_deref().unwrap_or(""), bytes.to_vec())?;
+        let mut interpreter = self.interpreter.clone();
+        self.settings.clone().fill_from(loaded.info).configure(&mut interpreter);
+        interpreter.load_rom(&loaded.rom)?;
+        self.interpreter = interpreter;
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.interpreter.reset();
+    }
+
+    // Throws when the ROM stops with an error
+    pub fn run_frame(&mut self) -> Result<(), JsError> {
+        self.interpreter.run_frame()?;
+
+        Ok(())
+    }
+
+    // One byte per pixel, 1 for lit and 0 for unlit, rows top to bottom
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.interpreter.display().pixels()
+    }
+
+    // 4 bytes per pixel, ready to wrap in an ImageData. Colours are 0xRRGGBB.
+    pub fn framebuffer_rgba(&self, on: u32, off: u32) -> Vec<u8> {
+        self.interpreter.display().to_rgba(rgba(on), rgba(off))
+    }
+
+    // True when the display changed since the last call, so callers can skip redrawing the canvas
+    pub fn take_dirty(&mut self) -> bool {
+        let display = self.interpreter.display_mut();
+        let dirty = display.is_dirty();
+        display.clear_dirty();
+
+        dirty
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        self.interpreter.keypad_mut().press(key);
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.interpreter.keypad_mut().release(key);
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.interpreter.sound_active()
+    }
+
+    // Fills a Float32Array with buzzer samples, e.g. from an AudioWorklet
+    pub fn fill_audio(&mut self, buffer: &mut [f32], sample_rate: u32) {
+        self.interpreter.fill_audio(buffer, sample_rate);
+    }
+
+    pub fn set_buzzer(&mut self, frequency: f32, volume: f32) {
+        let buzzer = self.interpreter.buzzer_mut();
+        buzzer.set_frequency(frequency);
+        buzzer.set_volume(volume);
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.interpreter.save_state()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+        self.interpreter.load_state(state)?;
+
+        Ok(())
+    }
+
+    // Accepts the same values as the --quirks command line option
+    pub fn set_quirks(&mut self, quirks: &str) -> Result<(), JsError> {
+        let quirks = quirks.parse()?;
+        self.interpreter.set_quirks(quirks);
+        self.settings.quirks = Some(quirks);
+
+        Ok(())
+    }
+
+    // Accepts the same values as the --on-error command line option
+    pub fn set_error_policies(&mut self, policies: &str) -> Result<(), JsError> {
+        self.interpreter.set_error_policies(policies.parse()?);
+
+        Ok(())
+    }
+
+    // Takes a u32 so JavaScript can pass a plain number rather than a BigInt
+    pub fn set_seed(&mut self, seed: u32) {
+        self.interpreter.set_seed(seed as u64);
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.interpreter.set_cycles_per_frame(cycles_per_frame);
+        self.settings.cycles_per_frame = Some(cycles_per_frame);
+    }
+
+    pub fn width(&self) -> usize {
+        DISPLAY_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        DISPLAY_HEIGHT
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+fn rgba(colour: u32) -> [u8; 4] {
+    [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8, 0xff]
+}
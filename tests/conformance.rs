@@ -0,0 +1,695 @@
+// Conformance suite. Runs test ROMs headlessly and checks the final framebuffer against a golden
+// image in fixtures/golden. The ROMs are modelled on the well-known community test ROMs (the IBM
+// logo, BC_test, corax+, and Timendus' flags, quirks and keypad tests) and are assembled here rather
+// than vendored, so the suite has no licensing strings attached and every check is readable below.
+//
+// Results are drawn the way corax+ draws them: a cell per check with a two digit label and a tick or
+// a cross. Golden images are 32 lines of # and . and can be re-recorded with
+//
+//     UPDATE_GOLDEN=1 cargo test --test conformance
+use chip_8_rust::database::Database;
+use chip_8_rust::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip_8_rust::instruction::{Instruction, InstructionFamily, INSTRUCTION_FAMILIES};
+use chip_8_rust::interpreter::{Backend, Interpreter, Observer};
+use chip_8_rust::lockstep::Lockstep;
+use chip_8_rust::quirks::Quirks;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// A minimal assembler: opcodes are written out in hex, and instructions that take an address can
+// refer to a label instead, which is filled in once the whole ROM is known
+struct Rom {
+    bytes: Vec<u8>,
+    labels: HashMap<String, u16>,
+    fixups: Vec<(usize, String)>,
+}
+
+impl Rom {
+    fn new() -> Rom {
+        Rom {
+            bytes: Vec::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn address(&self) -> u16 {
+        0x200 + self.bytes.len() as u16
+    }
+
+    fn op(&mut self, opcode: u16) {
+        self.bytes.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn ops(&mut self, opcodes: &[u16]) {
+        for opcode in opcodes.iter() {
+            self.op(*opcode);
+        }
+    }
+
+    // An instruction whose low 12 bits are the address of a label, e.g. at(0x2000, "report") is
+    // CALL report
+    fn at(&mut self, opcode: u16, label: &str) {
+        self.fixups.push((self.bytes.len(), label.to_string()));
+        self.op(opcode);
+    }
+
+    fn label(&mut self, name: &str) {
+        assert!(self.labels.insert(name.to_string(), self.address()).is_none(), "label {} defined twice", name);
+    }
+
+    fn data(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn build(mut self) -> Vec<u8> {
+        for (offset, label) in self.fixups.iter() {
+            let address = *self.labels.get(label).unwrap_or_else(|| panic!("undefined label {}", label));
+            let opcode = u16::from_be_bytes([self.bytes[*offset], self.bytes[*offset + 1]]) | address;
+            self.bytes[*offset..*offset + 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+
+        self.bytes
+    }
+}
+
+// Draws a result cell at (VA, VB): the label in V2 as two hex digits, then a tick if V0 equals V1
+// or a cross if it doesn't. Four cells fit on a row. Uses V3 and VF.
+fn report_routine(rom: &mut Rom) {
+    rom.label("report");
+    rom.ops(&[
+        0x8320, // LD V3, V2
+        0x8336, // SHR V3, V3
+        0x8336, // SHR V3, V3
+        0x8336, // SHR V3, V3
+        0x8336, // SHR V3, V3
+        0xf329, // LD F, V3
+        0xdab5, // DRW VA, VB, 5
+        0x7a05, // ADD VA, 5
+        0x630f, // LD V3, 0F
+        0x8322, // AND V3, V2
+        0xf329, // LD F, V3
+        0xdab5, // DRW VA, VB, 5
+        0x7a05, // ADD VA, 5
+    ]);
+    rom.at(0xa000, "cross");
+    rom.op(0x9010); // SNE V0, V1
+    rom.at(0xa000, "tick");
+    rom.ops(&[
+        0xdab5, // DRW VA, VB, 5
+        0x7a06, // ADD VA, 6
+        0x3a40, // SE VA, 64
+        0x00ee, // RET
+        0x6a00, // LD VA, 0
+        0x7b06, // ADD VB, 6
+        0x00ee, // RET
+    ]);
+    rom.label("tick");
+    rom.data(&[0x08, 0x10, 0xa0, 0x40, 0x00]);
+    rom.label("cross");
+    rom.data(&[0x88, 0x50, 0x20, 0x50, 0x88]);
+}
+
+// Reports V0 against an expected value
+fn report(rom: &mut Rom, label: u8, expected: u8) {
+    rom.op(0x6100 | expected as u16); // LD V1, expected
+    rom.op(0x6200 | label as u16); // LD V2, label
+    rom.at(0x2000, "report");
+}
+
+fn halt(rom: &mut Rom, name: &str) {
+    rom.label(name);
+    rom.at(0x1000, name);
+}
+
+// The IBM logo: only 00E0, Annn, 6xkk, 7xkk, Dxyn and 1nnn, the first thing a new interpreter runs
+fn ibm_logo() -> Vec<u8> {
+    const LOGO: [&str; 8] = [
+        "#######.############....######...........######.",
+        "..###.....###......###...#####.........#####....",
+        "..###.....###......###...###.###.....###.###....",
+        "..###.....##########.....###..###...###..###....",
+        "..###.....##########.....###...###.###...###....",
+        "..###.....###......###...###....#####....###....",
+        "..###.....###......###...###.....###.....###....",
+        "#######.############...#####......#......#####..",
+    ];
+    let mut rom = Rom::new();
+
+    rom.ops(&[
+        0x00e0, // CLS
+        0x6008, // LD V0, 8
+        0x6109, // LD V1, 9
+    ]);
+    for sprite in 0..6 {
+        rom.at(0xa000, &format!("logo{}", sprite));
+        rom.op(0xd01f); // DRW V0, V1, 15
+        rom.op(0x7008); // ADD V0, 8
+    }
+    halt(&mut rom, "halt");
+
+    // Each line of the logo becomes a stripe with a blank row under it, as in the original
+    for sprite in 0..6 {
+        rom.label(&format!("logo{}", sprite));
+        for row in 0..15 {
+            let line = LOGO[row / 2].as_bytes();
+            let byte = (0..8).fold(0, |byte, bit| byte << 1 | (row % 2 == 0 && line[sprite * 8 + bit] == b'#') as u8);
+            rom.data(&[byte]);
+        }
+    }
+
+    rom.build()
+}
+
+// Checks one opcode per cell, labelled with the opcode
+fn corax() -> Vec<u8> {
+    let mut rom = Rom::new();
+
+    rom.ops(&[0x00e0, 0x6a00, 0x6b00]); // CLS; LD VA, 0; LD VB, 0
+
+    // 2nnn and 00EE
+    rom.op(0x6000); // LD V0, 0
+    rom.at(0x2000, "set_42");
+    report(&mut rom, 0xee, 0x42);
+
+    // 1nnn
+    rom.op(0x6001); // LD V0, 1
+    rom.at(0x1000, "jumped");
+    rom.op(0x6000); // LD V0, 0
+    rom.label("jumped");
+    report(&mut rom, 0x10, 0x01);
+
+    // Each skip is tested taken and not taken, adding 1 and 2 when they don't skip
+    rom.ops(&[0x6407, 0x6507, 0x6608]); // LD V4, 7; LD V5, 7; LD V6, 8
+    rom.ops(&[0x6000, 0x3407, 0x7001, 0x3408, 0x7002]); // SE V4, 7 ...; SE V4, 8 ...
+    report(&mut rom, 0x30, 0x02);
+    rom.ops(&[0x6000, 0x4407, 0x7001, 0x4408, 0x7002]); // SNE V4, 7 ...; SNE V4, 8 ...
+    report(&mut rom, 0x40, 0x01);
+    rom.ops(&[0x6000, 0x5450, 0x7001, 0x5460, 0x7002]); // SE V4, V5 ...; SE V4, V6 ...
+    report(&mut rom, 0x50, 0x02);
+    rom.ops(&[0x6000, 0x9450, 0x7001, 0x9460, 0x7002]); // SNE V4, V5 ...; SNE V4, V6 ...
+    report(&mut rom, 0x90, 0x01);
+
+    rom.op(0x605a); // LD V0, 5A
+    report(&mut rom, 0x60, 0x5a);
+    rom.ops(&[0x60ff, 0x7003]); // LD V0, FF; ADD V0, 3
+    report(&mut rom, 0x70, 0x02);
+
+    // 8xy_, shifts use the same register for Vx and Vy so the result doesn't depend on quirks
+    let alu: [(u8, &[u16], u8); 9] = [
+        (0x80, &[0x6477, 0x8040], 0x77),         // LD V0, V4
+        (0x81, &[0x6030, 0x6405, 0x8041], 0x35), // OR V0, V4
+        (0x82, &[0x603c, 0x640f, 0x8042], 0x0c), // AND V0, V4
+        (0x83, &[0x603c, 0x640f, 0x8043], 0x33), // XOR V0, V4
+        (0x84, &[0x60f0, 0x6420, 0x8044], 0x10), // ADD V0, V4
+        (0x85, &[0x6010, 0x6420, 0x8045], 0xf0), // SUB V0, V4
+        (0x86, &[0x6081, 0x8006], 0x40),         // SHR V0, V0
+        (0x87, &[0x6010, 0x6430, 0x8047], 0x20), // SUBN V0, V4
+        (0x8e, &[0x6081, 0x800e], 0x02),         // SHL V0, V0
+    ];
+    for (label, opcodes, expected) in alu.iter() {
+        rom.ops(opcodes);
+        report(&mut rom, *label, *expected);
+    }
+
+    // Fx1E, reading the byte two past the start of data
+    rom.at(0xa000, "data");
+    rom.ops(&[0x6402, 0xf41e, 0xf065]); // LD V4, 2; ADD I, V4; LD V0, [I]
+    report(&mut rom, 0x1e, 0x33);
+
+    // Fx33, 137 should store 1, 3, 7
+    rom.op(0x6489); // LD V4, 137
+    rom.at(0xa000, "scratch");
+    rom.ops(&[0xf433, 0xf265]); // LD B, V4; LD V2, [I]
+    rom.ops(&[0x3103, 0x60ff, 0x3207, 0x60ff]); // SE V1, 3; LD V0, FF; SE V2, 7; LD V0, FF
+    report(&mut rom, 0x33, 0x01);
+
+    // Fx55 and Fx65 round trip, I is reloaded so the memory quirk doesn't matter
+    rom.ops(&[0x6012, 0x6134]); // LD V0, 12; LD V1, 34
+    rom.at(0xa000, "scratch");
+    rom.ops(&[0xf155, 0x6000, 0x6100]); // LD [I], V1; LD V0, 0; LD V1, 0
+    rom.at(0xa000, "scratch");
+    rom.ops(&[0xf165, 0x3134, 0x60ff]); // LD V1, [I]; SE V1, 34; LD V0, FF
+    report(&mut rom, 0x55, 0x12);
+
+    halt(&mut rom, "halt");
+    rom.label("set_42");
+    rom.ops(&[0x6042, 0x00ee]); // LD V0, 42; RET
+    report_routine(&mut rom);
+    rom.label("data");
+    rom.data(&[0x11, 0x22, 0x33, 0x44]);
+    rom.label("scratch");
+    rom.data(&[0; 4]);
+
+    rom.build()
+}
+
+// Checks the result and VF of 8xy4, 8xy5, 8xy6, 8xy7 and 8xyE. Cells are labelled with the last
+// opcode digit and a case: 1 without carry or borrow, 2 with, 3 with VF as the destination, where
+// the flag has to win, and 4 with VF as an input.
+fn flags() -> Vec<u8> {
+    let cases: [(u8, &[u16], u8, u8); 20] = [
+        (0x41, &[0x6410, 0x6520, 0x8454], 0x30, 0),         // ADD V4, V5
+        (0x42, &[0x64f0, 0x6520, 0x8454], 0x10, 1),
+        (0x43, &[0x6ff0, 0x6520, 0x8f54, 0x84f0], 1, 1),    // ADD VF, V5
+        (0x44, &[0x64f0, 0x6f20, 0x84f4], 0x10, 1),         // ADD V4, VF
+        (0x51, &[0x6430, 0x6510, 0x8455], 0x20, 1),         // SUB V4, V5
+        (0x52, &[0x6410, 0x6530, 0x8455], 0xe0, 0),
+        (0x53, &[0x6f30, 0x6510, 0x8f55, 0x84f0], 1, 1),    // SUB VF, V5
+        (0x54, &[0x6430, 0x6f10, 0x84f5], 0x20, 1),         // SUB V4, VF
+        (0x61, &[0x6404, 0x8446], 0x02, 0),                 // SHR V4, V4
+        (0x62, &[0x6405, 0x8446], 0x02, 1),
+        (0x63, &[0x6f05, 0x8ff6, 0x84f0], 1, 1),            // SHR VF, VF
+        (0x64, &[0x6401, 0x8446], 0x00, 1),
+        (0x71, &[0x6410, 0x6530, 0x8457], 0x20, 1),         // SUBN V4, V5
+        (0x72, &[0x6430, 0x6510, 0x8457], 0xe0, 0),
+        (0x73, &[0x6f10, 0x6530, 0x8f57, 0x84f0], 1, 1),    // SUBN VF, V5
+        (0x74, &[0x6410, 0x6f30, 0x84f7], 0x20, 1),         // SUBN V4, VF
+        (0xe1, &[0x6441, 0x844e], 0x82, 0),                 // SHL V4, V4
+        (0xe2, &[0x64c1, 0x844e], 0x82, 1),
+        (0xe3, &[0x6f81, 0x8ffe, 0x84f0], 1, 1),            // SHL VF, VF
+        (0xe4, &[0x6480, 0x844e], 0x00, 1),
+    ];
+    let mut rom = Rom::new();
+
+    rom.ops(&[0x00e0, 0x6a00, 0x6b00]);
+    for (label, opcodes, result, flag) in cases.iter() {
+        rom.ops(opcodes);
+        // V0 gets the flag, and V1 is spoiled if the result in V4 is wrong
+        rom.op(0x80f0); // LD V0, VF
+        rom.op(0x6100 | *flag as u16); // LD V1, flag
+        rom.op(0x3400 | *result as u16); // SE V4, result
+        rom.op(0x61ff); // LD V1, FF
+        rom.op(0x6200 | *label as u16); // LD V2, label
+        rom.at(0x2000, "report");
+    }
+    halt(&mut rom, "halt");
+    report_routine(&mut rom);
+
+    rom.build()
+}
+
+// Runs checks in order and stops at the first failure, showing E and the number of the check that
+// failed, or OK when everything passed
+fn bc_test() -> Vec<u8> {
+    let mut rom = Rom::new();
+
+    // 1: 00E0 clears a drawn digit, so drawing it again doesn't collide
+    rom.ops(&[0x00e0, 0x6901, 0x6400, 0xf429, 0x6500, 0xd555, 0x00e0, 0xd555, 0x3f00]);
+    rom.at(0x1000, "fail");
+    rom.op(0x00e0);
+
+    // 2: Dxyn sets VF only when it turns a pixel off
+    rom.ops(&[0x6902, 0xd555, 0x3f00]);
+    rom.at(0x1000, "fail");
+    rom.ops(&[0xd555, 0x3f01]);
+    rom.at(0x1000, "fail");
+    rom.ops(&[0xd555, 0x3f00]);
+    rom.at(0x1000, "fail");
+    rom.op(0x00e0);
+
+    // 3: Bnnn lands two bytes into the table. V0 and V2 are both set so that either jump quirk
+    // agrees, which relies on the table being in 2xx.
+    rom.ops(&[0x6903, 0x6002, 0x6202]);
+    rom.at(0xb000, "table");
+    rom.label("table");
+    assert_eq!(rom.address() >> 8, 2);
+    rom.at(0x1000, "fail");
+    rom.at(0x1000, "jumped");
+    rom.label("jumped");
+
+    // 4: Cxkk is masked by kk
+    rom.ops(&[0x6904, 0xc400, 0x3400]); // RND V4, 00; SE V4, 0
+    rom.at(0x1000, "fail");
+    rom.ops(&[0xc40f, 0x65f0, 0x8542, 0x3500]); // RND V4, 0F; LD V5, F0; AND V5, V4; SE V5, 0
+    rom.at(0x1000, "fail");
+
+    // 5: the delay timer reads back non zero and counts down to zero, with the sound timer running
+    // alongside it
+    rom.ops(&[0x6905, 0x6405, 0xf415, 0xf418, 0xf507, 0x4500]); // ... LD V5, DT; SNE V5, 0
+    rom.at(0x1000, "fail");
+    rom.label("wait");
+    rom.ops(&[0xf507, 0x3500]); // LD V5, DT; SE V5, 0
+    rom.at(0x1000, "wait");
+
+    // 6: Fx29 points I at the font, the top row of A is F0
+    rom.ops(&[0x6906, 0x640a, 0xf429, 0xf065, 0x30f0]);
+    rom.at(0x1000, "fail");
+
+    // 7: nested calls return in order
+    rom.ops(&[0x6907, 0x6000]);
+    rom.at(0x2000, "nest1");
+    rom.op(0x3003); // SE V0, 3
+    rom.at(0x1000, "fail");
+
+    rom.at(0xa000, "o");
+    rom.ops(&[0x6418, 0x650c, 0xd458, 0x7408]); // LD V4, 24; LD V5, 12; DRW V4, V5, 8; ADD V4, 8
+    rom.at(0xa000, "k");
+    rom.op(0xd458);
+    halt(&mut rom, "passed");
+
+    rom.label("fail");
+    rom.ops(&[0x640e, 0xf429, 0x6418, 0x650c, 0xd455]); // E at 24, 12
+    rom.ops(&[0x7406, 0xf929, 0xd455]); // then the check number
+    halt(&mut rom, "failed");
+
+    rom.label("nest1");
+    rom.op(0x7001);
+    rom.at(0x2000, "nest2");
+    rom.op(0x00ee);
+    rom.label("nest2");
+    rom.op(0x7001);
+    rom.at(0x2000, "nest3");
+    rom.op(0x00ee);
+    rom.label("nest3");
+    rom.ops(&[0x7001, 0x00ee]);
+
+    rom.label("o");
+    rom.data(&[0x3c, 0x66, 0x66, 0x66, 0x66, 0x66, 0x3c, 0x00]);
+    rom.label("k");
+    rom.data(&[0x66, 0x6c, 0x78, 0x70, 0x78, 0x6c, 0x66, 0x00]);
+
+    rom.build()
+}
+
+// Measures each quirk and shows a tick where the interpreter has it: 1 vF reset, 2 memory
+// increments I, 3 display wait, 4 clipping, 5 shifts use Vy, 6 jumps use Vx
+fn quirks_test() -> Vec<u8> {
+    let mut rom = Rom::new();
+
+    rom.ops(&[0x00e0, 0x6a00, 0x6b00]);
+
+    // The jump goes first so the table is in 2xx, where a quirky Bnnn reads V2
+    rom.ops(&[0x6000, 0x6202]); // LD V0, 0; LD V2, 2
+    rom.at(0xb000, "jump_table");
+    rom.label("jump_table");
+    assert_eq!(rom.address() >> 8, 2);
+    rom.at(0x1000, "jump_off");
+    rom.at(0x1000, "jump_on");
+    rom.label("jump_off");
+    rom.op(0x6000); // LD V0, 0
+    rom.at(0x1000, "jump_done");
+    rom.label("jump_on");
+    rom.op(0x6001); // LD V0, 1
+    rom.label("jump_done");
+    rom.op(0x8900); // LD V9, V0
+
+    rom.ops(&[0x6f05, 0x6401, 0x8441, 0x80f0]); // LD VF, 5; LD V4, 1; OR V4, V4; LD V0, VF
+    report(&mut rom, 0x01, 0x00);
+
+    rom.at(0xa000, "pattern");
+    rom.ops(&[0xf165, 0xf065]); // LD V1, [I]; LD V0, [I]
+    report(&mut rom, 0x02, 0xcc);
+
+    // Counts the sprites drawn in one frame, starting just after a timer tick
+    rom.ops(&[0x6001, 0xf015]); // LD V0, 1; LD DT, V0
+    rom.label("sync");
+    rom.ops(&[0xf007, 0x3000]); // LD V0, DT; SE V0, 0
+    rom.at(0x1000, "sync");
+    rom.ops(&[0x6001, 0xf015, 0x6600]); // LD V0, 1; LD DT, V0; LD V6, 0
+    rom.at(0xa000, "blank");
+    rom.label("count");
+    rom.ops(&[0xd441, 0x7601, 0xf007, 0x3000]); // DRW V4, V4, 1; ADD V6, 1; LD V0, DT; SE V0, 0
+    rom.at(0x1000, "count");
+    rom.op(0x8060); // LD V0, V6
+    report(&mut rom, 0x03, 0x01);
+
+    // A sprite at x 60 only collides with a pixel at x 0 if it wraps. Both are drawn again after.
+    rom.at(0xa000, "full");
+    rom.ops(&[0x643c, 0x651f, 0xd451]); // LD V4, 60; LD V5, 31; DRW V4, V5, 1
+    rom.at(0xa000, "dot");
+    rom.ops(&[0x6400, 0xd451, 0x80f0, 0xd451]); // LD V4, 0; DRW V4, V5, 1; LD V0, VF; DRW V4, V5, 1
+    rom.at(0xa000, "full");
+    rom.ops(&[0x643c, 0xd451]);
+    report(&mut rom, 0x04, 0x00);
+
+    rom.ops(&[0x6402, 0x6510, 0x8456, 0x8040]); // LD V4, 2; LD V5, 10; SHR V4, V5; LD V0, V4
+    report(&mut rom, 0x05, 0x08);
+
+    rom.op(0x8090); // LD V0, V9
+    report(&mut rom, 0x06, 0x01);
+    halt(&mut rom, "halt");
+
+    report_routine(&mut rom);
+    rom.label("pattern");
+    rom.data(&[0xaa, 0xbb, 0xcc]);
+    rom.label("blank");
+    rom.data(&[0x00]);
+    rom.label("full");
+    rom.data(&[0xff]);
+    rom.label("dot");
+    rom.data(&[0x80]);
+
+    rom.build()
+}
+
+// Waits for a key with Fx0A and draws it in the top left, then draws every key on two keypads: the
+// held keys on the left with Ex9E, and the rest on the right with ExA1
+fn keypad() -> Vec<u8> {
+    const LAYOUT: [u8; 16] = [0x1, 0x2, 0x3, 0xc, 0x4, 0x5, 0x6, 0xd, 0x7, 0x8, 0x9, 0xe, 0xa, 0x0, 0xb, 0xf];
+    let mut rom = Rom::new();
+
+    rom.ops(&[0x00e0, 0xf40a, 0xf429, 0x6500, 0xd555]); // CLS; LD V4, K; LD F, V4; DRW V5, V5, 5
+
+    // Gives the next keys time to be pressed
+    rom.ops(&[0x651e, 0xf515]); // LD V5, 30; LD DT, V5
+    rom.label("delay");
+    rom.ops(&[0xf507, 0x3500]); // LD V5, DT; SE V5, 0
+    rom.at(0x1000, "delay");
+
+    rom.op(0x6400); // LD V4, 0
+    rom.label("scan");
+    rom.op(0xe4a1); // SKNP V4
+    rom.at(0x2000, "held");
+    rom.op(0xe49e); // SKP V4
+    rom.at(0x2000, "free");
+    rom.ops(&[0x7401, 0x3410]); // ADD V4, 1; SE V4, 16
+    rom.at(0x1000, "scan");
+    halt(&mut rom, "halt");
+
+    rom.label("held");
+    rom.op(0x6600); // LD V6, 0
+    rom.at(0x1000, "draw_key");
+    rom.label("free");
+    rom.op(0x6622); // LD V6, 34
+    rom.label("draw_key");
+    rom.at(0xa000, "xs");
+    rom.ops(&[0xf41e, 0xf065, 0x8700, 0x8764]); // ADD I, V4; LD V0, [I]; LD V7, V0; ADD V7, V6
+    rom.at(0xa000, "ys");
+    rom.ops(&[0xf41e, 0xf065, 0x8800]); // ADD I, V4; LD V0, [I]; LD V8, V0
+    rom.ops(&[0xf429, 0xd785, 0x00ee]); // LD F, V4; DRW V7, V8, 5; RET
+
+    let position = |key: u8| LAYOUT.iter().position(|k| *k == key).unwrap() as u8;
+    rom.label("xs");
+    rom.data(&(0..16).map(|key| position(key) % 4 * 6).collect::<Vec<u8>>());
+    rom.label("ys");
+    rom.data(&(0..16).map(|key| 8 + position(key) / 4 * 6).collect::<Vec<u8>>());
+
+    rom.build()
+}
+
+// Remembers every opcode executed
+#[derive(Default)]
+struct Executed(HashSet<u16>);
+
+impl Observer for Executed {
+    fn before_step(&mut self, _interpreter: &Interpreter, opcode: u16) {
+        self.0.insert(opcode);
+    }
+}
+
+fn run(rom: &[u8], quirks: Quirks, frames: usize, keys: impl Fn(usize) -> u16, executed: &mut Executed) -> Interpreter {
+    let mut interpreter = Interpreter::new(quirks, 0);
+    interpreter.load_rom(rom).unwrap();
+
+    for frame in 0..frames {
+        interpreter.set_keys(keys(frame));
+        interpreter.run_frame_with(executed).unwrap();
+    }
+
+    interpreter
+}
+
+// Presses A and lets go, then holds 1, 5, 9 and F
+fn keypad_inputs(frame: usize) -> u16 {
+    match frame {
+        10..=14 => 1 << 0xa,
+        20.. => 1 << 0x1 | 1 << 0x5 | 1 << 0x9 | 1 << 0xf,
+        _ => 0,
+    }
+}
+
+fn render(display: &Display) -> String {
+    let mut image = String::new();
+    for y in 0..DISPLAY_HEIGHT {
+        for x in 0..DISPLAY_WIDTH {
+            image.push(if display.pixel(x, y) { '#' } else { '.' });
+        }
+        image.push('\n');
+    }
+
+    image
+}
+
+fn assert_golden(name: &str, interpreter: &Interpreter) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden").join(format!("{}.txt", name));
+    let actual = render(interpreter.display());
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("no golden image at {}, run with UPDATE_GOLDEN=1 to record it", path.display()));
+
+    assert!(expected == actual, "{} doesn't match its golden image\nexpected:\n{}\nactual:\n{}", name, expected, actual);
+}
+
+fn profiles() -> [(&'static str, Quirks); 3] {
+    [("chip8", Quirks::chip8()), ("schip", Quirks::schip()), ("xochip", Quirks::xochip())]
+}
+
+#[test]
+fn ibm_logo_matches_golden_image() {
+    let interpreter = run(&ibm_logo(), Quirks::chip8(), 20, |_| 0, &mut Executed::default());
+
+    assert_golden("ibm_logo", &interpreter);
+}
+
+// These ROMs don't depend on quirks, so every profile has to produce the same image
+#[test]
+fn corax_matches_golden_image_under_every_profile() {
+    for (_, quirks) in profiles().iter() {
+        let interpreter = run(&corax(), *quirks, 200, |_| 0, &mut Executed::default());
+
+        assert_golden("corax", &interpreter);
+    }
+}
+
+#[test]
+fn flags_matches_golden_image_under_every_profile() {
+    for (_, quirks) in profiles().iter() {
+        let interpreter = run(&flags(), *quirks, 200, |_| 0, &mut Executed::default());
+
+        assert_golden("flags", &interpreter);
+    }
+}
+
+#[test]
+fn bc_test_matches_golden_image_under_every_profile() {
+    for (_, quirks) in profiles().iter() {
+        let interpreter = run(&bc_test(), *quirks, 60, |_| 0, &mut Executed::default());
+
+        assert_golden("bc_test", &interpreter);
+    }
+}
+
+#[test]
+fn keypad_matches_golden_image() {
+    let interpreter = run(&keypad(), Quirks::chip8(), 150, keypad_inputs, &mut Executed::default());
+
+    assert_golden("keypad", &interpreter);
+}
+
+#[test]
+fn quirks_match_golden_image_for_each_profile() {
+    for (name, quirks) in profiles().iter() {
+        let interpreter = run(&quirks_test(), *quirks, 100, |_| 0, &mut Executed::default());
+
+        assert_golden(&format!("quirks_{}", name), &interpreter);
+    }
+}
+
+#[test]
+fn suite_database_recognises_every_rom() {
+    let database = Database::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/suite.txt")).unwrap();
+    let roms = [ibm_logo(), corax(), flags(), bc_test(), quirks_test(), keypad()];
+
+    let titles: Vec<Option<&str>> =
+        roms.iter().map(|rom| database.lookup(rom).map(|info| info.title.as_str())).collect();
+
+    // A ROM changed above needs its hash updated in fixtures/suite.txt
+    assert_eq!(
+        titles,
+        [
+            "IBM logo (conformance suite)",
+            "Opcode test (conformance suite)",
+            "Flags test (conformance suite)",
+            "BC test (conformance suite)",
+            "Quirks test (conformance suite)",
+            "Keypad test (conformance suite)"
+        ]
+        .map(Some)
+    );
+}
+
+// Names an opcode by the pattern it matches in the usual opcode tables, e.g. 8xy4 or Fx33
+fn pattern(opcode: u16) -> String {
+    const PATTERNS: [&str; 16] = [
+        "", "1nnn", "2nnn", "3xkk", "4xkk", "5xy0", "6xkk", "7xkk", "", "9xy0", "Annn", "Bnnn", "Cxkk", "Dxyn", "", "",
+    ];
+
+    match opcode >> 12 {
+        0x0 => format!("{:04X}", opcode),
+        0x8 => format!("8xy{:X}", opcode & 0xf),
+        0xe | 0xf => format!("{:X}x{:02X}", opcode >> 12, opcode & 0xff),
+        nibble => PATTERNS[nibble as usize].to_string(),
+    }
+}
+
+#[test]
+fn suite_executes_every_chip8_opcode_and_family() {
+    let mut executed = Executed::default();
+
+    run(&ibm_logo(), Quirks::chip8(), 20, |_| 0, &mut executed);
+    run(&corax(), Quirks::chip8(), 200, |_| 0, &mut executed);
+    run(&flags(), Quirks::chip8(), 200, |_| 0, &mut executed);
+    run(&bc_test(), Quirks::chip8(), 60, |_| 0, &mut executed);
+    run(&keypad(), Quirks::chip8(), 150, keypad_inputs, &mut executed);
+    run(&quirks_test(), Quirks::chip8(), 100, |_| 0, &mut executed);
+
+    let patterns: HashSet<String> = executed.0.iter().map(|opcode| pattern(*opcode)).collect();
+    let families: HashSet<InstructionFamily> = executed
+        .0
+        .iter()
+        .map(|opcode| Instruction::parse(((opcode >> 8) as u8, *opcode as u8)).unwrap().family())
+        .collect();
+    let missing: Vec<&str> = [
+        "00E0", "00EE", "1nnn", "2nnn", "3xkk", "4xkk", "5xy0", "6xkk", "7xkk", "8xy0", "8xy1", "8xy2", "8xy3",
+        "8xy4", "8xy5", "8xy6", "8xy7", "8xyE", "9xy0", "Annn", "Bnnn", "Cxkk", "Dxyn", "Ex9E", "ExA1", "Fx07",
+        "Fx0A", "Fx15", "Fx18", "Fx1E", "Fx29", "Fx33", "Fx55", "Fx65",
+    ]
+    .iter()
+    .cloned()
+    .filter(|name| !patterns.contains(*name))
+    .collect();
+
+    assert_eq!(missing, Vec::<&str>::new());
+    assert!(INSTRUCTION_FAMILIES.iter().all(|family| families.contains(family)));
+}
+
+// The threaded backend has to match the interpreter before every instruction of every ROM
+#[test]
+fn threaded_backend_matches_the_interpreter_in_lockstep() {
+    let roms = [(ibm_logo(), 20), (corax(), 200), (flags(), 200), (bc_test(), 60), (keypad(), 150), (quirks_test(), 100)];
+
+    for (rom, frames) in roms.iter() {
+        for (name, quirks) in profiles().iter() {
+            let machine = |backend| {
+                let mut interpreter = Interpreter::new(*quirks, 0);
+                interpreter.set_backend(backend);
+                interpreter.load_rom(rom).unwrap();
+
+                interpreter
+            };
+            let inputs: Vec<u16> = (0..*frames).map(keypad_inputs).collect();
+            let mut lockstep = Lockstep::new(machine(Backend::Interpreter), machine(Backend::Threaded));
+
+            if let Err(divergence) = lockstep.run(&inputs) {
+                panic!("{}\n{}", name, divergence);
+            }
+        }
+    }
+}
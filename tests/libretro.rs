@@ -0,0 +1,192 @@
+// A minimal libretro frontend that drives the core through its entry points the way RetroArch
+// would, capturing the video and audio it produces.
+use chip_8_rust::libretro::*;
+use std::os::raw::{c_uint, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+// The core is a global, so the tests take turns with it
+static FRONTEND: Mutex<()> = Mutex::new(());
+
+static PIXEL_FORMAT: Mutex<Option<c_uint>> = Mutex::new(None);
+static FRAME: Mutex<Option<(Vec<u32>, c_uint, c_uint)>> = Mutex::new(None);
+static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+static HELD: Mutex<Vec<(c_uint, c_uint)>> = Mutex::new(Vec::new());
+
+// LD V0, 1; LD F, V0; DRW V0, V0, 5; JP 206
+const FONT_ROM: [u8; 8] = [0x60, 0x01, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06];
+
+// LD V0, 30; LD ST, V0; JP 204
+const BEEP_ROM: [u8; 6] = [0x60, 0x1e, 0xf0, 0x18, 0x12, 0x04];
+
+// LD V1, K; LD F, V1; DRW V0, V0, 5; JP 206
+const KEY_ROM: [u8; 8] = [0xf1, 0x0a, 0xf1, 0x29, 0xd0, 0x05, 0x12, 0x06];
+
+// LD I, 200; LD AUDIO, [I]; LD V0, 62; LD V1, 0; LD F, V1; DRW V0, V1, 5; JP 20C. Only XO-CHIP has
+// LD AUDIO, and its sprites wrap around the edge where CHIP-8 clips them.
+const XOCHIP_ROM: [u8; 14] = [0xa2, 0x00, 0xf0, 0x02, 0x60, 0x3e, 0x61, 0x00, 0xf1, 0x29, 0xd0, 0x15, 0x12, 0x0c];
+
+unsafe extern "C" fn environment(command: c_uint, data: *mut c_void) -> bool {
+    if command != RETRO_ENVIRONMENT_SET_PIXEL_FORMAT {
+        return false;
+    }
+    *PIXEL_FORMAT.lock().unwrap() = Some(*(data as *const c_uint));
+
+    true
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let mut pixels = Vec::new();
+    for y in 0..height as usize {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        pixels.extend_from_slice(std::slice::from_raw_parts(row, width as usize));
+    }
+    *FRAME.lock().unwrap() = Some((pixels, width, height));
+}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    AUDIO.lock().unwrap().extend_from_slice(std::slice::from_raw_parts(data, frames * 2));
+
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    (port == 0 && HELD.lock().unwrap().contains(&(device, id))) as i16
+}
+
+fn start(rom: &[u8]) -> MutexGuard<'static, ()> {
+    let guard = FRONTEND.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *PIXEL_FORMAT.lock().unwrap() = None;
+    *FRAME.lock().unwrap() = None;
+    AUDIO.lock().unwrap().clear();
+    HELD.lock().unwrap().clear();
+
+    retro_set_environment(Some(environment));
+    retro_set_video_refresh(Some(video_refresh));
+    retro_set_audio_sample_batch(Some(audio_sample_batch));
+    retro_set_input_poll(Some(input_poll));
+    retro_set_input_state(Some(input_state));
+    retro_init();
+
+    let game = RetroGameInfo {
+        path: ptr::null(),
+        data: rom.as_ptr() as *const c_void,
+        size: rom.len(),
+        meta: ptr::null(),
+    };
+    assert!(unsafe { retro_load_game(&game) });
+
+    guard
+}
+
+fn run_frames(frames: usize) {
+    for _ in 0..frames {
+        unsafe { retro_run() };
+    }
+}
+
+fn lit(x: usize, y: usize) -> bool {
+    let frame = FRAME.lock().unwrap();
+    let (pixels, width, _) = frame.as_ref().expect("no frame presented");
+
+    pixels[y * *width as usize + x] == 0x00ff_ffff
+}
+
+#[test]
+fn load_game_requests_xrgb8888() {
+    let _frontend = start(&FONT_ROM);
+
+    assert_eq!(*PIXEL_FORMAT.lock().unwrap(), Some(RETRO_PIXEL_FORMAT_XRGB8888));
+    retro_deinit();
+}
+
+#[test]
+fn run_presents_a_frame_of_the_display() {
+    let _frontend = start(&FONT_ROM);
+
+    run_frames(1);
+
+    let (_, width, height) = FRAME.lock().unwrap().clone().unwrap();
+    assert_eq!((width, height), (64, 32));
+    assert!(lit(3, 1));
+    assert!(!lit(1, 1));
+    retro_deinit();
+}
+
+#[test]
+fn audio_batch_plays_the_buzzer() {
+    let _frontend = start(&BEEP_ROM);
+
+    let mut info = unsafe { std::mem::zeroed::<RetroSystemAvInfo>() };
+    unsafe { retro_get_system_av_info(&mut info) };
+    run_frames(2);
+
+    let audio = AUDIO.lock().unwrap();
+    assert_eq!(info.timing.sample_rate as usize, SAMPLE_RATE as usize);
+    assert_eq!(audio.len(), SAMPLES_PER_FRAME * 2 * 2);
+    assert!(audio.iter().any(|sample| *sample != 0));
+    retro_deinit();
+}
+
+#[test]
+fn joypad_and_keyboard_reach_the_keypad() {
+    let _frontend = start(&KEY_ROM);
+
+    run_frames(2);
+    HELD.lock().unwrap().push((RETRO_DEVICE_JOYPAD, RETRO_DEVICE_ID_JOYPAD_UP));
+    run_frames(2);
+    HELD.lock().unwrap().clear();
+    run_frames(2);
+
+    // Key 5 drawn at (0, 0)
+    assert!(lit(0, 0) && lit(3, 0) && !lit(3, 1));
+    retro_reset();
+    run_frames(1);
+    HELD.lock().unwrap().push((RETRO_DEVICE_KEYBOARD, b'x' as c_uint));
+    run_frames(2);
+    HELD.lock().unwrap().clear();
+    run_frames(2);
+
+    // Key 0 drawn after the reset
+    assert!(lit(0, 0) && lit(3, 1) && lit(0, 1));
+    retro_deinit();
+}
+
+#[test]
+fn serialize_round_trips() {
+    let _frontend = start(&FONT_ROM);
+    let size = retro_serialize_size();
+    let mut state = vec![0u8; size];
+
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, size) });
+    run_frames(3);
+    assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, size) });
+    let mut restored = vec![0u8; size];
+    assert!(unsafe { retro_serialize(restored.as_mut_ptr() as *mut c_void, size) });
+
+    assert_eq!(state, restored);
+    assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 4) });
+    retro_deinit();
+}
+
+#[test]
+fn load_game_detects_the_quirks() {
+    let _frontend = start(&XOCHIP_ROM);
+
+    run_frames(1);
+
+    assert!(lit(62, 0) && lit(0, 0));
+    retro_deinit();
+}
+
+#[test]
+fn bad_rom_keeps_presenting_frames() {
+    let _frontend = start(&[0x50, 0x01]);
+
+    run_frames(3);
+
+    assert!(FRAME.lock().unwrap().is_some());
+    retro_deinit();
+}
@@ -0,0 +1,48 @@
+// Differential tests: the interpreter against recorded traces, and quirk profiles against each other
+use chip_8_rust::interpreter::Interpreter;
+use chip_8_rust::lockstep::{compare_trace, Lockstep};
+use chip_8_rust::quirks::Quirks;
+
+// Exercises the flag setting arithmetic, BCD and register loads, see the comments in the fixture
+const ARITHMETIC: [u8; 22] = [
+    0x60, 0xff, // LD V0, FF
+    0x61, 0x02, // LD V1, 02
+    0x80, 0x14, // ADD V0, V1
+    0x82, 0x00, // LD V2, V0
+    0x82, 0x15, // SUB V2, V1
+    0x83, 0x26, // SHR V3, V2
+    0x84, 0x2e, // SHL V4, V2
+    0xa3, 0x00, // LD I, 300
+    0xf2, 0x33, // LD B, V2
+    0xf2, 0x65, // LD V2, [I]
+    0x12, 0x14, // JP 214
+];
+
+fn machine(quirks: Quirks) -> Interpreter {
+    let mut interpreter = Interpreter::new(quirks, 0);
+    interpreter.load_rom(&ARITHMETIC).unwrap();
+
+    interpreter
+}
+
+#[test]
+fn arithmetic_matches_the_recorded_trace() {
+    let expected = include_str!("fixtures/arithmetic.trace");
+
+    let result = compare_trace(&mut machine(Quirks::chip8()), &[0, 0], expected, 8);
+
+    if let Err(mismatch) = result {
+        panic!("{}", mismatch);
+    }
+}
+
+#[test]
+fn chip8_and_schip_diverge_at_the_shift() {
+    let mut lockstep = Lockstep::new(machine(Quirks::chip8()), machine(Quirks::schip()));
+
+    let divergence = lockstep.run(&[0; 2]).unwrap_err();
+
+    assert_eq!(divergence.differences, vec!["V3 7F != 00", "VF 01 != 00"]);
+    assert!(divergence.left_history.last().unwrap().ends_with("SHR V3, V2"));
+    assert_eq!(divergence.left.pc, 0x20c);
+}
@@ -0,0 +1,366 @@
+// Property tests. Each property is checked against a few hundred generated cases from a fixed seed,
+// or every possible input where there are few enough, so failures reproduce exactly. The fuzz
+// targets in fuzz/ push the same entry points much harder.
+use chip_8_rust::cartridge::{self, Cartridge};
+use chip_8_rust::detect::detect;
+use chip_8_rust::display::{Display, EdgeMode, DISPLAY_HEIGHT};
+use chip_8_rust::instruction::Instruction;
+use chip_8_rust::interpreter::{Backend, Interpreter};
+use chip_8_rust::lockstep::Lockstep;
+use chip_8_rust::octo;
+use chip_8_rust::patch::{self, PatchFormat};
+use chip_8_rust::policy::{ErrorPolicies, ErrorPolicy, ERROR_CLASSES};
+use chip_8_rust::quirks::Quirks;
+use chip_8_rust::rng::Rng;
+use chip_8_rust::snapshot::Snapshot;
+
+const CASES: u64 = 500;
+
+// Runs a property once per case, each with its own generator so a failing case number can be rerun
+// on its own
+fn check(property: impl Fn(u64, &mut Rng)) {
+    for case in 0..CASES {
+        property(case, &mut Rng::new(case + 1));
+    }
+}
+
+// Mostly valid instructions, so programs get somewhere before hitting a bad opcode
+fn random_rom(rng: &mut Rng) -> Vec<u8> {
+    let length = (rng.next_u64() % 256) as usize;
+    let mut rom = Vec::with_capacity(length * 2);
+    for _ in 0..length {
+        let mut opcode = rng.next_u64() as u16;
+        while rng.next_u8() < 230 && Instruction::decode(((opcode >> 8) as u8, opcode as u8)).is_none() {
+            opcode = rng.next_u64() as u16;
+        }
+        rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    rom
+}
+
+fn machine(program: &[u8], quirks: Quirks) -> Interpreter {
+    let mut interpreter = Interpreter::new(quirks, 1);
+    interpreter.load_rom(program).unwrap();
+
+    interpreter
+}
+
+#[test]
+fn parse_never_panics_and_agrees_with_decode() {
+    for opcode in 0..=u16::MAX {
+        let raw = ((opcode >> 8) as u8, opcode as u8);
+
+        let parsed = Instruction::parse(raw);
+
+        assert_eq!(parsed.as_ref().ok(), Instruction::decode(raw).as_ref(), "opcode {:04X}", opcode);
+        if let Ok(instruction) = parsed {
+            assert!(!instruction.to_string().is_empty(), "opcode {:04X}", opcode);
+        }
+    }
+}
+
+#[test]
+fn random_roms_never_panic() {
+    check(|_, rng| {
+        let mut interpreter = machine(&random_rom(rng), Quirks::from_bits(rng.next_u8()));
+
+        for _ in 0..30 {
+            interpreter.set_keys(rng.next_u64() as u16);
+            if interpreter.run_frame().is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[test]
+fn random_roms_never_panic_under_any_error_policy() {
+    let choices = [ErrorPolicy::Halt, ErrorPolicy::Wrap, ErrorPolicy::Ignore];
+    check(|_, rng| {
+        let mut interpreter = machine(&random_rom(rng), Quirks::from_bits(rng.next_u8()));
+        let mut policies = ErrorPolicies::default();
+        for class in ERROR_CLASSES.iter() {
+            policies.set(*class, choices[rng.next_u8() as usize % choices.len()]);
+        }
+        interpreter.set_error_policies(policies);
+
+        for _ in 0..30 {
+            interpreter.set_keys(rng.next_u64() as u16);
+            if let Err(error) = interpreter.run_frame() {
+                assert_eq!(policies.get(error.class()), ErrorPolicy::Halt);
+                break;
+            }
+        }
+    });
+}
+
+#[test]
+fn errors_leave_the_machine_unchanged() {
+    check(|case, rng| {
+        let mut interpreter = machine(&random_rom(rng), Quirks::from_bits(rng.next_u8()));
+
+        for _ in 0..2000 {
+            let before = Snapshot::of(&interpreter);
+            if let Err(error) = interpreter.step() {
+                assert_eq!(Snapshot::of(&interpreter), before, "case {}", case);
+                assert_eq!(interpreter.step(), Err(error), "case {}", case);
+                break;
+            }
+        }
+    });
+}
+
+#[test]
+fn decode_cache_never_changes_what_runs() {
+    check(|case, rng| {
+        let rom = random_rom(rng);
+        let quirks = Quirks::from_bits(rng.next_u8());
+        let mut cached = machine(&rom, quirks);
+        let mut uncached = machine(&rom, quirks);
+        uncached.set_decode_cache(false);
+        // Wrapping keeps more programs running, and stores through a random I write over code
+        let policies: ErrorPolicies = "all=wrap".parse().unwrap();
+        cached.set_error_policies(policies);
+        uncached.set_error_policies(policies);
+
+        for _ in 0..30 {
+            let keys = rng.next_u64() as u16;
+            cached.set_keys(keys);
+            uncached.set_keys(keys);
+
+            assert_eq!(cached.run_frame(), uncached.run_frame(), "case {}", case);
+            assert_eq!(cached.state_hash(), uncached.state_hash(), "case {}", case);
+        }
+    });
+}
+
+#[test]
+fn threaded_backend_matches_the_interpreter_instruction_for_instruction() {
+    let choices = [ErrorPolicy::Halt, ErrorPolicy::Wrap, ErrorPolicy::Ignore];
+    check(|case, rng| {
+        let rom = random_rom(rng);
+        let quirks = Quirks::from_bits(rng.next_u8());
+        let mut policies = ErrorPolicies::default();
+        for class in ERROR_CLASSES.iter() {
+            policies.set(*class, choices[rng.next_u8() as usize % choices.len()]);
+        }
+        let machine = |backend| {
+            let mut interpreter = machine(&rom, quirks);
+            interpreter.set_error_policies(policies);
+            interpreter.set_backend(backend);
+
+            interpreter
+        };
+        let inputs: Vec<u16> = (0..10).map(|_| rng.next_u64() as u16).collect();
+        let mut lockstep = Lockstep::new(machine(Backend::Interpreter), machine(Backend::Threaded));
+
+        if let Err(divergence) = lockstep.run(&inputs) {
+            panic!("case {}\n{}", case, divergence);
+        }
+    });
+}
+
+#[test]
+fn random_save_states_never_panic() {
+    check(|_, rng| {
+        let mut interpreter = machine(&random_rom(rng), Quirks::chip8());
+        let mut state = interpreter.save_state();
+        // Corrupt a few bytes after the header, or cut the state short
+        for _ in 0..8 {
+            let index = 5 + (rng.next_u64() as usize) % (state.len() - 5);
+            state[index] = rng.next_u8();
+        }
+        state.truncate(state.len() - (rng.next_u64() as usize % 64));
+
+        if interpreter.load_state(&state).is_ok() {
+            interpreter.reset();
+            let _ = interpreter.run_frame();
+        }
+    });
+}
+
+#[test]
+fn save_state_round_trips_at_any_point() {
+    check(|case, rng| {
+        let mut interpreter = machine(&random_rom(rng), Quirks::from_bits(rng.next_u8()));
+        for _ in 0..rng.next_u8() % 10 {
+            let _ = interpreter.run_frame();
+        }
+
+        let mut restored = Interpreter::new(Quirks::none(), 0);
+        restored.load_state(&interpreter.save_state()).unwrap();
+
+        assert_eq!(restored.state_hash(), interpreter.state_hash(), "case {}", case);
+        assert_eq!(restored.run_frame(), interpreter.run_frame(), "case {}", case);
+        assert_eq!(restored.state_hash(), interpreter.state_hash(), "case {}", case);
+    });
+}
+
+#[test]
+fn created_patches_reproduce_the_modified_rom() {
+    check(|case, rng| {
+        let original = random_rom(rng);
+        let mut modified = original.clone();
+        for _ in 0..rng.next_u8() % 16 {
+            if !modified.is_empty() {
+                let index = rng.next_u64() as usize % modified.len();
+                modified[index] = rng.next_u8();
+            }
+        }
+        modified.resize((modified.len() as i64 + (rng.next_u8() as i64 - 128)).max(0) as usize, rng.next_u8());
+
+        for format in [PatchFormat::Ips, PatchFormat::Bps] {
+            let created = patch::create(format, &original, &modified);
+
+            assert_eq!(patch::apply(&original, &created).as_ref(), Ok(&modified), "case {} {:?}", case, format);
+        }
+    });
+}
+
+#[test]
+fn corrupt_patches_never_panic() {
+    check(|_, rng| {
+        let original = random_rom(rng);
+        let mut created = patch::create(PatchFormat::Ips, &original, &random_rom(rng));
+        if rng.next_u8() < 128 {
+            created = patch::create(PatchFormat::Bps, &original, &random_rom(rng));
+        }
+        for _ in 0..4 {
+            let index = rng.next_u64() as usize % created.len();
+            created[index] = rng.next_u8();
+        }
+        created.truncate(created.len() - (rng.next_u64() as usize % 8).min(created.len()));
+
+        let _ = patch::apply(&original, &created);
+    });
+}
+
+#[test]
+fn corrupt_cartridges_never_panic() {
+    check(|_, rng| {
+        let mut gif = Vec::new();
+        let label = machine(&random_rom(rng), Quirks::default());
+        cartridge::write(&mut gif, &Cartridge::new(random_rom(rng)), label.display()).unwrap();
+        for _ in 0..4 {
+            let index = rng.next_u64() as usize % gif.len();
+            gif[index] = rng.next_u8();
+        }
+        gif.truncate(gif.len() - (rng.next_u64() as usize % 16));
+
+        let _ = cartridge::read(&gif);
+    });
+}
+
+#[test]
+fn octo_source_never_panics_the_compiler() {
+    const WORDS: [&str; 24] = [
+        ": main", ":", "main", "v0", "vf", ":=", "+=", "-=", "if", "then", "begin", "else", "end", "loop", "while",
+        "again", "i", "long", "0xFFF", "-1", "300", ":org", ":unpack", "sprite",
+    ];
+    check(|_, rng| {
+        let length = rng.next_u64() % 64;
+        let words: Vec<&str> = (0..length).map(|_| WORDS[rng.next_u64() as usize % WORDS.len()]).collect();
+
+        let _ = octo::compile(&words.join(" "));
+    });
+}
+
+#[test]
+fn detection_never_panics_and_stays_in_range() {
+    check(|_, rng| {
+        let rom = random_rom(rng);
+
+        let detection = detect(&rom);
+
+        assert!((0.0..=1.0).contains(&detection.confidence));
+        assert!(detection.instructions <= rom.len());
+    });
+}
+
+#[test]
+fn drawing_a_sprite_twice_restores_the_display() {
+    check(|case, rng| {
+        let edge_mode = if rng.next_u8() & 1 == 0 { EdgeMode::Clip } else { EdgeMode::Wrap };
+        let mut display = Display::new(edge_mode);
+        let mut rows = [0; DISPLAY_HEIGHT];
+        for row in rows.iter_mut() {
+            *row = rng.next_u64();
+        }
+        display.set_rows(rows);
+        let sprite: Vec<u8> = (0..rng.next_u8() % 16).map(|_| rng.next_u8()).collect();
+        let (x, y) = (rng.next_u8(), rng.next_u8());
+
+        display.draw_sprite(x, y, &sprite);
+        display.draw_sprite(x, y, &sprite);
+
+        assert_eq!(display.rows(), &rows, "case {}", case);
+    });
+}
+
+#[test]
+fn drawing_through_dxyn_twice_restores_the_display() {
+    check(|case, rng| {
+        let (x, y, height) = (rng.next_u8(), rng.next_u8(), rng.next_u8() % 16);
+        // LD V1, x; LD V2, y; LD I, 300; DRW V1, V2, height; DRW V1, V2, height
+        let mut program = vec![0x61, x, 0x62, y, 0xa3, 0x00, 0xd1, 0x20 | height, 0xd1, 0x20 | height];
+        program.resize(0x100, 0);
+        program.extend((0..16).map(|_| rng.next_u8()));
+        let mut interpreter = machine(&program, Quirks::from_bits(rng.next_u8()));
+        let mut rows = [0; DISPLAY_HEIGHT];
+        for row in rows.iter_mut() {
+            *row = rng.next_u64();
+        }
+        interpreter.display_mut().set_rows(rows);
+
+        for _ in 0..5 {
+            interpreter.step().unwrap();
+        }
+
+        assert_eq!(interpreter.display().rows(), &rows, "case {}", case);
+    });
+}
+
+#[test]
+fn bcd_digits_recombine_to_vx() {
+    for value in 0..=255u8 {
+        let x = value % 16;
+        // LD Vx, value; LD I, 300; LD B, Vx
+        let mut interpreter = machine(&[0x60 | x, value, 0xa3, 0x00, 0xf0 | x, 0x33], Quirks::chip8());
+
+        for _ in 0..3 {
+            interpreter.step().unwrap();
+        }
+
+        let digits = &interpreter.memory()[0x300..0x303];
+        assert!(digits.iter().all(|digit| *digit < 10), "value {}", value);
+        assert_eq!(digits[0] as u32 * 100 + digits[1] as u32 * 10 + digits[2] as u32, value as u32);
+    }
+}
+
+#[test]
+fn registers_round_trip_through_memory() {
+    check(|case, rng| {
+        let x = rng.next_u8() % 16;
+        let mut registers = [0; 16];
+        let mut program = Vec::new();
+        for (register, value) in registers.iter_mut().enumerate() {
+            *value = rng.next_u8();
+            program.extend_from_slice(&[0x60 | register as u8, *value]);
+        }
+        // LD I, 300; LD [I], Vx; then clear V0 to Vx; LD I, 300; LD Vx, [I]
+        program.extend_from_slice(&[0xa3, 0x00, 0xf0 | x, 0x55]);
+        for register in 0..=x {
+            program.extend_from_slice(&[0x60 | register, 0x00]);
+        }
+        program.extend_from_slice(&[0xa3, 0x00, 0xf0 | x, 0x65]);
+        let mut interpreter = machine(&program, Quirks::from_bits(rng.next_u8()));
+
+        for _ in 0..program.len() / 2 {
+            interpreter.step().unwrap();
+        }
+
+        assert_eq!(interpreter.registers(), &registers, "case {}", case);
+        assert_eq!(interpreter.memory()[0x300..=0x300 + x as usize], registers[..=x as usize], "case {}", case);
+    });
+}
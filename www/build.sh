@@ -0,0 +1,9 @@
+#!/bin/sh
+# Builds the wasm package for the browser harness (www/pkg) and for Node (www/pkg-node).
+# Needs the wasm32-unknown-unknown target and a wasm-bindgen CLI matching the version in Cargo.lock.
+set -e
+cd "$(dirname "$0")/.."
+
+cargo build --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm
+wasm-bindgen --target web --out-dir www/pkg target/wasm32-unknown-unknown/release/chip_8_rust.wasm
+wasm-bindgen --target nodejs --out-dir www/pkg-node target/wasm32-unknown-unknown/release/chip_8_rust.wasm
@@ -0,0 +1,109 @@
+import init, { Chip8 } from "./pkg/chip_8_rust.js";
+
+const ON_COLOUR = 0xffffff;
+const OFF_COLOUR = 0x000000;
+const FRAME_MS = 1000 / 60;
+
+// Same layout as the native front end, see KeyMap::qwerty
+const KEYS = {
+    "1": 0x1, "2": 0x2, "3": 0x3, "4": 0xc,
+    "q": 0x4, "w": 0x5, "e": 0x6, "r": 0xd,
+    "a": 0x7, "s": 0x8, "d": 0x9, "f": 0xe,
+    "z": 0xa, "x": 0x0, "c": 0xb, "v": 0xf,
+};
+
+await init();
+
+const chip8 = new Chip8();
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+let running = false;
+let savedState = null;
+let beep = null;
+
+function draw() {
+    if (!chip8.take_dirty()) {
+        return;
+    }
+    const pixels = new Uint8ClampedArray(chip8.framebuffer_rgba(ON_COLOUR, OFF_COLOUR));
+    context.putImageData(new ImageData(pixels, chip8.width(), chip8.height()), 0, 0);
+}
+
+function updateSound() {
+    if (chip8.sound_active() && beep === null) {
+        const audio = new AudioContext();
+        const oscillator = audio.createOscillator();
+        oscillator.type = "square";
+        oscillator.frequency.value = 440;
+        oscillator.connect(audio.destination);
+        oscillator.start();
+        beep = audio;
+    } else if (!chip8.sound_active() && beep !== null) {
+        beep.close();
+        beep = null;
+    }
+}
+
+let lastTime = performance.now();
+let pending = 0;
+function loop(time) {
+    pending += time - lastTime;
+    lastTime = time;
+    // Catch up on whole frames, but never more than a handful after the tab was in the background
+    pending = Math.min(pending, FRAME_MS * 5);
+    while (running && pending >= FRAME_MS) {
+        try {
+            chip8.run_frame();
+        } catch (error) {
+            // The ROM did something it can't continue from, stop until another one is loaded
+            running = false;
+            alert(error.message);
+        }
+        pending -= FRAME_MS;
+    }
+    draw();
+    updateSound();
+    requestAnimationFrame(loop);
+}
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+    const file = event.target.files[0];
+    if (!file) {
+        return;
+    }
+    try {
+        chip8.load_rom(new Uint8Array(await file.arrayBuffer()), file.name);
+        running = true;
+        document.getElementById("save").disabled = false;
+    } catch (error) {
+        alert(error.message);
+    }
+});
+
+document.getElementById("save").addEventListener("click", () => {
+    savedState = chip8.save_state();
+    document.getElementById("load").disabled = false;
+});
+
+document.getElementById("load").addEventListener("click", () => {
+    if (savedState !== null) {
+        chip8.load_state(savedState);
+    }
+});
+
+document.addEventListener("keydown", (event) => {
+    const key = KEYS[event.key.toLowerCase()];
+    if (key !== undefined) {
+        chip8.key_down(key);
+        event.preventDefault();
+    }
+});
+
+document.addEventListener("keyup", (event) => {
+    const key = KEYS[event.key.toLowerCase()];
+    if (key !== undefined) {
+        chip8.key_up(key);
+    }
+});
+
+requestAnimationFrame(loop);
@@ -0,0 +1,104 @@
+// Exercises the wasm package in Node without a browser. Run www/build.sh first, then
+//     node --test www/test.js
+const test = require("node:test");
+const assert = require("node:assert");
+const { Chip8 } = require("./pkg-node/chip_8_rust.js");
+
+// LD V0, 1; LD F, V0; DRW V0, V0, 5; LD V1, 30; LD ST, V1; JP 20A
+const ROM = new Uint8Array([0x60, 0x01, 0xf0, 0x29, 0xd0, 0x05, 0x61, 0x1e, 0xf1, 0x18, 0x12, 0x0a]);
+
+function pixel(framebuffer, x, y) {
+    return framebuffer[y * 64 + x];
+}
+
+test("run_frame draws into the framebuffer", () => {
+    const chip8 = new Chip8();
+    chip8.set_quirks("schip");
+    chip8.load_rom(ROM);
+
+    chip8.run_frame();
+    const framebuffer = chip8.framebuffer();
+
+    assert.ok(framebuffer instanceof Uint8Array);
+    assert.strictEqual(framebuffer.length, chip8.width() * chip8.height());
+    // The font sprite for 1 is 0x20 0x60 0x20 0x20 0x70, drawn at (1, 1)
+    assert.strictEqual(pixel(framebuffer, 3, 1), 1);
+    assert.strictEqual(pixel(framebuffer, 2, 2), 1);
+    assert.strictEqual(pixel(framebuffer, 1, 1), 0);
+    assert.ok(chip8.take_dirty());
+    assert.ok(!chip8.take_dirty());
+});
+
+test("sound is active while the sound timer runs", () => {
+    const chip8 = new Chip8();
+    chip8.set_quirks("schip");
+    chip8.load_rom(ROM);
+
+    chip8.run_frame();
+
+    assert.strictEqual(chip8.sound_active(), true);
+});
+
+test("keys reach the keypad", () => {
+    // Waits for key 0 with SKP, then draws the 0 sprite: LD V0, 0; SKP V0; JP 202; LD F, V0; DRW V0, V0, 5; JP 20A
+    const chip8 = new Chip8();
+    chip8.load_rom(new Uint8Array([0x60, 0x00, 0xe0, 0x9e, 0x12, 0x02, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x0a]));
+
+    chip8.run_frame();
+    assert.strictEqual(pixel(chip8.framebuffer(), 0, 0), 0);
+
+    chip8.key_down(0);
+    chip8.run_frame();
+    chip8.key_up(0);
+    assert.strictEqual(pixel(chip8.framebuffer(), 0, 0), 1);
+});
+
+test("load_rom detects the quirks unless set_quirks chose them", () => {
+    // LD I, 200; LD AUDIO, [I]; LD V0, 62; LD V1, 0; LD F, V1; DRW V0, V1, 5; JP 20C. Only XO-CHIP
+    // has LD AUDIO, and its sprites wrap around the edge where CHIP-8 clips them.
+    const rom = new Uint8Array([0xa2, 0x00, 0xf0, 0x02, 0x60, 0x3e, 0x61, 0x00, 0xf1, 0x29, 0xd0, 0x15, 0x12, 0x0c]);
+    const detected = new Chip8();
+    const chosen = new Chip8();
+    chosen.set_quirks("chip8");
+
+    detected.load_rom(rom);
+    chosen.load_rom(rom, "game.ch8");
+    detected.run_frame();
+    chosen.run_frame();
+
+    assert.strictEqual(pixel(detected.framebuffer(), 0, 0), 1);
+    assert.strictEqual(pixel(chosen.framebuffer(), 0, 0), 0);
+});
+
+test("save_state round trips", () => {
+    const chip8 = new Chip8();
+    chip8.load_rom(ROM);
+    chip8.run_frame();
+    const state = chip8.save_state();
+    const framebuffer = chip8.framebuffer();
+
+    const restored = new Chip8();
+    restored.load_state(state);
+
+    assert.deepStrictEqual(restored.framebuffer(), framebuffer);
+    assert.deepStrictEqual(restored.save_state(), state);
+});
+
+test("errors are thrown as exceptions", () => {
+    const chip8 = new Chip8();
+
+    assert.throws(() => chip8.load_rom(new Uint8Array(4000)), /at most 3584 bytes/);
+    assert.throws(() => chip8.load_state(new Uint8Array([1, 2, 3])), /truncated|not a save state/);
+    assert.throws(() => chip8.set_quirks("warp_drive"), /unknown quirk/);
+    chip8.load_rom(new Uint8Array([0x00, 0xee]));
+    assert.throws(() => chip8.run_frame(), /return with an empty stack/);
+});
+
+test("error policies keep a bad ROM running", () => {
+    const chip8 = new Chip8();
+    chip8.load_rom(new Uint8Array([0x00, 0xee]));
+
+    assert.throws(() => chip8.set_error_policies("stack_underflow=explode"), /not an error policy/);
+    chip8.set_error_policies("stack_underflow=ignore");
+    chip8.run_frame();
+});